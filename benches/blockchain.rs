@@ -0,0 +1,117 @@
+use blockchain::blockchain::mempool::{Mempool, ReplacementPolicy};
+use blockchain::blockchain::address::Address;
+use blockchain::blockchain::hash32::Hash32;
+use blockchain::blockchain::template::BlockTemplate;
+use blockchain::blockchain::transaction::Transaction;
+use blockchain::blockchain::wallet::Wallet;
+use blockchain::blockchain::{Block, BlockChain, Serialization};
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+// every block of it is mined for real, building it takes a minute or so
+const CHAIN_LENGTH: usize = 10_000;
+
+fn block_with_transactions(count: usize) -> Block {
+    let mut block = Block::new(0, Hash32::ZERO);
+    block.transactions = (0..count)
+        .map(|i| Transaction::new("A".into(), "B".into(), i as u64).with_nonce(i as u64).serialization())
+        .collect();
+    block.seal_transactions();
+    block
+}
+
+// the node only mines at one difficulty, this mirrors its loop so the
+// cost of each extra zero can be seen
+fn mine(block: &mut Block, difficulty: usize) {
+    let target = "0".repeat(difficulty);
+    while !hex::encode(block.hash()).starts_with(&target) {
+        *block += 1;
+    }
+}
+
+// who the miner pays
+fn recipient() -> Address {
+    Wallet::from_secret([8u8; 32]).address()
+}
+
+// every block pays the miner, who pays half of its rewards on to the
+// recipient once they have matured
+fn build_chain(length: usize) -> BlockChain {
+    let miner = Wallet::from_secret([7u8; 32]);
+    let mut chain = BlockChain::new(miner.address().into());
+    for nonce in 0..length as u64 {
+        let tx = Transaction::new(miner.address().into(), recipient().into(), 1).with_nonce(nonce).sign(&miner);
+        if nonce % 2 == 0 {
+            let _ = chain.add_transaction(&tx);
+        }
+        chain.mining().unwrap();
+    }
+    chain
+}
+
+fn block_hash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block_hash");
+    for count in [0, 10, 100, 1000] {
+        let block = block_with_transactions(count);
+        group.bench_with_input(BenchmarkId::from_parameter(count), &block, |b, block| {
+            b.iter(|| black_box(block).hash())
+        });
+    }
+    group.finish();
+}
+
+fn proof_of_work(c: &mut Criterion) {
+    let mut group = c.benchmark_group("proof_of_work");
+    group.sample_size(20);
+    for difficulty in 1..=4 {
+        group.bench_with_input(BenchmarkId::from_parameter(difficulty), &difficulty, |b, difficulty| {
+            b.iter_batched(
+                || block_with_transactions(10),
+                |mut block| mine(&mut block, *difficulty),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn chain_validation(c: &mut Criterion) {
+    let chain = build_chain(CHAIN_LENGTH);
+    let mut group = c.benchmark_group("chain_validation");
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::from_parameter(CHAIN_LENGTH), |b| {
+        b.iter(|| chain.validate_chain().unwrap())
+    });
+    group.finish();
+}
+
+// scanning every block against looking the account up in the state
+fn balance(c: &mut Criterion) {
+    let chain = build_chain(1_000);
+    let recipient: Address = recipient();
+    let mut group = c.benchmark_group("balance");
+    group.bench_function("scan", |b| {
+        b.iter(|| chain.calculate_total_amount(black_box(&recipient), 1))
+    });
+    group.bench_function("state", |b| b.iter(|| chain.state().balance(black_box(recipient.as_bytes()))));
+    group.finish();
+}
+
+fn mempool_selection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mempool_selection");
+    for size in [100, 1_000, 10_000] {
+        let mut pool = Mempool::new(ReplacementPolicy::default());
+        for i in 0..size {
+            let sender = format!("sender {}", i).into_bytes();
+            let tx = Transaction::new(sender, "B".into(), 1).with_fee((i * 7919 % 1000) as u64);
+            pool.add(tx).unwrap();
+        }
+        group.bench_with_input(BenchmarkId::from_parameter(size), &pool, |b, pool| {
+            b.iter(|| BlockTemplate::assemble(Hash32::ZERO, pool))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, block_hash, proof_of_work, chain_validation, balance, mempool_selection);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+#![no_main]
+
+use blockchain::blockchain::{Block, BlockChain};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+static CHAIN: OnceLock<BlockChain> = OnceLock::new();
+
+// a block of arbitrary transactions on top of a real chain. it is mined
+// here, otherwise validation would stop at the proof of work and never
+// look at the transactions
+fuzz_target!(|transactions: Vec<Vec<u8>>| {
+    let chain = CHAIN.get_or_init(|| BlockChain::new("fuzz".into()));
+
+    let tip = chain.last_block().expect("a new chain has its genesis block");
+    let mut block = Block::new(0, tip.hash());
+    block.transactions = transactions;
+    block.seal_transactions();
+    while !BlockChain::meets_difficulty(&block.hash()) {
+        block += 1;
+    }
+
+    let _ = chain.validate_block(&block);
+});
@@ -0,0 +1,38 @@
+#![no_main]
+
+use blockchain::blockchain::light::LightClient;
+use blockchain::blockchain::protocol::Message;
+use blockchain::blockchain::{BlockChain, Serialization};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+// mined once, every input is answered by the same node
+static CHAIN: OnceLock<BlockChain> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    let Some(message) = Message::decode(data) else {
+        return;
+    };
+    assert_eq!(Message::decode(&message.serialization()).as_ref(), Some(&message));
+
+    let chain = CHAIN.get_or_init(|| BlockChain::new("fuzz".into()));
+    chain.handle_message(&message);
+
+    // and what a light client does with the answers
+    let genesis = chain.get_block(0).expect("a new chain has its genesis block");
+    let mut client = LightClient::new(genesis.header());
+    match &message {
+        Message::Headers { start_height, headers } => {
+            let _ = client.add_headers(*start_height, headers.clone());
+        }
+        Message::FraudProof(proof) => {
+            let _ = client.process_fraud_proof(proof);
+        }
+        Message::Filters { filters, .. } => {
+            for filter in filters.iter() {
+                filter.matches_any(&genesis.hash(), &[b"fuzz".to_vec()]);
+            }
+        }
+        _ => {}
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use blockchain::blockchain::transaction::Transaction;
+use blockchain::blockchain::Serialization;
+use libfuzzer_sys::fuzz_target;
+
+// whatever a peer sends, decoding either fails or gives a transaction
+// that encodes back to the very same bytes. a second encoding of one
+// transaction (a flag byte of 2, say) would give it a second id
+fuzz_target!(|data: &[u8]| {
+    if let Some(tx) = Transaction::decode(data) {
+        let bytes = tx.serialization();
+        assert_eq!(bytes, data, "a transaction has one encoding");
+        let again = Transaction::decode(&bytes).expect("an encoded transaction decodes");
+        assert_eq!(again.serialization(), bytes);
+    }
+});
@@ -0,0 +1,203 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+// the first byte of every address on this chain, so an address for some
+// other kind of key or network can't be mistaken for one
+pub const ADDRESS_VERSION: u8 = 0x1c;
+
+// bitcoin's alphabet: no 0, O, I or l, which are easy to misread
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+const CHECKSUM_BYTES: usize = 4;
+
+// where coins are sent: the sha256 of a public key, written in base58check
+// (the version byte, the hash and 4 bytes of checksum, in base58). a typo
+// changes the checksum, so a mistyped address is rejected instead of
+// paying someone nobody has the key for.
+//
+// on chain an account is the text of its address, `as_bytes` is what
+// transactions and the state use. accounts that aren't keys (the coinbase
+// sender, contracts, names) stay plain bytes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address {
+    hash: [u8; 32],
+    // the base58check text, worked out once
+    encoded: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressError {
+    // a character outside the base58 alphabet
+    InvalidCharacter(char),
+    WrongLength(usize),
+    UnknownVersion(u8),
+    BadChecksum,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::InvalidCharacter(c) => write!(f, "{:?} is not a base58 character", c),
+            AddressError::WrongLength(len) => write!(f, "an address holds 37 bytes, not {}", len),
+            AddressError::UnknownVersion(version) => write!(f, "address version {} is not this chain's", version),
+            AddressError::BadChecksum => write!(f, "the address checksum doesn't match, it was probably mistyped"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let twice = Sha256::digest(Sha256::digest(payload));
+    twice[..CHECKSUM_BYTES].try_into().expect("a sha256 is longer than a checksum")
+}
+
+// base58 is the bytes read as one big-endian number, written in base 58.
+// every leading zero byte becomes a leading '1'
+fn base58_encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|b| **b == 0).count();
+    // little-endian base 58 digits
+    let mut digits = Vec::<u8>::new();
+    for byte in bytes.iter() {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut text = "1".repeat(zeros);
+    text.extend(digits.iter().rev().map(|d| ALPHABET[*d as usize] as char));
+    text
+}
+
+fn base58_decode(text: &str) -> Result<Vec<u8>, AddressError> {
+    let zeros = text.chars().take_while(|c| *c == '1').count();
+    // little-endian bytes
+    let mut bytes = Vec::<u8>::new();
+    for c in text.chars() {
+        let mut carry = ALPHABET
+            .iter()
+            .position(|a| *a as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes.iter().rev());
+    Ok(decoded)
+}
+
+impl Address {
+    pub fn from_public_key(public_key: &[u8]) -> Self {
+        Address::from_hash(Sha256::digest(public_key).into())
+    }
+
+    pub fn from_hash(hash: [u8; 32]) -> Self {
+        let mut payload = vec![ADDRESS_VERSION];
+        payload.extend(hash);
+        let sum = checksum(&payload);
+        payload.extend(sum);
+        Address {
+            hash,
+            encoded: base58_encode(&payload),
+        }
+    }
+
+    // an account read from a transaction or the state, none if it isn't an
+    // address, e.g. the coinbase sender
+    pub fn from_account(account: &[u8]) -> Option<Self> {
+        std::str::from_utf8(account).ok()?.parse().ok()
+    }
+
+    // the hash of the public key, what a p2pkh script checks
+    pub fn pubkey_hash(&self) -> &[u8; 32] {
+        &self.hash
+    }
+
+    // the account on chain
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.encoded.into_bytes()
+    }
+}
+
+// reads like the text, `address.as_bytes()` is the account on chain
+impl Deref for Address {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.encoded
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.encoded)
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.encoded
+    }
+}
+
+impl From<Address> for Vec<u8> {
+    fn from(address: Address) -> Self {
+        address.into_bytes()
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let payload: Vec<u8> = base58_decode(s)?;
+        if payload.len() != 1 + 32 + CHECKSUM_BYTES {
+            return Err(AddressError::WrongLength(payload.len()));
+        }
+        let (body, sum) = payload.split_at(1 + 32);
+        if checksum(body) != sum {
+            return Err(AddressError::BadChecksum);
+        }
+        if body[0] != ADDRESS_VERSION {
+            return Err(AddressError::UnknownVersion(body[0]));
+        }
+        Ok(Address::from_hash(body[1..].try_into().expect("split after 33 bytes")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_address_survives_the_round_trip_and_typos_are_caught() {
+        let address = Address::from_public_key(b"a public key");
+        let text = address.to_string();
+        assert_eq!(text.parse::<Address>(), Ok(address.clone()));
+        assert_eq!(Address::from_account(text.as_bytes()), Some(address));
+
+        // the last character changed, a leading '1' added, a 0
+        let mut typo: String = text[..text.len() - 1].to_string();
+        typo.push(if text.ends_with('2') { '3' } else { '2' });
+        assert_eq!(typo.parse::<Address>(), Err(AddressError::BadChecksum));
+        assert!(format!("1{}", text).parse::<Address>().is_err());
+        assert_eq!("0abc".parse::<Address>(), Err(AddressError::InvalidCharacter('0')));
+        assert_eq!(base58_decode(&base58_encode(&[0, 0, 1, 255])), Ok(vec![0, 0, 1, 255]));
+        assert_eq!(Address::from_account(b"THE BLOCKCHAIN"), None);
+    }
+}
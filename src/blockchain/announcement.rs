@@ -0,0 +1,153 @@
+use crate::blockchain::error::BlockchainError;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::script;
+use crate::blockchain::wallet::{self, Wallet};
+use crate::blockchain::{BlockChain, BlockHeader};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnouncementError {
+    InvalidSignature,
+    // signed correctly, by a node that isn't allowed to produce blocks
+    UnknownProducer,
+}
+
+impl fmt::Display for AnnouncementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnouncementError::InvalidSignature => write!(f, "announcement signature does not match"),
+            AnnouncementError::UnknownProducer => write!(f, "announcement is from an unknown producer"),
+        }
+    }
+}
+
+// a node telling its peers about a block it produced, signed with its node
+// key. the signature says who sent the block first, not who did the work:
+// proof of work doesn't care who found a block, so statistics built on
+// this trust the producer's word. with a fixed set of producers
+// (proof of authority) the signature is what makes a block valid at all
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockAnnouncement {
+    pub height: u64,
+    pub header: BlockHeader,
+    // the node key's public half
+    pub producer: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl BlockAnnouncement {
+    pub fn sign(height: u64, header: BlockHeader, node_key: &Wallet) -> Self {
+        let signature = node_key.sign(&announcement_message(height, &header)).to_vec();
+        BlockAnnouncement {
+            height,
+            header,
+            producer: node_key.public_key().to_vec(),
+            signature,
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), AnnouncementError> {
+        let message = announcement_message(self.height, &self.header);
+        if !script::check_signature(&self.producer, &self.signature, &message) {
+            return Err(AnnouncementError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    // verify, and the producer has to be one of `producers`, by address
+    pub fn verify_from(&self, producers: &HashSet<String>) -> Result<(), AnnouncementError> {
+        self.verify()?;
+        if !producers.contains(&self.producer_address()) {
+            return Err(AnnouncementError::UnknownProducer);
+        }
+        Ok(())
+    }
+
+    // the same address a wallet with the node key would have
+    pub fn producer_address(&self) -> String {
+        wallet::address_from_public_key(&self.producer).into()
+    }
+}
+
+// what gets signed: the block's hash and where it claims to sit
+fn announcement_message(height: u64, header: &BlockHeader) -> Vec<u8> {
+    let mut message = height.to_be_bytes().to_vec();
+    message.extend(header.hash().as_bytes());
+    message
+}
+
+// blocks announced by each producer, counted once per block hash
+#[derive(Debug, Clone, Default)]
+pub struct ProducerStats {
+    seen: HashSet<Hash32>,
+    blocks: BTreeMap<String, u64>,
+}
+
+impl ProducerStats {
+    pub fn new() -> Self {
+        ProducerStats::default()
+    }
+
+    // announcements that don't verify aren't counted. the same block
+    // announced again, by anyone, counts for whoever announced it first
+    pub fn record(&mut self, announcement: &BlockAnnouncement) -> Result<(), AnnouncementError> {
+        announcement.verify()?;
+        if self.seen.insert(announcement.header.hash()) {
+            *self.blocks.entry(announcement.producer_address()).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    // by producer address
+    pub fn blocks(&self) -> &BTreeMap<String, u64> {
+        &self.blocks
+    }
+
+    pub fn blocks_by(&self, address: &str) -> u64 {
+        self.blocks.get(address).copied().unwrap_or(0)
+    }
+}
+
+impl BlockChain {
+    // the announcement for the tip, what a node sends right after mining
+    pub fn announce_tip(&self, node_key: &Wallet) -> Result<BlockAnnouncement, BlockchainError> {
+        let header = self.last_block()?.header();
+        Ok(BlockAnnouncement::sign(self.chain.len() as u64 - 1, header, node_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::protocol::Message;
+    use crate::blockchain::test_utils::test_wallet;
+    use crate::blockchain::Serialization;
+
+    #[test]
+    fn announcements_are_attributed_to_the_key_that_signed_them() {
+        let (alice, bob) = (test_wallet("alice"), test_wallet("bob"));
+        let mut chain = BlockChain::with_difficulty(alice.address().into(), 0);
+        let mut stats = ProducerStats::new();
+
+        let first = chain.announce_tip(&alice).unwrap();
+        let message = Message::Announce(Box::new(first.clone()));
+        assert_eq!(Message::decode(&message.serialization()), Some(message));
+        stats.record(&first).unwrap();
+        // late, someone else already announced this block
+        stats.record(&chain.announce_tip(&bob).unwrap()).unwrap();
+        chain.mining().unwrap();
+        stats.record(&chain.announce_tip(&bob).unwrap()).unwrap();
+        assert_eq!(stats.blocks_by(&alice.address()), 1);
+        assert_eq!(stats.blocks_by(&bob.address()), 1);
+
+        // a different height than the one signed
+        let mut forged = chain.announce_tip(&bob).unwrap();
+        forged.height += 1;
+        assert_eq!(stats.record(&forged), Err(AnnouncementError::InvalidSignature));
+
+        let authorities: HashSet<String> = HashSet::from([alice.address().into()]);
+        assert_eq!(first.verify_from(&authorities), Ok(()));
+        assert_eq!(chain.announce_tip(&bob).unwrap().verify_from(&authorities), Err(AnnouncementError::UnknownProducer));
+    }
+}
@@ -0,0 +1,73 @@
+use sha2::{Digest, Sha256};
+
+// a user issued token, the whole supply is credited to the issuer when
+// the asset is created and moves around with transfer asset transactions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    pub issuer: Vec<u8>,
+    pub name: Vec<u8>,
+    pub total_supply: u64,
+}
+
+impl Asset {
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update((self.issuer.len() as u64).to_be_bytes());
+        hasher.update(&self.issuer);
+        hasher.update((self.name.len() as u64).to_be_bytes());
+        hasher.update(&self.name);
+        hasher.update(self.total_supply.to_be_bytes());
+        hasher.finalize().to_vec()
+    }
+}
+
+// like contract addresses, the id is derived from the creating transaction
+// so two issuers can use the same name without clashing
+pub fn asset_id(issuer: &[u8], nonce: u64) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"asset");
+    hasher.update(issuer);
+    hasher.update(nonce.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::state::{State, StateError};
+    use crate::blockchain::transaction::{Payload, Transaction};
+
+    fn send(sender: &str, recipient: &str, nonce: u64, payload: Payload) -> Transaction {
+        Transaction::new(sender.into(), recipient.into(), 0).with_nonce(nonce).with_payload(payload)
+    }
+
+    #[test]
+    fn the_issuer_gets_the_supply_and_transfers_move_it() {
+        let mut state = State::new();
+        let create = |supply: u64| Payload::CreateAsset { name: b"gold".to_vec(), supply };
+        assert_eq!(state.apply_transaction(&send("A", "A", 0, create(0)), 1), Err(StateError::EmptyAssetSupply));
+        state.apply_transaction(&send("A", "A", 0, create(100)), 1).unwrap();
+
+        let gold: Vec<u8> = asset_id(b"A", 0);
+        let asset = Asset { issuer: b"A".to_vec(), name: b"gold".to_vec(), total_supply: 100 };
+        assert_eq!(state.asset(&gold), Some(&asset));
+        assert_eq!(state.asset_balance(b"A", &gold), 100);
+
+        let transfer = |amount: u64| Payload::TransferAsset { asset_id: gold.clone(), amount };
+        state.apply_transaction(&send("A", "B", 1, transfer(30)), 1).unwrap();
+        assert_eq!((state.asset_balance(b"A", &gold), state.asset_balance(b"B", &gold)), (70, 30));
+        assert_eq!(
+            state.apply_transaction(&send("A", "B", 2, transfer(71)), 1),
+            Err(StateError::InsufficientAssetBalance { available: 70, requested: 71 })
+        );
+        // the same name from the same issuer later is another asset
+        assert_ne!(asset_id(b"A", 2), gold);
+        let unknown = Payload::TransferAsset { asset_id: asset_id(b"B", 0), amount: 1 };
+        assert_eq!(state.apply_transaction(&send("B", "A", 0, unknown), 1), Err(StateError::UnknownAsset));
+
+        // the supply only ever moves, it is never made or lost on the way
+        state.apply_transaction(&send("A", "B", 2, transfer(70)), 1).unwrap();
+        assert_eq!((state.asset_balance(b"A", &gold), state.asset_balance(b"B", &gold)), (0, 100));
+        assert!(!state.account(b"A").unwrap().assets.contains_key(&gold));
+    }
+}
@@ -0,0 +1,164 @@
+use crate::blockchain::hash32::Hash32;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditEvent {
+    BlockAppended { height: u64, hash: Hash32 },
+    TransactionAdded { txid: Vec<u8> },
+    // an opt-in or fee bump replacement of a pooled transaction
+    TransactionReplaced { txid: Vec<u8> },
+    TransactionRejected { txid: Vec<u8> },
+    // was in the block template but the state refused it
+    TransactionDropped { txid: Vec<u8> },
+    // pushed out of a full pool by one paying a higher fee rate
+    TransactionEvicted { txid: Vec<u8> },
+    // a mined transaction moved an address's balance by `delta`. the txid
+    // is empty for the fees a miner collects at the end of a block. with a
+    // watch list only watched addresses get these
+    BalanceChanged { address: Vec<u8>, height: u64, txid: Vec<u8>, delta: i64 },
+    // a watched address sent or received a transaction in a mined block
+    WatchedAddressActivity { address: Vec<u8>, height: u64, txid: Vec<u8> },
+    // blocks above `height` were taken off the chain
+    Rollback { height: u64, blocks: u64 },
+    Reorg { fork_height: u64, removed: u64, added: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    // nanoseconds since the unix epoch, like block timestamps
+    pub time_stamp: u128,
+    pub event: AuditEvent,
+    // free text saying why, e.g. the error that rejected a transaction
+    pub cause: String,
+}
+
+// every change made to the chain, the pool or balances, oldest first.
+// entries are only ever appended.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    entries: Vec<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        AuditLog::default()
+    }
+
+    pub fn record(&mut self, event: AuditEvent, cause: impl Into<String>) {
+        let time_stamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        self.entries.push(AuditEntry {
+            time_stamp,
+            event,
+            cause: cause.into(),
+        });
+    }
+
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+
+    pub fn since(&self, time_stamp: u128) -> impl Iterator<Item = &AuditEntry> {
+        self.entries.iter().filter(move |e| e.time_stamp >= time_stamp)
+    }
+
+    // "why did my balance change?"
+    pub fn balance_history<'a>(&'a self, address: &'a [u8]) -> impl Iterator<Item = &'a AuditEntry> {
+        self.entries.iter().filter(move |e| {
+            matches!(&e.event, AuditEvent::BalanceChanged { address: a, .. } if a == address)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::mempool::DEFAULT_MAX_WEIGHT;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::transaction::Transaction;
+    use crate::blockchain::BlockChain;
+
+    fn events(chain: &BlockChain, from: usize) -> Vec<AuditEvent> {
+        chain.audit_log().entries()[from..].iter().map(|e| e.event.clone()).collect()
+    }
+
+    #[test]
+    fn blocks_appended_and_taken_off_are_on_record() {
+        let mut chain = BlockChain::with_difficulty(miner().address().into(), 0);
+        let mut peer = BlockChain::empty(test_wallet("peer").address().into(), 0, chain.chain_id());
+        peer.chain = chain.blocks().to_vec();
+        peer.reindex().unwrap();
+
+        let from: usize = chain.audit_log().entries().len();
+        chain.mining().unwrap();
+        let tip = chain.last_block().unwrap();
+        let appended = AuditEvent::BlockAppended { height: 2, hash: tip.hash() };
+        assert!(events(&chain, from).contains(&appended));
+        assert!(chain.audit_log().balance_history(miner().address().as_bytes()).any(|e| matches!(e.event,
+            AuditEvent::BalanceChanged { height: 2, delta: 1, .. })));
+
+        // the peer's two blocks take ours off
+        peer.mining().unwrap();
+        peer.mining().unwrap();
+        let from: usize = chain.audit_log().entries().len();
+        assert_eq!(chain.replace_chain(peer.blocks().to_vec()), Ok(true));
+        let reorg = AuditEvent::Reorg { fork_height: 1, removed: 1, added: 2 };
+        assert_eq!(events(&chain, from).last(), Some(&reorg));
+    }
+
+    #[test]
+    fn the_pool_and_the_balances_it_moves_are_on_record() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 5 {
+            chain.mining().unwrap();
+        }
+        let tx = |nonce: u64, fee: u64| {
+            Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).with_nonce(nonce).with_fee(fee)
+        };
+        // room for two transactions
+        chain.set_mempool_max_weight(2 * tx(0, 0).sign(&wallet).weight());
+
+        let from: usize = chain.audit_log().entries().len();
+        let (first, cheap) = (tx(0, 2).sign(&wallet), tx(1, 0).sign(&wallet));
+        let (bump, better) = (tx(0, 3).sign(&wallet), tx(2, 1).sign(&wallet));
+        chain.add_transaction(&first).unwrap();
+        chain.add_transaction(&cheap).unwrap();
+        assert!(chain.add_transaction(&tx(2, 1)).is_err());
+        chain.add_transaction(&bump).unwrap();
+        chain.add_transaction(&better).unwrap();
+        let expected: Vec<AuditEvent> = vec![
+            AuditEvent::TransactionAdded { txid: first.id() },
+            AuditEvent::TransactionAdded { txid: cheap.id() },
+            AuditEvent::TransactionRejected { txid: tx(2, 1).id() },
+            AuditEvent::TransactionReplaced { txid: bump.id() },
+            AuditEvent::TransactionAdded { txid: better.id() },
+            AuditEvent::TransactionEvicted { txid: cheap.id() },
+        ];
+        assert_eq!(events(&chain, from), expected);
+
+        // mined, the payments move B's balance and the fees the miner's
+        chain.set_mempool_max_weight(DEFAULT_MAX_WEIGHT);
+        chain.add_transaction(&cheap).unwrap();
+        chain.mining().unwrap();
+        let height: u64 = chain.blocks().len() as u64 - 1;
+        let received: Vec<(Vec<u8>, i64)> = chain
+            .audit_log()
+            .balance_history(b"B")
+            .filter_map(|e| match &e.event {
+                AuditEvent::BalanceChanged { txid, delta, .. } => Some((txid.clone(), *delta)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(received, vec![(bump.id(), 1), (cheap.id(), 1), (better.id(), 1)]);
+        let fees = AuditEvent::BalanceChanged {
+            address: wallet.address().into_bytes(),
+            height,
+            txid: Vec::new(),
+            delta: 4,
+        };
+        assert!(chain.audit_log().balance_history(wallet.address().as_bytes()).any(|e| e.event == fees));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::blockchain::state::State;
+use crate::blockchain::BlockChain;
+use crate::blockchain::validation::ValidationError;
+use std::collections::HashMap;
+
+// every account's balance at the tip, and what each block changed, kept
+// as blocks are added and undone. the balance at the tip is one lookup, a
+// few blocks below it the tip's minus those blocks' changes, instead of a
+// copy of the whole state rolled back to there. accounts are the bytes on
+// chain, so names and contracts have balances here too
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceIndex {
+    balances: HashMap<Vec<u8>, i128>,
+    // what the blocks from `first_height` on changed, oldest first
+    changes: Vec<Vec<(Vec<u8>, i128)>>,
+    first_height: u64,
+}
+
+impl BalanceIndex {
+    pub fn new() -> Self {
+        BalanceIndex::default()
+    }
+
+    // the balances of `state`, the one right after the block at `height`,
+    // e.g. a fast sync snapshot. nothing before it can be looked up
+    pub fn from_state(state: &State, height: u64) -> Self {
+        BalanceIndex {
+            balances: state
+                .accounts()
+                .map(|(address, account)| (address.clone(), account.balance as i128))
+                .collect(),
+            changes: Vec::new(),
+            first_height: height + 1,
+        }
+    }
+
+    // blocks have to be added in order, `changes` as the state reports them
+    pub fn add_block(&mut self, changes: Vec<(Vec<u8>, i64)>) {
+        let changes: Vec<(Vec<u8>, i128)> = changes.into_iter().map(|(address, delta)| (address, delta as i128)).collect();
+        for (address, delta) in changes.iter() {
+            *self.balances.entry(address.clone()).or_default() += delta;
+        }
+        self.changes.push(changes);
+    }
+
+    // takes the newest block's changes back out, false if there is none
+    pub fn rollback_block(&mut self) -> bool {
+        let Some(changes) = self.changes.pop() else {
+            return false;
+        };
+        for (address, delta) in changes {
+            let balance: &mut i128 = self.balances.entry(address).or_default();
+            *balance -= delta;
+        }
+        true
+    }
+
+    // forgets the changes of the oldest blocks until `keep` are left, the
+    // balances before them can't be looked up anymore
+    pub fn prune(&mut self, keep: usize) {
+        let forgotten: usize = self.changes.len().saturating_sub(keep);
+        self.changes.drain(..forgotten);
+        self.first_height += forgotten as u64;
+    }
+
+    pub fn balance(&self, address: &[u8]) -> i128 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    // the balance right after the block at `height`, none past the tip or
+    // before the first block the index knows
+    pub fn balance_at(&self, address: &[u8], height: u64) -> Option<i128> {
+        let undone: usize = height.checked_add(1)?.checked_sub(self.first_height)? as usize;
+        let later: &[Vec<(Vec<u8>, i128)>] = self.changes.get(undone..)?;
+        let moved: i128 = later
+            .iter()
+            .flat_map(|changes| changes.iter())
+            .filter(|(changed, _)| changed == address)
+            .map(|(_, delta)| delta)
+            .sum();
+        Some(self.balance(address) - moved)
+    }
+}
+
+impl BlockChain {
+    // rebuilds the transaction, address and balance indexes from the blocks,
+    // for when one of them is suspected wrong. the blocks were checked when
+    // they were added, their signatures aren't checked again. `reindex`
+    // checks everything
+    pub fn rebuild_index(&mut self) -> Result<(), ValidationError> {
+        self.rebuild(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn older_balances_are_the_tips_minus_later_changes() {
+        let mut index = BalanceIndex::new();
+        index.add_block(vec![(b"a".to_vec(), 10)]);
+        index.add_block(vec![(b"a".to_vec(), -4), (b"b".to_vec(), 4)]);
+        index.add_block(vec![(b"b".to_vec(), 1)]);
+        assert_eq!((index.balance(b"a"), index.balance(b"b")), (6, 5));
+        assert_eq!(index.balance_at(b"a", 0), Some(10));
+        assert_eq!(index.balance_at(b"b", 1), Some(4));
+        assert_eq!(index.balance_at(b"b", 3), None);
+
+        assert!(index.rollback_block());
+        assert_eq!(index.balance(b"b"), 4);
+        assert_eq!(index.balance_at(b"b", 2), None);
+
+        // the newest block's changes are left, the balances before it too
+        index.prune(1);
+        assert_eq!(index.balance_at(b"a", 0), Some(10));
+        index.prune(0);
+        assert_eq!((index.balance_at(b"a", 0), index.balance_at(b"a", 1)), (None, Some(6)));
+    }
+
+    #[test]
+    fn the_index_follows_the_state_and_can_be_rebuilt() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        for _ in 0..5 {
+            chain.mining().unwrap();
+        }
+        for (address, account) in chain.state().accounts() {
+            assert_eq!(chain.balances.balance(address), account.balance as i128);
+        }
+        assert_eq!(chain.balance(b"miner", 3), 4);
+
+        let balances: BalanceIndex = chain.balances.clone();
+        chain.balances = BalanceIndex::new();
+        chain.rebuild_index().unwrap();
+        assert_eq!(chain.balances, balances);
+    }
+}
@@ -0,0 +1,47 @@
+use sha2::{Digest, Sha256};
+
+// 2048 bits like ethereum's logs bloom, each item sets three of them
+pub const BLOOM_BYTES: usize = 256;
+
+// a compact summary of every address and topic logged in a block. a
+// query can skip a block whose bloom doesn't have all the bits of what it
+// looks for, a match can still be a false positive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bloom(pub [u8; BLOOM_BYTES]);
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom([0u8; BLOOM_BYTES])
+    }
+}
+
+impl Bloom {
+    // the three bit positions of an item, 11 bits taken from each of the
+    // first three pairs of bytes of its hash
+    fn bits(item: &[u8]) -> [usize; 3] {
+        let hash = Sha256::digest(item);
+        let mut bits = [0usize; 3];
+        for (i, bit) in bits.iter_mut().enumerate() {
+            *bit = (((hash[2 * i] as usize) << 8) | hash[2 * i + 1] as usize) % (BLOOM_BYTES * 8);
+        }
+        bits
+    }
+
+    pub fn accrue(&mut self, item: &[u8]) {
+        for bit in Bloom::bits(item) {
+            self.0[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        Bloom::bits(item)
+            .iter()
+            .all(|bit| self.0[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    pub fn union(&mut self, other: &Bloom) {
+        for (byte, other_byte) in self.0.iter_mut().zip(other.0.iter()) {
+            *byte |= other_byte;
+        }
+    }
+}
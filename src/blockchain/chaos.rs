@@ -0,0 +1,265 @@
+use crate::blockchain::light::FullNode;
+use crate::blockchain::merkle::MerkleProof;
+use crate::blockchain::protocol::Message;
+use crate::blockchain::{BlockHeader, Serialization};
+use std::cell::RefCell;
+
+// how often each fault happens, in parts per thousand of the messages or
+// calls going through the driver. all zero is a perfect network
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChaosConfig {
+    pub drop: u32,
+    pub delay: u32,
+    pub corrupt: u32,
+    pub crash: u32,
+    // how many messages or calls a crashed peer misses before it is back
+    pub crash_length: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Dropped,
+    Delayed,
+    Corrupted,
+    Crashed,
+}
+
+// decides which faults to inject. everything it does comes from the
+// seed, so a run that breaks something can be replayed exactly
+#[derive(Debug)]
+pub struct ChaosDriver {
+    config: ChaosConfig,
+    rng: SplitMix64,
+    // held back, they arrive with the next message that gets through
+    delayed: Vec<Message>,
+    // messages or calls left before a crashed peer is back
+    down_for: u32,
+    // every fault injected so far, oldest first
+    faults: Vec<Fault>,
+}
+
+impl ChaosDriver {
+    pub fn new(seed: u64, config: ChaosConfig) -> Self {
+        ChaosDriver {
+            config,
+            rng: SplitMix64(seed),
+            delayed: Vec::<Message>::new(),
+            down_for: 0,
+            faults: Vec::<Fault>::new(),
+        }
+    }
+
+    pub fn faults(&self) -> &[Fault] {
+        &self.faults
+    }
+
+    fn roll(&mut self, per_mille: u32) -> bool {
+        per_mille > 0 && self.rng.below(1000) < per_mille as u64
+    }
+
+    fn inject(&mut self, fault: Fault) {
+        self.faults.push(fault);
+    }
+
+    fn drops(&mut self) -> bool {
+        let dropped = self.roll(self.config.drop);
+        if dropped {
+            self.inject(Fault::Dropped);
+        }
+        dropped
+    }
+
+    fn delays(&mut self) -> bool {
+        let delayed = self.roll(self.config.delay);
+        if delayed {
+            self.inject(Fault::Delayed);
+        }
+        delayed
+    }
+
+    // true while the peer on the other side is down, it may go down on
+    // this very call
+    pub fn is_crashed(&mut self) -> bool {
+        if self.down_for > 0 {
+            self.down_for -= 1;
+            return true;
+        }
+        if self.roll(self.config.crash) {
+            self.inject(Fault::Crashed);
+            self.down_for = self.config.crash_length;
+            return true;
+        }
+        false
+    }
+
+    // flips one random bit, meant for anything read back from the wire or
+    // from disk. returns whether it did
+    pub fn corrupt(&mut self, bytes: &mut [u8]) -> bool {
+        if bytes.is_empty() || !self.roll(self.config.corrupt) {
+            return false;
+        }
+        let bit = self.rng.below(bytes.len() as u64 * 8);
+        bytes[(bit / 8) as usize] ^= 1 << (bit % 8);
+        self.inject(Fault::Corrupted);
+        true
+    }
+
+    // sends one message over a faulty link and returns what arrives on the
+    // other side right now, earlier delayed messages included
+    pub fn transmit(&mut self, message: Message) -> Vec<Message> {
+        if self.is_crashed() {
+            return Vec::new();
+        }
+        if self.drops() {
+            return Vec::new();
+        }
+
+        let mut delivered: Vec<Message> = std::mem::take(&mut self.delayed);
+        if self.delays() {
+            self.delayed.push(message);
+            return delivered;
+        }
+
+        let mut bytes = message.serialization();
+        if self.corrupt(&mut bytes) {
+            // anything that doesn't decode is treated as a request we don't know
+            delivered.push(Message::deserialization(&bytes).unwrap_or(Message::NotFound));
+        } else {
+            delivered.push(message);
+        }
+        delivered
+    }
+}
+
+// a full node behind a faulty network: answers get lost, arrive late
+// (answering an older request), lose blocks, get corrupted or don't come
+// at all while the node is down
+pub struct ChaosNode<'a, N: FullNode> {
+    node: &'a N,
+    driver: RefCell<ChaosDriver>,
+    // a delayed answer, handed out instead of the next one
+    late_headers: RefCell<Option<Vec<BlockHeader>>>,
+}
+
+impl<'a, N: FullNode> ChaosNode<'a, N> {
+    pub fn new(node: &'a N, driver: ChaosDriver) -> Self {
+        ChaosNode {
+            node,
+            driver: RefCell::new(driver),
+            late_headers: RefCell::new(None),
+        }
+    }
+
+    pub fn faults(&self) -> Vec<Fault> {
+        self.driver.borrow().faults().to_vec()
+    }
+}
+
+impl<N: FullNode> FullNode for ChaosNode<'_, N> {
+    fn headers_from(&self, from_height: u64) -> Vec<BlockHeader> {
+        let mut driver = self.driver.borrow_mut();
+        if driver.is_crashed() {
+            return Vec::new();
+        }
+        if driver.drops() {
+            return Vec::new();
+        }
+
+        let mut headers = self.node.headers_from(from_height);
+        if driver.delays() {
+            return self.late_headers.replace(Some(headers)).unwrap_or_default();
+        }
+        if let Some(late) = self.late_headers.take() {
+            headers = late;
+        }
+
+        // blocks go missing from the middle of the answer
+        if headers.len() > 1 && driver.drops() {
+            let index = driver.rng.below(headers.len() as u64) as usize;
+            headers.remove(index);
+        }
+
+        headers
+            .into_iter()
+            .filter_map(|header| {
+                let mut bytes = header.serialization();
+                if driver.corrupt(&mut bytes) {
+                    // garbage that doesn't even decode is lost on the way
+                    BlockHeader::decode(&bytes)
+                } else {
+                    Some(header)
+                }
+            })
+            .collect()
+    }
+
+    fn transaction_proof(&self, txid: &[u8]) -> Option<(u64, MerkleProof)> {
+        let mut driver = self.driver.borrow_mut();
+        if driver.is_crashed() {
+            return None;
+        }
+        if driver.drops() {
+            return None;
+        }
+
+        let (height, mut proof) = self.node.transaction_proof(txid)?;
+        // a proof that no longer leads to the root
+        if let Some(step) = proof.steps.first_mut() {
+            driver.corrupt(&mut step.hash);
+        }
+        Some((height, proof))
+    }
+}
+
+// tiny deterministic generator, seeded runs have to be replayable on any
+// machine
+#[derive(Debug, Clone)]
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform enough in 0..bound for picking faults and fixtures
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::BlockChain;
+    use crate::blockchain::light::LightClient;
+
+    #[test]
+    fn light_client_syncs_through_a_faulty_network() {
+        let mut chain = BlockChain::new("miner".into());
+        for _ in 0..6 {
+            chain.mining().unwrap();
+        }
+        let tip_hash = chain.last_block().unwrap().hash();
+        let config = ChaosConfig {
+            drop: 200,
+            delay: 200,
+            corrupt: 100,
+            crash: 50,
+            crash_length: 3,
+        };
+
+        for seed in 0..8 {
+            let node = ChaosNode::new(&chain, ChaosDriver::new(seed, config));
+            let mut client = LightClient::new(chain.get_block(0).unwrap().header());
+            for _ in 0..100 {
+                let _ = client.sync(&node);
+            }
+            assert_eq!(client.tip().hash(), tip_hash, "seed {}", seed);
+            assert!(!node.faults().is_empty());
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::blockchain::protocol::Message;
+use crate::blockchain::BlockChain;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::SystemTime;
+use tracing::warn;
+
+// furthest the local clock may be from the network's before the node stops
+// trusting it, in nanoseconds like block timestamps (70 minutes)
+pub const MAX_CLOCK_OFFSET: u128 = 70 * 60 * 1_000_000_000;
+// a couple of peers could lie about the time, the median only counts once
+// there are enough of them
+pub const MIN_PEER_SAMPLES: usize = 5;
+// the first peers to answer are kept, later ones can't push them out
+pub const MAX_PEER_SAMPLES: usize = 200;
+
+// the local clock is `offset` nanoseconds away from the network's time,
+// positive when the network is ahead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSkew {
+    pub offset: i128,
+}
+
+impl fmt::Display for ClockSkew {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.offset > 0 { "behind" } else { "ahead of" };
+        write!(
+            f,
+            "the local clock is {} seconds {} the network, check the system time",
+            self.offset.unsigned_abs() / 1_000_000_000,
+            direction
+        )
+    }
+}
+
+// what peers said the time was when we shook hands, kept as offsets from
+// our own clock. the median offset is the network's opinion of how wrong
+// we are.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkClock {
+    // peer time minus local time, by peer
+    offsets: BTreeMap<String, i128>,
+}
+
+impl NetworkClock {
+    pub fn new() -> Self {
+        NetworkClock::default()
+    }
+
+    // a peer sent its time; `local_time` is ours when the message arrived
+    pub fn add_sample(&mut self, peer: &str, peer_time: u128, local_time: u128) {
+        if self.offsets.len() >= MAX_PEER_SAMPLES && !self.offsets.contains_key(peer) {
+            return;
+        }
+        let offset = peer_time as i128 - local_time as i128;
+        self.offsets.insert(peer.to_string(), offset);
+    }
+
+    pub fn samples(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // zero until enough peers have answered
+    pub fn offset(&self) -> i128 {
+        if self.offsets.len() < MIN_PEER_SAMPLES {
+            return 0;
+        }
+        let mut offsets: Vec<i128> = self.offsets.values().copied().collect();
+        offsets.sort();
+        offsets[offsets.len() / 2]
+    }
+
+    pub fn adjusted_time(&self, local_time: u128) -> u128 {
+        (local_time as i128 + self.offset()).max(0) as u128
+    }
+
+    pub fn check(&self) -> Result<(), ClockSkew> {
+        let offset = self.offset();
+        if offset.unsigned_abs() > MAX_CLOCK_OFFSET {
+            return Err(ClockSkew { offset });
+        }
+        Ok(())
+    }
+}
+
+pub fn local_time() -> u128 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos()
+}
+
+impl BlockChain {
+    // the handshake message this node opens a connection with, and answers
+    // a peer's with
+    pub fn version_message(&self) -> Message {
+        Message::Version {
+            time_stamp: local_time(),
+            height: self.chain.len() as u64 - 1,
+        }
+    }
+
+    // called by whatever drives the connection when a peer's version
+    // arrives, the message itself doesn't say who sent it
+    pub fn record_peer_version(&mut self, peer: &str, message: &Message) {
+        let Message::Version { time_stamp, height } = message else {
+            return;
+        };
+        if self.is_banned(peer) {
+            return;
+        }
+        self.sync.add_peer(peer, *height, local_time());
+        let was_skewed = self.clock.check().is_err();
+        self.clock.add_sample(peer, *time_stamp, local_time());
+        if let Err(skew) = self.clock.check()
+            && !was_skewed
+        {
+            warn!(%skew, peers = self.clock.samples(), "clock out of sync with the network");
+        }
+    }
+
+    pub fn clock(&self) -> &NetworkClock {
+        &self.clock
+    }
+
+    // local time corrected by the peers' median offset, what new blocks
+    // are stamped with
+    pub fn network_time(&self) -> u128 {
+        self.clock.adjusted_time(local_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::error::BlockchainError;
+
+    const MINUTE: u128 = 60 * 1_000_000_000;
+
+    #[test]
+    fn a_few_lying_peers_do_not_move_the_median() {
+        let mut clock = NetworkClock::new();
+        let now: u128 = 1_000_000 * MINUTE;
+        for (peer, offset) in [("a", 1), ("b", 2), ("c", 2), ("d", 3)] {
+            clock.add_sample(peer, now + offset * MINUTE, now);
+        }
+        // not enough peers yet
+        assert_eq!(clock.offset(), 0);
+
+        clock.add_sample("liar", now + 10_000 * MINUTE, now);
+        assert_eq!(clock.offset(), (2 * MINUTE) as i128);
+        assert_eq!(clock.adjusted_time(now), now + 2 * MINUTE);
+        assert!(clock.check().is_ok());
+    }
+
+    #[test]
+    fn a_skewed_clock_refuses_to_mine() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        let ahead = local_time() + 2 * MAX_CLOCK_OFFSET;
+        for peer in ["a", "b", "c", "d", "e"] {
+            chain.record_peer_version(peer, &Message::Version { time_stamp: ahead, height: 0 });
+        }
+        assert!(chain.clock().check().is_err());
+
+        let height = chain.blocks().len();
+        assert!(matches!(chain.mining(), Err(BlockchainError::ClockSkew(_))));
+        assert_eq!(chain.blocks().len(), height);
+    }
+}
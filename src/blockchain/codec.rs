@@ -0,0 +1,166 @@
+use crate::blockchain::bloom::{Bloom, BLOOM_BYTES};
+use crate::blockchain::difficulty::Retarget;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::{Block, BlockChain};
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// serde for the types the rest of the world sees: blocks, transactions
+// and whole chains. `Serialization` stays the byte layout hashes and the
+// block file use, this is for http apis and other tools. hashes and blooms
+// are hex in json and plain bytes in bincode
+
+#[derive(Debug)]
+pub enum CodecError {
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Json(e) => write!(f, "json: {}", e),
+            CodecError::Bincode(e) => write!(f, "bincode: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub fn to_json<T: Serialize>(value: &T) -> Result<String, CodecError> {
+    serde_json::to_string(value).map_err(CodecError::Json)
+}
+
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, CodecError> {
+    serde_json::from_str(json).map_err(CodecError::Json)
+}
+
+pub fn to_bincode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+    bincode::serialize(value).map_err(CodecError::Bincode)
+}
+
+pub fn from_bincode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+    bincode::deserialize(bytes).map_err(CodecError::Bincode)
+}
+
+// hex for formats people read, bytes for the rest
+fn serialize_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&hex::encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        let text = String::deserialize(deserializer)?;
+        hex::decode(text).map_err(D::Error::custom)
+    } else {
+        Vec::<u8>::deserialize(deserializer)
+    }
+}
+
+impl Serialize for Hash32 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(self.as_bytes(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash32 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Hash32::from_slice(&deserialize_bytes(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for Bloom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bloom {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes: Vec<u8> = deserialize_bytes(deserializer)?;
+        let bits: [u8; BLOOM_BYTES] = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| D::Error::custom(format!("a bloom is {} bytes, not {}", BLOOM_BYTES, bytes.len())))?;
+        Ok(Bloom(bits))
+    }
+}
+
+// a chain is its blocks and the rules they were mined under. everything
+// else (state, indexes, the pool) is the node's own and is rebuilt, or
+// starts empty, when it is read back
+#[derive(Serialize, Deserialize)]
+struct ChainFile {
+    address: String,
+    difficulty: usize,
+    retarget: Option<Retarget>,
+    chain_id: u64,
+    blocks: Vec<Block>,
+}
+
+impl Serialize for BlockChain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ChainFile {
+            address: self.blockchain_address.clone(),
+            difficulty: self.difficulty,
+            retarget: self.retarget,
+            chain_id: self.chain_id,
+            blocks: self.chain.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+// the blocks are fully validated, like a chain from a peer
+impl<'de> Deserialize<'de> for BlockChain {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let file = ChainFile::deserialize(deserializer)?;
+        let mut chain = BlockChain::empty(file.address, file.difficulty, file.chain_id);
+        chain.retarget = file.retarget;
+        chain.chain = file.blocks;
+        chain.reindex().map_err(D::Error::custom)?;
+        for block in chain.chain.iter_mut() {
+            block.seal();
+        }
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::transaction::Transaction;
+
+    #[test]
+    fn a_chain_survives_json_and_bincode() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 1);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), test_wallet("bob").address().into_bytes(), 2).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+
+        let json: String = to_json(&chain).unwrap();
+        // hashes are hex, the block's own isn't sent, it's the header's
+        assert!(json.contains(&format!("\"previous_hash\":\"{}\"", chain.blocks()[1].hash())));
+        let read: BlockChain = from_json(&json).unwrap();
+        assert_eq!(read.last_block().unwrap().hash(), chain.last_block().unwrap().hash());
+        assert_eq!(read.state().root(), chain.state().root());
+
+        let read: BlockChain = from_bincode(&to_bincode(&chain).unwrap()).unwrap();
+        assert_eq!(read.blocks().len(), chain.blocks().len());
+        let tx_read: Transaction = from_bincode(&to_bincode(&tx).unwrap()).unwrap();
+        assert_eq!(tx_read.id(), tx.id());
+
+        // a block someone changed doesn't make it into a chain
+        let tampered: String = json.replacen("\"nonce\":", "\"nonce\":1", 1);
+        assert!(from_json::<BlockChain>(&tampered).is_err());
+    }
+}
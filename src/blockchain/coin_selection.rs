@@ -0,0 +1,259 @@
+use std::fmt;
+
+// which of a wallet's coins pay for a transaction. in an output based
+// model a wallet holds many separate coins and a payment spends whole
+// ones, what is spent over the amount and the fee comes back as change.
+// the strategies trade off differently: spending the largest coins keeps
+// transactions small, the smallest ones clean up dust, an exact match
+// needs no change output at all
+
+// a coin the wallet can spend: whatever identifies the output it is, and
+// what it is worth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    pub outpoint: Vec<u8>,
+    pub value: u64,
+}
+
+// what a payment needs: `amount` to the recipient, a `base_fee` for the
+// transaction and `fee_per_input` for every coin it spends. change smaller
+// than `dust` costs more to spend later than it is worth, it is left to
+// the miner instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub amount: u64,
+    pub base_fee: u64,
+    pub fee_per_input: u64,
+    pub dust: u64,
+}
+
+impl Target {
+    pub fn new(amount: u64) -> Self {
+        Target {
+            amount,
+            base_fee: 0,
+            fee_per_input: 0,
+            dust: 0,
+        }
+    }
+
+    pub fn with_fees(mut self, base_fee: u64, fee_per_input: u64) -> Self {
+        self.base_fee = base_fee;
+        self.fee_per_input = fee_per_input;
+        self
+    }
+
+    pub fn with_dust(mut self, dust: u64) -> Self {
+        self.dust = dust;
+        self
+    }
+
+    // the fee of a transaction spending `inputs` coins
+    pub fn fee(&self, inputs: usize) -> u64 {
+        self.base_fee.saturating_add(self.fee_per_input.saturating_mul(inputs as u64))
+    }
+}
+
+// the coins to spend, the fee paid and the change, 0 meaning no change
+// output
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub coins: Vec<Coin>,
+    pub fee: u64,
+    pub change: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectionError {
+    // all the coins together aren't enough, `needed` counts the fees of
+    // spending every one of them
+    InsufficientFunds { available: u64, needed: u64 },
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectionError::InsufficientFunds { available, needed } => {
+                write!(f, "the wallet has {} in coins, the payment needs {}", available, needed)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SelectionError {}
+
+// a coin selection policy. the built in ones are below, a wallet can bring
+// its own, e.g. one that avoids coins from a certain sender
+pub trait CoinSelector {
+    fn select(&self, coins: &[Coin], target: &Target) -> Result<Selection, SelectionError>;
+}
+
+// `coins` and the fee and change spending them gives, none if they aren't
+// enough
+fn settle(coins: Vec<Coin>, target: &Target) -> Option<Selection> {
+    let total: u64 = coins.iter().map(|coin| coin.value).sum();
+    let mut fee: u64 = target.fee(coins.len());
+    let change: u64 = total.checked_sub(target.amount.checked_add(fee)?)?;
+    if change < target.dust {
+        fee += change;
+        return Some(Selection { coins, fee, change: 0 });
+    }
+    Some(Selection { coins, fee, change })
+}
+
+// takes coins in the order given until they pay for the amount and their
+// own fees
+fn accumulate<'a>(ordered: impl Iterator<Item = &'a Coin>, target: &Target) -> Result<Selection, SelectionError> {
+    let mut taken = Vec::<Coin>::new();
+    for coin in ordered {
+        taken.push(coin.clone());
+        if let Some(selection) = settle(taken.clone(), target) {
+            return Ok(selection);
+        }
+    }
+    Err(SelectionError::InsufficientFunds {
+        available: taken.iter().map(|coin| coin.value).sum(),
+        needed: target.amount.saturating_add(target.fee(taken.len())),
+    })
+}
+
+// the fewest inputs, the smallest transaction
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(&self, coins: &[Coin], target: &Target) -> Result<Selection, SelectionError> {
+        let mut ordered: Vec<&Coin> = coins.iter().collect();
+        ordered.sort_by_key(|coin| std::cmp::Reverse(coin.value));
+        accumulate(ordered.into_iter(), target)
+    }
+}
+
+// spends the small coins while fees are low, so they don't pile up
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmallestFirst;
+
+impl CoinSelector for SmallestFirst {
+    fn select(&self, coins: &[Coin], target: &Target) -> Result<Selection, SelectionError> {
+        let mut ordered: Vec<&Coin> = coins.iter().collect();
+        ordered.sort_by_key(|coin| coin.value);
+        accumulate(ordered.into_iter(), target)
+    }
+}
+
+// bitcoin core's branch and bound: a depth first search, largest coins
+// first, for coins that pay the amount and their fees with less than
+// `dust` left over, so no change output is needed. gives up after
+// `max_tries` branches and falls back to largest first
+#[derive(Debug, Clone, Copy)]
+pub struct BranchAndBound {
+    pub max_tries: usize,
+}
+
+impl Default for BranchAndBound {
+    fn default() -> Self {
+        BranchAndBound { max_tries: 100_000 }
+    }
+}
+
+impl BranchAndBound {
+    // what a coin adds once its own fee is paid, coins worth less than
+    // that are never part of an exact match
+    fn effective(coin: &Coin, target: &Target) -> Option<u64> {
+        coin.value.checked_sub(target.fee_per_input).filter(|value| *value > 0)
+    }
+
+    fn search(&self, coins: &[&Coin], target: &Target) -> Option<Vec<Coin>> {
+        let low: u64 = target.amount.checked_add(target.base_fee)?;
+        let high: u64 = low.saturating_add(target.dust);
+        let values: Vec<u64> = coins.iter().filter_map(|coin| BranchAndBound::effective(coin, target)).collect();
+        // what the coins from each position on add up to
+        let mut remaining: Vec<u64> = vec![0; values.len() + 1];
+        for i in (0..values.len()).rev() {
+            remaining[i] = remaining[i + 1].saturating_add(values[i]);
+        }
+
+        // the coins in the current branch, by position
+        let mut chosen = Vec::<usize>::new();
+        let mut total: u64 = 0;
+        let mut next: usize = 0;
+        for _ in 0..self.max_tries {
+            let backtrack = if total > high || total.saturating_add(remaining[next]) < low {
+                true
+            } else if total >= low {
+                return Some(chosen.iter().map(|i| coins[*i].clone()).collect());
+            } else {
+                next >= values.len()
+            };
+            if backtrack {
+                // leave out the last coin taken and try the branch without it
+                let last: usize = chosen.pop()?;
+                total -= values[last];
+                next = last + 1;
+            } else {
+                chosen.push(next);
+                total += values[next];
+                next += 1;
+            }
+        }
+        None
+    }
+}
+
+impl CoinSelector for BranchAndBound {
+    fn select(&self, coins: &[Coin], target: &Target) -> Result<Selection, SelectionError> {
+        let mut ordered: Vec<&Coin> = coins
+            .iter()
+            .filter(|coin| BranchAndBound::effective(coin, target).is_some())
+            .collect();
+        ordered.sort_by_key(|coin| std::cmp::Reverse(coin.value));
+        match self.search(&ordered, target).and_then(|coins| settle(coins, target)) {
+            Some(selection) => Ok(selection),
+            None => LargestFirst.select(coins, target),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coins(values: &[u64]) -> Vec<Coin> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| Coin { outpoint: vec![i as u8], value: *value })
+            .collect()
+    }
+
+    fn values(selection: &Selection) -> Vec<u64> {
+        selection.coins.iter().map(|coin| coin.value).collect()
+    }
+
+    #[test]
+    fn each_strategy_pays_the_amount_and_its_fees() {
+        let wallet: Vec<Coin> = coins(&[1, 2, 5, 10, 20]);
+        let target = Target::new(11).with_fees(1, 1);
+
+        let largest = LargestFirst.select(&wallet, &target).unwrap();
+        assert_eq!((values(&largest), largest.fee, largest.change), (vec![20], 2, 7));
+        let smallest = SmallestFirst.select(&wallet, &target).unwrap();
+        assert_eq!((values(&smallest), smallest.fee, smallest.change), (vec![1, 2, 5, 10], 5, 2));
+
+        // 10 and 5 pay 11 and 3 in fees with 1 left, too little for change
+        let exact = BranchAndBound::default().select(&wallet, &target.with_dust(2)).unwrap();
+        assert_eq!((values(&exact), exact.fee, exact.change), (vec![10, 5], 4, 0));
+
+        assert_eq!(
+            LargestFirst.select(&wallet, &Target::new(40).with_fees(0, 1)),
+            Err(SelectionError::InsufficientFunds { available: 38, needed: 45 })
+        );
+    }
+
+    #[test]
+    fn branch_and_bound_falls_back_when_nothing_matches() {
+        let wallet: Vec<Coin> = coins(&[50, 30]);
+        let selection = BranchAndBound::default().select(&wallet, &Target::new(10)).unwrap();
+        assert_eq!((values(&selection), selection.change), (vec![50], 40));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::{Block, BlockChain};
+use std::time::Duration;
+
+// how far ahead of the network's time a block may be stamped, two hours
+// like bitcoin. clocks are never quite in sync, but a block from far in
+// the future would let its miner drag the retarget (and anything else
+// reading block times) wherever they like
+pub const MAX_FUTURE_DRIFT: Duration = Duration::from_secs(2 * 60 * 60);
+
+// a block has to be stamped after the median of this many blocks before
+// it, eleven like bitcoin. one miner's clock can't drag the time back, and
+// unlike "after the previous block" a single block stamped too far ahead
+// doesn't hold up the ones after it
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+// the node's settings that aren't part of the chain itself, two nodes with
+// different ones still follow the same blocks. they may disagree for a
+// while on a block near the edge, until the time catches up with it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainConfig {
+    pub max_future_drift: Duration,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        ChainConfig {
+            max_future_drift: MAX_FUTURE_DRIFT,
+        }
+    }
+}
+
+impl ChainConfig {
+    pub fn new() -> Self {
+        ChainConfig::default()
+    }
+
+    pub fn with_max_future_drift(mut self, max_future_drift: Duration) -> Self {
+        self.max_future_drift = max_future_drift;
+        self
+    }
+}
+
+impl BlockChain {
+    pub fn config(&self) -> &ChainConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: ChainConfig) {
+        self.config = config;
+    }
+
+    // median-time-past: the median time stamp of the (up to)
+    // MEDIAN_TIME_SPAN blocks before the one at `index`, none for genesis
+    pub fn median_time_past(&self, index: usize) -> Option<u128> {
+        let before: &[Block] = &self.chain[index.saturating_sub(MEDIAN_TIME_SPAN)..index.min(self.chain.len())];
+        let mut time_stamps: Vec<u128> = before.iter().map(|block| block.header.time_stamp).collect();
+        time_stamps.sort_unstable();
+        time_stamps.get(time_stamps.len() / 2).copied()
+    }
+
+    // the block at `index` is stamped after the median-time-past of the
+    // blocks before it and not further ahead of the network's time than the
+    // config allows. blocks mined here are checked too, proof of work moves
+    // the timestamp forward when it runs out of nonces
+    pub(crate) fn check_time_stamp(&self, block: &Block, index: usize) -> Result<(), ValidationError> {
+        if let Some(median) = self.median_time_past(index)
+            && block.header.time_stamp <= median
+        {
+            return Err(ValidationError::TimestampTooOld { index, median });
+        }
+        let ahead: u128 = block.header.time_stamp.saturating_sub(self.network_time());
+        if ahead > self.config.max_future_drift.as_nanos() {
+            let drift = Duration::from_nanos(ahead.min(u64::MAX as u128) as u64);
+            return Err(ValidationError::TimestampTooFarInFuture { index, drift });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::error::BlockchainError;
+    use crate::blockchain::genesis::GenesisConfig;
+
+    #[test]
+    fn blocks_from_too_far_in_the_future_are_refused() {
+        let genesis = GenesisConfig::new().with_difficulty(0);
+        let mut node = BlockChain::from_genesis(&genesis);
+        let mut peer = BlockChain::from_genesis(&genesis);
+        let three_hours: u128 = Duration::from_secs(3 * 60 * 60).as_nanos();
+
+        // a peer that lets its blocks run a day ahead
+        peer.set_config(ChainConfig::new().with_max_future_drift(Duration::from_secs(24 * 60 * 60)));
+        let mut pending = peer.prepare_mining("peer").unwrap();
+        pending.block.header.time_stamp += three_hours;
+        peer.finish_block(pending).unwrap();
+        let block: Block = peer.last_block().unwrap().clone();
+
+        assert!(matches!(
+            node.validate_block(&block),
+            Err(ValidationError::TimestampTooFarInFuture { index: 1, drift }) if drift > MAX_FUTURE_DRIFT
+        ));
+        node.set_config(ChainConfig::new().with_max_future_drift(Duration::from_secs(4 * 60 * 60)));
+        assert_eq!(node.validate_block(&block), Ok(()));
+
+        // the node's own blocks are held to the same limit
+        node.set_config(ChainConfig::new());
+        let mut pending = node.prepare_mining("node").unwrap();
+        pending.block.header.time_stamp += three_hours;
+        assert!(matches!(
+            node.finish_block(pending),
+            Err(BlockchainError::Validation(ValidationError::TimestampTooFarInFuture { index: 1, .. }))
+        ));
+        assert_eq!(node.blocks().len(), 1);
+    }
+
+    #[test]
+    fn blocks_are_stamped_after_the_median_of_the_last_ones() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        for _ in 0..3 {
+            chain.mining().unwrap();
+        }
+        let index: usize = chain.blocks().len();
+        // four blocks before it, the later of the two in the middle
+        let median: u128 = chain.blocks()[2].header.time_stamp;
+        assert_eq!(chain.median_time_past(index), Some(median));
+        assert_eq!(chain.median_time_past(0), None);
+
+        let mut pending = chain.prepare_mining("miner").unwrap();
+        pending.block.header.time_stamp = median;
+        assert_eq!(
+            chain.finish_block(pending),
+            Err(BlockchainError::Validation(ValidationError::TimestampTooOld { index, median }))
+        );
+        let mut pending = chain.prepare_mining("miner").unwrap();
+        pending.block.header.time_stamp = median + 1;
+        chain.finish_block(pending).unwrap();
+        assert_eq!(chain.validate_chain(), Ok(()));
+    }
+}
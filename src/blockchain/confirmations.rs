@@ -0,0 +1,196 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::{Block, BlockChain};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfirmationEvent {
+    // the transaction is `confirmations` blocks deep, counting the block at
+    // `height` that holds it
+    Confirmed { txid: Vec<u8>, height: u64, confirmations: u64 },
+    // the block at `height` that held it is no longer part of the chain. the
+    // transaction may come back in another block, the callback fires again
+    // when that one is deep enough
+    Unconfirmed { txid: Vec<u8>, height: u64 },
+}
+
+pub type ConfirmationCallback = Box<dyn FnMut(&ConfirmationEvent) + Send + Sync>;
+
+struct Watch {
+    txid: Vec<u8>,
+    confirmations: u64,
+    callback: ConfirmationCallback,
+    // height of the block holding the transaction, on the current chain
+    included_at: Option<u64>,
+    // Confirmed was sent for `included_at`, a disconnect has to take it back
+    fired: bool,
+}
+
+// callbacks for transactions reaching some depth. the tracker remembers the
+// hash of every block it has seen, so whatever changes the chain (mining, a
+// reorg, a reindex) only has to hand it the new chain: the blocks whose hash
+// changed were disconnected, the ones after them connected
+#[derive(Default)]
+pub struct ConfirmationTracker {
+    watches: Vec<Watch>,
+    // added since the last update, the blocks already seen are searched
+    // for them once
+    pending: Vec<Watch>,
+    // block hashes by height, as of the last update
+    hashes: Vec<Hash32>,
+}
+
+impl fmt::Debug for ConfirmationTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfirmationTracker")
+            .field("watches", &self.len())
+            .field("blocks", &self.hashes.len())
+            .finish()
+    }
+}
+
+impl ConfirmationTracker {
+    pub fn new() -> Self {
+        ConfirmationTracker::default()
+    }
+
+    // the watch only starts counting on the next update, a transaction
+    // that is already deep enough is reported then
+    pub fn watch(&mut self, txid: Vec<u8>, confirmations: u64, callback: ConfirmationCallback) {
+        self.pending.push(Watch {
+            txid,
+            confirmations: confirmations.max(1),
+            callback,
+            included_at: None,
+            fired: false,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.watches.len() + self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // catches up with `chain`, firing Unconfirmed for every confirmed
+    // transaction in a block that is gone and Confirmed for every one that
+    // reached its depth
+    pub fn update(&mut self, chain: &[Block]) {
+        // the highest block both chains still share, usually the old tip
+        let mut fork = self.hashes.len().min(chain.len());
+        while fork > 0 && self.hashes[fork - 1] != chain[fork - 1].hash() {
+            fork -= 1;
+        }
+
+        // newest first, the order a node undoes blocks in
+        for height in (fork..self.hashes.len()).rev() {
+            let height = height as u64;
+            for watch in self.watches.iter_mut().filter(|w| w.included_at == Some(height)) {
+                if watch.fired {
+                    (watch.callback)(&ConfirmationEvent::Unconfirmed { txid: watch.txid.clone(), height });
+                }
+                watch.included_at = None;
+                watch.fired = false;
+            }
+        }
+        self.hashes.truncate(fork);
+
+        let mut pending: Vec<Watch> = std::mem::take(&mut self.pending);
+        let searched: usize = if pending.is_empty() { 0 } else { fork };
+        for (height, block) in chain.iter().enumerate().take(searched) {
+            let txids: Vec<Vec<u8>> = block.txids();
+            for watch in pending.iter_mut().filter(|w| w.included_at.is_none()) {
+                if txids.contains(&watch.txid) {
+                    watch.included_at = Some(height as u64);
+                }
+            }
+        }
+        self.watches.append(&mut pending);
+
+        for (height, block) in chain.iter().enumerate().skip(fork) {
+            let txids: Vec<Vec<u8>> = block.txids();
+            for watch in self.watches.iter_mut().filter(|w| w.included_at.is_none()) {
+                if txids.contains(&watch.txid) {
+                    watch.included_at = Some(height as u64);
+                }
+            }
+            self.hashes.push(block.hash());
+        }
+
+        let tip = chain.len() as u64;
+        for watch in self.watches.iter_mut().filter(|w| !w.fired) {
+            let Some(height) = watch.included_at else {
+                continue;
+            };
+            let confirmations = tip - height;
+            if confirmations >= watch.confirmations {
+                watch.fired = true;
+                (watch.callback)(&ConfirmationEvent::Confirmed {
+                    txid: watch.txid.clone(),
+                    height,
+                    confirmations,
+                });
+            }
+        }
+    }
+}
+
+impl BlockChain {
+    // `callback` hears when the transaction `txid` is `confirmations` blocks
+    // deep, and again if a reorg takes its block away
+    pub fn on_confirmations(
+        &mut self,
+        txid: Vec<u8>,
+        confirmations: u64,
+        callback: impl FnMut(&ConfirmationEvent) + Send + Sync + 'static,
+    ) {
+        self.confirmations.watch(txid, confirmations, Box::new(callback));
+        self.confirmations.update(&self.chain);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::transaction::Transaction;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn a_reorg_takes_back_a_confirmation() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), test_wallet("B").address().into_bytes(), 1).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+        let height = chain.blocks().len() as u64 - 1;
+
+        let events = Arc::new(Mutex::new(Vec::<ConfirmationEvent>::new()));
+        let heard = Arc::clone(&events);
+        chain.on_confirmations(tx.id(), 3, move |event| heard.lock().unwrap().push(event.clone()));
+        chain.mining().unwrap();
+        assert!(events.lock().unwrap().is_empty());
+        chain.mining().unwrap();
+        let confirmed = ConfirmationEvent::Confirmed { txid: tx.id(), height, confirmations: 3 };
+        assert_eq!(*events.lock().unwrap(), vec![confirmed.clone()]);
+
+        // the block with the transaction loses to a branch without it
+        chain.chain.truncate(height as usize);
+        chain.reindex().unwrap();
+        chain.mining().unwrap();
+        let unconfirmed = ConfirmationEvent::Unconfirmed { txid: tx.id(), height };
+        assert_eq!(*events.lock().unwrap(), vec![confirmed.clone(), unconfirmed.clone()]);
+
+        // back in a block and deep enough again
+        chain.add_transaction(&tx).unwrap();
+        for _ in 0..3 {
+            chain.mining().unwrap();
+        }
+        let again = ConfirmationEvent::Confirmed { txid: tx.id(), height: height + 1, confirmations: 3 };
+        assert_eq!(*events.lock().unwrap(), vec![confirmed, unconfirmed, again]);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::blockchain::audit::AuditEvent;
+use crate::blockchain::difficulty::chain_work;
+use crate::blockchain::events::ChainEvent;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::{Block, BlockChain};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+impl BlockChain {
+    // the valid chain with the most work wins. `candidate` is a whole
+    // chain from genesis, e.g. the blocks a peer has, and replaces ours if
+    // its blocks past the fork took more work to mine than ours did and it
+    // is valid from start to end. counting blocks isn't enough once the
+    // difficulty moves: a few hard blocks can be more work than many easy
+    // ones. as much work as ours doesn't, the block seen first stays.
+    // returns whether it replaced ours
+    //
+    // transactions in our blocks past the fork that the candidate didn't
+    // mine go back into the pool, with whatever was already waiting there,
+    // and are checked again against the new state. the ones it rejects,
+    // e.g. a spend of coins the new chain never paid, are dropped
+    pub fn replace_chain(&mut self, candidate: Vec<Block>) -> Result<bool, ValidationError> {
+        if candidate.is_empty() {
+            return Ok(false);
+        }
+        let genesis = self.chain.first().ok_or(ValidationError::EmptyChain)?;
+        if candidate[0].compute_hash() != genesis.hash() {
+            return Err(ValidationError::GenesisMismatch);
+        }
+        let fork_height: usize = self
+            .chain
+            .iter()
+            .zip(candidate.iter())
+            .take_while(|(ours, theirs)| ours.hash() == theirs.compute_hash())
+            .count();
+        // the work a block claims is only checked when it is replayed, a
+        // candidate that lies about it fails there
+        let our_work: u128 = chain_work(&self.chain[fork_height..]);
+        let their_work: u128 = chain_work(&candidate[fork_height..]);
+        if their_work <= our_work {
+            return Ok(false);
+        }
+
+        // the candidate is replayed in place of our chain, which is put
+        // back as it was if any block of it is invalid
+        let ours: Vec<Block> = std::mem::replace(&mut self.chain, candidate);
+        if let Err(e) = self.reindex() {
+            self.chain = ours;
+            return Err(e);
+        }
+        for block in self.chain.iter_mut() {
+            block.seal();
+        }
+
+        let orphaned: &[Block] = &ours[fork_height..];
+        let removed = orphaned.len() as u64;
+        let added = (self.chain.len() - fork_height) as u64;
+        self.audit_log.record(
+            AuditEvent::Reorg { fork_height: fork_height as u64 - 1, removed, added },
+            "replaced by a chain with more work",
+        );
+        if removed > 0 {
+            self.metrics.record_reorg(removed);
+        }
+        self.events.publish(ChainEvent::ChainReorg { fork_height: fork_height as u64 - 1, removed, added });
+        for (height, block) in self.chain.iter().enumerate().skip(fork_height) {
+            let event = ChainEvent::NewBlock { height: height as u64, hash: block.hash(), transactions: block.transactions.len() };
+            self.events.publish(event);
+        }
+
+        let mined: HashSet<Vec<u8>> = self.chain[fork_height..].iter().flat_map(|block| block.txids()).collect();
+        let mut pending: Vec<Transaction> = orphaned
+            .iter()
+            .flat_map(|block| block.transactions.iter())
+            .filter_map(|t| Transaction::decode(t))
+            .filter(|tx| tx.sender_address != BlockChain::MINING_SENDER.as_bytes())
+            .collect();
+        pending.extend(self.transaction_pool.transactions().iter().cloned());
+        self.transaction_pool.clear();
+        let mut reinserted: usize = 0;
+        for tx in pending.into_iter().filter(|tx| !mined.contains(&tx.id())) {
+            if self.submit_transaction(tx, false).is_ok() {
+                reinserted += 1;
+            }
+        }
+
+        // the chain is replaced even if it can't be saved, the next start
+        // loads the old one from the file and syncs again
+        if let Some(store) = self.storage.as_mut() {
+            let saved = store.rewrite(&self.chain);
+            if let Err(e) = &saved {
+                warn!(error = %e, "replaced chain not saved");
+            }
+            self.record_storage(&saved);
+        }
+        info!(fork_height = fork_height - 1, removed, added, reinserted, their_work, our_work, "switched to a chain with more work");
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::difficulty::Retarget;
+    use crate::blockchain::events::EventKind;
+    use crate::blockchain::genesis::{GenesisConfig, GENESIS_TIME_STAMP};
+    use crate::blockchain::hash32::Hash32;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+
+    // a second node that has the same blocks as `chain`
+    fn peer_of(chain: &BlockChain, address: String) -> BlockChain {
+        let mut peer = BlockChain::empty(address, chain.difficulty, chain.chain_id());
+        peer.set_retarget(chain.retarget());
+        peer.chain = chain.blocks().to_vec();
+        peer.reindex().unwrap();
+        peer
+    }
+
+    #[test]
+    fn a_longer_chain_wins_and_orphaned_transactions_go_back_to_the_pool() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let mut peer = peer_of(&chain, test_wallet("peer").address().into());
+
+        // we mine a payment, the peer mines two empty blocks meanwhile
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+        peer.mining().unwrap();
+        peer.mining().unwrap();
+
+        // as long as ours isn't enough
+        let shorter: Vec<Block> = peer.blocks()[..chain.blocks().len()].to_vec();
+        assert_eq!(chain.replace_chain(shorter), Ok(false));
+
+        let mut forged: Vec<Block> = peer.blocks().to_vec();
+        forged.last_mut().unwrap().header.state_root = Hash32::ZERO;
+        assert!(chain.replace_chain(forged).is_err());
+        assert!(chain.find_transaction(&tx.id()).is_some());
+
+        let reorgs = chain.subscribe(&[EventKind::ChainReorg, EventKind::NewBlock]);
+        assert_eq!(chain.replace_chain(peer.blocks().to_vec()), Ok(true));
+        assert_eq!(chain.last_block().unwrap().hash(), peer.last_block().unwrap().hash());
+        assert_eq!(chain.state().root(), peer.state().root());
+        let heard: Vec<ChainEvent> = reorgs.try_iter().collect();
+        let fork_height: u64 = chain.blocks().len() as u64 - 3;
+        assert_eq!(heard[0], ChainEvent::ChainReorg { fork_height, removed: 1, added: 2 });
+        assert_eq!(heard.len(), 3);
+        assert!(chain.find_transaction(&tx.id()).is_none());
+        let pending: Vec<Vec<u8>> = chain.pending_transactions().iter().map(|t| t.id()).collect();
+        assert_eq!(pending, vec![tx.id()]);
+        assert_eq!(chain.metrics().reorgs, 1);
+
+        // a network made at another time has another genesis
+        let genesis = GenesisConfig::new().with_difficulty(0).with_time_stamp(GENESIS_TIME_STAMP + 1);
+        let other = BlockChain::with_genesis(wallet.address().into(), &genesis);
+        let mut longer: Vec<Block> = other.blocks().to_vec();
+        longer.extend(peer.blocks()[1..].iter().cloned());
+        assert_eq!(chain.replace_chain(longer), Err(ValidationError::GenesisMismatch));
+    }
+
+    #[test]
+    fn fewer_harder_blocks_beat_more_easy_ones() {
+        // retargets every 2 blocks: blocks found faster than a quarter of
+        // the 1s target add a zero, slower ones keep the difficulty
+        let retarget = Retarget { interval: 2, block_time: std::time::Duration::from_secs(1) };
+        let mut chain = BlockChain::with_difficulty(miner().address().into(), 0);
+        chain.set_retarget(Some(retarget));
+        let mut peer = peer_of(&chain, test_wallet("peer").address().into());
+
+        // the peer mines 4 blocks at once, we take our time over 5
+        for _ in 0..4 {
+            peer.mining().unwrap();
+        }
+        for _ in 0..5 {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            chain.mining().unwrap();
+        }
+        assert!(peer.blocks().len() < chain.blocks().len());
+        assert!(peer.cumulative_work() > chain.cumulative_work());
+
+        assert_eq!(chain.replace_chain(peer.blocks().to_vec()), Ok(true));
+        assert_eq!(chain.cumulative_work(), peer.cumulative_work());
+    }
+}
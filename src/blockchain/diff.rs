@@ -0,0 +1,161 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::{Block, BlockChain};
+use std::collections::HashMap;
+use std::fmt;
+
+// a height where the two chains don't have the same block. a side is none
+// when its chain is shorter than that
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockDifference {
+    pub height: u64,
+    pub left: Option<Hash32>,
+    pub right: Option<Hash32>,
+}
+
+// how two chains compare, `left` and `right` in the order they were given
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainDiff {
+    // the last height both have the same block at, none when even the
+    // genesis blocks differ
+    pub common_height: Option<u64>,
+    pub blocks: Vec<BlockDifference>,
+    // (height, txid) of transactions only one side mined
+    pub only_left: Vec<(u64, Vec<u8>)>,
+    pub only_right: Vec<(u64, Vec<u8>)>,
+}
+
+impl ChainDiff {
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+impl fmt::Display for ChainDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "the chains are identical");
+        }
+        match self.common_height {
+            Some(height) => writeln!(f, "diverge after block #{}", height)?,
+            None => writeln!(f, "different genesis blocks")?,
+        }
+        let short = |hash: &Option<Hash32>| match hash {
+            Some(hash) => format!("{:.8}", hash),
+            None => "-".to_string(),
+        };
+        for block in &self.blocks {
+            writeln!(f, "  #{} {} | {}", block.height, short(&block.left), short(&block.right))?;
+        }
+        for (height, txid) in &self.only_left {
+            writeln!(f, "  only left:  {} in #{}", hex::encode(txid), height)?;
+        }
+        for (height, txid) in &self.only_right {
+            writeln!(f, "  only right: {} in #{}", hex::encode(txid), height)?;
+        }
+        Ok(())
+    }
+}
+
+// compares two chains from genesis, e.g. ours and one a peer synced, to
+// find where they went different ways. blocks are linked by hash, so past
+// the first differing height every block differs and only the blocks from
+// there on need their transactions compared
+pub fn diff_blocks(left: &[Block], right: &[Block]) -> ChainDiff {
+    let left_hashes: Vec<Hash32> = left.iter().map(|b| b.hash()).collect();
+    let right_hashes: Vec<Hash32> = right.iter().map(|b| b.hash()).collect();
+    let common: usize = left_hashes
+        .iter()
+        .zip(right_hashes.iter())
+        .take_while(|(l, r)| l == r)
+        .count();
+
+    let mut blocks = Vec::<BlockDifference>::new();
+    for height in common..left.len().max(right.len()) {
+        blocks.push(BlockDifference {
+            height: height as u64,
+            left: left_hashes.get(height).cloned(),
+            right: right_hashes.get(height).cloned(),
+        });
+    }
+
+    let left_txids = mined_txids(left, common);
+    let right_txids = mined_txids(right, common);
+    ChainDiff {
+        common_height: common.checked_sub(1).map(|h| h as u64),
+        blocks,
+        only_left: missing_from(&left_txids, &right_txids),
+        only_right: missing_from(&right_txids, &left_txids),
+    }
+}
+
+// (height, txid) of every transaction from block `from` on
+fn mined_txids(blocks: &[Block], from: usize) -> Vec<(u64, Vec<u8>)> {
+    let mut txids = Vec::<(u64, Vec<u8>)>::new();
+    for (height, block) in blocks.iter().enumerate().skip(from) {
+        for txid in block.txids() {
+            txids.push((height as u64, txid));
+        }
+    }
+    txids
+}
+
+// the ones in `ours` that `theirs` doesn't have. coinbases paying the same
+// miner the same reward share a txid, so they're counted, not just looked up
+fn missing_from(ours: &[(u64, Vec<u8>)], theirs: &[(u64, Vec<u8>)]) -> Vec<(u64, Vec<u8>)> {
+    let mut counts: HashMap<&[u8], usize> = HashMap::new();
+    for (_, txid) in theirs {
+        *counts.entry(txid).or_default() += 1;
+    }
+    ours.iter()
+        .filter(|(_, txid)| match counts.get_mut(txid.as_slice()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+impl BlockChain {
+    // `other` can be another node's chain or blocks loaded from anywhere
+    pub fn diff(&self, other: &[Block]) -> ChainDiff {
+        diff_blocks(&self.chain, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{funded_wallet, test_wallet, ChainBuilder};
+    use crate::blockchain::transaction::Transaction;
+
+    #[test]
+    fn reports_where_chains_diverge_and_what_only_one_has() {
+        // the first rewards have to mature before anyone can send anything
+        let mut chain = ChainBuilder::new()
+            .with_blocks(BlockChain::COINBASE_MATURITY as usize + 5)
+            .with_random_txs(10)
+            .build();
+        let mut copy: Vec<Block> = chain.blocks().to_vec();
+        assert!(chain.diff(&copy).is_empty());
+
+        let recipient = test_wallet("account 1").address().into_bytes();
+        let sender = funded_wallet(&chain, 1, &recipient).unwrap();
+        let nonce: u64 = chain.next_nonce(sender.address().as_bytes());
+        let tx = Transaction::new(sender.address().into_bytes(), recipient, 1).with_nonce(nonce).sign(&sender);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+        // a different block at the copy's tip, with the same transactions
+        let tip: usize = copy.len() - 1;
+        copy[tip] += 1;
+
+        let diff = chain.diff(&copy);
+        assert_eq!(diff.common_height, Some(tip as u64 - 1));
+        assert_eq!(diff.blocks.len(), chain.blocks().len() - tip);
+        assert_eq!(diff.blocks.last().unwrap().right, None);
+        assert!(diff.only_left.iter().any(|(_, txid)| *txid == tx.id()));
+        assert!(diff.only_right.is_empty());
+    }
+}
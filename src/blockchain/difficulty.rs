@@ -0,0 +1,230 @@
+use crate::blockchain::{Block, BlockChain, BlockHeader};
+use std::time::Duration;
+
+// blocks the hashrate is averaged over when nothing else is asked for,
+// few enough to follow changes and enough to smooth out luck
+pub const HASHRATE_WINDOW: usize = 20;
+// the network's retarget rules, see `Retarget`
+pub const RETARGET_INTERVAL: u64 = 60;
+pub const TARGET_BLOCK_TIME: Duration = Duration::from_secs(10);
+// a sha256 hash has 64 hex digits
+pub const MAX_DIFFICULTY: usize = 64;
+
+// how the difficulty follows the hashrate: every `interval` blocks the time
+// the last `interval` blocks took is compared with `block_time` each. one
+// more leading zero is 16 times the work, so the difficulty only moves when
+// blocks came over 4 times too fast or too slow, halfway between two steps
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Retarget {
+    pub interval: u64,
+    pub block_time: Duration,
+}
+
+impl Default for Retarget {
+    fn default() -> Self {
+        Retarget {
+            interval: RETARGET_INTERVAL,
+            block_time: TARGET_BLOCK_TIME,
+        }
+    }
+}
+
+impl Retarget {
+    // the difficulty of the block after `parents`. the first mined block
+    // has the chain's `initial` difficulty, every later one the one before
+    // it had unless it lands on a retarget
+    pub fn next_difficulty(&self, parents: &[Block], initial: usize) -> usize {
+        let header_at = |height: u64| parents.get(height as usize).map(|parent| &parent.header);
+        self.difficulty_at(parents.len() as u64, initial, header_at).expect("every block before it is there")
+    }
+
+    // the same for the block at `height` from headers alone, `header_at`
+    // finds the one at a height below it. none when a header it needs
+    // isn't there, a light client started from a checkpoint doesn't hold
+    // the ones before
+    pub fn difficulty_at<'a>(
+        &self,
+        height: u64,
+        initial: usize,
+        header_at: impl Fn(u64) -> Option<&'a BlockHeader>,
+    ) -> Option<usize> {
+        let previous = match height {
+            0 | 1 => return Some(initial),
+            _ => header_at(height - 1)?.difficulty,
+        };
+        if self.interval == 0 || !height.is_multiple_of(self.interval) {
+            return Some(previous);
+        }
+
+        // from the block `interval` back. never from genesis, its time
+        // stamp is fixed when the network is made and says nothing about the
+        // hashrate, the first retarget starts from the first mined block
+        let last = height - 1;
+        let first = last.saturating_sub(self.interval).max(1);
+        let took = header_at(last)?.time_stamp.saturating_sub(header_at(first)?.time_stamp);
+        let expected = self.block_time.as_nanos() * (last - first) as u128;
+        let difficulty = if took * 4 < expected {
+            (previous + 1).min(MAX_DIFFICULTY)
+        } else if took > expected * 4 {
+            previous.saturating_sub(1)
+        } else {
+            previous
+        };
+        Some(difficulty)
+    }
+}
+
+// hashes a miner tries on average to find a block: every leading zero hex
+// digit the target asks for cuts the odds by 16
+pub fn expected_hashes(difficulty: usize) -> f64 {
+    16f64.powi(difficulty as i32)
+}
+
+// the same as a whole number, what fork choice adds up. a chain's work is
+// what it took to mine, so with the difficulty moving a short chain of hard
+// blocks can outweigh a long one of easy blocks
+pub fn block_work(difficulty: usize) -> u128 {
+    16u128.checked_pow(difficulty as u32).unwrap_or(u128::MAX)
+}
+
+// work of `blocks` together, e.g. of a branch past a fork
+pub fn chain_work(blocks: &[Block]) -> u128 {
+    blocks
+        .iter()
+        .fold(0u128, |work, block| work.saturating_add(block_work(block.header.difficulty)))
+}
+
+// the same from headers, all a light client has
+pub fn header_work(headers: &[BlockHeader]) -> u128 {
+    headers
+        .iter()
+        .fold(0u128, |work, header| work.saturating_add(block_work(header.difficulty)))
+}
+
+impl BlockChain {
+    // the difficulty the block at `height` had to meet, as its header says.
+    // the genesis block isn't mined
+    pub fn difficulty_at(&self, height: u64) -> Option<usize> {
+        self.chain.get(height as usize).map(|block| block.header.difficulty)
+    }
+
+    // what the block at `height` has to have in its header under this
+    // chain's rules, worked out from the blocks before it
+    pub fn expected_difficulty(&self, height: u64) -> usize {
+        let parents: &[Block] = &self.chain[..(height as usize).min(self.chain.len())];
+        match (height, self.retarget) {
+            (0, _) => 0,
+            (_, Some(retarget)) => retarget.next_difficulty(parents, self.difficulty),
+            (_, None) => self.difficulty,
+        }
+    }
+
+    // none keeps every block at the difficulty the chain was created with.
+    // like the difficulty, it has to be what the blocks were mined under,
+    // a chain loaded with other rules doesn't validate
+    pub fn set_retarget(&mut self, retarget: Option<Retarget>) {
+        self.retarget = retarget;
+    }
+
+    pub fn retarget(&self) -> Option<Retarget> {
+        self.retarget
+    }
+
+    // the work of every block since genesis, which isn't mined
+    pub fn cumulative_work(&self) -> u128 {
+        chain_work(self.chain.get(1..).unwrap_or(&[]))
+    }
+
+    // (height, difficulty) of the last `window` blocks, oldest first
+    pub fn difficulty_history(&self, window: usize) -> Vec<(u64, usize)> {
+        if window == 0 {
+            return Vec::new();
+        }
+        let tip = self.chain.len() as u64 - 1;
+        let from = tip.saturating_sub(window as u64 - 1).max(1);
+        (from..=tip)
+            .filter_map(|height| Some((height, self.difficulty_at(height)?)))
+            .collect()
+    }
+
+    // hashes per second the network is doing, from the work the last
+    // `window` blocks needed and the time they took. nobody reports their
+    // hashes, so this is what the difficulty says they must have done: a
+    // few lucky blocks in a row make it look higher than it is
+    pub fn estimated_hashrate(&self, window: usize) -> f64 {
+        let tip = self.chain.len() - 1;
+        let window = window.min(tip);
+        if window == 0 {
+            return 0.0;
+        }
+
+        let started = self.chain[tip - window].header.time_stamp;
+        let elapsed = self.chain[tip].header.time_stamp.saturating_sub(started) as f64 / 1e9;
+        if elapsed <= 0.0 {
+            return 0.0;
+        }
+        let work: f64 = self
+            .difficulty_history(window)
+            .iter()
+            .map(|(_, difficulty)| expected_hashes(*difficulty))
+            .sum();
+        work / elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::hash32::Hash32;
+    use crate::blockchain::validation::ValidationError;
+
+    #[test]
+    fn hashrate_is_the_expected_work_over_the_time_taken() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 1);
+        for _ in 0..4 {
+            chain.mining().unwrap();
+        }
+        // one block a second
+        for (height, block) in chain.chain.iter_mut().enumerate() {
+            block.header.time_stamp = height as u128 * 1_000_000_000;
+        }
+
+        assert_eq!(chain.difficulty_history(3), vec![(3, 1), (4, 1), (5, 1)]);
+        assert_eq!(chain.difficulty_history(100).len(), 5);
+        assert_eq!(chain.estimated_hashrate(4), 16.0);
+        assert_eq!(chain.estimated_hashrate(100), 16.0);
+    }
+
+    #[test]
+    fn the_difficulty_follows_the_block_times() {
+        let retarget = Retarget { interval: 4, block_time: Duration::from_secs(10) };
+        let blocks_every = |seconds: u128| -> Vec<Block> {
+            (0..4u128)
+                .map(|height| {
+                    let mut block = Block::new(0, Hash32::ZERO);
+                    block.header.time_stamp = height * seconds * 1_000_000_000;
+                    block.header.difficulty = 3;
+                    block
+                })
+                .collect()
+        };
+        assert_eq!(retarget.next_difficulty(&blocks_every(10), 3), 3);
+        assert_eq!(retarget.next_difficulty(&blocks_every(1), 3), 4);
+        assert_eq!(retarget.next_difficulty(&blocks_every(100), 3), 2);
+        // only every 4 blocks
+        assert_eq!(retarget.next_difficulty(&blocks_every(1)[..3], 3), 3);
+
+        // mined in no time, so the fifth block needs one more zero
+        let mut chain = BlockChain::with_difficulty("miner".into(), 1);
+        chain.set_retarget(Some(retarget));
+        for _ in 0..3 {
+            chain.mining().unwrap();
+        }
+        assert_eq!(chain.difficulty_history(4), vec![(1, 1), (2, 1), (3, 1), (4, 2)]);
+        assert_eq!(chain.validate_chain(), Ok(()));
+
+        chain.chain[4].header.difficulty = 1;
+        assert_eq!(chain.validate_chain(), Err(ValidationError::WrongDifficulty { index: 4, expected: 2 }));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::{Block, BlockChain};
+use std::collections::HashMap;
+
+// hex digits of the hash shown in each box, enough to tell blocks apart
+const SHORT_HASH: usize = 8;
+
+// labels end up inside double quotes
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// renders blocks as a graphviz digraph, every block pointing to the block
+// it builds on. `main` is the chain from genesis, `side` blocks are off it
+// (stale forks, uncles) and are drawn dashed wherever their parent is
+// known. `dot -Tsvg chain.dot > chain.svg` turns the output into a picture.
+pub fn blocks_to_dot(main: &[Block], side: &[Block]) -> String {
+    let mut heights: HashMap<Hash32, u64> = HashMap::new();
+    for (height, block) in main.iter().enumerate() {
+        heights.insert(block.hash(), height as u64);
+    }
+
+    // a side block's height is one past its parent's, which may itself be
+    // a side block listed after it
+    let mut pending: Vec<&Block> = side.iter().collect();
+    loop {
+        let before = pending.len();
+        pending.retain(|block| match heights.get(&block.header.previous_hash) {
+            Some(parent_height) => {
+                heights.insert(block.hash(), parent_height + 1);
+                false
+            }
+            None => true,
+        });
+        if pending.is_empty() || pending.len() == before {
+            break;
+        }
+    }
+
+    let mut dot = String::from("digraph chain {\n");
+    dot.push_str("    rankdir=LR;\n");
+    dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+    let blocks = main.iter().map(|block| (block, false)).chain(side.iter().map(|block| (block, true)));
+    for (block, is_side) in blocks {
+        let hash = block.hash().to_string();
+        let height = match heights.get(&block.hash()) {
+            Some(height) => height.to_string(),
+            None => "?".to_string(),
+        };
+        let miner = match block.miner() {
+            Some(address) => escape(&String::from_utf8_lossy(&address)),
+            None => "none".to_string(),
+        };
+        let style = if is_side { ", style=dashed, color=gray40" } else { "" };
+        dot.push_str(&format!(
+            "    \"{}\" [label=\"#{}\\n{}\\nminer: {}\"{}];\n",
+            hash,
+            height,
+            &hash[..SHORT_HASH],
+            miner,
+            style
+        ));
+
+        if heights.contains_key(&block.header.previous_hash) {
+            let edge_style = if is_side { " [style=dashed, color=gray40]" } else { "" };
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\"{};\n",
+                block.header.previous_hash,
+                hash,
+                edge_style
+            ));
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+impl BlockChain {
+    // the node keeps no blocks off its own chain yet, so this is the main
+    // chain alone. blocks_to_dot draws forks once there are some to show.
+    pub fn to_dot(&self) -> String {
+        blocks_to_dot(&self.chain, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forks_are_drawn_dashed_off_the_block_they_build_on() {
+        let mut chain = BlockChain::with_difficulty("main \"miner\"".into(), 0);
+        chain.mining().unwrap();
+        let main: &[Block] = chain.blocks();
+        let mut fork = BlockChain::with_difficulty("fork".into(), 0);
+        let mut stale: Block = fork.blocks()[1].clone();
+        stale.header.previous_hash = main[1].hash();
+        stale.seal();
+        fork.mining().unwrap();
+        // its parent is nowhere to be seen, `stale` took its place
+        let orphan: Block = fork.blocks()[2].clone();
+
+        let dot: String = blocks_to_dot(main, &[orphan.clone(), stale.clone()]);
+        assert!(dot.starts_with("digraph chain {\n") && dot.ends_with("}\n"));
+        for pair in main.windows(2) {
+            assert!(dot.contains(&format!("    \"{}\" -> \"{}\";\n", pair[0].hash(), pair[1].hash())));
+        }
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\" [style=dashed", main[1].hash(), stale.hash())));
+        assert!(dot.contains("label=\"#2\\n") && dot.contains("miner: main \\\"miner\\\""));
+        let orphan_line: &str = dot.lines().find(|line| line.contains(&format!("\"{}\" [", orphan.hash()))).unwrap();
+        assert!(orphan_line.contains("label=\"#?\\n"));
+        assert!(!dot.contains(&format!("-> \"{}\"", orphan.hash())));
+        assert_eq!(chain.to_dot(), blocks_to_dot(main, &[]));
+    }
+}
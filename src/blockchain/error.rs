@@ -0,0 +1,98 @@
+use crate::blockchain::clock::ClockSkew;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::mempool::MempoolError;
+use crate::blockchain::storage::StorageError;
+use crate::blockchain::validation::ValidationError;
+use std::fmt;
+
+// what the chain's own api returns instead of panicking. the narrower
+// errors (pool, validation) convert into it, so code embedding the crate
+// can use `?` on all of them and still match on the cause
+#[derive(Debug, PartialEq)]
+pub enum BlockchainError {
+    // a chain always starts with its genesis block, only a chain built
+    // by hand can be empty
+    EmptyChain,
+    // a block has to be built on the tip, anything else breaks the chain
+    UnknownParent { previous_hash: Hash32 },
+    // the local clock is too far from the network's to stamp a block
+    ClockSkew(ClockSkew),
+    // the miner's own reward didn't get into the pool, no block is mined
+    CoinbaseRejected(MempoolError),
+    MalformedTransaction,
+    MalformedHeader,
+    Mempool(MempoolError),
+    Validation(ValidationError),
+    // the block file couldn't be written, the chain in memory is ahead of it
+    Storage(StorageError),
+    // the mining cancel handle was set before a nonce was found, the
+    // chain is as it was before mining
+    MiningCancelled,
+}
+
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockchainError::EmptyChain => write!(f, "the chain has no blocks"),
+            BlockchainError::UnknownParent { previous_hash } => {
+                write!(f, "block {} is not the tip of the chain", previous_hash)
+            }
+            BlockchainError::ClockSkew(skew) => write!(f, "{}", skew),
+            BlockchainError::CoinbaseRejected(e) => write!(f, "coinbase rejected: {}", e),
+            BlockchainError::MalformedTransaction => write!(f, "bytes are not a transaction"),
+            BlockchainError::MalformedHeader => write!(f, "bytes are not a block header"),
+            BlockchainError::Mempool(e) => write!(f, "{}", e),
+            BlockchainError::Validation(e) => write!(f, "{}", e),
+            BlockchainError::Storage(e) => write!(f, "{}", e),
+            BlockchainError::MiningCancelled => write!(f, "mining was cancelled before a block was found"),
+        }
+    }
+}
+
+impl std::error::Error for BlockchainError {}
+
+impl From<ClockSkew> for BlockchainError {
+    fn from(skew: ClockSkew) -> Self {
+        BlockchainError::ClockSkew(skew)
+    }
+}
+
+impl From<MempoolError> for BlockchainError {
+    fn from(e: MempoolError) -> Self {
+        BlockchainError::Mempool(e)
+    }
+}
+
+impl From<ValidationError> for BlockchainError {
+    fn from(e: ValidationError) -> Self {
+        BlockchainError::Validation(e)
+    }
+}
+
+impl From<StorageError> for BlockchainError {
+    fn from(e: StorageError) -> Self {
+        BlockchainError::Storage(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::transaction::Transaction;
+    use crate::blockchain::BlockChain;
+
+    #[test]
+    fn misuse_is_an_error_not_a_panic() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        let genesis = chain.blocks()[0].hash();
+        assert_eq!(
+            chain.create_block(&genesis),
+            Err(BlockchainError::UnknownParent { previous_hash: genesis })
+        );
+        assert_eq!(Transaction::from_bytes(&[1, 2, 3]).unwrap_err(), BlockchainError::MalformedTransaction);
+
+        chain.chain.clear();
+        assert_eq!(chain.last_block().unwrap_err(), BlockchainError::EmptyChain);
+        assert_eq!(chain.mining(), Err(BlockchainError::EmptyChain));
+    }
+}
@@ -0,0 +1,499 @@
+use crate::blockchain::coin_selection::SelectionError;
+use crate::blockchain::error::BlockchainError;
+use crate::blockchain::fast_sync::FastSyncError;
+use crate::blockchain::gas::OutOfGas;
+use crate::blockchain::light::LightClientError;
+use crate::blockchain::mempool::MempoolError;
+use crate::blockchain::peers::PeerError;
+use crate::blockchain::precompile::PrecompileError;
+use crate::blockchain::script::ScriptError;
+use crate::blockchain::state::StateError;
+use crate::blockchain::storage::StorageError;
+use crate::blockchain::transaction::SignatureError;
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::wallets::WalletError;
+#[cfg(feature = "vm")]
+use crate::blockchain::vm::VmError;
+use std::fmt;
+
+// machine readable codes for everything the node can refuse, so clients
+// branch on a code instead of parsing messages. the rpc and http layers
+// send both the name and the number. once released neither ever changes:
+// new codes are added, old ones are never reused.
+//
+// numbers are grouped by what went wrong:
+// 1xxx the transaction itself, 2xxx the pool, 3xxx blocks and the chain,
+// 4xxx named things in the state, 5xxx contracts, 6xxx the node's wallets,
+// 7xxx peers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    MalformedTransaction,
+    InvalidSignature,
+    InvalidScript,
+    InsufficientFunds,
+    InvalidPayload,
+    OutOfGas,
+    GasLimitTooHigh,
+    ImmatureCoinbase,
+    // signed for another network
+    WrongChain,
+    // spends an output another transaction spends
+    DoubleSpend,
+    // spends an output that doesn't exist, or no longer does
+    UnknownOutput,
+    // out of order with the sender's other transactions, or a replay
+    InvalidNonce,
+
+    AlreadyKnown,
+    InsufficientFee,
+    ReplacementRejected,
+    // the pool is at its size limit and the fee rate is too low to evict
+    // anything
+    MempoolFull,
+    // a node's own policy, the transaction may be valid elsewhere
+    PolicyRejected,
+
+    UnknownBlock,
+    UnknownTransaction,
+    UnknownParent,
+    InvalidBlock,
+    InvalidProofOfWork,
+    NotBestChain,
+    InvalidProof,
+    // the node's clock is too far off to mine
+    ClockSkew,
+    // the node couldn't save or load its blocks
+    StorageFailed,
+    // mining stopped before a block was found, e.g. one came from a peer
+    MiningCancelled,
+
+    AlreadyExists,
+    UnknownAsset,
+    UnknownName,
+    UnknownProposal,
+    Unauthorized,
+    VotingClosed,
+
+    InvalidContract,
+    UnknownContract,
+    ContractTrapped,
+
+    UnknownWallet,
+    // an encrypted wallet asked to sign while locked
+    WalletLocked,
+    WrongPassphrase,
+
+    UnknownPeer,
+    PeerBanned,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 41] = [
+        ErrorCode::MalformedTransaction,
+        ErrorCode::InvalidSignature,
+        ErrorCode::InvalidScript,
+        ErrorCode::InsufficientFunds,
+        ErrorCode::InvalidPayload,
+        ErrorCode::OutOfGas,
+        ErrorCode::GasLimitTooHigh,
+        ErrorCode::ImmatureCoinbase,
+        ErrorCode::WrongChain,
+        ErrorCode::DoubleSpend,
+        ErrorCode::UnknownOutput,
+        ErrorCode::InvalidNonce,
+        ErrorCode::AlreadyKnown,
+        ErrorCode::InsufficientFee,
+        ErrorCode::ReplacementRejected,
+        ErrorCode::MempoolFull,
+        ErrorCode::PolicyRejected,
+        ErrorCode::UnknownBlock,
+        ErrorCode::UnknownTransaction,
+        ErrorCode::UnknownParent,
+        ErrorCode::InvalidBlock,
+        ErrorCode::InvalidProofOfWork,
+        ErrorCode::NotBestChain,
+        ErrorCode::InvalidProof,
+        ErrorCode::ClockSkew,
+        ErrorCode::StorageFailed,
+        ErrorCode::MiningCancelled,
+        ErrorCode::AlreadyExists,
+        ErrorCode::UnknownAsset,
+        ErrorCode::UnknownName,
+        ErrorCode::UnknownProposal,
+        ErrorCode::Unauthorized,
+        ErrorCode::VotingClosed,
+        ErrorCode::InvalidContract,
+        ErrorCode::UnknownContract,
+        ErrorCode::ContractTrapped,
+        ErrorCode::UnknownWallet,
+        ErrorCode::WalletLocked,
+        ErrorCode::WrongPassphrase,
+        ErrorCode::UnknownPeer,
+        ErrorCode::PeerBanned,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::MalformedTransaction => "malformed-transaction",
+            ErrorCode::InvalidSignature => "invalid-signature",
+            ErrorCode::InvalidScript => "invalid-script",
+            ErrorCode::InsufficientFunds => "insufficient-funds",
+            ErrorCode::InvalidPayload => "invalid-payload",
+            ErrorCode::OutOfGas => "out-of-gas",
+            ErrorCode::GasLimitTooHigh => "gas-limit-too-high",
+            ErrorCode::ImmatureCoinbase => "immature-coinbase",
+            ErrorCode::WrongChain => "wrong-chain",
+            ErrorCode::DoubleSpend => "double-spend",
+            ErrorCode::UnknownOutput => "unknown-output",
+            ErrorCode::InvalidNonce => "invalid-nonce",
+            ErrorCode::AlreadyKnown => "already-known",
+            ErrorCode::InsufficientFee => "insufficient-fee",
+            ErrorCode::ReplacementRejected => "replacement-rejected",
+            ErrorCode::MempoolFull => "mempool-full",
+            ErrorCode::PolicyRejected => "policy-rejected",
+            ErrorCode::UnknownBlock => "unknown-block",
+            ErrorCode::UnknownTransaction => "unknown-transaction",
+            ErrorCode::UnknownParent => "unknown-parent",
+            ErrorCode::InvalidBlock => "invalid-block",
+            ErrorCode::InvalidProofOfWork => "invalid-proof-of-work",
+            ErrorCode::NotBestChain => "not-best-chain",
+            ErrorCode::InvalidProof => "invalid-proof",
+            ErrorCode::ClockSkew => "clock-skew",
+            ErrorCode::StorageFailed => "storage-failed",
+            ErrorCode::MiningCancelled => "mining-cancelled",
+            ErrorCode::AlreadyExists => "already-exists",
+            ErrorCode::UnknownAsset => "unknown-asset",
+            ErrorCode::UnknownName => "unknown-name",
+            ErrorCode::UnknownProposal => "unknown-proposal",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::VotingClosed => "voting-closed",
+            ErrorCode::InvalidContract => "invalid-contract",
+            ErrorCode::UnknownContract => "unknown-contract",
+            ErrorCode::ContractTrapped => "contract-trapped",
+            ErrorCode::UnknownWallet => "unknown-wallet",
+            ErrorCode::WalletLocked => "wallet-locked",
+            ErrorCode::WrongPassphrase => "wrong-passphrase",
+            ErrorCode::UnknownPeer => "unknown-peer",
+            ErrorCode::PeerBanned => "peer-banned",
+        }
+    }
+
+    pub fn number(&self) -> u32 {
+        match self {
+            ErrorCode::MalformedTransaction => 1000,
+            ErrorCode::InvalidSignature => 1001,
+            ErrorCode::InvalidScript => 1002,
+            ErrorCode::InsufficientFunds => 1003,
+            ErrorCode::InvalidPayload => 1004,
+            ErrorCode::OutOfGas => 1005,
+            ErrorCode::GasLimitTooHigh => 1006,
+            ErrorCode::ImmatureCoinbase => 1007,
+            ErrorCode::WrongChain => 1008,
+            ErrorCode::DoubleSpend => 1009,
+            ErrorCode::UnknownOutput => 1010,
+            ErrorCode::InvalidNonce => 1011,
+            ErrorCode::AlreadyKnown => 2000,
+            ErrorCode::InsufficientFee => 2001,
+            ErrorCode::ReplacementRejected => 2002,
+            ErrorCode::MempoolFull => 2003,
+            ErrorCode::PolicyRejected => 2004,
+            ErrorCode::UnknownBlock => 3000,
+            ErrorCode::UnknownTransaction => 3001,
+            ErrorCode::UnknownParent => 3002,
+            ErrorCode::InvalidBlock => 3003,
+            ErrorCode::InvalidProofOfWork => 3004,
+            ErrorCode::NotBestChain => 3005,
+            ErrorCode::InvalidProof => 3006,
+            ErrorCode::ClockSkew => 3007,
+            ErrorCode::StorageFailed => 3008,
+            ErrorCode::MiningCancelled => 3009,
+            ErrorCode::AlreadyExists => 4000,
+            ErrorCode::UnknownAsset => 4001,
+            ErrorCode::UnknownName => 4002,
+            ErrorCode::UnknownProposal => 4003,
+            ErrorCode::Unauthorized => 4004,
+            ErrorCode::VotingClosed => 4005,
+            ErrorCode::InvalidContract => 5000,
+            ErrorCode::UnknownContract => 5001,
+            ErrorCode::ContractTrapped => 5002,
+            ErrorCode::UnknownWallet => 6000,
+            ErrorCode::WalletLocked => 6001,
+            ErrorCode::WrongPassphrase => 6002,
+            ErrorCode::UnknownPeer => 7000,
+            ErrorCode::PeerBanned => 7001,
+        }
+    }
+
+    // the other way round, for clients reading a code off the wire
+    pub fn from_name(name: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL.into_iter().find(|code| code.as_str() == name)
+    }
+
+    pub fn from_number(number: u32) -> Option<ErrorCode> {
+        ErrorCode::ALL.into_iter().find(|code| code.number() == number)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// every library error maps onto exactly one code, wrapped errors take the
+// code of what they wrap
+pub trait HasErrorCode {
+    fn code(&self) -> ErrorCode;
+}
+
+impl HasErrorCode for BlockchainError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            BlockchainError::EmptyChain => ErrorCode::UnknownBlock,
+            BlockchainError::UnknownParent { .. } => ErrorCode::UnknownParent,
+            BlockchainError::ClockSkew(_) => ErrorCode::ClockSkew,
+            BlockchainError::CoinbaseRejected(e) => e.code(),
+            BlockchainError::MalformedTransaction => ErrorCode::MalformedTransaction,
+            BlockchainError::MalformedHeader => ErrorCode::InvalidBlock,
+            BlockchainError::Mempool(e) => e.code(),
+            BlockchainError::Validation(e) => e.code(),
+            BlockchainError::Storage(e) => e.code(),
+            BlockchainError::MiningCancelled => ErrorCode::MiningCancelled,
+        }
+    }
+}
+
+impl HasErrorCode for FastSyncError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            FastSyncError::Headers(e) => e.code(),
+            FastSyncError::BrokenHistory { .. } | FastSyncError::UnexpectedBlock { .. } => ErrorCode::InvalidBlock,
+            FastSyncError::MmrMismatch { .. } | FastSyncError::SnapshotMismatch { .. } => ErrorCode::InvalidProof,
+            FastSyncError::SnapshotUnavailable { .. } => ErrorCode::UnknownBlock,
+            FastSyncError::InvalidBlock(e) => e.code(),
+        }
+    }
+}
+
+impl HasErrorCode for OutOfGas {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::OutOfGas
+    }
+}
+
+impl HasErrorCode for PrecompileError {
+    fn code(&self) -> ErrorCode {
+        // only scripts surface these, a contract just sees the call fail
+        ErrorCode::InvalidScript
+    }
+}
+
+impl HasErrorCode for ScriptError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            ScriptError::UnknownOpCode(_)
+            | ScriptError::TruncatedPush
+            | ScriptError::PushTooLarge(_)
+            | ScriptError::StackUnderflow => ErrorCode::InvalidScript,
+            // the unlocking script doesn't satisfy the lock: in practice a
+            // wrong key or a bad signature
+            ScriptError::EqualVerifyFailed
+            | ScriptError::EvaluatedToFalse
+            | ScriptError::MissingUnlockingScript => ErrorCode::InvalidSignature,
+            ScriptError::OutOfGas => ErrorCode::OutOfGas,
+            ScriptError::Precompile(e) => e.code(),
+        }
+    }
+}
+
+#[cfg(feature = "vm")]
+impl HasErrorCode for VmError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            VmError::InvalidModule(_) | VmError::MissingExport(_) => ErrorCode::InvalidContract,
+            VmError::AlreadyDeployed => ErrorCode::AlreadyExists,
+            VmError::UnknownContract => ErrorCode::UnknownContract,
+            VmError::Trap(_) => ErrorCode::ContractTrapped,
+            VmError::OutOfGas => ErrorCode::OutOfGas,
+        }
+    }
+}
+
+impl HasErrorCode for SignatureError {
+    fn code(&self) -> ErrorCode {
+        ErrorCode::InvalidSignature
+    }
+}
+
+impl HasErrorCode for StateError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            StateError::UnsupportedPayload
+            | StateError::EmptyAssetSupply
+            | StateError::InvalidName
+            | StateError::ParameterOutOfRange { .. } => ErrorCode::InvalidPayload,
+            StateError::AssetAlreadyExists | StateError::NameTaken { .. } | StateError::ProposalExists => {
+                ErrorCode::AlreadyExists
+            }
+            StateError::UnknownAsset => ErrorCode::UnknownAsset,
+            StateError::InsufficientAssetBalance { .. } | StateError::InsufficientBalance { .. } => {
+                ErrorCode::InsufficientFunds
+            }
+            StateError::AmountOverflow => ErrorCode::MalformedTransaction,
+            // an expired name resolves to nothing, same as one never taken
+            StateError::NameNotFound | StateError::NameExpired => ErrorCode::UnknownName,
+            StateError::NotNameOwner | StateError::NoVotingPower => ErrorCode::Unauthorized,
+            StateError::UnknownProposal => ErrorCode::UnknownProposal,
+            StateError::VotingClosed => ErrorCode::VotingClosed,
+            StateError::GasLimitTooHigh { .. } => ErrorCode::GasLimitTooHigh,
+            StateError::ImmatureCoinbase { .. } => ErrorCode::ImmatureCoinbase,
+            StateError::MissingOutput => ErrorCode::UnknownOutput,
+            StateError::NotOutputOwner => ErrorCode::Unauthorized,
+            StateError::DuplicateInput => ErrorCode::DoubleSpend,
+            StateError::WrongNonce { .. } => ErrorCode::InvalidNonce,
+            StateError::Script(e) => e.code(),
+            #[cfg(feature = "vm")]
+            StateError::Vm(e) => e.code(),
+        }
+    }
+}
+
+impl HasErrorCode for StorageError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            StorageError::Io(_) | StorageError::Corrupt { .. } => ErrorCode::StorageFailed,
+            StorageError::Invalid(e) => e.code(),
+        }
+    }
+}
+
+impl HasErrorCode for SelectionError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            SelectionError::InsufficientFunds { .. } => ErrorCode::InsufficientFunds,
+        }
+    }
+}
+
+impl HasErrorCode for WalletError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            WalletError::AlreadyLoaded(_) => ErrorCode::AlreadyExists,
+            WalletError::UnknownWallet(_) => ErrorCode::UnknownWallet,
+            WalletError::Locked(_) => ErrorCode::WalletLocked,
+            WalletError::WrongPassphrase => ErrorCode::WrongPassphrase,
+            WalletError::NotSender(_) => ErrorCode::Unauthorized,
+            WalletError::Rejected(e) => e.code(),
+        }
+    }
+}
+
+impl HasErrorCode for PeerError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            PeerError::UnknownPeer(_) | PeerError::NotBanned(_) => ErrorCode::UnknownPeer,
+            PeerError::Banned { .. } => ErrorCode::PeerBanned,
+        }
+    }
+}
+
+impl HasErrorCode for MempoolError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            MempoolError::Malformed(_) => ErrorCode::MalformedTransaction,
+            MempoolError::AlreadyInPool => ErrorCode::AlreadyKnown,
+            MempoolError::ReplacementDisabled { .. } | MempoolError::NotReplaceable { .. } => {
+                ErrorCode::ReplacementRejected
+            }
+            MempoolError::InsufficientFee { .. } => ErrorCode::InsufficientFee,
+            MempoolError::InvalidScript(e) => e.code(),
+            MempoolError::InvalidPayload(e) => e.code(),
+            MempoolError::WrongChain { .. } => ErrorCode::WrongChain,
+            MempoolError::InvalidSignature(e) => e.code(),
+            MempoolError::Rejected { .. } => ErrorCode::PolicyRejected,
+            MempoolError::Full { .. } => ErrorCode::MempoolFull,
+            MempoolError::DoubleSpend { .. } => ErrorCode::DoubleSpend,
+            MempoolError::NonceTooLow { .. } | MempoolError::NonceTooHigh { .. } => ErrorCode::InvalidNonce,
+            MempoolError::InsufficientFunds { .. } => ErrorCode::InsufficientFunds,
+        }
+    }
+}
+
+impl HasErrorCode for ValidationError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            ValidationError::MalformedTransaction { .. } => ErrorCode::MalformedTransaction,
+            ValidationError::InvalidProofOfWork { .. } | ValidationError::WrongDifficulty { .. } => {
+                ErrorCode::InvalidProofOfWork
+            }
+            ValidationError::UnsupportedPayload { .. } => ErrorCode::InvalidPayload,
+            ValidationError::InvalidTransaction { error, .. } => error.code(),
+            ValidationError::WrongChain { .. } => ErrorCode::WrongChain,
+            ValidationError::DoubleSpend { .. } => ErrorCode::DoubleSpend,
+            ValidationError::InvalidSignature { .. } => ErrorCode::InvalidSignature,
+            ValidationError::EmptyChain
+            | ValidationError::GenesisMismatch
+            | ValidationError::BrokenLink { .. }
+            | ValidationError::StateRootMismatch { .. }
+            | ValidationError::LogsBloomMismatch { .. }
+            | ValidationError::ReceiptsRootMismatch { .. }
+            | ValidationError::MmrRootMismatch { .. }
+            | ValidationError::MerkleRootMismatch { .. }
+            | ValidationError::MisplacedAllocation { .. }
+            | ValidationError::MissingCoinbase { .. }
+            | ValidationError::ExtraCoinbase { .. }
+            | ValidationError::InvalidCoinbase { .. }
+            | ValidationError::InvalidFees { .. }
+            | ValidationError::UnsupportedVersion { .. }
+            | ValidationError::TimestampTooFarInFuture { .. }
+            | ValidationError::TimestampTooOld { .. } => ErrorCode::InvalidBlock,
+        }
+    }
+}
+
+impl HasErrorCode for LightClientError {
+    fn code(&self) -> ErrorCode {
+        match self {
+            LightClientError::UnknownParent => ErrorCode::UnknownParent,
+            LightClientError::InvalidProofOfWork { .. } | LightClientError::WrongDifficulty { .. } => {
+                ErrorCode::InvalidProofOfWork
+            }
+            LightClientError::NotBestChain => ErrorCode::NotBestChain,
+            LightClientError::TransactionNotFound => ErrorCode::UnknownTransaction,
+            LightClientError::UnknownBlock { .. } => ErrorCode::UnknownBlock,
+            LightClientError::BrokenLink { .. }
+            | LightClientError::CheckpointMismatch { .. }
+            | LightClientError::InvalidBlock { .. } => ErrorCode::InvalidBlock,
+            LightClientError::InvalidMerkleProof
+            | LightClientError::InvalidFraudProof
+            | LightClientError::InvalidStateProof
+            | LightClientError::InvalidReceiptProof
+            | LightClientError::InvalidMmrProof => ErrorCode::InvalidProof,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn codes_are_unique_and_round_trip() {
+        let names: HashSet<&str> = ErrorCode::ALL.iter().map(|code| code.as_str()).collect();
+        let numbers: HashSet<u32> = ErrorCode::ALL.iter().map(|code| code.number()).collect();
+        assert_eq!(names.len(), ErrorCode::ALL.len());
+        assert_eq!(numbers.len(), ErrorCode::ALL.len());
+
+        for code in ErrorCode::ALL {
+            assert_eq!(ErrorCode::from_name(code.as_str()), Some(code));
+            assert_eq!(ErrorCode::from_number(code.number()), Some(code));
+        }
+    }
+
+    #[test]
+    fn wrapped_errors_take_the_inner_code() {
+        let error = MempoolError::InvalidPayload(StateError::Script(ScriptError::EvaluatedToFalse));
+        assert_eq!(error.code(), ErrorCode::InvalidSignature);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::json::json_string;
+use crate::blockchain::BlockChain;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+// what the chain tells whoever subscribed, as it happens. the audit log
+// keeps everything for later, this is for a wallet or a dashboard that
+// wants to hear about a block the moment it is added instead of polling
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    // mined here or received from a peer, and now the tip
+    NewBlock { height: u64, hash: Hash32, transactions: usize },
+    // accepted into the pool, or replacing one that was there
+    NewTransaction { txid: Vec<u8> },
+    // the blocks above `fork_height` were replaced by `added` others. a
+    // NewBlock follows for each of those, and a NewTransaction for each
+    // transaction of the removed ones that went back into the pool
+    ChainReorg { fork_height: u64, removed: u64, added: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    NewBlock,
+    NewTransaction,
+    ChainReorg,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 3] = [EventKind::NewBlock, EventKind::NewTransaction, EventKind::ChainReorg];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::NewBlock => "NewBlock",
+            EventKind::NewTransaction => "NewTransaction",
+            EventKind::ChainReorg => "ChainReorg",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<EventKind> {
+        EventKind::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+}
+
+impl ChainEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            ChainEvent::NewBlock { .. } => EventKind::NewBlock,
+            ChainEvent::NewTransaction { .. } => EventKind::NewTransaction,
+            ChainEvent::ChainReorg { .. } => EventKind::ChainReorg,
+        }
+    }
+
+    // {"event":"NewBlock",...}, one websocket message per event
+    pub fn to_json(&self) -> String {
+        let fields: String = match self {
+            ChainEvent::NewBlock { height, hash, transactions } => format!(
+                "\"height\":{},\"hash\":{},\"transactions\":{}",
+                height,
+                json_string(&hash.to_string()),
+                transactions
+            ),
+            ChainEvent::NewTransaction { txid } => format!("\"txid\":{}", json_string(&hex::encode(txid))),
+            ChainEvent::ChainReorg { fork_height, removed, added } => {
+                format!("\"fork_height\":{},\"removed\":{},\"added\":{}", fork_height, removed, added)
+            }
+        };
+        format!("{{\"event\":{},{}}}", json_string(self.kind().as_str()), fields)
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    kinds: Vec<EventKind>,
+    sender: Sender<ChainEvent>,
+}
+
+// one channel per subscriber. sending never blocks the chain: an event
+// waits in the channel until the subscriber reads it, and a subscriber
+// that dropped its receiver is forgotten on the next event
+#[derive(Debug, Default)]
+pub struct EventBus {
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    // the events of `kinds` from now on, none of the ones before
+    pub fn subscribe(&mut self, kinds: &[EventKind]) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel::<ChainEvent>();
+        self.subscribers.push(Subscriber {
+            kinds: kinds.to_vec(),
+            sender,
+        });
+        receiver
+    }
+
+    pub fn publish(&mut self, event: ChainEvent) {
+        let kind: EventKind = event.kind();
+        self.subscribers
+            .retain(|subscriber| !subscriber.kinds.contains(&kind) || subscriber.sender.send(event.clone()).is_ok());
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+}
+
+impl BlockChain {
+    // a channel of the chain's events of `kinds`, EventKind::ALL for every
+    // one. dropping the receiver unsubscribes
+    pub fn subscribe(&mut self, kinds: &[EventKind]) -> Receiver<ChainEvent> {
+        self.events.subscribe(kinds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::transaction::Transaction;
+
+    #[test]
+    fn subscribers_hear_about_blocks_and_transactions_as_they_happen() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        let everything = chain.subscribe(&EventKind::ALL);
+        let blocks = chain.subscribe(&[EventKind::NewBlock]);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+
+        let height: u64 = chain.blocks().len() as u64 - 1;
+        let last = ChainEvent::NewBlock { height, hash: chain.last_block().unwrap().hash(), transactions: 2 };
+        let heard: Vec<ChainEvent> = everything.try_iter().collect();
+        // the coinbase of each block isn't announced, only what was sent
+        assert_eq!(heard.len() as u64, BlockChain::COINBASE_MATURITY + 2);
+        assert_eq!(heard[heard.len() - 2], ChainEvent::NewTransaction { txid: tx.id() });
+        assert_eq!(heard.last(), Some(&last));
+        assert!(blocks.try_iter().all(|event| event.kind() == EventKind::NewBlock));
+        assert!(last.to_json().starts_with(&format!("{{\"event\":\"NewBlock\",\"height\":{},", height)));
+
+        // a dropped receiver is an unsubscribe
+        drop(blocks);
+        chain.mining().unwrap();
+        assert_eq!(chain.events.len(), 1);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::blockchain::difficulty::HASHRATE_WINDOW;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::json::{block_json, json_string, percent_decode, transaction_json};
+use crate::blockchain::template::BlockTemplate;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::{Block, BlockChain};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+// blocks listed on the front page
+const LATEST_BLOCKS: usize = 20;
+
+// the whole explorer is this one page, it routes on the url fragment
+// (#/block/3, #/tx/<txid>, #/address/<address>) and fetches the api below
+const PAGE: &str = include_str!("explorer.html");
+
+pub struct ExplorerResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl ExplorerResponse {
+    fn json(body: String) -> Self {
+        ExplorerResponse {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn not_found(what: &str) -> Self {
+        ExplorerResponse {
+            status: 404,
+            content_type: "application/json",
+            body: format!("{{\"error\":{}}}", json_string(&format!("{} not found", what))),
+        }
+    }
+}
+
+// answers a GET for `path`, everything the explorer serves:
+//
+//     /explorer                               the page
+//     /explorer/api/stats                     height, difficulty and hashrate
+//     /explorer/api/mempool                   the next block and the pool by fee rate
+//     /explorer/api/blocks                    latest blocks, newest first
+//     /explorer/api/blocks/{height}           a block and its transactions
+//     /explorer/api/transactions/{txid}       a mined transaction, txid in hex
+//     /explorer/api/addresses/{address}       balances and transactions
+//     /explorer/api/wallets                   the node's wallets and their balances
+//     /explorer/api/privacy                   what the chain gives away about each wallet
+pub fn route(chain: &BlockChain, path: &str) -> ExplorerResponse {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        [""] | ["explorer"] => ExplorerResponse {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: PAGE.to_string(),
+        },
+        ["explorer", "api", "stats"] => ExplorerResponse::json(stats_json(chain)),
+        ["explorer", "api", "mempool"] => ExplorerResponse::json(mempool_json(chain)),
+        ["explorer", "api", "blocks"] => {
+            let blocks: Vec<String> = chain
+                .blocks()
+                .iter()
+                .enumerate()
+                .rev()
+                .take(LATEST_BLOCKS)
+                .map(|(height, block)| block_summary(height, block))
+                .collect();
+            ExplorerResponse::json(format!("[{}]", blocks.join(",")))
+        }
+        ["explorer", "api", "blocks", height] => match height.parse::<usize>() {
+            Ok(height) if height < chain.blocks().len() => {
+                ExplorerResponse::json(block_json(height, &chain.blocks()[height]))
+            }
+            _ => ExplorerResponse::not_found("block"),
+        },
+        ["explorer", "api", "transactions", txid] => {
+            let Ok(txid) = hex::decode(txid) else {
+                return ExplorerResponse::not_found("transaction");
+            };
+            let found = chain.find_transaction(&txid).and_then(|(block, location)| {
+                let tx = Transaction::decode(&block.transactions[location.index])?;
+                Some((block, location, tx))
+            });
+            match found {
+                Some((block, location, tx)) => {
+                    let height = location.height;
+                    let success = block.receipts.get(location.index).map(|receipt| receipt.success);
+                    ExplorerResponse::json(format!(
+                        "{{\"height\":{},\"block\":{},\"success\":{},\"transaction\":{}}}",
+                        height,
+                        json_string(&block.hash().to_string()),
+                        success.map_or("null".to_string(), |s| s.to_string()),
+                        transaction_json(&tx)
+                    ))
+                }
+                None => ExplorerResponse::not_found("transaction"),
+            }
+        }
+        ["explorer", "api", "wallets"] => ExplorerResponse::json(wallets_json(chain)),
+        ["explorer", "api", "privacy"] => ExplorerResponse::json(privacy_json(chain)),
+        ["explorer", "api", "addresses", address] => {
+            let address: Vec<u8> = percent_decode(address);
+            ExplorerResponse::json(address_json(chain, &address))
+        }
+        _ => ExplorerResponse::not_found("page"),
+    }
+}
+
+// serves the explorer until the process is stopped. the chain is shared
+// with whatever keeps mining, it is only locked while answering
+pub fn serve(chain: &Mutex<BlockChain>, address: &str) -> std::io::Result<()> {
+    let server = tiny_http::Server::http(address).map_err(std::io::Error::other)?;
+    info!(address, "explorer listening");
+
+    for request in server.incoming_requests() {
+        let response = if *request.method() == tiny_http::Method::Get {
+            route(&chain.lock().unwrap(), request.url())
+        } else {
+            ExplorerResponse::not_found("page")
+        };
+        let header = tiny_http::Header::from_bytes("Content-Type", response.content_type)
+            .expect("content types are valid header values");
+        let reply = tiny_http::Response::from_string(response.body)
+            .with_status_code(response.status)
+            .with_header(header);
+        if let Err(e) = request.respond(reply) {
+            warn!(error = %e, "explorer response not sent");
+        }
+    }
+    Ok(())
+}
+
+fn stats_json(chain: &BlockChain) -> String {
+    let history: Vec<String> = chain
+        .difficulty_history(HASHRATE_WINDOW)
+        .iter()
+        .map(|(height, difficulty)| format!("[{},{}]", height, difficulty))
+        .collect();
+    format!(
+        "{{\"height\":{},\"difficulty\":{},\"hashrate\":{:.2},\"pending_transactions\":{},\"difficulty_history\":[{}]}}",
+        chain.blocks().len() - 1,
+        chain.difficulty(),
+        chain.estimated_hashrate(HASHRATE_WINDOW),
+        chain.pending_transactions().len(),
+        history.join(",")
+    )
+}
+
+// what the next block would hold if it was mined now, best paying first,
+// and the whole pool bucketed by fee rate
+fn mempool_json(chain: &BlockChain) -> String {
+    let template = BlockTemplate::assemble(Hash32::ZERO, chain.mempool());
+    let next_block: Vec<String> = template
+        .transactions
+        .iter()
+        .take(LATEST_BLOCKS)
+        .filter_map(|t| Transaction::decode(t))
+        .map(|tx| transaction_json(&tx))
+        .collect();
+    let histogram: Vec<String> = chain
+        .mempool()
+        .fee_histogram()
+        .iter()
+        .map(|bucket| {
+            format!(
+                "{{\"min_fee_rate\":{},\"count\":{},\"weight\":{},\"cumulative_weight\":{}}}",
+                bucket.min_fee_rate, bucket.count, bucket.weight, bucket.cumulative_weight
+            )
+        })
+        .collect();
+    format!(
+        "{{\"transactions\":{},\"size\":{},\"total_fees\":{},\"next_block\":[{}],\"histogram\":[{}]}}",
+        template.transactions.len(),
+        template.transactions.iter().map(|t| t.len()).sum::<usize>(),
+        template.total_fees,
+        next_block.join(","),
+        histogram.join(",")
+    )
+}
+
+fn block_summary(height: usize, block: &Block) -> String {
+    format!(
+        "{{\"height\":{},\"hash\":{},\"time_stamp\":{},\"transactions\":{},\"miner\":{}}}",
+        height,
+        json_string(&block.hash().to_string()),
+        block.header.time_stamp,
+        block.transactions.len(),
+        block.miner().map_or("null".to_string(), |m| json_string(&String::from_utf8_lossy(&m)))
+    )
+}
+
+fn address_json(chain: &BlockChain, address: &[u8]) -> String {
+    let height = chain.blocks().len() as u64;
+    // newest first, like the block list
+    let transactions: Vec<String> = chain
+        .index()
+        .address_history(address)
+        .iter()
+        .rev()
+        .filter_map(|location| {
+            let block = &chain.blocks()[location.height as usize];
+            let tx = Transaction::decode(&block.transactions[location.index])?;
+            Some(format!(
+                "{{\"height\":{},\"transaction\":{}}}",
+                location.height,
+                transaction_json(&tx)
+            ))
+        })
+        .collect();
+
+    format!(
+        "{{\"address\":{},\"balance\":{},\"spendable\":{},\"transactions\":[{}]}}",
+        json_string(&String::from_utf8_lossy(address)),
+        chain.state().balance(address),
+        chain.state().spendable_balance(address, height),
+        transactions.join(",")
+    )
+}
+
+// by name, the page links each address to its history
+fn wallets_json(chain: &BlockChain) -> String {
+    let wallets: Vec<String> = chain
+        .wallets()
+        .iter()
+        .map(|(name, wallet)| {
+            format!(
+                "{{\"name\":{},\"address\":{},\"balance\":{}}}",
+                json_string(name),
+                json_string(&wallet.address()),
+                chain.state().balance(wallet.address().as_bytes())
+            )
+        })
+        .collect();
+    format!("[{}]", wallets.join(","))
+}
+
+fn privacy_json(chain: &BlockChain) -> String {
+    let reports: Vec<String> = chain
+        .privacy_reports()
+        .iter()
+        .map(|(name, report)| {
+            let linked: Vec<String> = report.linked_wallets.iter().map(|w| json_string(w)).collect();
+            format!(
+                "{{\"name\":{},\"address\":{},\"score\":{},\"transactions\":{},\"reused\":{},\"linked_wallets\":[{}],\"round_amounts\":{}}}",
+                json_string(name),
+                json_string(&report.address),
+                report.score,
+                report.transactions,
+                report.reused,
+                linked.join(","),
+                report.round_amounts
+            )
+        })
+        .collect();
+    format!("[{}]", reports.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::wallet::Wallet;
+
+    #[test]
+    fn api_finds_blocks_transactions_and_addresses() {
+        let mut chain = BlockChain::with_difficulty("my miner".into(), 0);
+        chain.mining().unwrap();
+
+        let latest = route(&chain, "/explorer/api/blocks");
+        assert_eq!(latest.status, 200);
+        assert!(latest.body.starts_with("[{\"height\":2,"));
+
+        let block = route(&chain, "/explorer/api/blocks/1");
+        assert!(block.body.contains("\"miner\":\"my miner\""));
+        assert_eq!(route(&chain, "/explorer/api/blocks/3").status, 404);
+
+        let txid = hex::encode(&chain.blocks()[1].txids()[0]);
+        let tx = route(&chain, &format!("/explorer/api/transactions/{}", txid));
+        assert!(tx.body.starts_with("{\"height\":1,"));
+
+        let stats = route(&chain, "/explorer/api/stats");
+        assert!(stats.body.starts_with("{\"height\":2,\"difficulty\":0,"));
+
+        let pool = route(&chain, "/explorer/api/mempool");
+        assert_eq!(pool.body, "{\"transactions\":0,\"size\":0,\"total_fees\":0,\"next_block\":[],\"histogram\":[]}");
+
+        chain.load_wallet("cold", Wallet::from_secret([1u8; 32])).unwrap();
+        let wallets = route(&chain, "/explorer/api/wallets");
+        assert!(wallets.body.starts_with("[{\"name\":\"cold\","));
+        let privacy = route(&chain, "/explorer/api/privacy");
+        assert!(privacy.body.contains("\"score\":100,\"transactions\":0,"));
+
+        let address = route(&chain, "/explorer/api/addresses/my%20miner");
+        assert!(address.body.contains("\"balance\":2"));
+    }
+}
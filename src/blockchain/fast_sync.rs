@@ -0,0 +1,226 @@
+use crate::blockchain::balances::BalanceIndex;
+use crate::blockchain::light::{Checkpoint, FullNode, LightClient, LightClientError};
+use crate::blockchain::mmr::MerkleMountainRange;
+use crate::blockchain::state::State;
+use crate::blockchain::transaction::DEFAULT_CHAIN_ID;
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::{Block, BlockChain, BlockHeader};
+use std::fmt;
+use tracing::info;
+
+// full blocks a fast synced node downloads and executes, the state before
+// them comes from a snapshot
+pub const RECENT_BLOCKS: u64 = 16;
+
+#[derive(Debug, PartialEq)]
+pub enum FastSyncError {
+    Headers(LightClientError),
+    // the headers before the checkpoint don't lead up to it
+    BrokenHistory { height: u64 },
+    // a header doesn't commit to the mountain range of the headers before it
+    MmrMismatch { height: u64 },
+    SnapshotUnavailable { height: u64 },
+    // the snapshot's root isn't the state root its header commits to
+    SnapshotMismatch { height: u64 },
+    // a recent block that isn't the one whose header was verified
+    UnexpectedBlock { height: u64 },
+    InvalidBlock(ValidationError),
+}
+
+impl fmt::Display for FastSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FastSyncError::Headers(e) => write!(f, "{}", e),
+            FastSyncError::BrokenHistory { height } => {
+                write!(f, "header {} does not lead up to the checkpoint", height)
+            }
+            FastSyncError::MmrMismatch { height } => {
+                write!(f, "header {} does not commit to the headers before it", height)
+            }
+            FastSyncError::SnapshotUnavailable { height } => {
+                write!(f, "the node has no state snapshot at height {}", height)
+            }
+            FastSyncError::SnapshotMismatch { height } => {
+                write!(f, "state snapshot doesn't match the state root of block {}", height)
+            }
+            FastSyncError::UnexpectedBlock { height } => {
+                write!(f, "block {} is not the one the headers committed to", height)
+            }
+            FastSyncError::InvalidBlock(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<LightClientError> for FastSyncError {
+    fn from(e: LightClientError) -> Self {
+        FastSyncError::Headers(e)
+    }
+}
+
+impl From<ValidationError> for FastSyncError {
+    fn from(e: ValidationError) -> Self {
+        FastSyncError::InvalidBlock(e)
+    }
+}
+
+// what a fast syncing node needs on top of headers, a remote peer would
+// serve the snapshot in chunks over the network
+pub trait SnapshotSource: FullNode {
+    // the state right after the block at `height`
+    fn state_snapshot(&self, height: u64) -> Option<State>;
+    // full blocks from `from_height` up to the tip
+    fn blocks_from(&self, from_height: u64) -> Vec<Block>;
+}
+
+impl SnapshotSource for BlockChain {
+    fn state_snapshot(&self, height: u64) -> Option<State> {
+        self.state_at(height)
+    }
+
+    fn blocks_from(&self, from_height: u64) -> Vec<Block> {
+        self.get_block_range(from_height as usize..self.chain.len()).to_vec()
+    }
+}
+
+impl BlockChain {
+    // joins the network without replaying it from genesis:
+    //
+    // 1. headers from the checkpoint to the tip are checked like a light
+    //    client does (links and proof of work), the ones before it only have
+    //    to link up to the checkpoint, which is trusted
+    // 2. every header has to commit to the mountain range of the ones before
+    //    it, the same commitment mined blocks are checked against
+    // 3. the state a few blocks below the tip is taken from the node, and
+    //    only accepted if its root is the one that block's header commits to
+    // 4. the blocks after it are downloaded and executed in full
+    //
+    // older blocks are kept as headers only, so the chain can't be validated
+    // or reindexed from genesis, only extended
+    pub fn fast_sync(
+        address: String,
+        checkpoint: Checkpoint,
+        node: &impl SnapshotSource,
+    ) -> Result<BlockChain, FastSyncError> {
+        let mut light: LightClient = LightClient::from_checkpoint(checkpoint.clone(), node)?;
+        light.sync(node)?;
+
+        let mut headers: Vec<BlockHeader> = node
+            .headers_from(0)
+            .into_iter()
+            .take(checkpoint.height as usize)
+            .collect();
+        if headers.len() as u64 != checkpoint.height {
+            return Err(FastSyncError::BrokenHistory { height: headers.len() as u64 });
+        }
+        headers.extend(light.headers().iter().cloned());
+        for (height, pair) in headers.windows(2).enumerate().take(checkpoint.height as usize) {
+            if pair[1].previous_hash != pair[0].hash() {
+                return Err(FastSyncError::BrokenHistory { height: height as u64 });
+            }
+        }
+
+        let mut header_mmr: MerkleMountainRange = MerkleMountainRange::new();
+        for (height, header) in headers.iter().enumerate() {
+            if header.mmr_root != header_mmr.root() {
+                return Err(FastSyncError::MmrMismatch { height: height as u64 });
+            }
+            header_mmr.push(&header.hash());
+        }
+
+        let snapshot_height: u64 = light.height().saturating_sub(RECENT_BLOCKS);
+        let state: State = node
+            .state_snapshot(snapshot_height)
+            .ok_or(FastSyncError::SnapshotUnavailable { height: snapshot_height })?;
+        if state.root() != headers[snapshot_height as usize].state_root {
+            return Err(FastSyncError::SnapshotMismatch { height: snapshot_height });
+        }
+
+        let mut chain = BlockChain::empty(address, BlockChain::DIFFICULTY, DEFAULT_CHAIN_ID);
+        chain.balances = BalanceIndex::from_state(&state, snapshot_height);
+        chain.state = state;
+        for header in headers.iter().take(snapshot_height as usize + 1) {
+            let block: Block = Block::from_header(header.clone());
+            chain.header_mmr.push(&block.hash());
+            chain.chain.push(block);
+        }
+        chain.confirmations.update(&chain.chain);
+
+        for block in node.blocks_from(snapshot_height + 1) {
+            let height = chain.chain.len() as u64;
+            if let Some(header) = headers.get(height as usize)
+                && header.hash() != block.compute_hash()
+            {
+                return Err(FastSyncError::UnexpectedBlock { height });
+            }
+            chain.apply_block(block)?;
+        }
+
+        info!(
+            snapshot_height,
+            height = chain.chain.len() - 1,
+            "fast synced from a state snapshot"
+        );
+        Ok(chain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::merkle::MerkleProof;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::transaction::Transaction;
+
+    // serves a snapshot from one block earlier than asked
+    struct StaleSnapshots<'a>(&'a BlockChain);
+
+    impl FullNode for StaleSnapshots<'_> {
+        fn headers_from(&self, from_height: u64) -> Vec<BlockHeader> {
+            self.0.headers_from(from_height)
+        }
+
+        fn transaction_proof(&self, txid: &[u8]) -> Option<(u64, MerkleProof)> {
+            self.0.transaction_proof(txid)
+        }
+    }
+
+    impl SnapshotSource for StaleSnapshots<'_> {
+        fn state_snapshot(&self, height: u64) -> Option<State> {
+            self.0.state_at(height - 1)
+        }
+
+        fn blocks_from(&self, from_height: u64) -> Vec<Block> {
+            self.0.blocks_from(from_height)
+        }
+    }
+
+    #[test]
+    fn a_fast_synced_node_ends_up_on_the_same_tip() {
+        let wallet = miner();
+        let mut node = BlockChain::new(wallet.address().into());
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            node.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), test_wallet("B").address().into_bytes(), 1).sign(&wallet);
+        node.add_transaction(&tx).unwrap();
+        for _ in 0..RECENT_BLOCKS + 4 {
+            node.mining().unwrap();
+        }
+        let checkpoint = Checkpoint { height: 5, hash: node.blocks()[5].hash() };
+
+        let mut synced = BlockChain::fast_sync("new node".into(), checkpoint.clone(), &node).unwrap();
+        assert_eq!(synced.last_block().unwrap().hash(), node.last_block().unwrap().hash());
+        assert_eq!(synced.state().root(), node.state().root());
+        // before the snapshot only the headers were downloaded
+        assert!(synced.blocks()[5].transactions.is_empty());
+        assert!(synced.find_transaction(&tx.id()).is_none());
+        synced.mining().unwrap();
+        assert_eq!(synced.blocks().len(), node.blocks().len() + 1);
+
+        let snapshot_height = node.blocks().len() as u64 - 1 - RECENT_BLOCKS;
+        assert_eq!(
+            BlockChain::fast_sync("new node".into(), checkpoint, &StaleSnapshots(&node)).unwrap_err(),
+            FastSyncError::SnapshotMismatch { height: snapshot_height }
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::{Block, BlockChain};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+// golomb-rice parameters from bip158: remainders are P bits and the false
+// positive rate is about 1 in M
+const P: u8 = 19;
+const M: u64 = 784_931;
+
+// the block hash keys the item hashes, so a match in one block says
+// nothing about another
+fn hash_to_range(block_hash: &[u8], item: &[u8], range: u64) -> u64 {
+    let mut hasher = Sha256::new();
+    hasher.update(block_hash);
+    hasher.update(item);
+    let hash = hasher.finalize();
+    let value = u64::from_be_bytes(hash[..8].try_into().unwrap());
+    ((value as u128 * range as u128) >> 64) as u64
+}
+
+fn hashed_set(block_hash: &[u8], items: &[Vec<u8>]) -> Vec<u64> {
+    let range = items.len() as u64 * M;
+    let mut values: Vec<u64> = items
+        .iter()
+        .map(|item| hash_to_range(block_hash, item, range))
+        .collect();
+    values.sort();
+    values.dedup();
+    values
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bits: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.bits / 8)?;
+        let bit = byte & (0x80 >> (self.bits % 8)) != 0;
+        self.bits += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value: u64 = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+}
+
+// a golomb coded set of the addresses a block touches. a wallet checks
+// its own addresses against it locally and only fetches blocks that
+// match, so the node never learns which addresses it cares about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockFilter {
+    // number of items in the set
+    pub n: u64,
+    pub data: Vec<u8>,
+}
+
+impl BlockFilter {
+    pub fn build(block_hash: &[u8], items: &[Vec<u8>]) -> Self {
+        let values = hashed_set(block_hash, items);
+        let mut writer = BitWriter {
+            bytes: Vec::new(),
+            bits: 0,
+        };
+
+        // sorted values are coded as their differences: the quotient in
+        // unary, the remainder in P bits
+        let mut last: u64 = 0;
+        for value in values.iter() {
+            let delta = value - last;
+            for _ in 0..(delta >> P) {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta & ((1 << P) - 1), P);
+            last = *value;
+        }
+
+        BlockFilter {
+            n: values.len() as u64,
+            data: writer.bytes,
+        }
+    }
+
+    fn decode(&self) -> Vec<u64> {
+        let mut reader = BitReader {
+            bytes: &self.data,
+            bits: 0,
+        };
+        let mut values = Vec::<u64>::new();
+        let mut last: u64 = 0;
+
+        for _ in 0..self.n {
+            let mut quotient: u64 = 0;
+            while let Some(true) = reader.read_bit() {
+                quotient += 1;
+            }
+            // a filter from a peer can be garbage, it just stops matching
+            let Some(remainder) = reader.read_bits(P) else {
+                break;
+            };
+            let Some(value) = quotient
+                .checked_mul(1 << P)
+                .and_then(|delta| last.checked_add(delta | remainder))
+            else {
+                break;
+            };
+            last = value;
+            values.push(last);
+        }
+        values
+    }
+
+    // true if any of `items` may be in the block, false positives happen
+    // about once every M queried items
+    pub fn matches_any(&self, block_hash: &[u8], items: &[Vec<u8>]) -> bool {
+        if self.n == 0 || items.is_empty() {
+            return false;
+        }
+
+        let range = self.n.saturating_mul(M);
+        let set = self.decode();
+        items
+            .iter()
+            .any(|item| set.binary_search(&hash_to_range(block_hash, item, range)).is_ok())
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.n.to_be_bytes());
+        hasher.update(&self.data);
+        hasher.finalize().to_vec()
+    }
+}
+
+// every sender and recipient in the block, the log emitting contracts too
+pub fn filter_items(block: &Block) -> Vec<Vec<u8>> {
+    let mut items = Vec::<Vec<u8>>::new();
+    for tx in block.transactions.iter().filter_map(|t| Transaction::decode(t)) {
+        items.push(tx.sender_address);
+        items.push(tx.recipient_address);
+    }
+    for receipt in block.receipts.iter() {
+        items.extend(receipt.logs.iter().map(|log| log.address.clone()));
+    }
+    items
+}
+
+// the filter headers worked out so far, each with the hash of its block.
+// a header is only extended from the one before, so asking for each
+// height in turn hashes every filter once instead of the whole chain every
+// time. behind a lock as Metrics is, so answering a peer stays `&self`
+#[derive(Debug, Default)]
+pub struct FilterHeaders {
+    chain: Mutex<Vec<(Hash32, Vec<u8>)>>,
+}
+
+impl FilterHeaders {
+    pub fn new() -> Self {
+        FilterHeaders::default()
+    }
+
+    fn header(&self, blocks: &[Block], height: u64) -> Option<Vec<u8>> {
+        if height as usize >= blocks.len() {
+            return None;
+        }
+        let mut headers = self.chain.lock().unwrap();
+
+        // a block hash commits to every block before it, so the headers
+        // are good up to the last one whose block is still in the chain.
+        // after a reorg the ones past the fork go
+        while let Some((hash, _)) = headers.last() {
+            match blocks.get(headers.len() - 1) {
+                Some(block) if block.hash() == *hash => break,
+                _ => headers.pop(),
+            };
+        }
+
+        while headers.len() <= height as usize {
+            let block: &Block = &blocks[headers.len()];
+            let previous: Vec<u8> = headers.last().map(|(_, header)| header.clone()).unwrap_or(vec![0u8; 32]);
+            let mut hasher = Sha256::new();
+            hasher.update(BlockFilter::build(&block.hash(), &filter_items(block)).hash());
+            hasher.update(&previous);
+            headers.push((block.hash(), hasher.finalize().to_vec()));
+        }
+        Some(headers[height as usize].1.clone())
+    }
+}
+
+impl BlockChain {
+    pub fn block_filter(&self, height: u64) -> Option<BlockFilter> {
+        let block = self.chain.get(height as usize)?;
+        Some(BlockFilter::build(&block.hash(), &filter_items(block)))
+    }
+
+    // like bip157, each filter header chains the filter to the previous
+    // header. a client asking several nodes compares these to spot one
+    // serving a wrong filter.
+    pub fn filter_header(&self, height: u64) -> Option<Vec<u8>> {
+        self.filter_headers.header(&self.chain, height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(prefix: &str, count: usize) -> Vec<Vec<u8>> {
+        (0..count).map(|i| format!("{}{}", prefix, i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn a_filter_matches_what_is_in_it_and_rarely_anything_else() {
+        let block_hash: [u8; 32] = [7; 32];
+        let items: Vec<Vec<u8>> = addresses("in", 50);
+        let filter = BlockFilter::build(&block_hash, &items);
+        assert_eq!(filter.n, 50);
+        assert!(items.iter().all(|item| filter.matches_any(&block_hash, std::slice::from_ref(item))));
+
+        // about one in M, ten thousand strangers would match once in some
+        // eighty sets. the hashes are fixed, the count doesn't change
+        let strangers: usize = addresses("out", 10_000)
+            .iter()
+            .filter(|item| filter.matches_any(&block_hash, std::slice::from_ref(item)))
+            .count();
+        assert!(strangers <= 2, "{} false positives", strangers);
+        // the same items under another block's hash are other values
+        assert!(!filter.matches_any(&[8; 32], &items[..1]));
+        assert!(!BlockFilter::build(&block_hash, &[]).matches_any(&block_hash, &items));
+    }
+
+    #[test]
+    fn filter_headers_chain_every_filter_and_follow_a_reorg() {
+        let from_genesis = |chain: &BlockChain, height: u64| -> Vec<u8> {
+            (0..=height).fold(vec![0u8; 32], |header, h| {
+                let mut hasher = Sha256::new();
+                hasher.update(chain.block_filter(h).unwrap().hash());
+                hasher.update(&header);
+                hasher.finalize().to_vec()
+            })
+        };
+        let mut ours = BlockChain::with_difficulty("ours".into(), 0);
+        let mut theirs = BlockChain::with_difficulty("theirs".into(), 0);
+        for _ in 0..3 {
+            ours.mining().unwrap();
+            theirs.mining().unwrap();
+        }
+        let tip: u64 = ours.blocks().len() as u64 - 1;
+        assert!(ours.block_filter(tip).unwrap().matches_any(&ours.blocks()[tip as usize].hash(), &[b"ours".to_vec()]));
+        for height in 0..=tip {
+            assert_eq!(ours.filter_header(height), Some(from_genesis(&ours, height)));
+        }
+        assert_eq!(ours.filter_header(tip + 1), None);
+
+        // theirs pays another miner, the blocks differ after genesis
+        let before: Option<Vec<u8>> = ours.filter_header(1);
+        ours.chain = theirs.chain.clone();
+        assert_eq!(ours.filter_header(tip), Some(from_genesis(&theirs, tip)));
+        assert_ne!(ours.filter_header(1), before);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::blockchain::genesis;
+use crate::blockchain::merkle::{self, MerkleProof};
+use crate::blockchain::state::State;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::{BlockChain, BlockHeader};
+
+// evidence that a block is invalid, small enough to relay to light
+// clients. only rules that can be re-checked from the proof alone have
+// one, breaking a rule that needs the state (balances, names, ...) shows
+// up as a wrong state root that only a full replay catches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FraudProof {
+    // the header alone, against the difficulty the retarget rule gives
+    // it. whoever checks the proof works that out from the headers before
+    InvalidProofOfWork { header: BlockHeader },
+    // a transaction breaking a stateless rule and the proof that the
+    // block really contains it
+    InvalidTransaction {
+        header: BlockHeader,
+        transaction: Vec<u8>,
+        proof: MerkleProof,
+    },
+}
+
+impl FraudProof {
+    pub fn header(&self) -> &BlockHeader {
+        match self {
+            FraudProof::InvalidProofOfWork { header } => header,
+            FraudProof::InvalidTransaction { header, .. } => header,
+        }
+    }
+
+    // true if the proof really shows the block is invalid on the chain
+    // `chain_id`, where the retarget rule gives the block
+    // `expected_difficulty` (see `BlockChain::expected_difficulty`)
+    pub fn verify(&self, expected_difficulty: usize, chain_id: u64) -> bool {
+        match self {
+            FraudProof::InvalidProofOfWork { header } => breaks_proof_of_work(header, expected_difficulty),
+            FraudProof::InvalidTransaction {
+                header,
+                transaction,
+                proof,
+            } => {
+                let txid = merkle::txid(transaction);
+                merkle::verify_merkle_proof(&header.merkle_root, proof, &txid)
+                    && breaks_stateless_rules(transaction, chain_id)
+            }
+        }
+    }
+}
+
+// the same check prepare_block makes: the header claims the difficulty
+// it should have and its hash meets it
+fn breaks_proof_of_work(header: &BlockHeader, expected_difficulty: usize) -> bool {
+    header.difficulty != expected_difficulty || !BlockChain::meets_target(&header.hash(), expected_difficulty)
+}
+
+// what a mined block's transaction has to be on its own: decodable,
+// supported, for this chain and signed by its sender. only the coinbase
+// goes unsigned, genesis allocations have no place outside the genesis
+// block, which is trusted rather than proven
+fn breaks_stateless_rules(transaction: &[u8], chain_id: u64) -> bool {
+    let Some(tx) = Transaction::decode(transaction) else {
+        return true;
+    };
+    if State::check_stateless(&tx).is_err() || tx.chain_id != chain_id || genesis::is_allocation(&tx) {
+        return true;
+    }
+    tx.sender_address != BlockChain::MINING_SENDER.as_bytes() && tx.verify().is_err()
+}
+
+impl BlockChain {
+    // proof that the block at `index` is invalid, if it breaks a rule that
+    // has one. the genesis block isn't mined
+    pub fn fraud_proof(&self, index: usize) -> Option<FraudProof> {
+        if index == 0 {
+            return None;
+        }
+        let block = self.chain.get(index)?;
+        let header = block.header();
+
+        if breaks_proof_of_work(&header, self.expected_difficulty(index as u64)) {
+            return Some(FraudProof::InvalidProofOfWork { header });
+        }
+
+        let transaction = block
+            .transactions
+            .iter()
+            .find(|tx| breaks_stateless_rules(tx, self.chain_id))?;
+        let proof = block.merkle_proof(&merkle::txid(transaction))?;
+
+        Some(FraudProof::InvalidTransaction {
+            header,
+            transaction: transaction.clone(),
+            proof,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::light::LightClient;
+    use crate::blockchain::test_utils::test_wallet;
+    use crate::blockchain::Serialization;
+
+    #[test]
+    fn proofs_hold_the_block_to_the_chain_id_signatures_and_retarget() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        let tip: usize = chain.blocks().len() - 1;
+        assert_eq!(chain.fraud_proof(tip), None);
+        assert_eq!(chain.fraud_proof(0), None);
+        let id: u64 = chain.chain_id();
+
+        // signed for another chain, a valid transaction there
+        let wallet = test_wallet("A");
+        let foreign = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 0)
+            .with_chain_id(id + 1)
+            .sign(&wallet);
+        chain.chain[tip].transactions.push(foreign.serialization());
+        chain.chain[tip].seal_transactions();
+        let proof: FraudProof = chain.fraud_proof(tip).unwrap();
+        assert!(proof.verify(0, id));
+        assert!(!proof.verify(0, id + 1));
+
+        // the value changed after it was signed
+        let mut forged: Transaction = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 0).sign(&wallet);
+        forged.value = 1000;
+        chain.chain[tip].transactions[1] = forged.serialization();
+        chain.chain[tip].seal_transactions();
+        let proof: FraudProof = chain.fraud_proof(tip).unwrap();
+        assert!(proof.verify(0, id));
+        let mut client = LightClient::new(chain.blocks()[0].header()).with_rules(0, None);
+        client.sync(&chain).unwrap();
+        assert_eq!(client.process_fraud_proof(&proof), Ok(tip as u64 - 1));
+
+        // a header claiming more work than the retarget rule gives it
+        chain.chain[tip].transactions.pop();
+        chain.chain[tip].seal_transactions();
+        chain.chain[tip].header.difficulty = 1;
+        let proof: FraudProof = chain.fraud_proof(tip).unwrap();
+        assert!(matches!(proof, FraudProof::InvalidProofOfWork { .. }));
+        assert!(proof.verify(0, id));
+    }
+}
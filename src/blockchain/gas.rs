@@ -0,0 +1,83 @@
+use std::fmt;
+
+// what one unit of work costs, scripts are charged per opcode and wasm
+// contracts per unit of wasmi fuel (roughly one per instruction)
+pub const SCRIPT_OP_GAS: u64 = 3;
+pub const SCRIPT_HASH_GAS: u64 = 60;
+pub const SCRIPT_CHECKSIG_GAS: u64 = 3_000;
+pub const CONTRACT_CALL_GAS: u64 = 700;
+pub const DEPLOY_GAS_PER_BYTE: u64 = 200;
+pub const STORAGE_GAS_PER_BYTE: u64 = 100;
+pub const LOG_GAS: u64 = 375;
+pub const LOG_TOPIC_GAS: u64 = 375;
+pub const LOG_DATA_GAS_PER_BYTE: u64 = 8;
+
+// precompiles, the per word part is charged per 32 bytes of input
+pub const SHA256_GAS: u64 = 60;
+pub const SHA256_WORD_GAS: u64 = 12;
+pub const ED25519_VERIFY_GAS: u64 = 3_000;
+pub const ED25519_VERIFY_WORD_GAS: u64 = 12;
+
+// no single transaction can ask for more, it bounds the time any node
+// spends executing one
+pub const MAX_TX_GAS: u64 = 10_000_000;
+
+#[derive(Debug, PartialEq)]
+pub struct OutOfGas;
+
+impl fmt::Display for OutOfGas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "out of gas")
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GasMeter {
+    limit: u64,
+    used: u64,
+}
+
+impl GasMeter {
+    pub fn new(limit: u64) -> Self {
+        GasMeter { limit, used: 0 }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit - self.used
+    }
+
+    // running out leaves the meter exhausted, out of gas burns the whole limit
+    pub fn charge(&mut self, amount: u64) -> Result<(), OutOfGas> {
+        if amount > self.remaining() {
+            self.used = self.limit;
+            return Err(OutOfGas);
+        }
+        self.used += amount;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_out_burns_the_whole_limit() {
+        let mut meter = GasMeter::new(100);
+        meter.charge(60).unwrap();
+        meter.charge(40).unwrap();
+        assert_eq!((meter.used(), meter.remaining()), (100, 0));
+        assert_eq!(meter.charge(0), Ok(()));
+
+        let mut meter = GasMeter::new(100);
+        meter.charge(30).unwrap();
+        assert_eq!(meter.charge(71), Err(OutOfGas));
+        // nothing left for whatever was to run after
+        assert_eq!((meter.used(), meter.remaining()), (100, 0));
+        assert_eq!(meter.charge(1), Err(OutOfGas));
+    }
+}
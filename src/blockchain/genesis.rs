@@ -0,0 +1,133 @@
+use crate::blockchain::transaction::{Transaction, DEFAULT_CHAIN_ID};
+use crate::blockchain::BlockChain;
+
+// the sender of the genesis allocations. like the coinbase sender nobody
+// holds its key, its balance goes as far below zero as the coins it paid
+// out
+pub const GENESIS_SENDER: &str = "THE GENESIS";
+
+// when the default network's genesis block was made, 2024-01-01 in
+// nanoseconds like every block time stamp
+pub const GENESIS_TIME_STAMP: u128 = 1_704_067_200_000_000_000;
+
+// everything the genesis block is made of. it isn't mined, every node
+// builds it from this, so two nodes with the same config start from the
+// same genesis hash and can sync. a test network also starts with its
+// accounts funded, they can send straight away instead of mining and
+// waiting for the rewards to mature
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenesisConfig {
+    pub time_stamp: u128,
+    pub chain_id: u64,
+    // what the first mined block has to meet
+    pub difficulty: usize,
+    // accounts and the coins each one starts with, paid in this order
+    pub allocations: Vec<(Vec<u8>, u64)>,
+}
+
+// the default network's
+impl Default for GenesisConfig {
+    fn default() -> Self {
+        GenesisConfig {
+            time_stamp: GENESIS_TIME_STAMP,
+            chain_id: DEFAULT_CHAIN_ID,
+            difficulty: BlockChain::DIFFICULTY,
+            allocations: Vec::new(),
+        }
+    }
+}
+
+impl GenesisConfig {
+    pub fn new() -> Self {
+        GenesisConfig::default()
+    }
+
+    pub fn with_time_stamp(mut self, time_stamp: u128) -> Self {
+        self.time_stamp = time_stamp;
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn with_difficulty(mut self, difficulty: usize) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    pub fn with_allocation(mut self, account: impl Into<Vec<u8>>, amount: u64) -> Self {
+        self.allocations.push((account.into(), amount));
+        self
+    }
+
+    // the genesis block's transactions. each one has its own nonce, so two
+    // allocations of the same amount to the same account still have
+    // different txids
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.allocations
+            .iter()
+            .enumerate()
+            .map(|(nonce, (account, amount))| {
+                Transaction::new(GENESIS_SENDER.into(), account.clone(), *amount)
+                    .with_nonce(nonce as u64)
+                    .with_chain_id(self.chain_id)
+            })
+            .collect()
+    }
+}
+
+// whether `tx` pays out genesis coins. it isn't signed, so it is only
+// valid in the genesis block, which every node has to agree on anyway
+pub fn is_allocation(tx: &Transaction) -> bool {
+    tx.sender_address == GENESIS_SENDER.as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+    use crate::blockchain::validation::ValidationError;
+    use crate::blockchain::{Block, Serialization};
+
+    #[test]
+    fn allocated_coins_can_be_spent_right_away() {
+        let (alice, bob) = (test_wallet("alice"), test_wallet("bob"));
+        let config = GenesisConfig::new()
+            .with_allocation(alice.address(), 100)
+            .with_allocation(bob.address(), 50);
+        let mut chain = BlockChain::with_genesis("miner".into(), &config.clone().with_difficulty(0));
+        assert_eq!(chain.calculate_total_amount(&alice.address(), 1), 100);
+        assert_eq!(chain.state().supply().issued, 150 + 1);
+
+        let tx = Transaction::new(alice.address().into_bytes(), bob.address().into_bytes(), 30).sign(&alice);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+        assert_eq!(chain.calculate_total_amount(&bob.address(), 1), 80);
+        chain.validate_chain().unwrap();
+
+        // an allocation anywhere else would print money
+        let mut block = Block::new(0, chain.last_block().unwrap().hash());
+        block.header.difficulty = chain.difficulty();
+        block.transactions = vec![config.transactions()[0].serialization()];
+        block.seal_transactions();
+        assert_eq!(chain.validate_block(&block), Err(ValidationError::MisplacedAllocation { index: 3 }));
+    }
+
+    #[test]
+    fn nodes_built_from_one_config_share_a_genesis_and_sync() {
+        let config = GenesisConfig::new().with_difficulty(0).with_allocation(test_wallet("alice").address(), 10);
+        let mut node: BlockChain = BlockChain::from_genesis(&config);
+        assert_eq!(node.blocks(), BlockChain::from_genesis(&config).blocks());
+        assert_eq!(node.blocks()[0].header.time_stamp, GENESIS_TIME_STAMP);
+        let later: GenesisConfig = config.clone().with_time_stamp(GENESIS_TIME_STAMP + 1);
+        assert_ne!(BlockChain::from_genesis(&later).blocks()[0].hash(), node.blocks()[0].hash());
+
+        // a node that only has the genesis block takes the miner's chain
+        let mut miner = BlockChain::with_genesis("miner".into(), &config);
+        miner.mining().unwrap();
+        assert_eq!(node.replace_chain(miner.blocks().to_vec()), Ok(true));
+        assert_eq!(node.state().root(), miner.state().root());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::blockchain::BlockChain;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// blocks a proposal stays open for votes after it was mined
+pub const VOTING_PERIOD: u64 = 100;
+// the most a block can be voted to pay its miner. far above anything
+// sensible, and a balance takes trillions of blocks of it to overflow, a
+// reward that overflowed it would leave no block able to pay its coinbase
+pub const MAX_MINING_REWARD: u64 = 1_000_000;
+
+// chain parameters that passed proposals are allowed to change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Parameter {
+    MiningReward,
+    FeeBurnPercent,
+}
+
+impl Parameter {
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Parameter::MiningReward => 0,
+            Parameter::FeeBurnPercent => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Parameter> {
+        match byte {
+            0 => Some(Parameter::MiningReward),
+            1 => Some(Parameter::FeeBurnPercent),
+            _ => None,
+        }
+    }
+
+    // the largest value a proposal can set
+    pub fn max(self) -> u64 {
+        match self {
+            Parameter::MiningReward => MAX_MINING_REWARD,
+            Parameter::FeeBurnPercent => 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChainParameters {
+    pub mining_reward: u64,
+    // share of every transaction fee destroyed instead of paid to the
+    // miner, 0 to 100. the base fee burn of EIP-1559, without the base fee
+    pub fee_burn_percent: u64,
+}
+
+impl Default for ChainParameters {
+    fn default() -> Self {
+        ChainParameters {
+            mining_reward: BlockChain::MINING_REWARD,
+            fee_burn_percent: 0,
+        }
+    }
+}
+
+impl ChainParameters {
+    // proposals above the maximum never get in, this holds whatever else
+    // sets a parameter to it
+    pub fn set(&mut self, parameter: Parameter, value: u64) {
+        let value: u64 = value.min(parameter.max());
+        match parameter {
+            Parameter::MiningReward => self.mining_reward = value,
+            Parameter::FeeBurnPercent => self.fee_burn_percent = value,
+        }
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.mining_reward.to_be_bytes());
+        hasher.update(self.fee_burn_percent.to_be_bytes());
+        hasher.finalize().to_vec()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProposalStatus {
+    Open,
+    Passed,
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proposal {
+    pub proposer: Vec<u8>,
+    pub parameter: Parameter,
+    pub value: u64,
+    // a vote weighs the voter's balance after the block at this height, the
+    // last one before the proposal. the state keeps the balances of the
+    // voting period for that, see `State::voting_weight`
+    pub snapshot_height: u64,
+    // first height at which votes are no longer accepted, the proposal
+    // is tallied when the block at this height is applied
+    pub voting_ends_at: u64,
+    // true for yes, a later vote from the same address replaces the earlier
+    pub votes: BTreeMap<Vec<u8>, bool>,
+    pub status: ProposalStatus,
+}
+
+impl Proposal {
+    pub fn is_open(&self, height: u64) -> bool {
+        self.status == ProposalStatus::Open && height < self.voting_ends_at
+    }
+
+    // (yes, no) weight of the votes cast so far, `weight` of each voter
+    pub fn tally(&self, weight: impl Fn(&[u8]) -> u64) -> (u64, u64) {
+        let mut yes: u64 = 0;
+        let mut no: u64 = 0;
+        for (voter, approve) in self.votes.iter() {
+            let weight: u64 = weight(voter);
+            if *approve {
+                yes = yes.saturating_add(weight);
+            } else {
+                no = no.saturating_add(weight);
+            }
+        }
+        (yes, no)
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update((self.proposer.len() as u64).to_be_bytes());
+        hasher.update(&self.proposer);
+        hasher.update([self.parameter.to_byte()]);
+        hasher.update(self.value.to_be_bytes());
+        hasher.update(self.snapshot_height.to_be_bytes());
+        hasher.update(self.voting_ends_at.to_be_bytes());
+        for (voter, approve) in self.votes.iter() {
+            hasher.update((voter.len() as u64).to_be_bytes());
+            hasher.update(voter);
+            hasher.update([*approve as u8]);
+        }
+        hasher.update([self.status as u8]);
+        hasher.finalize().to_vec()
+    }
+}
+
+pub fn proposal_id(proposer: &[u8], nonce: u64) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"proposal");
+    hasher.update(proposer);
+    hasher.update(nonce.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::genesis::GENESIS_SENDER;
+    use crate::blockchain::state::{State, StateError};
+    use crate::blockchain::transaction::{Payload, Transaction};
+
+    fn send(sender: &str, nonce: u64, payload: Payload) -> Transaction {
+        Transaction::new(sender.into(), sender.into(), 0).with_nonce(nonce).with_payload(payload)
+    }
+
+    #[test]
+    fn votes_weigh_what_the_voter_held_when_the_proposal_was_made() {
+        let mut state = State::new();
+        for (address, amount) in [("A", 60), ("B", 30), ("C", 10)] {
+            state.apply_transaction(&Transaction::new(GENESIS_SENDER.into(), address.into(), amount), 0).unwrap();
+        }
+        state.end_block(0, None).unwrap();
+
+        let propose = |value: u64| Payload::Propose { parameter: Parameter::MiningReward, value };
+        state.apply_transaction(&send("A", 0, propose(5)), 1).unwrap();
+        state.apply_transaction(&send("A", 1, propose(7)), 1).unwrap();
+        let (five, seven): (Vec<u8>, Vec<u8>) = (proposal_id(b"A", 0), proposal_id(b"A", 1));
+        // coins received after the snapshot don't buy a vote
+        state.apply_transaction(&Transaction::new(GENESIS_SENDER.into(), "D".into(), 1000), 1).unwrap();
+        state.end_block(1, None).unwrap();
+
+        let vote = |proposal: &[u8], approve: bool| Payload::Vote { proposal_id: proposal.to_vec(), approve };
+        assert_eq!(state.apply_transaction(&send("D", 0, vote(&five, true)), 2), Err(StateError::NoVotingPower));
+        state.apply_transaction(&send("A", 2, vote(&five, false)), 2).unwrap();
+        state.apply_transaction(&send("B", 0, vote(&five, true)), 2).unwrap();
+        state.apply_transaction(&send("C", 0, vote(&five, true)), 2).unwrap();
+        assert_eq!(state.tally(&five), Some((40, 60)));
+        // a second vote replaces the first
+        state.apply_transaction(&send("A", 3, vote(&seven, false)), 2).unwrap();
+        state.apply_transaction(&send("A", 4, vote(&seven, true)), 2).unwrap();
+        state.apply_transaction(&send("B", 1, vote(&seven, false)), 2).unwrap();
+        assert_eq!(state.tally(&seven), Some((60, 30)));
+        state.end_block(2, None).unwrap();
+
+        let ends: u64 = 1 + VOTING_PERIOD;
+        assert_eq!(state.apply_transaction(&send("C", 1, vote(&seven, false)), ends), Err(StateError::VotingClosed));
+        state.end_block(ends, None).unwrap();
+        assert_eq!(state.proposal(&five).unwrap().status, ProposalStatus::Rejected);
+        assert_eq!(state.proposal(&seven).unwrap().status, ProposalStatus::Passed);
+        assert_eq!(state.parameters().mining_reward, 7);
+    }
+
+    #[test]
+    fn parameters_stay_within_their_bounds() {
+        let mut state = State::new();
+        let propose = |parameter: Parameter, value: u64| Payload::Propose { parameter, value };
+        // a reward no balance can hold would leave every coinbase failing
+        assert_eq!(
+            state.apply_transaction(&send("A", 0, propose(Parameter::MiningReward, u64::MAX)), 1),
+            Err(StateError::ParameterOutOfRange { parameter: Parameter::MiningReward, value: u64::MAX })
+        );
+        assert!(state.apply_transaction(&send("A", 0, propose(Parameter::FeeBurnPercent, 101)), 1).is_err());
+        state.apply_transaction(&send("A", 0, propose(Parameter::MiningReward, MAX_MINING_REWARD)), 1).unwrap();
+
+        let mut parameters = ChainParameters::default();
+        parameters.set(Parameter::MiningReward, u64::MAX);
+        parameters.set(Parameter::FeeBurnPercent, 250);
+        assert_eq!((parameters.mining_reward, parameters.fee_burn_percent), (MAX_MINING_REWARD, 100));
+    }
+}
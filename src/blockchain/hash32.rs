@@ -0,0 +1,150 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+// a sha256 digest. block hashes and the roots a header commits to used to
+// be a `Vec<u8>`, which let a one-byte "hash" like the genesis block's old
+// previous_hash through and allocated on every lookup. this is always 32
+// bytes and lives on the stack
+#[derive(Clone, Copy, Default)]
+pub struct Hash32([u8; 32]);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hash32Error {
+    // the hex or the bytes weren't 32 bytes long
+    WrongLength(usize),
+    NotHex,
+}
+
+impl fmt::Display for Hash32Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hash32Error::WrongLength(len) => write!(f, "a hash is 32 bytes, not {}", len),
+            Hash32Error::NotHex => write!(f, "a hash is written in hex"),
+        }
+    }
+}
+
+impl std::error::Error for Hash32Error {}
+
+impl Hash32 {
+    // what the genesis block points back to
+    pub const ZERO: Hash32 = Hash32([0u8; 32]);
+
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Hash32(bytes)
+    }
+
+    pub fn digest(data: &[u8]) -> Self {
+        Hash32(Sha256::digest(data).into())
+    }
+
+    // a hash read from the wire or from disk, which has to be exactly 32
+    // bytes long
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Hash32Error> {
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| Hash32Error::WrongLength(bytes.len()))?;
+        Ok(Hash32(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+// compares every byte whatever the first difference is, so how long a
+// comparison takes doesn't tell anyone how much of a hash they guessed
+impl PartialEq for Hash32 {
+    fn eq(&self, other: &Self) -> bool {
+        let difference: u8 = self.0.iter().zip(other.0.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        std::hint::black_box(difference) == 0
+    }
+}
+
+impl Eq for Hash32 {}
+
+impl PartialOrd for Hash32 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Hash32 {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl std::hash::Hash for Hash32 {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl Deref for Hash32 {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Hash32 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Hash32 {
+    fn from(bytes: [u8; 32]) -> Self {
+        Hash32(bytes)
+    }
+}
+
+impl From<Hash32> for Vec<u8> {
+    fn from(hash: Hash32) -> Self {
+        hash.0.to_vec()
+    }
+}
+
+// lowercase hex. width and precision work like they do for a string, so
+// `{:.8}` shows the first 8 digits, enough to tell blocks apart in a log
+impl fmt::Display for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash32({})", hex::encode(self.0))
+    }
+}
+
+impl FromStr for Hash32 {
+    type Err = Hash32Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes: Vec<u8> = hex::decode(s).map_err(|_| Hash32Error::NotHex)?;
+        Hash32::from_slice(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_hash_goes_to_hex_and_back() {
+        let hash = Hash32::digest(b"block");
+        let text = hash.to_string();
+        assert_eq!(text.len(), 64);
+        assert_eq!(text.parse::<Hash32>(), Ok(hash));
+        assert_eq!(format!("{:.8}", hash), text[..8]);
+
+        assert_eq!("abcd".parse::<Hash32>(), Err(Hash32Error::WrongLength(2)));
+        assert_eq!("zz".parse::<Hash32>(), Err(Hash32Error::NotHex));
+        assert_ne!(hash, Hash32::ZERO);
+        assert_eq!(Hash32::from_slice(&hash), Ok(hash));
+    }
+}
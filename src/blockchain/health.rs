@@ -0,0 +1,151 @@
+use crate::blockchain::clock::local_time;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::json::json_string;
+use crate::blockchain::storage::StorageError;
+use crate::blockchain::BlockChain;
+use std::fmt;
+use std::path::PathBuf;
+
+// the last thing that went wrong on the node, local time in nanoseconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeError {
+    pub time: u128,
+    pub message: String,
+}
+
+// whether blocks still reach the disk
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageHealth {
+    // nothing is saved, by design
+    Memory,
+    Ok { path: PathBuf },
+    // the last write failed, the file is behind the chain until one works
+    Failing { path: PathBuf, error: String },
+}
+
+// everything a load balancer or a monitoring probe asks the node, in one
+// call. a node is healthy when it has caught up with its peers and can
+// save what it mines. an old error doesn't make it unhealthy, it is there
+// for whoever looks into why it was
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStatus {
+    pub height: u64,
+    pub tip: Hash32,
+    pub synced: bool,
+    // the highest tip any peer claims, 0 without peers
+    pub best_peer_height: u64,
+    pub peers: usize,
+    pub mempool: usize,
+    pub storage: StorageHealth,
+    pub last_error: Option<NodeError>,
+}
+
+impl NodeStatus {
+    pub fn is_healthy(&self) -> bool {
+        self.synced && !matches!(self.storage, StorageHealth::Failing { .. })
+    }
+
+    pub fn to_json(&self) -> String {
+        let storage: String = match &self.storage {
+            StorageHealth::Memory => "{\"status\":\"memory\"}".to_string(),
+            StorageHealth::Ok { path } => format!(
+                "{{\"status\":\"ok\",\"path\":{}}}",
+                json_string(&path.display().to_string())
+            ),
+            StorageHealth::Failing { path, error } => format!(
+                "{{\"status\":\"failing\",\"path\":{},\"error\":{}}}",
+                json_string(&path.display().to_string()),
+                json_string(error)
+            ),
+        };
+        let last_error: String = self.last_error.as_ref().map_or("null".to_string(), |error| {
+            format!("{{\"time\":{},\"message\":{}}}", error.time, json_string(&error.message))
+        });
+        format!(
+            "{{\"healthy\":{},\"height\":{},\"tip\":{},\"synced\":{},\"best_peer_height\":{},\"peers\":{},\"mempool\":{},\"storage\":{},\"last_error\":{}}}",
+            self.is_healthy(),
+            self.height,
+            json_string(&self.tip.to_string()),
+            self.synced,
+            self.best_peer_height,
+            self.peers,
+            self.mempool,
+            storage,
+            last_error
+        )
+    }
+}
+
+impl BlockChain {
+    pub fn status(&self) -> NodeStatus {
+        let sync = self.sync_status();
+        let metrics = self.metrics.snapshot();
+        let storage: StorageHealth = match (self.storage_path(), metrics.storage_error) {
+            (None, _) => StorageHealth::Memory,
+            (Some(path), None) => StorageHealth::Ok { path: path.to_path_buf() },
+            (Some(path), Some(error)) => StorageHealth::Failing { path: path.to_path_buf(), error },
+        };
+        NodeStatus {
+            height: sync.height,
+            tip: self.chain.last().map_or(Hash32::ZERO, |block| block.hash()),
+            synced: sync.is_synced(),
+            best_peer_height: sync.best_peer_height,
+            peers: sync.peers.len(),
+            mempool: self.transaction_pool.len(),
+            storage,
+            last_error: metrics.last_error,
+        }
+    }
+
+    // whatever logged the error keeps going, the status remembers it
+    pub(crate) fn record_error(&self, error: &impl fmt::Display) {
+        self.metrics.record_error(NodeError {
+            time: local_time(),
+            message: error.to_string(),
+        });
+    }
+
+    // a write to the node's files: a failed one marks the storage failing
+    // until the next one works
+    pub(crate) fn record_storage(&self, saved: &Result<(), StorageError>) {
+        self.metrics.record_storage(saved.as_ref().err().map(|e| e.to_string()));
+        if let Err(e) = saved {
+            self.record_error(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+
+    #[test]
+    fn a_node_is_unhealthy_while_behind_or_unable_to_save() {
+        let dir = std::env::temp_dir().join(format!("blockchain-health-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut chain = BlockChain::open(&dir, miner().address().into()).unwrap();
+        chain.mining().unwrap();
+        let status = chain.status();
+        assert!(status.is_healthy());
+        let tip: u64 = chain.blocks().len() as u64 - 1;
+        assert_eq!((status.height, status.tip), (tip, chain.last_block().unwrap().hash()));
+        assert_eq!(status.storage, StorageHealth::Ok { path: chain.storage_path().unwrap().to_path_buf() });
+
+        // a peer that is further along
+        chain.sync.add_peer("ahead", tip + 10, local_time());
+        let status = chain.status();
+        assert_eq!((status.synced, status.peers, status.is_healthy()), (false, 1, false));
+        assert!(status.to_json().starts_with(&format!("{{\"healthy\":false,\"height\":{},", tip)));
+        chain.sync.remove_peer("ahead");
+
+        // the node's directory is gone, an encrypted wallet can't be saved
+        std::fs::remove_dir_all(&dir).unwrap();
+        chain.load_wallet("cold", miner()).unwrap();
+        chain.encrypt_wallet("cold", "correct horse").unwrap();
+        let status = chain.status();
+        assert!(matches!(status.storage, StorageHealth::Failing { .. }));
+        assert!(!status.is_healthy());
+        assert!(status.last_error.is_some());
+    }
+}
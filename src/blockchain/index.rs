@@ -0,0 +1,255 @@
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::{Block, BlockChain};
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+// where a mined transaction is: the height of its block and its position
+// in the block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxLocation {
+    pub height: u64,
+    pub index: usize,
+}
+
+// lookups that would otherwise scan the whole chain. nothing in here is
+// needed to validate blocks, all of it can be rebuilt from them.
+#[derive(Debug, Clone, Default)]
+pub struct ChainIndex {
+    transactions: HashMap<Vec<u8>, TxLocation>,
+    // every transaction an address sent or received, oldest first
+    addresses: HashMap<Vec<u8>, Vec<TxLocation>>,
+    // when set, only these addresses get a history, for light or pruned
+    // nodes that can't afford one for every address. the txid index is
+    // kept either way, it's what transaction proofs are served from
+    watch_list: Option<HashSet<Vec<u8>>>,
+}
+
+impl ChainIndex {
+    pub fn new() -> Self {
+        ChainIndex::default()
+    }
+
+    // blocks have to be added in order, from genesis
+    pub fn add_block(&mut self, height: u64, block: &Block) {
+        for (index, bytes) in block.transactions.iter().enumerate() {
+            let location = TxLocation { height, index };
+            let Some(tx) = Transaction::decode(bytes) else {
+                continue;
+            };
+            // every coinbase paying the same reward to the same miner has
+            // the same txid, the first one is kept like a scan would find
+            self.transactions.entry(tx.id()).or_insert(location);
+
+            for address in touched(&tx) {
+                if self.is_indexed(&address) {
+                    self.addresses.entry(address).or_default().push(location);
+                }
+            }
+        }
+    }
+
+    // whether `address` gets a history: all of them unless there's a watch
+    // list
+    pub fn is_indexed(&self, address: &[u8]) -> bool {
+        self.watch_list.as_ref().is_none_or(|watched| watched.contains(address))
+    }
+
+    // the first address watched drops the history of every other one. the
+    // blocks are scanned for the new address's history
+    pub fn watch(&mut self, address: &[u8], blocks: &[Block]) {
+        let watched = self.watch_list.get_or_insert_with(|| {
+            self.addresses.clear();
+            HashSet::new()
+        });
+        if !watched.insert(address.to_vec()) {
+            return;
+        }
+
+        let mut history = Vec::<TxLocation>::new();
+        for (height, block) in blocks.iter().enumerate() {
+            for (index, bytes) in block.transactions.iter().enumerate() {
+                if Transaction::decode(bytes).is_some_and(|tx| touched(&tx).contains(&address.to_vec())) {
+                    history.push(TxLocation { height: height as u64, index });
+                }
+            }
+        }
+        if !history.is_empty() {
+            self.addresses.insert(address.to_vec(), history);
+        }
+    }
+
+    // false if the address wasn't watched
+    pub fn unwatch(&mut self, address: &[u8]) -> bool {
+        let Some(watched) = self.watch_list.as_mut() else {
+            return false;
+        };
+        self.addresses.remove(address);
+        watched.remove(address)
+    }
+
+    // none while every address is indexed
+    pub fn watch_list(&self) -> Option<&HashSet<Vec<u8>>> {
+        self.watch_list.as_ref()
+    }
+
+    // (address, txid) for every transaction in `block` that a watched
+    // address sent or received
+    pub fn watched_activity(&self, block: &Block) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let Some(watched) = &self.watch_list else {
+            return Vec::new();
+        };
+        let mut activity = Vec::<(Vec<u8>, Vec<u8>)>::new();
+        for tx in block.transactions.iter().filter_map(|t| Transaction::decode(t)) {
+            for address in touched(&tx) {
+                if watched.contains(&address) {
+                    activity.push((address, tx.id()));
+                }
+            }
+        }
+        activity
+    }
+
+    pub fn transaction(&self, txid: &[u8]) -> Option<TxLocation> {
+        self.transactions.get(txid).copied()
+    }
+
+    pub fn address_history(&self, address: &[u8]) -> &[TxLocation] {
+        self.addresses.get(address).map_or(&[], |locations| locations.as_slice())
+    }
+}
+
+// the sender, and the recipient when it's someone else
+fn touched(tx: &Transaction) -> Vec<Vec<u8>> {
+    let mut addresses: Vec<Vec<u8>> = vec![tx.sender_address.clone()];
+    if tx.recipient_address != tx.sender_address {
+        addresses.push(tx.recipient_address.clone());
+    }
+    addresses
+}
+
+impl BlockChain {
+    pub fn index(&self) -> &ChainIndex {
+        &self.index
+    }
+
+    // the block and position of a mined transaction
+    pub fn find_transaction(&self, txid: &[u8]) -> Option<(&Block, TxLocation)> {
+        let location = self.index.transaction(txid)?;
+        Some((&self.chain[location.height as usize], location))
+    }
+
+    // from now on only watched addresses get a history and balance change
+    // events, and mined blocks record an event for every transaction one of
+    // them sent or received
+    pub fn watch_address(&mut self, address: &[u8]) {
+        self.index.watch(address, &self.chain);
+    }
+
+    pub fn unwatch_address(&mut self, address: &[u8]) -> bool {
+        self.index.unwatch(address)
+    }
+
+    // throws away everything derived from the blocks and rebuilds it: the
+    // state (balances and everything else the state root covers), the
+    // header mmr, the transaction, address and balance indexes and the
+    // cached block template. for recovering from a corrupted index, or
+    // filling in one that didn't exist when the blocks were added. the
+    // blocks are fully validated on the way, a chain that fails leaves
+    // everything as it was.
+    pub fn reindex(&mut self) -> Result<(), ValidationError> {
+        self.rebuild(true)
+    }
+
+    // `reindex`, trusting the signatures. only for blocks this node
+    // checked itself, e.g. its block file after a clean shutdown
+    pub(crate) fn rebuild(&mut self, check_signatures: bool) -> Result<(), ValidationError> {
+        let (state, header_mmr, balances) = self.replay_chain(check_signatures)?;
+
+        let mut index = ChainIndex {
+            watch_list: self.index.watch_list.clone(),
+            ..ChainIndex::default()
+        };
+        for (height, block) in self.chain.iter().enumerate() {
+            index.add_block(height as u64, block);
+        }
+
+        self.state = state;
+        self.header_mmr = header_mmr;
+        self.index = index;
+        self.balances = balances;
+        self.template_cache = None;
+        self.confirmations.update(&self.chain);
+        info!(blocks = self.chain.len(), "reindexed the chain");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::audit::AuditEvent;
+    use crate::blockchain::test_utils::{funded_wallet, test_wallet, ChainBuilder};
+
+    #[test]
+    fn reindex_rebuilds_what_the_blocks_imply() {
+        let mut chain = ChainBuilder::new().with_blocks(20).with_random_txs(40).build();
+        let root = chain.state().root();
+        let address: Vec<u8> = test_wallet("account 0").address().into_bytes();
+        let address: &[u8] = &address;
+        let history: Vec<TxLocation> = chain.index().address_history(address).to_vec();
+        assert!(!history.is_empty());
+
+        // as good as lost
+        chain.index = ChainIndex::new();
+        chain.state = Default::default();
+        chain.reindex().unwrap();
+
+        assert_eq!(chain.state().root(), root);
+        assert_eq!(chain.index().address_history(address), history.as_slice());
+        // coinbases can share a txid, look up a transfer
+        let (height, transfer): (usize, Transaction) = chain
+            .blocks()
+            .iter()
+            .enumerate()
+            .flat_map(|(height, block)| {
+                block
+                    .transactions
+                    .iter()
+                    .filter_map(move |t| Some((height, Transaction::decode(t)?)))
+            })
+            .find(|(_, tx)| tx.sender_address != BlockChain::MINING_SENDER.as_bytes())
+            .unwrap();
+        let (block, location) = chain.find_transaction(&transfer.id()).unwrap();
+        assert_eq!(location.height, height as u64);
+        assert_eq!(block.hash(), chain.blocks()[height].hash());
+    }
+
+    #[test]
+    fn only_watched_addresses_are_indexed_and_reported() {
+        let mut chain = ChainBuilder::new().with_blocks(10).with_random_txs(20).build();
+        let watched: Vec<u8> = test_wallet("account 1").address().into_bytes();
+        let sender = funded_wallet(&chain, 1, &watched).unwrap();
+        let watched: &[u8] = &watched;
+        let history: Vec<TxLocation> = chain.index().address_history(watched).to_vec();
+
+        chain.watch_address(watched);
+        assert_eq!(chain.index().address_history(watched), history.as_slice());
+        assert!(chain.index().address_history(sender.address().as_bytes()).is_empty());
+
+        let nonce: u64 = chain.next_nonce(sender.address().as_bytes());
+        let tx = Transaction::new(sender.address().into_bytes(), watched.to_vec(), 1).with_nonce(nonce).sign(&sender);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+        assert_eq!(chain.index().address_history(watched).len(), history.len() + 1);
+        assert!(chain.index().address_history(sender.address().as_bytes()).is_empty());
+
+        let height = chain.blocks().len() as u64 - 1;
+        let reported: Vec<&AuditEvent> = chain.audit_log().entries().iter().map(|e| &e.event).collect();
+        assert!(reported.iter().any(|e| matches!(e,
+            AuditEvent::WatchedAddressActivity { address, height: h, .. } if address == watched && *h == height)));
+        // the sender isn't watched, its balance change isn't reported
+        assert!(!reported.iter().any(|e| matches!(e,
+            AuditEvent::BalanceChanged { address, height: h, .. } if *address == sender.address().into_bytes() && *h == height)));
+    }
+}
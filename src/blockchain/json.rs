@@ -0,0 +1,96 @@
+use crate::blockchain::transaction::{Payload, Transaction};
+use crate::blockchain::Block;
+
+// the bits of json the node writes by hand: reports, the explorer's and
+// the node's http apis. everything is built with format!, this only has to
+// get strings right. the url decoding those apis need lives here too
+
+// `text` as a json string literal, quotes included
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// a block with its transactions decoded
+pub fn block_json(height: usize, block: &Block) -> String {
+    let transactions: Vec<String> = block
+        .transactions
+        .iter()
+        .filter_map(|t| Transaction::decode(t))
+        .map(|tx| transaction_json(&tx))
+        .collect();
+    format!(
+        "{{\"height\":{},\"hash\":{},\"version\":{},\"previous_hash\":{},\"time_stamp\":{},\"nonce\":{},\"merkle_root\":{},\"state_root\":{},\"miner\":{},\"transactions\":[{}]}}",
+        height,
+        json_string(&block.hash().to_string()),
+        block.header.version,
+        json_string(&block.header.previous_hash.to_string()),
+        block.header.time_stamp,
+        block.header.nonce,
+        json_string(&block.header.merkle_root.to_string()),
+        json_string(&block.header.state_root.to_string()),
+        block.miner().map_or("null".to_string(), |m| json_string(&String::from_utf8_lossy(&m))),
+        transactions.join(",")
+    )
+}
+
+pub fn transaction_json(tx: &Transaction) -> String {
+    format!(
+        "{{\"txid\":{},\"sender\":{},\"recipient\":{},\"value\":{},\"fee\":{},\"nonce\":{},\"payload\":{}}}",
+        json_string(&hex::encode(tx.id())),
+        json_string(&String::from_utf8_lossy(&tx.sender_address)),
+        json_string(&String::from_utf8_lossy(&tx.recipient_address)),
+        tx.value,
+        tx.fee,
+        tx.nonce,
+        json_string(payload_kind(&tx.payload))
+    )
+}
+
+fn payload_kind(payload: &Payload) -> &'static str {
+    match payload {
+        Payload::Transfer => "transfer",
+        Payload::Deploy { .. } => "deploy",
+        Payload::Call { .. } => "call",
+        Payload::CreateAsset { .. } => "create asset",
+        Payload::TransferAsset { .. } => "transfer asset",
+        Payload::ClaimName { .. } => "claim name",
+        Payload::UpdateName { .. } => "update name",
+        Payload::Propose { .. } => "propose",
+        Payload::Vote { .. } => "vote",
+        Payload::Spend { .. } => "spend",
+    }
+}
+
+// addresses are arbitrary bytes, clients escape them into the url
+pub fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::<u8>::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
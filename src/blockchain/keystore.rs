@@ -0,0 +1,266 @@
+use crate::blockchain::address::Address;
+use crate::blockchain::clock::local_time;
+use crate::blockchain::transaction::{put_bytes, take_array, take_bytes, Transaction};
+use crate::blockchain::wallet::Wallet;
+use crate::blockchain::wallets::WalletError;
+use crate::blockchain::BlockChain;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// rounds of pbkdf2 between a passphrase and the key it encrypts with.
+// every guess at the passphrase costs an attacker with the file as much
+pub const KDF_ROUNDS: u32 = 100_000;
+
+// the longest a wallet stays unlocked, whatever the caller asks for
+pub const MAX_UNLOCK: Duration = Duration::from_secs(24 * 60 * 60);
+
+// a wallet's secret key encrypted with chacha20-poly1305, under a key
+// derived from the passphrase and a random salt. the public key is kept in
+// the clear, the address, balance and history don't need the passphrase
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedKey {
+    pub public_key: [u8; 32],
+    rounds: u32,
+    salt: [u8; 16],
+    nonce: [u8; 12],
+    // the 32 byte secret and the 16 byte tag
+    ciphertext: Vec<u8>,
+}
+
+fn cipher(passphrase: &str, salt: &[u8], rounds: u32) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+impl EncryptedKey {
+    pub fn encrypt(wallet: &Wallet, passphrase: &str) -> Self {
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        getrandom::fill(&mut salt).expect("the operating system has no random number generator");
+        getrandom::fill(&mut nonce).expect("the operating system has no random number generator");
+        let ciphertext = cipher(passphrase, &salt, KDF_ROUNDS)
+            .encrypt(Nonce::from_slice(&nonce), wallet.secret().as_slice())
+            .expect("a 32 byte secret always encrypts");
+        EncryptedKey {
+            public_key: wallet.public_key(),
+            rounds: KDF_ROUNDS,
+            salt,
+            nonce,
+            ciphertext,
+        }
+    }
+
+    // the tag only checks out with the passphrase it was encrypted with
+    pub fn decrypt(&self, passphrase: &str) -> Result<Wallet, WalletError> {
+        let secret: [u8; 32] = cipher(passphrase, &self.salt, self.rounds)
+            .decrypt(Nonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+            .ok()
+            .and_then(|secret| secret.try_into().ok())
+            .ok_or(WalletError::WrongPassphrase)?;
+        Ok(Wallet::from_secret(secret))
+    }
+
+    pub fn address(&self) -> Address {
+        Address::from_public_key(&self.public_key)
+    }
+}
+
+// the node's encrypted wallets, by name, and the ones unlocked for now.
+// an unlocked wallet's key is only ever in memory, and only until its
+// time is up or it is locked again
+#[derive(Debug, Clone, Default)]
+pub struct Keystore {
+    keys: BTreeMap<String, EncryptedKey>,
+    // the decrypted wallet and when it locks again, local time in
+    // nanoseconds
+    unlocked: BTreeMap<String, (Wallet, u128)>,
+}
+
+impl Keystore {
+    pub fn new() -> Self {
+        Keystore::default()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.keys.contains_key(name)
+    }
+
+    pub fn key(&self, name: &str) -> Option<&EncryptedKey> {
+        self.keys.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.keys.keys()
+    }
+
+    pub(crate) fn insert(&mut self, name: &str, key: EncryptedKey) {
+        self.unlocked.remove(name);
+        self.keys.insert(name.to_string(), key);
+    }
+
+    // until `until`, an unlock replaces the time the last one gave
+    pub fn unlock(&mut self, name: &str, passphrase: &str, until: u128) -> Result<(), WalletError> {
+        let key: &EncryptedKey = self.keys.get(name).ok_or_else(|| WalletError::UnknownWallet(name.to_string()))?;
+        let wallet: Wallet = key.decrypt(passphrase)?;
+        self.unlocked.insert(name.to_string(), (wallet, until));
+        Ok(())
+    }
+
+    // whether it was unlocked
+    pub fn lock(&mut self, name: &str) -> bool {
+        self.unlocked.remove(name).is_some()
+    }
+
+    pub fn unlocked_until(&self, name: &str, now: u128) -> Option<u128> {
+        self.unlocked.get(name).map(|(_, until)| *until).filter(|until| *until > now)
+    }
+
+    // the key, while the wallet is unlocked
+    pub fn wallet(&self, name: &str, now: u128) -> Result<&Wallet, WalletError> {
+        if !self.keys.contains_key(name) {
+            return Err(WalletError::UnknownWallet(name.to_string()));
+        }
+        match self.unlocked.get(name) {
+            Some((wallet, until)) if *until > now => Ok(wallet),
+            _ => Err(WalletError::Locked(name.to_string())),
+        }
+    }
+
+    // forgets the keys whose time is up
+    pub fn expire(&mut self, now: u128) {
+        self.unlocked.retain(|_, (_, until)| *until > now);
+    }
+
+    // the encrypted keys only, an unlocked wallet is locked in the file
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        for (name, key) in self.keys.iter() {
+            put_bytes(&mut bin, name.as_bytes());
+            bin.extend(key.public_key);
+            bin.extend(key.rounds.to_be_bytes());
+            bin.extend(key.salt);
+            bin.extend(key.nonce);
+            put_bytes(&mut bin, &key.ciphertext);
+        }
+        bin
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Keystore> {
+        let mut keystore = Keystore::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let name = String::from_utf8(take_bytes(bytes, &mut pos).ok()?).ok()?;
+            let key = EncryptedKey {
+                public_key: take_array(bytes, &mut pos).ok()?,
+                rounds: u32::from_be_bytes(take_array(bytes, &mut pos).ok()?),
+                salt: take_array(bytes, &mut pos).ok()?,
+                nonce: take_array(bytes, &mut pos).ok()?,
+                ciphertext: take_bytes(bytes, &mut pos).ok()?,
+            };
+            keystore.keys.insert(name, key);
+        }
+        Some(keystore)
+    }
+}
+
+impl BlockChain {
+    pub fn keystore(&self) -> &Keystore {
+        &self.keystore
+    }
+
+    // encrypts a loaded wallet with `passphrase` and moves it to the
+    // keystore, locked. from then on it only signs while unlocked
+    pub fn encrypt_wallet(&mut self, name: &str, passphrase: &str) -> Result<(), WalletError> {
+        let wallet: Wallet = self
+            .wallets
+            .remove(name)
+            .ok_or_else(|| WalletError::UnknownWallet(name.to_string()))?;
+        self.keystore.insert(name, EncryptedKey::encrypt(&wallet, passphrase));
+        self.save_keystore();
+        info!(wallet = name, "encrypted a wallet");
+        Ok(())
+    }
+
+    // walletunlock: the wallet signs for `timeout`, at most a day. when
+    // it locks again
+    pub fn unlock_wallet(&mut self, name: &str, passphrase: &str, timeout: Duration) -> Result<u128, WalletError> {
+        let until: u128 = local_time().saturating_add(timeout.min(MAX_UNLOCK).as_nanos());
+        self.keystore.unlock(name, passphrase, until)?;
+        Ok(until)
+    }
+
+    // walletlock: the key is forgotten straight away
+    pub fn lock_wallet(&mut self, name: &str) -> Result<(), WalletError> {
+        if !self.keystore.contains(name) {
+            return Err(WalletError::UnknownWallet(name.to_string()));
+        }
+        self.keystore.lock(name);
+        Ok(())
+    }
+
+    // signmessage: the wallet's signature over `message`
+    pub fn sign_message(&self, name: &str, message: &[u8]) -> Result<[u8; 64], WalletError> {
+        Ok(self.wallet(name)?.sign(message))
+    }
+
+    // signtransaction: `tx` signed by the wallet, which has to be its
+    // sender. it isn't sent anywhere
+    pub fn sign_transaction(&self, name: &str, tx: Transaction) -> Result<Transaction, WalletError> {
+        let wallet: &Wallet = self.wallet(name)?;
+        if tx.sender_address != wallet.address().as_bytes() {
+            return Err(WalletError::NotSender(name.to_string()));
+        }
+        Ok(tx.sign(wallet))
+    }
+
+    // the keystore is saved whenever a wallet is encrypted, a keystore
+    // that can't be saved only lasts until the node stops
+    fn save_keystore(&self) {
+        if let Some(store) = self.storage.as_ref() {
+            let saved = store.save_keystore(&self.keystore);
+            if let Err(e) = &saved {
+                warn!(error = %e, "keystore not saved");
+            }
+            self.record_storage(&saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn an_encrypted_wallet_only_signs_while_unlocked() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        let wallet = test_wallet("cold");
+        chain.load_wallet("cold", wallet.clone()).unwrap();
+        chain.encrypt_wallet("cold", "correct horse").unwrap();
+        assert!(chain.wallets().get("cold").is_none());
+        assert_eq!(chain.wallet_address("cold"), Ok(wallet.address()));
+
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1);
+        assert_eq!(chain.sign_transaction("cold", tx.clone()), Err(WalletError::Locked("cold".into())));
+        assert_eq!(chain.unlock_wallet("cold", "battery staple", MAX_UNLOCK), Err(WalletError::WrongPassphrase));
+        chain.unlock_wallet("cold", "correct horse", Duration::from_secs(60)).unwrap();
+        assert_eq!(chain.sign_transaction("cold", tx.clone()), Ok(tx.clone().sign(&wallet)));
+        assert_eq!(chain.sign_message("cold", b"hello"), Ok(wallet.sign(b"hello")));
+
+        chain.lock_wallet("cold").unwrap();
+        assert_eq!(chain.sign_message("cold", b"hello"), Err(WalletError::Locked("cold".into())));
+        // a timeout that has already run out
+        chain.unlock_wallet("cold", "correct horse", Duration::ZERO).unwrap();
+        assert_eq!(chain.sign_message("cold", b"hello"), Err(WalletError::Locked("cold".into())));
+
+        // saved without the unlocked key
+        let keystore = Keystore::decode(&chain.keystore().encode()).unwrap();
+        assert_eq!(keystore.key("cold"), chain.keystore().key("cold"));
+        assert_eq!(keystore.key("cold").unwrap().decrypt("correct horse").unwrap().address(), wallet.address());
+    }
+}
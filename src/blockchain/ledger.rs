@@ -0,0 +1,213 @@
+use crate::blockchain::state::State;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::BlockChain;
+use std::collections::HashMap;
+use std::fmt;
+
+// not an address anyone can send from, it's where gas and burned fees go
+pub const BURNED_ACCOUNT: &str = "<burned>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    // a block reward, new coins paid out by the mining sender
+    Issuance,
+    Transfer,
+    // gas is always burned
+    Gas,
+    FeeBurned,
+    // the part of a fee the miner keeps
+    FeeTip,
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            EntryKind::Issuance => "issuance",
+            EntryKind::Transfer => "transfer",
+            EntryKind::Gas => "gas",
+            EntryKind::FeeBurned => "fee burned",
+            EntryKind::FeeTip => "fee tip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// one movement of coins. every address is an asset account in these
+// books: a debit adds `amount` to the debited account and the same amount
+// is credited (taken) from the other one, so each entry balances on its
+// own. the mining sender is credited with everything ever issued
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub height: u64,
+    pub txid: Vec<u8>,
+    pub kind: EntryKind,
+    pub debit: Vec<u8>,
+    pub credit: Vec<u8>,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    // debits minus credits, the balance the entries give the account
+    pub fn balance(&self, account: &[u8]) -> i64 {
+        self.entries
+            .iter()
+            .map(|e| {
+                let mut net: i64 = 0;
+                if e.debit == account {
+                    net += e.amount as i64;
+                }
+                if e.credit == account {
+                    net -= e.amount as i64;
+                }
+                net
+            })
+            .sum()
+    }
+
+    // every account's balance, by account
+    pub fn balances(&self) -> HashMap<Vec<u8>, i64> {
+        let mut balances: HashMap<Vec<u8>, i64> = HashMap::new();
+        for e in &self.entries {
+            *balances.entry(e.debit.clone()).or_default() += e.amount as i64;
+            *balances.entry(e.credit.clone()).or_default() -= e.amount as i64;
+        }
+        balances
+    }
+
+    // one line per entry with a header, what spreadsheets and accounting
+    // tools import
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("height,txid,kind,debit,credit,amount\n");
+        for e in &self.entries {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                e.height,
+                hex::encode(&e.txid),
+                e.kind,
+                csv_field(&e.debit),
+                csv_field(&e.credit),
+                e.amount
+            ));
+        }
+        csv
+    }
+
+    fn push(&mut self, height: u64, txid: &[u8], kind: EntryKind, debit: &[u8], credit: &[u8], amount: u64) {
+        if amount == 0 {
+            return;
+        }
+        self.entries.push(LedgerEntry {
+            height,
+            txid: txid.to_vec(),
+            kind,
+            debit: debit.to_vec(),
+            credit: credit.to_vec(),
+            amount,
+        });
+    }
+}
+
+// addresses are arbitrary bytes, quoted when they'd break the line
+fn csv_field(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.into_owned()
+    }
+}
+
+impl BlockChain {
+    // the chain as double-entry books. the blocks are replayed, receipts
+    // say how much gas a transaction really used and whether its value
+    // moved, and the governance parameters in force at each block decide
+    // how its fees were split
+    pub fn ledger(&self) -> Result<Ledger, ValidationError> {
+        let mut ledger = Ledger::default();
+        let mut state: State = State::new();
+        let burned: &[u8] = BURNED_ACCOUNT.as_bytes();
+
+        for (index, block) in self.chain.iter().enumerate() {
+            let height = index as u64;
+            let miner: Option<Vec<u8>> = block.miner();
+            for t in block.transactions.iter() {
+                let tx: Transaction =
+                    Transaction::decode(t).ok_or(ValidationError::MalformedTransaction { index })?;
+                let fee_burn_percent = state.parameters().fee_burn_percent;
+                let receipt = state
+                    .apply_transaction(&tx, height)
+                    .map_err(|error| ValidationError::InvalidTransaction { index, error })?;
+
+                let txid = tx.id();
+                let sender: &[u8] = &tx.sender_address;
+                if receipt.success {
+                    let kind = if sender == BlockChain::MINING_SENDER.as_bytes() {
+                        EntryKind::Issuance
+                    } else {
+                        EntryKind::Transfer
+                    };
+                    ledger.push(height, &txid, kind, &tx.recipient_address, sender, tx.value);
+                }
+                let gas = receipt.gas_used.saturating_mul(tx.gas_price);
+                ledger.push(height, &txid, EntryKind::Gas, burned, sender, gas);
+
+                let fee_burned = (tx.fee as u128 * fee_burn_percent as u128 / 100) as u64;
+                ledger.push(height, &txid, EntryKind::FeeBurned, burned, sender, fee_burned);
+                // with no miner to collect it the tip is lost too
+                let tip_to: &[u8] = miner.as_deref().unwrap_or(burned);
+                ledger.push(height, &txid, EntryKind::FeeTip, tip_to, sender, tx.fee - fee_burned);
+            }
+            state
+                .end_block(height, miner.as_deref())
+                .map_err(|error| ValidationError::InvalidFees { index, error })?;
+        }
+        Ok(ledger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{funded_wallet, test_wallet, ChainBuilder};
+
+    #[test]
+    fn the_books_balance_to_the_issued_supply() {
+        // the first rewards have to mature before anyone can send anything
+        let mut chain = ChainBuilder::new()
+            .with_blocks(BlockChain::COINBASE_MATURITY as usize + 10)
+            .with_random_txs(30)
+            .build();
+        let recipient = test_wallet("account 1").address().into_bytes();
+        let sender = funded_wallet(&chain, 4, &recipient).unwrap();
+        let nonce: u64 = chain.next_nonce(sender.address().as_bytes());
+        let tx = Transaction::new(sender.address().into_bytes(), recipient, 1)
+            .with_fee(3)
+            .with_nonce(nonce)
+            .sign(&sender);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+
+        let ledger = chain.ledger().unwrap();
+        let balances = ledger.balances();
+        // every entry balances, so the whole book does
+        assert_eq!(balances.values().sum::<i64>(), 0);
+
+        let supply = chain.state().supply();
+        assert_eq!(-ledger.balance(BlockChain::MINING_SENDER.as_bytes()), supply.issued as i64);
+        assert_eq!(ledger.balance(BURNED_ACCOUNT.as_bytes()), supply.burned);
+        for (address, account) in chain.state().accounts() {
+            assert_eq!(ledger.balance(address), account.balance);
+        }
+        assert!(ledger.to_csv().lines().any(|line| line.contains("fee tip")));
+    }
+}
@@ -0,0 +1,427 @@
+use crate::blockchain::difficulty::{header_work, Retarget};
+use crate::blockchain::fraud::FraudProof;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::merkle::{self, MerkleProof};
+use crate::blockchain::mmr::{self, MmrProof};
+use crate::blockchain::protocol::locator_heights;
+use crate::blockchain::transaction::DEFAULT_CHAIN_ID;
+use std::collections::BTreeSet;
+use crate::blockchain::{BlockChain, BlockHeader, BLOCK_VERSION};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum LightClientError {
+    // the first header doesn't connect to any header we know
+    UnknownParent,
+    BrokenLink { height: u64 },
+    InvalidProofOfWork { height: u64 },
+    // the header claims another difficulty than the retarget rule gives
+    WrongDifficulty { height: u64, expected: usize },
+    // the batch doesn't make a chain with more work than the one we follow
+    NotBestChain,
+    TransactionNotFound,
+    UnknownBlock { height: u64 },
+    InvalidMerkleProof,
+    // a header at the checkpoint height with another hash
+    CheckpointMismatch { height: u64 },
+    // a fraud proof showed this block to be invalid
+    InvalidBlock { height: u64 },
+    InvalidFraudProof,
+    InvalidStateProof,
+    InvalidReceiptProof,
+    InvalidMmrProof,
+}
+
+impl fmt::Display for LightClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightClientError::UnknownParent => write!(f, "headers don't connect to the known chain"),
+            LightClientError::BrokenLink { height } => {
+                write!(f, "header {} does not point to the header before it", height)
+            }
+            LightClientError::InvalidProofOfWork { height } => {
+                write!(f, "header {} does not meet the difficulty target", height)
+            }
+            LightClientError::WrongDifficulty { height, expected } => {
+                write!(f, "header {} does not have the expected difficulty {}", height, expected)
+            }
+            LightClientError::NotBestChain => write!(f, "headers don't extend the best chain"),
+            LightClientError::TransactionNotFound => write!(f, "the full node has no such transaction"),
+            LightClientError::UnknownBlock { height } => write!(f, "no header at height {}", height),
+            LightClientError::InvalidMerkleProof => {
+                write!(f, "merkle proof doesn't match the header's merkle root")
+            }
+            LightClientError::CheckpointMismatch { height } => {
+                write!(f, "header {} is not the checkpointed one", height)
+            }
+            LightClientError::InvalidBlock { height } => {
+                write!(f, "block {} was proven invalid", height)
+            }
+            LightClientError::InvalidFraudProof => write!(f, "fraud proof doesn't hold"),
+            LightClientError::InvalidStateProof => {
+                write!(f, "state proof doesn't match the header's state root")
+            }
+            LightClientError::InvalidReceiptProof => {
+                write!(f, "receipt proof doesn't match the header's receipts root")
+            }
+            LightClientError::InvalidMmrProof => {
+                write!(f, "block is not under the tip's mountain range root")
+            }
+        }
+    }
+}
+
+// what a light client needs from a full node, a remote peer would
+// implement this over the network
+pub trait FullNode {
+    // headers from `from_height` up to the tip
+    fn headers_from(&self, from_height: u64) -> Vec<BlockHeader>;
+    // the height of the block holding the transaction and a proof of it
+    fn transaction_proof(&self, txid: &[u8]) -> Option<(u64, MerkleProof)>;
+}
+
+impl FullNode for BlockChain {
+    fn headers_from(&self, from_height: u64) -> Vec<BlockHeader> {
+        self.headers().skip(from_height as usize).cloned().collect()
+    }
+
+    fn transaction_proof(&self, txid: &[u8]) -> Option<(u64, MerkleProof)> {
+        let (block, location) = self.find_transaction(txid)?;
+        Some((location.height, block.merkle_proof(txid)?))
+    }
+}
+
+// a block the client trusts without checking what comes before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub hash: Hash32,
+}
+
+// follows the chain through headers only and checks transactions with
+// merkle proofs, trusting nothing but proof of work
+#[derive(Debug, Clone)]
+pub struct LightClient {
+    // height of the first header we hold, 0 unless started from a checkpoint
+    base_height: u64,
+    // the best known header chain from `base_height` on
+    headers: Vec<BlockHeader>,
+    // chains without this block are refused
+    checkpoint: Option<Checkpoint>,
+    // hashes of blocks shown invalid by fraud proofs, chains with them
+    // are refused
+    invalid_blocks: BTreeSet<Hash32>,
+    // the chain's difficulty rules, see `with_rules`
+    difficulty: usize,
+    retarget: Option<Retarget>,
+    // what the chain's transactions are signed for, fraud proofs are
+    // checked against it
+    chain_id: u64,
+}
+
+impl LightClient {
+    // the genesis header isn't mined, it has to be trusted
+    pub fn new(genesis: BlockHeader) -> Self {
+        LightClient {
+            base_height: 0,
+            headers: vec![genesis],
+            checkpoint: None,
+            invalid_blocks: BTreeSet::new(),
+            difficulty: BlockChain::DIFFICULTY,
+            retarget: None,
+            chain_id: DEFAULT_CHAIN_ID,
+        }
+    }
+
+    // skips everything before the checkpoint, the node only provides the
+    // header and it has to hash to what was configured
+    pub fn from_checkpoint(
+        checkpoint: Checkpoint,
+        node: &impl FullNode,
+    ) -> Result<Self, LightClientError> {
+        let header = node
+            .headers_from(checkpoint.height)
+            .into_iter()
+            .next()
+            .filter(|header| header.hash() == checkpoint.hash)
+            .ok_or(LightClientError::CheckpointMismatch {
+                height: checkpoint.height,
+            })?;
+
+        Ok(LightClient {
+            base_height: checkpoint.height,
+            headers: vec![header],
+            checkpoint: Some(checkpoint),
+            invalid_blocks: BTreeSet::new(),
+            difficulty: BlockChain::DIFFICULTY,
+            retarget: None,
+            chain_id: DEFAULT_CHAIN_ID,
+        })
+    }
+
+    // a client syncing from genesis can still pin a block it knows
+    pub fn with_checkpoint(mut self, checkpoint: Checkpoint) -> Self {
+        self.checkpoint = Some(checkpoint);
+        self
+    }
+
+    // the difficulty and retarget rule of the chain followed, the ones
+    // `BlockChain::new` has unless set. like a full node, a client with other
+    // rules refuses the chain's headers
+    pub fn with_rules(mut self, difficulty: usize, retarget: Option<Retarget>) -> Self {
+        self.difficulty = difficulty;
+        self.retarget = retarget;
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    pub fn header(&self, height: u64) -> Option<&BlockHeader> {
+        let index = height.checked_sub(self.base_height)?;
+        self.headers.get(index as usize)
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().unwrap()
+    }
+
+    pub fn height(&self) -> u64 {
+        self.base_height + self.headers.len() as u64 - 1
+    }
+
+    // what to send in `GetHeaders`, down to the first header we hold
+    pub fn get_locator(&self) -> Vec<Hash32> {
+        locator_heights(self.height(), self.base_height)
+            .into_iter()
+            .filter_map(|height| self.header(height))
+            .map(|header| header.hash())
+            .collect()
+    }
+
+    // fetches whatever the node has past our tip. a node on another fork
+    // is asked again from the start so its chain can replace ours if it
+    // has more work
+    pub fn sync(&mut self, node: &impl FullNode) -> Result<u64, LightClientError> {
+        let from = self.height() + 1;
+        match self.add_headers(from, node.headers_from(from)) {
+            Err(LightClientError::UnknownParent) => {
+                let first = self.base_height + 1;
+                self.add_headers(first, node.headers_from(first))
+            }
+            result => result,
+        }
+    }
+
+    // the difficulty the header at `height` has to have, worked out from
+    // the ones before it: ours below `start_height`, `headers` from there
+    fn expected_difficulty(&self, height: u64, start_height: u64, headers: &[BlockHeader]) -> Option<usize> {
+        let header_at = |at: u64| match at.checked_sub(start_height) {
+            Some(index) => headers.get(index as usize),
+            None => self.header(at),
+        };
+        match self.retarget {
+            Some(retarget) => retarget.difficulty_at(height, self.difficulty, header_at),
+            None => Some(self.difficulty),
+        }
+    }
+
+    // `headers` start at `start_height`. they replace what we have from
+    // there on if they have more work, the fork choice of
+    // `BlockChain::replace_chain`: with the difficulty retargeting a shorter
+    // chain of harder blocks can beat a longer one
+    pub fn add_headers(
+        &mut self,
+        start_height: u64,
+        headers: Vec<BlockHeader>,
+    ) -> Result<u64, LightClientError> {
+        if headers.is_empty() {
+            return Ok(self.height());
+        }
+        // nothing at or before the base can be replaced
+        if start_height <= self.base_height || start_height > self.height() + 1 {
+            return Err(LightClientError::UnknownParent);
+        }
+
+        let mut parent_hash: Hash32 = self.header(start_height - 1).unwrap().hash();
+        if headers[0].previous_hash != parent_hash {
+            return Err(LightClientError::UnknownParent);
+        }
+
+        for (i, header) in headers.iter().enumerate() {
+            let height = start_height + i as u64;
+            if header.previous_hash != parent_hash {
+                return Err(LightClientError::BrokenLink { height });
+            }
+            parent_hash = header.hash();
+            if header.version != BLOCK_VERSION || self.invalid_blocks.contains(&parent_hash) {
+                return Err(LightClientError::InvalidBlock { height });
+            }
+            let parent_difficulty: usize = match i {
+                0 => self.header(start_height - 1).unwrap().difficulty,
+                _ => headers[i - 1].difficulty,
+            };
+            match self.expected_difficulty(height, start_height, &headers) {
+                Some(expected) if header.difficulty != expected => {
+                    return Err(LightClientError::WrongDifficulty { height, expected });
+                }
+                Some(_) => {}
+                // a retarget looking back past the checkpoint we started
+                // from. it moves the difficulty one step at most
+                None if header.difficulty.abs_diff(parent_difficulty) > 1 => {
+                    return Err(LightClientError::WrongDifficulty { height, expected: parent_difficulty });
+                }
+                None => {}
+            }
+            if !BlockChain::meets_target(&parent_hash, header.difficulty) {
+                return Err(LightClientError::InvalidProofOfWork { height });
+            }
+            if let Some(checkpoint) = &self.checkpoint
+                && checkpoint.height == height
+                && checkpoint.hash != parent_hash
+            {
+                return Err(LightClientError::CheckpointMismatch { height });
+            }
+        }
+
+        let replaced: usize = (start_height - self.base_height) as usize;
+        if header_work(&headers) <= header_work(&self.headers[replaced..]) {
+            return Err(LightClientError::NotBestChain);
+        }
+        self.headers.truncate(replaced);
+        self.headers.extend(headers);
+        Ok(self.height())
+    }
+
+    // drops the proven invalid block and everything after it, the next
+    // sync has to find another chain. returns our new height
+    pub fn process_fraud_proof(&mut self, proof: &FraudProof) -> Result<u64, LightClientError> {
+        // the difficulty is the retarget rule's when we hold the headers
+        // before the block. one we can't place is only shown invalid by
+        // missing the difficulty it claims
+        let header: &BlockHeader = proof.header();
+        let height: Option<u64> = self
+            .headers
+            .iter()
+            .position(|parent| parent.hash() == header.previous_hash)
+            .map(|index| self.base_height + index as u64 + 1);
+        let expected: usize = height
+            .and_then(|height| self.expected_difficulty(height, height, &[]))
+            .unwrap_or(header.difficulty);
+        if !proof.verify(expected, self.chain_id) {
+            return Err(LightClientError::InvalidFraudProof);
+        }
+
+        let hash = header.hash();
+        if let Some(index) = self.headers.iter().position(|header| header.hash() == hash) {
+            // the first header is trusted, a proof against it means the
+            // checkpoint or genesis was wrong to begin with
+            if index == 0 {
+                return Err(LightClientError::CheckpointMismatch {
+                    height: self.base_height,
+                });
+            }
+            self.headers.truncate(index);
+        }
+        self.invalid_blocks.insert(hash);
+        Ok(self.height())
+    }
+
+    // asks the node for a proof and checks it against our own header,
+    // returns the height the transaction was mined at
+    pub fn verify_transaction(
+        &self,
+        node: &impl FullNode,
+        txid: &[u8],
+    ) -> Result<u64, LightClientError> {
+        let (height, proof) = node
+            .transaction_proof(txid)
+            .ok_or(LightClientError::TransactionNotFound)?;
+        let header = self
+            .header(height)
+            .ok_or(LightClientError::UnknownBlock { height })?;
+
+        if !merkle::verify_merkle_proof(&header.merkle_root, &proof, txid) {
+            return Err(LightClientError::InvalidMerkleProof);
+        }
+        Ok(height)
+    }
+
+    // checks that `header` is part of our chain from the tip alone, without
+    // the headers in between. a client that pruned old headers or only
+    // synced the tip can still tell whether an old block belongs.
+    pub fn verify_ancestor(&self, header: &BlockHeader, proof: &MmrProof) -> Result<(), LightClientError> {
+        if proof.leaf_count != self.height() {
+            return Err(LightClientError::InvalidMmrProof);
+        }
+        if !mmr::verify_mmr_proof(&self.tip().mmr_root, &header.hash(), proof) {
+            return Err(LightClientError::InvalidMmrProof);
+        }
+        Ok(())
+    }
+
+    // blocks on top of the one at `height`, counting it
+    pub fn confirmations(&self, height: u64) -> u64 {
+        (self.height() + 1).saturating_sub(height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // a header on `parent`, with `difficulty` leading zeros
+    fn mine(parent: &BlockHeader, time_stamp: u128, difficulty: usize) -> BlockHeader {
+        let mut header: BlockHeader = parent.clone();
+        header.previous_hash = parent.hash();
+        header.time_stamp = time_stamp;
+        header.difficulty = difficulty;
+        header.nonce = 0;
+        while !BlockChain::meets_target(&header.hash(), difficulty) {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    #[test]
+    fn headers_follow_the_retarget_rule_and_the_most_work_wins() {
+        let retarget = Some(Retarget { interval: 2, block_time: Duration::from_secs(60) });
+        let mut chain = BlockChain::with_difficulty("miner".into(), 1);
+        chain.set_retarget(retarget);
+        for _ in 0..5 {
+            chain.mining().unwrap();
+        }
+        // mined in no time, every retarget made it harder
+        let headers: Vec<BlockHeader> = chain.headers().cloned().collect();
+        assert_eq!(headers[6].difficulty, 3);
+
+        let mut stranger = LightClient::new(headers[0].clone());
+        assert_eq!(
+            stranger.add_headers(1, headers[1..].to_vec()),
+            Err(LightClientError::WrongDifficulty { height: 1, expected: BlockChain::DIFFICULTY })
+        );
+        let mut client = LightClient::new(headers[0].clone()).with_rules(1, retarget);
+        assert_eq!(client.sync(&chain), Ok(6));
+
+        // a branch from 5 that took its time, so the retarget at 6 eases
+        // off. longer than ours, but far less work
+        let slow: u128 = headers[4].time_stamp + Duration::from_secs(1000).as_nanos();
+        let five = mine(&headers[4], slow, 2);
+        let six = mine(&five, slow + 1, 1);
+        let seven = mine(&six, slow + 2, 1);
+        assert_eq!(client.add_headers(5, vec![five.clone(), six, seven]), Err(LightClientError::NotBestChain));
+        let harder = mine(&five, slow + 1, 2);
+        assert_eq!(
+            client.add_headers(5, vec![five, harder]),
+            Err(LightClientError::WrongDifficulty { height: 6, expected: 1 })
+        );
+        assert_eq!(client.tip().hash(), headers[6].hash());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::blockchain::receipt::Log;
+use crate::blockchain::BlockChain;
+
+// what get_logs looks for, every field left empty matches anything
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    // inclusive height range, `to_height` defaults to the tip
+    pub from_height: u64,
+    pub to_height: Option<u64>,
+    // logs emitted by any of these contracts
+    pub addresses: Vec<Vec<u8>>,
+    // positional like ethereum: the nth entry has to match the nth topic
+    // of the log, None is a wildcard for that position
+    pub topics: Vec<Option<Vec<u8>>>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log: &Log) -> bool {
+        if !self.addresses.is_empty() && !self.addresses.contains(&log.address) {
+            return false;
+        }
+
+        self.topics.iter().enumerate().all(|(i, wanted)| match wanted {
+            Some(topic) => log.topics.get(i) == Some(topic),
+            None => true,
+        })
+    }
+}
+
+// a log together with where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub height: u64,
+    pub transaction_index: usize,
+    pub log: Log,
+}
+
+impl BlockChain {
+    pub fn get_logs(&self, filter: &LogFilter) -> Vec<LogEntry> {
+        let mut found = Vec::<LogEntry>::new();
+        let to_height = filter.to_height.unwrap_or(u64::MAX);
+
+        for (height, block) in self.chain.iter().enumerate() {
+            let height = height as u64;
+            if height < filter.from_height || height > to_height {
+                continue;
+            }
+
+            // the bloom tells which blocks can't have a match without
+            // looking at their receipts
+            let address_possible = filter.addresses.is_empty()
+                || filter.addresses.iter().any(|a| block.header.logs_bloom.contains(a));
+            let topics_possible = filter
+                .topics
+                .iter()
+                .flatten()
+                .all(|topic| block.header.logs_bloom.contains(topic));
+            if !address_possible || !topics_possible {
+                continue;
+            }
+
+            for (transaction_index, receipt) in block.receipts.iter().enumerate() {
+                for log in receipt.logs.iter().filter(|log| filter.matches(log)) {
+                    found.push(LogEntry {
+                        height,
+                        transaction_index,
+                        log: log.clone(),
+                    });
+                }
+            }
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::receipt::{self, Receipt};
+
+    fn log(address: &str, topics: &[&str]) -> Log {
+        Log {
+            address: address.into(),
+            topics: topics.iter().map(|topic| topic.as_bytes().to_vec()).collect(),
+            data: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn get_logs_finds_what_the_filter_asks_for() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        // no contracts without the vm, the receipts get their logs by hand
+        let logs: [Vec<Log>; 3] = [
+            vec![],
+            vec![log("token", &["Transfer", "A"]), log("dex", &["Swap"])],
+            vec![log("token", &["Transfer", "B"])],
+        ];
+        for (block, logs) in chain.chain.iter_mut().zip(logs) {
+            block.receipts = vec![Receipt { success: true, gas_used: 0, logs }];
+            block.header.logs_bloom = receipt::logs_bloom(&block.receipts);
+        }
+        let found = |filter: LogFilter| -> Vec<(u64, Vec<u8>)> {
+            chain.get_logs(&filter).into_iter().map(|entry| (entry.height, entry.log.topics.concat())).collect()
+        };
+
+        let transfer = Some(b"Transfer".to_vec());
+        let tokens = LogFilter { addresses: vec![b"token".to_vec()], ..LogFilter::default() };
+        assert_eq!(found(tokens.clone()), vec![(1, b"TransferA".to_vec()), (2, b"TransferB".to_vec())]);
+        assert_eq!(found(LogFilter { from_height: 2, ..tokens.clone() }).len(), 1);
+        assert_eq!(found(LogFilter { to_height: Some(0), ..tokens }).len(), 0);
+        // topics are positional, None matches anything in its place
+        let to_b = LogFilter { topics: vec![transfer.clone(), Some(b"B".to_vec())], ..LogFilter::default() };
+        assert_eq!(found(to_b), vec![(2, b"TransferB".to_vec())]);
+        let second = LogFilter { topics: vec![None, Some(b"A".to_vec())], ..LogFilter::default() };
+        assert_eq!(found(second), vec![(1, b"TransferA".to_vec())]);
+        let swapped = LogFilter { topics: vec![Some(b"A".to_vec()), transfer], ..LogFilter::default() };
+        assert!(found(swapped).is_empty());
+        assert_eq!(found(LogFilter::default()).len(), 3);
+    }
+}
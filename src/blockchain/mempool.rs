@@ -0,0 +1,548 @@
+use crate::blockchain::transaction::{SignatureError, Transaction};
+use crate::blockchain::utxo::OutPoint;
+use crate::blockchain::{script::ScriptError, state::StateError, BlockChain, DeserializeError};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+// rules applied when a new transaction conflicts (same sender and nonce)
+// with one that is already waiting in the pool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplacementPolicy {
+    // the first transaction seen wins, conflicts are always rejected
+    Disabled,
+    // any pooled transaction can be replaced by one paying at least
+    // `min_increase_percent` more fee
+    FeeBump { min_increase_percent: u64 },
+    // like FeeBump, but only if the original transaction signaled that
+    // it is replaceable
+    OptIn { min_increase_percent: u64 },
+}
+
+impl Default for ReplacementPolicy {
+    fn default() -> Self {
+        ReplacementPolicy::FeeBump {
+            min_increase_percent: 10,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MempoolError {
+    // the bytes offered aren't a transaction
+    Malformed(DeserializeError),
+    AlreadyInPool,
+    ReplacementDisabled { nonce: u64 },
+    NotReplaceable { nonce: u64 },
+    InsufficientFee { old_fee: u64, new_fee: u64, required_fee: u64 },
+    InvalidScript(ScriptError),
+    InvalidPayload(StateError),
+    // signed for another network
+    WrongChain { expected: u64, found: u64 },
+    InvalidSignature(SignatureError),
+    // refused by a policy rule, not because it's invalid
+    Rejected { validator: String, reason: String },
+    // the pool is at its limit and the transaction pays less than
+    // everything in it
+    Full { min_fee_rate: u64 },
+    // a transaction in the pool already spends this output. unlike a
+    // conflict on the nonce it is never replaced, the first spend seen wins
+    DoubleSpend { outpoint: OutPoint },
+    // already used by a mined transaction of the sender
+    NonceTooLow { expected: u64, found: u64 },
+    // leaves a gap after the sender's last transaction, pooled ones
+    // included. it couldn't be mined until the gap is filled
+    NonceTooHigh { expected: u64, found: u64 },
+    // value, fee and the most gas it can burn are more than the sender
+    // can spend at the height of the next block
+    InsufficientFunds { spendable: i64, requested: u64 },
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::Malformed(e) => write!(f, "malformed transaction: {}", e),
+            MempoolError::AlreadyInPool => {
+                write!(f, "transaction is already in the pool")
+            }
+            MempoolError::ReplacementDisabled { nonce } => {
+                write!(
+                    f,
+                    "a transaction with nonce {} is already in the pool and replacement is disabled",
+                    nonce
+                )
+            }
+            MempoolError::NotReplaceable { nonce } => {
+                write!(
+                    f,
+                    "the pooled transaction with nonce {} did not signal replaceability",
+                    nonce
+                )
+            }
+            MempoolError::InsufficientFee {
+                old_fee,
+                new_fee,
+                required_fee,
+            } => {
+                write!(
+                    f,
+                    "replacement fee {} is too low, the pooled transaction pays {} and at least {} is required",
+                    new_fee, old_fee, required_fee
+                )
+            }
+            MempoolError::InvalidScript(e) => {
+                write!(f, "spending conditions not met: {}", e)
+            }
+            MempoolError::InvalidPayload(e) => {
+                write!(f, "invalid payload: {}", e)
+            }
+            MempoolError::WrongChain { expected, found } => {
+                write!(f, "transaction is for chain {}, this is chain {}", found, expected)
+            }
+            MempoolError::InvalidSignature(e) => write!(f, "{}", e),
+            MempoolError::Rejected { validator, reason } => write!(f, "rejected by {}: {}", validator, reason),
+            MempoolError::Full { min_fee_rate } => {
+                write!(f, "the pool is full, a transaction has to pay a fee rate above {}", min_fee_rate)
+            }
+            MempoolError::NonceTooLow { expected, found } => {
+                write!(f, "nonce {} was already used, the sender's next nonce is {}", found, expected)
+            }
+            MempoolError::NonceTooHigh { expected, found } => {
+                write!(f, "nonce {} is too far ahead, the sender's next nonce is {}", found, expected)
+            }
+            MempoolError::InsufficientFunds { spendable, requested } => {
+                write!(f, "{} requested but the sender can only spend {}", requested, spendable)
+            }
+            MempoolError::DoubleSpend { outpoint } => {
+                write!(f, "output {} of {} is already spent by a pooled transaction", outpoint.index, hex::encode(&outpoint.txid))
+            }
+        }
+    }
+}
+
+// fee paid per 1000 weight units of the transaction, see
+// `Transaction::weight`. per unit would round most fees down to nothing.
+// the pool's ordering, the minimum fee rate policy and fee estimation all
+// go by this, so a signature costs the same everywhere
+pub fn fee_rate(tx: &Transaction) -> u64 {
+    let weight = tx.weight() as u128;
+    (tx.fee as u128 * 1000 / weight.max(1)).min(u64::MAX as u128) as u64
+}
+
+// pool transactions paying between `min_fee_rate` and twice that
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeBucket {
+    pub min_fee_rate: u64,
+    pub count: usize,
+    pub weight: u64,
+    // weight of everything in this bucket and the ones paying more, what a
+    // block would have to hold to reach this bucket
+    pub cumulative_weight: u64,
+}
+
+fn is_coinbase(tx: &Transaction) -> bool {
+    tx.sender_address == BlockChain::MINING_SENDER.as_bytes()
+}
+
+// total weight the pool holds by default, a few hundred transactions of
+// the usual size. bitcoind keeps 300 MB
+pub const DEFAULT_MAX_WEIGHT: u64 = 1_000_000;
+
+// what adding a transaction did to the pool
+#[derive(Debug, Clone)]
+pub enum Admitted {
+    // a new one, and the transactions evicted to make room for it
+    Added { evicted: Vec<Transaction> },
+    // it took the place of this conflicting transaction, and evicted
+    // others if it weighs more
+    Replaced { original: Box<Transaction>, evicted: Vec<Transaction> },
+}
+
+#[derive(Debug)]
+pub struct Mempool {
+    // in the order they arrived, `pending` is the order they're mined in
+    transactions: Vec<Transaction>,
+    // txids of `transactions`, so a duplicate is found without comparing
+    // bytes against the whole pool
+    ids: HashSet<Vec<u8>>,
+    policy: ReplacementPolicy,
+    // the pool never weighs more than this, the lowest fee rates are
+    // evicted first
+    max_weight: u64,
+    weight: u64,
+    // bumped on every change to the pool, so anything derived from its
+    // content (like a block template) knows when it became stale. the
+    // miner's coinbase comes and goes with every block and no template
+    // holds it, it doesn't count
+    generation: u64,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new(ReplacementPolicy::default())
+    }
+}
+
+impl Mempool {
+    pub fn new(policy: ReplacementPolicy) -> Self {
+        Mempool {
+            transactions: Vec::<Transaction>::new(),
+            ids: HashSet::<Vec<u8>>::new(),
+            policy,
+            max_weight: DEFAULT_MAX_WEIGHT,
+            weight: 0,
+            generation: 0,
+        }
+    }
+
+    pub fn with_max_weight(mut self, max_weight: u64) -> Self {
+        self.max_weight = max_weight;
+        self
+    }
+
+    // a lower limit evicts right away
+    pub fn set_max_weight(&mut self, max_weight: u64) -> Vec<Transaction> {
+        self.max_weight = max_weight;
+        let evicted = self.evict(0);
+        if !evicted.is_empty() {
+            self.generation += 1;
+        }
+        evicted
+    }
+
+    pub fn max_weight(&self) -> u64 {
+        self.max_weight
+    }
+
+    // total weight of the pooled transactions
+    pub fn weight(&self) -> u64 {
+        self.weight
+    }
+
+    pub fn policy(&self) -> ReplacementPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: ReplacementPolicy) {
+        self.policy = policy;
+    }
+
+    // admission: either the transaction is new, or it replaces a conflicting
+    // one following the configured policy. a new one that doesn't fit
+    // evicts whatever pays a lower fee rate, or is refused if nothing does
+    pub fn add(&mut self, tx: Transaction) -> Result<Admitted, MempoolError> {
+        if self.ids.contains(&tx.id()) {
+            return Err(MempoolError::AlreadyInPool);
+        }
+        let conflict = self.transactions.iter().position(|t| t.conflicts_with(&tx));
+        // the transaction it replaces may spend the same outputs
+        for (index, pooled) in self.transactions.iter().enumerate() {
+            if conflict == Some(index) {
+                continue;
+            }
+            if let Some(outpoint) = tx.payload.inputs().iter().find(|input| pooled.payload.inputs().contains(input)) {
+                return Err(MempoolError::DoubleSpend { outpoint: outpoint.clone() });
+            }
+        }
+
+        let Some(index) = conflict else {
+            self.check_room(&tx, None)?;
+            let evicted = self.evict(tx.weight());
+            if !is_coinbase(&tx) || !evicted.is_empty() {
+                self.generation += 1;
+            }
+            self.insert(tx);
+            return Ok(Admitted::Added { evicted });
+        };
+
+        let original = &self.transactions[index];
+        let min_increase_percent = match self.policy {
+            ReplacementPolicy::Disabled => {
+                return Err(MempoolError::ReplacementDisabled { nonce: tx.nonce });
+            }
+            ReplacementPolicy::OptIn { .. } if !original.replaceable => {
+                return Err(MempoolError::NotReplaceable { nonce: tx.nonce });
+            }
+            ReplacementPolicy::FeeBump { min_increase_percent }
+            | ReplacementPolicy::OptIn { min_increase_percent } => min_increase_percent,
+        };
+
+        // the replacement always has to pay strictly more than the original
+        let bump = (original.fee as u128 * min_increase_percent as u128) / 100;
+        let required_fee = (original.fee as u128 + bump.max(1)).min(u64::MAX as u128) as u64;
+        if tx.fee < required_fee {
+            return Err(MempoolError::InsufficientFee {
+                old_fee: original.fee,
+                new_fee: tx.fee,
+                required_fee,
+            });
+        }
+
+        // a replacement is held to the pool's limit like any other
+        self.check_room(&tx, Some(index))?;
+        let original = self.remove_at(index);
+        let evicted = self.evict(tx.weight());
+        self.insert(tx);
+        self.generation += 1;
+        Ok(Admitted::Replaced {
+            original: Box::new(original),
+            evicted,
+        })
+    }
+
+    // whether `tx` fits once the transactions paying a lower fee rate are
+    // evicted. the one at `replacing` leaves the pool either way
+    fn check_room(&self, tx: &Transaction, replacing: Option<usize>) -> Result<(), MempoolError> {
+        let mut weight: u64 = self.weight;
+        let mut freed: u64 = 0;
+        for (index, pooled) in self.transactions.iter().enumerate() {
+            if replacing == Some(index) {
+                weight -= pooled.weight();
+            } else if fee_rate(pooled) < fee_rate(tx) {
+                freed += pooled.weight();
+            }
+        }
+        let room: u64 = self.max_weight.saturating_sub(tx.weight());
+        if weight > room.saturating_add(freed) || tx.weight() > self.max_weight {
+            let min_fee_rate = self.transactions.iter().map(fee_rate).min().unwrap_or(0);
+            return Err(MempoolError::Full { min_fee_rate });
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        self.weight += tx.weight();
+        self.ids.insert(tx.id());
+        self.transactions.push(tx);
+    }
+
+    fn remove_at(&mut self, index: usize) -> Transaction {
+        let tx = self.transactions.remove(index);
+        self.weight -= tx.weight();
+        self.ids.remove(&tx.id());
+        tx
+    }
+
+    // evicts the lowest fee rates until `incoming` more weight fits, the
+    // newest of them first when they pay the same
+    fn evict(&mut self, incoming: u64) -> Vec<Transaction> {
+        let mut evicted = Vec::<Transaction>::new();
+        while self.weight + incoming > self.max_weight && !self.transactions.is_empty() {
+            let (index, _) = self
+                .transactions
+                .iter()
+                .enumerate()
+                .min_by_key(|(index, tx)| (fee_rate(tx), std::cmp::Reverse(*index)))
+                .expect("the pool isn't empty");
+            evicted.push(self.remove_at(index));
+        }
+        evicted
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    // the pool in the order it would be mined when nothing depends on
+    // anything else: highest fee rate first, then lowest nonce
+    pub fn pending(&self) -> Vec<&Transaction> {
+        let mut pending: Vec<&Transaction> = self.transactions.iter().collect();
+        pending.sort_by(|a, b| fee_rate(b).cmp(&fee_rate(a)).then(a.nonce.cmp(&b.nonce)));
+        pending
+    }
+
+    pub fn contains(&self, txid: &[u8]) -> bool {
+        self.ids.contains(txid)
+    }
+
+    // takes out the transactions a block mined, returns how many were in
+    // the pool
+    pub fn remove_confirmed(&mut self, txids: &[Vec<u8>]) -> usize {
+        let mined: HashSet<&[u8]> = txids.iter().map(|id| id.as_slice()).collect();
+        let before = self.transactions.len();
+        let mut changed: bool = false;
+        let mut index = 0;
+        while index < self.transactions.len() {
+            if mined.contains(self.transactions[index].id().as_slice()) {
+                changed |= !is_coinbase(&self.remove_at(index));
+            } else {
+                index += 1;
+            }
+        }
+        if changed {
+            self.generation += 1;
+        }
+        before - self.transactions.len()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.transactions.clear();
+        self.ids.clear();
+        self.weight = 0;
+        self.generation += 1;
+    }
+
+    // the pool by fee rate, highest first. bucket bounds double (0, 1, 2,
+    // 4, 8, ...), so a handful of buckets covers any spread of fees
+    pub fn fee_histogram(&self) -> Vec<FeeBucket> {
+        let mut buckets: BTreeMap<u64, (usize, u64)> = BTreeMap::new();
+        for tx in self.transactions.iter() {
+            let rate = fee_rate(tx);
+            let min_fee_rate = if rate == 0 { 0 } else { 1 << rate.ilog2() };
+            let bucket = buckets.entry(min_fee_rate).or_default();
+            bucket.0 += 1;
+            bucket.1 += tx.weight();
+        }
+
+        let mut cumulative_weight: u64 = 0;
+        buckets
+            .into_iter()
+            .rev()
+            .map(|(min_fee_rate, (count, weight))| {
+                cumulative_weight += weight;
+                FeeBucket {
+                    min_fee_rate,
+                    count,
+                    weight,
+                    cumulative_weight,
+                }
+            })
+            .collect()
+    }
+
+    // the fee rate that should get a transaction into a block holding
+    // `block_weight` weight units: enough to beat the first bucket that no
+    // longer fits. 0 when the whole pool fits
+    pub fn estimate_fee_rate(&self, block_weight: u64) -> u64 {
+        self.fee_histogram()
+            .iter()
+            .find(|bucket| bucket.cumulative_weight > block_weight)
+            .map_or(0, |bucket| (bucket.min_fee_rate * 2).max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+    use crate::blockchain::Serialization;
+
+    #[test]
+    fn histogram_buckets_the_pool_by_fee_rate_best_paying_first() {
+        let mut pool = Mempool::default();
+        for (i, fee) in [0u64, 0, 40, 100, 100, 5_000].into_iter().enumerate() {
+            let sender = format!("sender {}", i).into_bytes();
+            pool.add(Transaction::new(sender, b"B".to_vec(), 1).with_fee(fee)).unwrap();
+        }
+        let weight = pool.transactions()[0].weight();
+
+        let histogram = pool.fee_histogram();
+        let rates: Vec<u64> = pool.transactions().iter().map(fee_rate).collect();
+        assert_eq!(histogram.iter().map(|b| b.count).collect::<Vec<usize>>(), vec![1, 2, 1, 2]);
+        assert_eq!(histogram[0].min_fee_rate, 1 << rates[5].ilog2());
+        assert_eq!(histogram[3].min_fee_rate, 0);
+        assert_eq!(histogram[3].cumulative_weight, 6 * weight);
+
+        // room for the best three only
+        assert_eq!(pool.estimate_fee_rate(3 * weight), histogram[2].min_fee_rate * 2);
+        assert_eq!(pool.estimate_fee_rate(6 * weight), 0);
+    }
+
+    #[test]
+    fn a_full_pool_evicts_the_lowest_fee_rate() {
+        let tx = |sender: &str, fee: u64| Transaction::new(sender.into(), b"B".to_vec(), 1).with_fee(fee);
+        let weight = tx("A", 10).weight();
+        let mut pool = Mempool::default().with_max_weight(3 * weight);
+        for (sender, fee) in [("A", 10), ("B", 30), ("C", 20)] {
+            pool.add(tx(sender, fee)).unwrap();
+        }
+        let order: Vec<u64> = pool.pending().iter().map(|tx| tx.fee).collect();
+        assert_eq!(order, vec![30, 20, 10]);
+
+        // paying less than everything in it isn't enough
+        let min_fee_rate = fee_rate(&tx("A", 10));
+        assert_eq!(pool.add(tx("D", 5)).unwrap_err(), MempoolError::Full { min_fee_rate });
+        let Admitted::Added { evicted } = pool.add(tx("E", 40)).unwrap() else {
+            panic!("E isn't a replacement");
+        };
+        assert_eq!(evicted.iter().map(|tx| tx.fee).collect::<Vec<u64>>(), vec![10]);
+        assert!(!pool.contains(&tx("A", 10).id()));
+        assert_eq!(pool.add(tx("E", 40)).unwrap_err(), MempoolError::AlreadyInPool);
+
+        assert_eq!(pool.remove_confirmed(&[tx("B", 30).id(), tx("A", 10).id()]), 1);
+        assert_eq!((pool.len(), pool.weight()), (2, 2 * weight));
+        assert_eq!(pool.set_max_weight(weight).len(), 1);
+        assert!(pool.contains(&tx("E", 40).id()));
+    }
+
+    #[test]
+    fn a_conflict_is_replaced_only_as_the_policy_allows() {
+        let tx = |fee: u64| Transaction::new(b"A".to_vec(), b"B".to_vec(), 1).with_fee(fee);
+        let mut pool = Mempool::new(ReplacementPolicy::Disabled);
+        pool.add(tx(10)).unwrap();
+        assert_eq!(pool.add(tx(100)).unwrap_err(), MempoolError::ReplacementDisabled { nonce: 0 });
+
+        // opting in is the original's call
+        pool.set_policy(ReplacementPolicy::OptIn { min_increase_percent: 10 });
+        assert_eq!(pool.add(tx(100)).unwrap_err(), MempoolError::NotReplaceable { nonce: 0 });
+        let mut pool = Mempool::new(ReplacementPolicy::OptIn { min_increase_percent: 10 });
+        pool.add(tx(10).replaceable()).unwrap();
+        let low = MempoolError::InsufficientFee {
+            old_fee: 10,
+            new_fee: 10,
+            required_fee: 11,
+        };
+        assert_eq!(pool.add(tx(10)).unwrap_err(), low);
+        let Admitted::Replaced { original, evicted } = pool.add(tx(11)).unwrap() else {
+            panic!("11 replaces 10");
+        };
+        assert_eq!((original.fee, evicted.len()), (10, 0));
+        assert_eq!(pool.transactions().iter().map(|t| t.fee).collect::<Vec<u64>>(), vec![11]);
+    }
+
+    #[test]
+    fn a_replacement_makes_room_like_any_other_transaction() {
+        let tx = |sender: &str, fee: u64| Transaction::new(sender.into(), b"B".to_vec(), 1).with_fee(fee);
+        // the same sender and nonce, 20 more bytes
+        let heavy = |fee: u64| Transaction::new(b"A".to_vec(), vec![b'B'; 21], 1).with_fee(fee);
+        let weight = tx("A", 10).weight();
+        let mut pool = Mempool::default().with_max_weight(2 * weight);
+        pool.add(tx("A", 10)).unwrap();
+        pool.add(tx("B", 30)).unwrap();
+
+        // enough of a bump, but it doesn't fit next to B and pays less
+        let min_fee_rate = fee_rate(&tx("A", 10));
+        assert_eq!(pool.add(heavy(12)).unwrap_err(), MempoolError::Full { min_fee_rate });
+        assert_eq!(pool.weight(), 2 * weight);
+
+        let Admitted::Replaced { original, evicted } = pool.add(heavy(60)).unwrap() else {
+            panic!("the heavy one conflicts with A");
+        };
+        assert_eq!((original.fee, evicted.iter().map(|t| t.fee).collect::<Vec<u64>>()), (10, vec![30]));
+        assert_eq!(pool.weight(), heavy(60).weight());
+        assert!(pool.weight() <= pool.max_weight());
+    }
+
+    #[test]
+    fn a_signature_weighs_less_than_the_rest_of_the_transaction() {
+        let wallet = test_wallet("A");
+        let unsigned = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).with_fee(100);
+        let signed = unsigned.clone().sign(&wallet);
+        let signature_bytes = signed.serialization().len() - unsigned.serialization().len();
+
+        assert_eq!(signed.witness_size(), signature_bytes as u64);
+        assert_eq!(signed.weight(), unsigned.weight() + signature_bytes as u64);
+        assert!(fee_rate(&signed) > fee_rate(&unsigned) * 3 / 4);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::blockchain::hash32::Hash32;
+use sha2::{Digest, Sha256};
+
+// the id of a transaction is the hash of its serialized bytes, the same
+// bytes a block stores
+pub fn txid(tx_bytes: &[u8]) -> Vec<u8> {
+    Sha256::digest(tx_bytes).to_vec()
+}
+
+// leaves and inner nodes are hashed with different prefixes so a node can
+// never be passed off as a transaction
+fn leaf_hash(txid: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(txid);
+    hasher.finalize().to_vec()
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+// one level up: pairs are hashed together, an odd node out is carried up
+// as it is (bitcoin duplicates it instead, which lets two different
+// transaction lists share a root)
+fn next_level(level: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => single.clone(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// the root of an empty block is all zeros
+pub fn merkle_root(txids: &[Vec<u8>]) -> Hash32 {
+    if txids.is_empty() {
+        return Hash32::ZERO;
+    }
+
+    let mut level: Vec<Vec<u8>> = txids.iter().map(|id| leaf_hash(id)).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    Hash32::from_slice(&level[0]).expect("a node is a sha256")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleStep {
+    pub hash: Vec<u8>,
+    // whether `hash` goes on the left when combining
+    pub is_left: bool,
+}
+
+// the hashes needed to climb from a transaction to the root, levels
+// where the node was carried up have no step
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub steps: Vec<MerkleStep>,
+}
+
+pub fn merkle_proof(txids: &[Vec<u8>], txid: &[u8]) -> Option<MerkleProof> {
+    let mut index = txids.iter().position(|id| id == txid)?;
+    let mut level: Vec<Vec<u8>> = txids.iter().map(|id| leaf_hash(id)).collect();
+    let mut steps = Vec::<MerkleStep>::new();
+
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            steps.push(MerkleStep {
+                hash: level[sibling].clone(),
+                is_left: sibling < index,
+            });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+
+    Some(MerkleProof { steps })
+}
+
+pub fn verify_merkle_proof(root: &[u8], proof: &MerkleProof, txid: &[u8]) -> bool {
+    let mut hash: Vec<u8> = leaf_hash(txid);
+    for step in proof.steps.iter() {
+        hash = if step.is_left {
+            node_hash(&step.hash, &hash)
+        } else {
+            node_hash(&hash, &step.hash)
+        };
+    }
+    hash == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txids(count: u8) -> Vec<Vec<u8>> {
+        (0..count).map(|n| txid(&[n])).collect()
+    }
+
+    #[test]
+    fn every_transaction_proves_against_the_root_at_any_count() {
+        assert_eq!(merkle_root(&[]), Hash32::ZERO);
+        // a single transaction is its own root, with nothing to climb
+        let single: Vec<Vec<u8>> = txids(1);
+        assert_eq!(merkle_root(&single).as_bytes().to_vec(), leaf_hash(&single[0]));
+        assert_eq!(merkle_proof(&single, &single[0]).unwrap().steps, vec![]);
+
+        for count in 1..=9u8 {
+            let ids: Vec<Vec<u8>> = txids(count);
+            let root: Hash32 = merkle_root(&ids);
+            for id in ids.iter() {
+                let proof: MerkleProof = merkle_proof(&ids, id).unwrap();
+                assert!(verify_merkle_proof(root.as_bytes(), &proof, id), "{} of {}", hex::encode(id), count);
+            }
+        }
+        assert_eq!(merkle_proof(&txids(3), &txid(b"missing")), None);
+    }
+
+    #[test]
+    fn an_odd_one_out_is_carried_up_not_duplicated() {
+        // three leaves: the last climbs a level on its own, no step for it
+        let ids: Vec<Vec<u8>> = txids(3);
+        let expected = node_hash(&node_hash(&leaf_hash(&ids[0]), &leaf_hash(&ids[1])), &leaf_hash(&ids[2]));
+        assert_eq!(merkle_root(&ids).as_bytes().to_vec(), expected);
+        assert_eq!(merkle_proof(&ids, &ids[2]).unwrap().steps.len(), 1);
+
+        // bitcoin's duplication would give [a, b, c] and [a, b, c, c] one root
+        let mut doubled: Vec<Vec<u8>> = ids.clone();
+        doubled.push(ids[2].clone());
+        assert_ne!(merkle_root(&ids), merkle_root(&doubled));
+    }
+
+    #[test]
+    fn a_tampered_proof_is_refused() {
+        let ids: Vec<Vec<u8>> = txids(5);
+        let root: Hash32 = merkle_root(&ids);
+        let proof: MerkleProof = merkle_proof(&ids, &ids[1]).unwrap();
+        assert!(verify_merkle_proof(root.as_bytes(), &proof, &ids[1]));
+        assert!(!verify_merkle_proof(root.as_bytes(), &proof, &ids[0]));
+
+        let mut sibling: MerkleProof = proof.clone();
+        sibling.steps[0].hash[0] ^= 1;
+        assert!(!verify_merkle_proof(root.as_bytes(), &sibling, &ids[1]));
+        let mut side: MerkleProof = proof.clone();
+        side.steps[0].is_left = !side.steps[0].is_left;
+        assert!(!verify_merkle_proof(root.as_bytes(), &side, &ids[1]));
+        let mut short: MerkleProof = proof;
+        short.steps.pop();
+        assert!(!verify_merkle_proof(root.as_bytes(), &short, &ids[1]));
+    }
+}
@@ -0,0 +1,187 @@
+use crate::blockchain::health::NodeError;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// a running count and total, enough for the average and the last value
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Timing {
+    pub count: u64,
+    pub last: Duration,
+    pub total: Duration,
+}
+
+impl Timing {
+    fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.last = duration;
+        self.total += duration;
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.count as u32
+    }
+}
+
+// a copy of everything collected so far
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    // time between the timestamps of consecutive blocks
+    pub block_intervals: Timing,
+    // how long full chain validations took
+    pub validation_latency: Timing,
+    pub mempool_added: u64,
+    pub mempool_replaced: u64,
+    pub mempool_rejected: u64,
+    // left the pool because they were mined
+    pub mempool_removed: u64,
+    pub mempool_size: u64,
+    pub peer_count: u64,
+    pub reorgs: u64,
+    // blocks taken off the chain by all the reorgs together
+    pub reorged_blocks: u64,
+    pub errors: u64,
+    pub last_error: Option<NodeError>,
+    // why the last write to the node's files failed, none once one works
+    pub storage_error: Option<String>,
+}
+
+impl MetricsSnapshot {
+    // prometheus text exposition format, what a /metrics endpoint serves
+    pub fn to_prometheus(&self) -> String {
+        let lines: Vec<(&str, &str, f64)> = vec![
+            ("blocks_produced_total", "counter", self.block_intervals.count as f64),
+            ("block_interval_seconds_avg", "gauge", self.block_intervals.average().as_secs_f64()),
+            ("validations_total", "counter", self.validation_latency.count as f64),
+            ("validation_seconds_last", "gauge", self.validation_latency.last.as_secs_f64()),
+            ("validation_seconds_avg", "gauge", self.validation_latency.average().as_secs_f64()),
+            ("mempool_added_total", "counter", self.mempool_added as f64),
+            ("mempool_replaced_total", "counter", self.mempool_replaced as f64),
+            ("mempool_rejected_total", "counter", self.mempool_rejected as f64),
+            ("mempool_removed_total", "counter", self.mempool_removed as f64),
+            ("mempool_size", "gauge", self.mempool_size as f64),
+            ("peers", "gauge", self.peer_count as f64),
+            ("reorgs_total", "counter", self.reorgs as f64),
+            ("reorged_blocks_total", "counter", self.reorged_blocks as f64),
+            ("errors_total", "counter", self.errors as f64),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, value) in lines {
+            out.push_str(&format!("# TYPE blockchain_{} {}\nblockchain_{} {}\n", name, kind, name, value));
+        }
+        out
+    }
+}
+
+// collected while the node runs, recording only needs a shared reference
+// so read only paths like validation can report too
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<MetricsSnapshot>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.inner.lock().unwrap().clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut MetricsSnapshot)) {
+        f(&mut self.inner.lock().unwrap());
+    }
+
+    pub fn record_block_interval(&self, interval: Duration) {
+        self.update(|m| m.block_intervals.record(interval));
+    }
+
+    pub fn record_validation(&self, latency: Duration) {
+        self.update(|m| m.validation_latency.record(latency));
+    }
+
+    pub fn record_mempool_added(&self, size: usize) {
+        self.update(|m| {
+            m.mempool_added += 1;
+            m.mempool_size = size as u64;
+        });
+    }
+
+    pub fn record_mempool_replaced(&self) {
+        self.update(|m| m.mempool_replaced += 1);
+    }
+
+    pub fn record_mempool_rejected(&self) {
+        self.update(|m| m.mempool_rejected += 1);
+    }
+
+    pub fn record_mempool_removed(&self, removed: usize, size: usize) {
+        self.update(|m| {
+            m.mempool_removed += removed as u64;
+            m.mempool_size = size as u64;
+        });
+    }
+
+    pub fn record_peer_count(&self, peers: usize) {
+        self.update(|m| m.peer_count = peers as u64);
+    }
+
+    pub fn record_reorg(&self, depth: u64) {
+        self.update(|m| {
+            m.reorgs += 1;
+            m.reorged_blocks += depth;
+        });
+    }
+
+    pub fn record_error(&self, error: NodeError) {
+        self.update(|m| {
+            m.errors += 1;
+            m.last_error = Some(error);
+        });
+    }
+
+    pub fn record_storage(&self, error: Option<String>) {
+        self.update(|m| m.storage_error = error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::transaction::Transaction;
+    use crate::blockchain::BlockChain;
+
+    #[test]
+    fn mining_and_the_pool_show_up_in_the_counters() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 1 {
+            chain.mining().unwrap();
+        }
+        let before: MetricsSnapshot = chain.metrics();
+        let tx = |nonce: u64| Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).with_nonce(nonce);
+
+        chain.add_transaction(&tx(0).sign(&wallet)).unwrap();
+        // nobody signed it
+        assert!(chain.add_transaction(&tx(1)).is_err());
+        let admitted: MetricsSnapshot = chain.metrics();
+        assert_eq!(admitted.mempool_added, before.mempool_added + 1);
+        assert_eq!(admitted.mempool_rejected, before.mempool_rejected + 1);
+        assert_eq!(admitted.mempool_size, 1);
+
+        chain.mining().unwrap();
+        let mined: MetricsSnapshot = chain.metrics();
+        assert_eq!(mined.block_intervals.count, before.block_intervals.count + 1);
+        // the coinbase goes through the pool too, and leaves it with the payment
+        assert_eq!(mined.mempool_added, admitted.mempool_added + 1);
+        assert_eq!(mined.mempool_removed, admitted.mempool_removed + 2);
+        assert_eq!(mined.mempool_size, 0);
+        let produced: String = format!("blockchain_blocks_produced_total {}\n", mined.block_intervals.count);
+        assert!(mined.to_prometheus().contains(&produced));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::blockchain::audit::AuditEvent;
+use crate::blockchain::error::BlockchainError;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::pow;
+use crate::blockchain::profile::{BlockProfile, Stage};
+use crate::blockchain::state::State;
+use crate::blockchain::{Block, BlockChain};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::info_span;
+
+// a block built on the tip that still needs its proof of work. its
+// transactions were applied to `state`, a copy of the chain's that
+// replaces it once the block is found, so the chain doesn't change until
+// then
+#[derive(Debug)]
+pub(crate) struct PendingBlock {
+    pub(crate) block: Block,
+    pub(crate) state: State,
+    // audit log entries for the block, recorded when it is appended
+    pub(crate) events: Vec<(AuditEvent, String)>,
+    // txids of the pool transactions the block took
+    pub(crate) taken: Vec<Vec<u8>>,
+    pub(crate) fees_collected: i64,
+    pub(crate) started: Instant,
+    // the stages so far, finish_block adds the last ones
+    pub(crate) profile: BlockProfile,
+}
+
+// how far a block mined in the background got
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MiningProgress {
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningProgress {
+    pub fn hash_rate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+// a block being mined on its own thread, see `BlockChain::mine_async`
+#[derive(Debug)]
+pub struct MiningHandle {
+    thread: JoinHandle<Result<Hash32, BlockchainError>>,
+    cancel: Arc<AtomicBool>,
+    tried: Arc<AtomicU64>,
+    started: Instant,
+}
+
+impl MiningHandle {
+    // blocks until mining is over: the hash of the block it appended, or
+    // why there is none
+    pub fn wait(self) -> Result<Hash32, BlockchainError> {
+        self.thread.join().expect("the mining thread panicked")
+    }
+
+    // stops the search, `wait` then returns `MiningCancelled`. too late
+    // once the block is found
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    // whether `wait` would return straight away
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    pub fn progress(&self) -> MiningProgress {
+        MiningProgress {
+            hashes: self.tried.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+// a thread that panicked holding the chain left it as consistent as any
+// other, the chain only changes between blocks
+fn lock(chain: &Mutex<BlockChain>) -> MutexGuard<'_, BlockChain> {
+    chain.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl BlockChain {
+    // mines the next block for the configured address on a thread of its
+    // own. the chain is locked while the block is built and again while it
+    // is appended, not during proof of work, so the node keeps taking
+    // transactions and answering queries in between. those transactions
+    // wait for the next block. if some other block lands on the tip first
+    // this one is dropped and `wait` returns `UnknownParent`
+    pub fn mine_async(chain: &Arc<Mutex<BlockChain>>) -> MiningHandle {
+        let (cancel, recipient): (Arc<AtomicBool>, String) = {
+            let chain = lock(chain);
+            (chain.mining_cancel_handle(), chain.blockchain_address.clone())
+        };
+        // a cancel that came before this block was for an earlier one
+        cancel.store(false, Ordering::Relaxed);
+        let tried = Arc::new(AtomicU64::new(0));
+
+        let thread = {
+            let (chain, cancel, tried) = (Arc::clone(chain), Arc::clone(&cancel), Arc::clone(&tried));
+            thread::spawn(move || {
+                let (mut pending, threads) = {
+                    let mut chain = lock(&chain);
+                    let _span = info_span!("mining", height = chain.chain.len(), recipient = recipient.as_str()).entered();
+                    let pending: PendingBlock = chain.prepare_mining(&recipient)?;
+                    let threads: usize = chain.pow_threads(pending.block.header.difficulty);
+                    (pending, threads)
+                };
+                let found: Option<Hash32> = pending
+                    .profile
+                    .time(Stage::ProofOfWork, || pow::work(&mut pending.block, threads, &cancel, &tried));
+
+                let mut chain = lock(&chain);
+                let Some(hash) = found else {
+                    chain.abandon_block(&pending);
+                    return Err(BlockchainError::MiningCancelled);
+                };
+                chain.finish_block(pending)?;
+                Ok(hash)
+            })
+        };
+
+        MiningHandle {
+            thread,
+            cancel,
+            tried,
+            started: Instant::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_chain_answers_while_a_block_is_mined() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        let height: usize = chain.blocks().len();
+        // nobody finds 12 zeros in a test, the block only ends when cancelled
+        chain.difficulty = 12;
+        chain.set_mining_threads(2);
+        let chain = Arc::new(Mutex::new(chain));
+
+        let handle: MiningHandle = BlockChain::mine_async(&chain);
+        while handle.progress().hashes == 0 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(lock(&chain).blocks().len(), height);
+        assert_eq!(lock(&chain).pending_transactions().len(), 1);
+        handle.cancel();
+        assert_eq!(handle.wait(), Err(BlockchainError::MiningCancelled));
+        assert!(lock(&chain).pending_transactions().is_empty());
+
+        lock(&chain).difficulty = 0;
+        let hash: Hash32 = BlockChain::mine_async(&chain).wait().unwrap();
+        assert_eq!(lock(&chain).last_block().unwrap().hash(), hash);
+        assert_eq!(lock(&chain).blocks().len(), height + 1);
+    }
+}
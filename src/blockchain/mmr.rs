@@ -0,0 +1,252 @@
+use crate::blockchain::hash32::Hash32;
+use sha2::{Digest, Sha256};
+
+fn leaf_hash(item: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(item);
+    hasher.finalize().to_vec()
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().to_vec()
+}
+
+// the sizes of the perfect trees ("mountains") holding `leaf_count`
+// leaves, largest first: one per set bit of the count
+fn mountain_sizes(leaf_count: u64) -> Vec<u64> {
+    (0..64)
+        .rev()
+        .map(|bit| 1u64 << bit)
+        .filter(|size| leaf_count & size != 0)
+        .collect()
+}
+
+// peaks are folded right to left, the leaf count goes in too so a proof
+// is only valid for the size it was made for
+fn bag_peaks(leaf_count: u64, peaks: &[Vec<u8>]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(leaf_count.to_be_bytes());
+    if let Some((last, rest)) = peaks.split_last() {
+        let bagged = rest
+            .iter()
+            .rev()
+            .fold(last.clone(), |acc, peak| node_hash(peak, &acc));
+        hasher.update(bagged);
+    }
+    hasher.finalize().to_vec()
+}
+
+// merkle root of a perfect tree, `leaves` has a power of two length
+fn perfect_root(leaves: &[Vec<u8>]) -> Vec<u8> {
+    let mut level: Vec<Vec<u8>> = leaves.to_vec();
+    while level.len() > 1 {
+        level = level.chunks(2).map(|pair| node_hash(&pair[0], &pair[1])).collect();
+    }
+    level.remove(0)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmrProof {
+    pub leaf_index: u64,
+    pub leaf_count: u64,
+    // from the leaf up to the peak of its mountain
+    pub siblings: Vec<Vec<u8>>,
+    // every peak, the one of the leaf's mountain included
+    pub peaks: Vec<Vec<u8>>,
+}
+
+// an append only accumulator: a list of perfect merkle trees that merge
+// like a binary counter, so appending and proving are O(log n) and an old
+// leaf stays provable against any later root
+#[derive(Debug, Clone, Default)]
+pub struct MerkleMountainRange {
+    leaves: Vec<Vec<u8>>,
+    // (tree height, root) of each mountain, largest first
+    peaks: Vec<(u32, Vec<u8>)>,
+}
+
+impl MerkleMountainRange {
+    pub fn new() -> Self {
+        MerkleMountainRange {
+            leaves: Vec::new(),
+            peaks: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.leaves.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn push(&mut self, item: &[u8]) {
+        let leaf = leaf_hash(item);
+        self.leaves.push(leaf.clone());
+
+        // two mountains of the same height merge, like carrying a bit
+        let mut peak: (u32, Vec<u8>) = (0, leaf);
+        while let Some((height, _)) = self.peaks.last() {
+            if *height != peak.0 {
+                break;
+            }
+            let (height, left) = self.peaks.pop().unwrap();
+            peak = (height + 1, node_hash(&left, &peak.1));
+        }
+        self.peaks.push(peak);
+    }
+
+    pub fn root(&self) -> Hash32 {
+        let peaks: Vec<Vec<u8>> = self.peaks.iter().map(|(_, hash)| hash.clone()).collect();
+        Hash32::from_slice(&bag_peaks(self.len(), &peaks)).expect("the bagged peaks are a sha256")
+    }
+
+    fn peaks_at(&self, leaf_count: u64) -> Option<Vec<Vec<u8>>> {
+        if leaf_count > self.len() {
+            return None;
+        }
+
+        let mut start: usize = 0;
+        let mut peaks = Vec::<Vec<u8>>::new();
+        for size in mountain_sizes(leaf_count) {
+            peaks.push(perfect_root(&self.leaves[start..start + size as usize]));
+            start += size as usize;
+        }
+        Some(peaks)
+    }
+
+    // proof that leaf `leaf_index` is under the root of the first
+    // `leaf_count` leaves
+    pub fn prove(&self, leaf_index: u64, leaf_count: u64) -> Option<MmrProof> {
+        if leaf_index >= leaf_count {
+            return None;
+        }
+        let peaks = self.peaks_at(leaf_count)?;
+
+        // find the mountain holding the leaf
+        let mut start: u64 = 0;
+        let mut size: u64 = 0;
+        for mountain in mountain_sizes(leaf_count) {
+            if leaf_index < start + mountain {
+                size = mountain;
+                break;
+            }
+            start += mountain;
+        }
+
+        let mut level: Vec<Vec<u8>> = self.leaves[start as usize..(start + size) as usize].to_vec();
+        let mut index = (leaf_index - start) as usize;
+        let mut siblings = Vec::<Vec<u8>>::new();
+        while level.len() > 1 {
+            siblings.push(level[index ^ 1].clone());
+            level = level.chunks(2).map(|pair| node_hash(&pair[0], &pair[1])).collect();
+            index /= 2;
+        }
+
+        Some(MmrProof {
+            leaf_index,
+            leaf_count,
+            siblings,
+            peaks,
+        })
+    }
+}
+
+pub fn verify_mmr_proof(root: &[u8], item: &[u8], proof: &MmrProof) -> bool {
+    if proof.leaf_index >= proof.leaf_count {
+        return false;
+    }
+
+    let sizes = mountain_sizes(proof.leaf_count);
+    if sizes.len() != proof.peaks.len() {
+        return false;
+    }
+
+    let mut start: u64 = 0;
+    for (mountain, size) in sizes.iter().enumerate() {
+        if proof.leaf_index >= start + size {
+            start += size;
+            continue;
+        }
+
+        if 1u64 << proof.siblings.len() != *size {
+            return false;
+        }
+
+        let mut hash: Vec<u8> = leaf_hash(item);
+        let mut index = proof.leaf_index - start;
+        for sibling in proof.siblings.iter() {
+            hash = if index & 1 == 0 {
+                node_hash(&hash, sibling)
+            } else {
+                node_hash(sibling, &hash)
+            };
+            index /= 2;
+        }
+
+        return hash == proof.peaks[mountain] && bag_peaks(proof.leaf_count, &proof.peaks) == root;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(n: u64) -> Vec<u8> {
+        n.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn an_old_leaf_proves_against_every_later_root() {
+        let mut mmr = MerkleMountainRange::new();
+        let mut roots = Vec::<Hash32>::new();
+        for n in 0..9 {
+            mmr.push(&item(n));
+            roots.push(mmr.root());
+        }
+
+        for count in 1..=9u64 {
+            let root: &Hash32 = &roots[count as usize - 1];
+            for leaf in 0..count {
+                let proof: MmrProof = mmr.prove(leaf, count).unwrap();
+                assert!(verify_mmr_proof(root.as_bytes(), &item(leaf), &proof), "leaf {} of {}", leaf, count);
+                assert!(!verify_mmr_proof(root.as_bytes(), &item(leaf + 1), &proof));
+            }
+        }
+        assert_eq!(mmr.prove(9, 9), None);
+        assert_eq!(mmr.prove(0, 10), None);
+    }
+
+    #[test]
+    fn a_tampered_proof_is_refused() {
+        let mut mmr = MerkleMountainRange::new();
+        for n in 0..7 {
+            mmr.push(&item(n));
+        }
+        let root: Hash32 = mmr.root();
+        // 7 leaves are mountains of 4, 2 and 1, leaf 5 sits in the middle one
+        let proof: MmrProof = mmr.prove(5, 7).unwrap();
+        assert!(verify_mmr_proof(root.as_bytes(), &item(5), &proof));
+
+        let mut sibling: MmrProof = proof.clone();
+        sibling.siblings[0][0] ^= 1;
+        assert!(!verify_mmr_proof(root.as_bytes(), &item(5), &sibling));
+        let mut peak: MmrProof = proof.clone();
+        peak.peaks[0][0] ^= 1;
+        assert!(!verify_mmr_proof(root.as_bytes(), &item(5), &peak));
+        // the count is bagged into the root, the proof is for 7 leaves only
+        let mut count: MmrProof = proof.clone();
+        count.leaf_count = 6;
+        assert!(!verify_mmr_proof(root.as_bytes(), &item(5), &count));
+        let mut index: MmrProof = proof;
+        index.leaf_index = 4;
+        assert!(!verify_mmr_proof(root.as_bytes(), &item(5), &index));
+    }
+}
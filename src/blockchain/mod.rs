@@ -1,23 +1,176 @@
-use std::{panic, time::SystemTime};
-use std::ops::AddAssign;
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
+use std::ops::{AddAssign, Range};
 use std::cmp::PartialEq;
-use std::ops::Index;
-use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info, info_span, warn};
 use transaction::*;
+use address::Address;
+use audit::{AuditEvent, AuditLog};
+use balances::BalanceIndex;
+use bloom::Bloom;
+use clock::NetworkClock;
+use config::ChainConfig;
+use confirmations::ConfirmationTracker;
+use difficulty::Retarget;
+use error::BlockchainError;
+use events::{ChainEvent, EventBus};
+use filter::FilterHeaders;
+use gas::GasMeter;
+use genesis::GenesisConfig;
+use hash32::Hash32;
+use index::ChainIndex;
+use mempool::*;
+use metrics::{Metrics, MetricsSnapshot};
+use mining::PendingBlock;
+use merkle::MerkleProof;
+use mmr::MerkleMountainRange;
+use peers::BanList;
+use profile::{BlockProfile, Stage};
+use receipt::Receipt;
+use script::*;
+use state::*;
+use storage::BlockStore;
+use sync::SyncTracker;
+use template::*;
+use validator::{TxContext, TxPipeline};
 
+pub mod address;
+pub mod announcement;
+pub mod asset;
+pub mod audit;
+pub mod balances;
+pub mod bloom;
+pub mod chaos;
+pub mod clock;
+pub mod coin_selection;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod config;
+pub mod confirmations;
+pub mod consensus;
+pub mod diff;
+pub mod difficulty;
+pub mod dot;
+pub mod error;
+pub mod error_code;
+pub mod events;
+#[cfg(feature = "explorer")]
+pub mod explorer;
+pub mod fast_sync;
+pub mod filter;
+pub mod fraud;
+pub mod gas;
+pub mod genesis;
+pub mod governance;
+pub mod hash32;
+pub mod health;
+pub mod index;
+pub mod json;
+pub mod keystore;
+pub mod ledger;
+pub mod light;
+pub mod logs;
+pub mod mempool;
+pub mod merkle;
+pub mod metrics;
+pub mod mining;
+pub mod mmr;
+pub mod names;
+pub mod node;
+pub mod peers;
+pub mod pow;
+pub mod precompile;
+pub mod privacy;
+pub mod profile;
+pub mod protocol;
+pub mod receipt;
+pub mod regtest;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod script;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod simulation;
+pub mod stamp;
+pub mod state;
+pub mod state_proof;
+pub mod storage;
+pub mod sync;
+pub mod template;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 pub mod transaction;
+pub mod trie;
+pub mod utxo;
+pub mod validation;
+pub mod validator;
+pub mod vectors;
+pub mod verify;
+pub mod view;
+#[cfg(feature = "vm")]
+pub mod vm;
+pub mod wallet;
+pub mod wallets;
+#[cfg(feature = "ws")]
+pub mod ws;
 
+// the byte layout hashes, the block file and the wire use. reading
+// never trusts the bytes: whatever comes from a peer or a damaged file is
+// an error, not a panic and not a value made of garbage
 pub trait Serialization<T> {
     fn serialization(&self) -> Vec<u8>;
-    fn deserialization(bytes: &Vec<u8>) -> T;
+    fn deserialization(bytes: &[u8]) -> Result<T, DeserializeError>;
 }
 
+// why bytes aren't the value they were read as. offsets are from the
+// start of the bytes being read, which may be a part of a larger message
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeserializeError {
+    // the bytes end before the field at `offset`, which needs `needed`
+    Truncated { offset: usize, needed: usize },
+    // a length prefix that isn't the one the field always has, e.g. 8 for
+    // an amount
+    InvalidLength { offset: usize, expected: usize, found: usize },
+    // the tag of an enum (a payload, a message) that doesn't exist
+    UnknownTag { kind: &'static str, tag: u8 },
+    // a flag that is neither 0 nor 1. any other byte would decode to the
+    // same transaction under a different id
+    InvalidBool { offset: usize, byte: u8 },
+    // a list with more items than it may hold
+    TooLong { count: u64, max: u64 },
+    // the value ended and there are bytes left
+    TrailingBytes { count: usize },
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::Truncated { offset, needed } => {
+                write!(f, "the bytes end before offset {} where {} more are needed", offset, needed)
+            }
+            DeserializeError::InvalidLength { offset, expected, found } => {
+                write!(f, "the field at offset {} is {} bytes, it has to be {}", offset, found, expected)
+            }
+            DeserializeError::UnknownTag { kind, tag } => write!(f, "there is no {} with tag {}", kind, tag),
+            DeserializeError::InvalidBool { offset, byte } => {
+                write!(f, "the flag at offset {} is {}, it has to be 0 or 1", offset, byte)
+            }
+            DeserializeError::TooLong { count, max } => write!(f, "a list of {} items, at most {} are allowed", count, max),
+            DeserializeError::TrailingBytes { count } => write!(f, "{} bytes are left after the value", count),
+        }
+    }
+}
+
+impl std::error::Error for DeserializeError {}
+
 pub enum BlockSearch {
     // tag value
     SearchByIndex(usize),
-    SearchByPreviousHash(Vec<u8>),
-    SearchByBlockHash(Vec<u8>),
+    SearchByPreviousHash(Hash32),
+    SearchByBlockHash(Hash32),
     SearchByNonce(i32),
     SearchByTimestamp(u128),
     SearchByTransaction(Vec<u8>),
@@ -29,224 +182,838 @@ pub enum BlockSearchResult<'a> {
     Success(&'a Block),
     FailOfEmptyBlocks,
     FailOfIndex(usize),
-    FailOfPreviousHash(Vec<u8>),
-    FailOfBlockHash(Vec<u8>),
+    FailOfPreviousHash(Hash32),
+    FailOfBlockHash(Hash32),
     FailOfNonce(i32),
     FailOfTimestamp(u128),
     FailOfTransaction(Vec<u8>),
 }
 
-#[derive(Debug)]
-pub struct Block {
+// the version new blocks are mined with, the only one this node
+// understands. a change to what a block means bumps it
+pub const BLOCK_VERSION: u32 = 1;
+
+// everything the block hash covers, enough to check proof of work and
+// linkage without the transactions. a peer can follow the chain through
+// headers alone and only download the bodies it wants
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockHeader {
+    pub version: u32,
     pub nonce: i32,
-    pub previous_hash: Vec<u8>,
+    pub previous_hash: Hash32,
     pub time_stamp: u128,
+    // leading zero hex digits the hash has to have
+    pub difficulty: usize,
+    // the transactions are committed through their merkle root, so a
+    // transaction can be shown to be in the block without the others
+    pub merkle_root: Hash32,
+    pub state_root: Hash32,
+    pub logs_bloom: Bloom,
+    pub receipts_root: Hash32,
+    // root of the mountain range over the hashes of every earlier block
+    pub mmr_root: Hash32,
+}
+
+impl BlockHeader {
+    // the hash of the header's bytes, as peers are sent them
+    pub fn hash(&self) -> Hash32 {
+        Hash32::digest(&self.serialization())
+    }
+}
+
+// a header and its body, the transactions the header's merkle root
+// commits to and the receipts executing them produced
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Block {
+    // the merkle root in it is worked out once when the transactions are
+    // final instead of on every hash. whoever changes `transactions` calls
+    // `seal_transactions`
+    pub header: BlockHeader,
     pub transactions: Vec<Vec<u8>>,
+    // one per transaction, produced by executing the block. only the bloom
+    // and the root are part of the hash, the receipts can be rebuilt by
+    // replaying
+    pub receipts: Vec<Receipt>,
+    // the hash, worked out once when the block is sealed after proof of
+    // work so lookups don't rehash the header every time. none until then,
+    // and cleared again when mining changes the nonce or the transactions.
+    // never read from outside, a deserialized block hashes its header
+    #[cfg_attr(feature = "serde", serde(skip))]
+    hash: Option<Hash32>,
 }
 
 impl AddAssign<i32> for Block {
     fn add_assign(&mut self, rhs: i32) {
-        self.nonce += rhs;
+        self.header.nonce += rhs;
+        self.hash = None;
     }
 }
 
+// field by field, the cached hash aside: a block read back from bytes
+// isn't sealed yet and is still the same block. two blocks with the same
+// header but different receipts are not equal, even though they hash the
+// same
 impl PartialEq for Block {
     fn eq(&self, other: &Self) -> bool {
-        let self_hash: Vec<u8> = self.hash();
-        let other_hash: Vec<u8> = self.hash();
-        self_hash == other_hash
+        self.header == other.header && self.transactions == other.transactions && self.receipts == other.receipts
+    }
+}
+
+impl Eq for Block {}
+
+// by the block hash, the cached one when the block is sealed. equal blocks
+// have equal headers, so they land in the same bucket
+impl std::hash::Hash for Block {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Block::hash(self).hash(state);
     }
 }
 
 impl Block {
     // TODO: consider if we need to make this private
-    pub fn new(nonce: i32, previous_hash: Vec<u8>) -> Self {
+    pub fn new(nonce: i32, previous_hash: Hash32) -> Self {
         let time_now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap();
 
         Block {
-            nonce: nonce,
-            previous_hash: previous_hash,
-            time_stamp: time_now.as_nanos(),
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                nonce,
+                previous_hash,
+                time_stamp: time_now.as_nanos(),
+                difficulty: 0,
+                merkle_root: merkle::merkle_root(&[]),
+                state_root: Hash32::ZERO,
+                logs_bloom: Bloom::default(),
+                receipts_root: receipt::receipts_root(&[], &[]),
+                mmr_root: Hash32::ZERO,
+            },
             transactions: Vec::<Vec<u8>>::new(),
+            receipts: Vec::<Receipt>::new(),
+            hash: None,
         }
     }
 
     pub fn print(&self) {
-        println!("{} Block {}", ("-").repeat(26), ("-").repeat(26));
-        println!("timestamp: {:}", self.time_stamp);
-        println!("nonce: {}", self.nonce);
-        println!("hash: {:?}", self.hash());
-        println!("previous_hash: {:?}", self.previous_hash);
-        // println!("transactions: {:?}", self.transactions); // raw transaction
+        info!(
+            time_stamp = self.header.time_stamp,
+            nonce = self.header.nonce,
+            hash = %self.hash(),
+            previous_hash = %self.header.previous_hash,
+            state_root = %self.header.state_root,
+            transactions = self.transactions.len(),
+            "block"
+        );
 
         // encoded transactions
-        println!("{} transactions {}", ("*").repeat(4), ("*").repeat(41));
         for (i, tx) in self.transactions.iter().enumerate() {
-            // TODO: verify, to_vec suppose to allow us not lose ownership
-            let deserilized: Transaction = Transaction::deserialization(&tx.to_vec());
+            let deserialized: Transaction = match Transaction::deserialization(tx) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    warn!(index = i, error = %e, "malformed transaction");
+                    continue;
+                }
+            };
 
-            // transaction implement our custom default trait
-            println!("tx index: {}", i);
-            println!("tx to_vec: {:?}", tx.to_vec());
-            println!("tx deserialized: {}", deserilized);
+            info!(
+                index = i,
+                sender = %String::from_utf8_lossy(&deserialized.sender_address),
+                recipient = %String::from_utf8_lossy(&deserialized.recipient_address),
+                value = deserialized.value,
+                fee = deserialized.fee,
+                nonce = deserialized.nonce,
+                "transaction"
+            );
+            debug!(index = i, raw = ?tx, "transaction bytes");
         }
+    }
 
-        // blocks ends here
-        println!("{}", ("*").repeat(59));
+    // a copy of the header, e.g. to send to a peer following headers
+    pub fn header(&self) -> BlockHeader {
+        self.header.clone()
     }
 
-    pub fn hash(&self) -> Vec<u8> {
-        let mut bin = Vec::<u8>::new();
-        bin.extend(self.nonce.to_be_bytes());
-        bin.extend(self.previous_hash.clone()); // TODO: use reference
-        bin.extend(self.time_stamp.to_be_bytes());
+    // a block known only by its header, what a node that synced from a
+    // snapshot keeps for the blocks before it. it hashes like the real one
+    pub fn from_header(header: BlockHeader) -> Self {
+        let mut block = Block {
+            header,
+            transactions: Vec::<Vec<u8>>::new(),
+            receipts: Vec::<Receipt>::new(),
+            hash: None,
+        };
+        block.seal();
+        block
+    }
 
-        for tx in self.transactions.iter() {
-            bin.extend(tx.clone());
+    // a header synced ahead of its body and the transactions downloaded
+    // for it afterwards. none if they aren't the ones the header commits
+    // to. the receipts come from executing the block
+    pub fn from_parts(header: BlockHeader, transactions: Vec<Vec<u8>>) -> Option<Self> {
+        let mut block = Block {
+            header,
+            transactions,
+            receipts: Vec::<Receipt>::new(),
+            hash: None,
+        };
+        if block.compute_merkle_root() != block.header.merkle_root {
+            return None;
         }
+        block.seal();
+        Some(block)
+    }
+
+    // the cached hash once the block is sealed. a block whose fields were
+    // changed after sealing keeps answering with the old hash, validation
+    // always uses `compute_hash`
+    pub fn hash(&self) -> Hash32 {
+        self.hash.unwrap_or_else(|| self.compute_hash())
+    }
+
+    // the hash of the header as it is now
+    pub fn compute_hash(&self) -> Hash32 {
+        self.header.hash()
+    }
+
+    // the header is final (mined, or received and checked), its hash is
+    // worked out one last time
+    pub fn seal(&mut self) {
+        self.hash = Some(self.compute_hash());
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(bin);
+    pub fn txids(&self) -> Vec<Vec<u8>> {
+        self.transactions.iter().map(|tx| merkle::txid(tx)).collect()
+    }
 
-        hasher.finalize().to_vec()
+    // the root the transactions actually give, which a received block's
+    // `merkle_root` has to match
+    pub fn compute_merkle_root(&self) -> Hash32 {
+        merkle::merkle_root(&self.txids())
+    }
+
+    // commits the header to the transactions as they are now
+    pub fn seal_transactions(&mut self) {
+        self.header.merkle_root = self.compute_merkle_root();
+        self.hash = None;
+    }
+
+    // none if the transaction is not in this block
+    pub fn merkle_proof(&self, txid: &[u8]) -> Option<MerkleProof> {
+        merkle::merkle_proof(&self.txids(), txid)
+    }
+
+    // the address the coinbase transaction pays, if the block has one
+    pub fn miner(&self) -> Option<Vec<u8>> {
+        self.transactions
+            .iter()
+            .filter_map(|t| Transaction::decode(t))
+            .find(|tx| tx.sender_address == BlockChain::MINING_SENDER.as_bytes())
+            .map(|tx| tx.recipient_address)
     }
 }
 
 #[derive(Debug)]
 pub struct BlockChain {
-    transaction_pool: Vec<Vec<u8>>,
+    transaction_pool: Mempool,
+    // the checks a transaction passes on its way into the pool
+    tx_pipeline: TxPipeline,
+    // last assembled template, reused while the pool and the tip don't change
+    template_cache: Option<BlockTemplate>,
+    // account state at the tip of the chain
+    state: State,
+    // over the hashes of all the blocks in the chain
+    header_mmr: MerkleMountainRange,
+    // bip157 filter headers, filled in as they are asked for
+    filter_headers: FilterHeaders,
+    // where transactions are, by txid and by address
+    index: ChainIndex,
+    // balances at the tip and what each block changed
+    balances: BalanceIndex,
+    metrics: Metrics,
+    audit_log: AuditLog,
+    // whoever wants to hear about new blocks, transactions and reorgs
+    events: EventBus,
+    // peers' opinion of the time, from their handshakes
+    clock: NetworkClock,
+    // how downloading the chain from each peer is going
+    sync: SyncTracker,
+    // peers the operator asked to stay connected to
+    added_nodes: BTreeSet<String>,
+    // peers the node refuses, saved with the chain
+    banlist: BanList,
+    // leading zero hex digits the first mined block needs. 0 accepts any
+    // hash, so blocks are mined instantly (tests and local experiments)
+    difficulty: usize,
+    // how the difficulty moves after that, none keeps it where it started
+    retarget: Option<Retarget>,
+    // threads proof of work runs on, 0 is one per core
+    mining_threads: usize,
+    // set from outside to stop the block being mined
+    cancel_mining: Arc<AtomicBool>,
+    // node settings, e.g. how far ahead a block may be stamped
+    config: ChainConfig,
+    // where the time of the last block mined or received went
+    profile: BlockProfile,
+    // the network this chain is, only transactions signed for it are
+    // accepted
+    chain_id: u64,
     chain: Vec<Block>,
-    blockchain_address: String, // TODO: what represent this address exactly?
-}
-
-impl Index<usize> for BlockChain {
-    type Output = Block;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        let res: Option<&Block> = self.chain.get(index);
-        match res {
-            Some(block) => {
-                return block;
-                // btw, block is a struct, a complex type, if that was a i32 for example, we dont have
-                // to deal with reference, in this case our reference is block, coming from the let res variable
-            }
-            None => {
-                panic!("index out of range for the chain") // TODO: consider to change
-            }
-        }
-    }
+    // where mining() sends the coinbase, set at construction and changed
+    // with set_reward_address
+    blockchain_address: String,
+    // the block file new blocks are appended to, none for a chain only
+    // kept in memory
+    storage: Option<BlockStore>,
+    // callbacks waiting for transactions to get deep enough
+    confirmations: ConfirmationTracker,
+    // the keys the node holds, by name
+    wallets: wallets::Wallets,
+    // the wallets whose keys are encrypted, they sign only while unlocked
+    keystore: keystore::Keystore,
 }
 
 impl BlockChain {
     const DIFFICULTY: usize = 3;
     const MINING_SENDER: &str = "THE BLOCKCHAIN"; // TODO: this must to be an address
     const MINING_REWARD: u64 = 1; // TODO: right now we're not considering floats actually
+    // blocks a mining reward has to wait before it can be spent
+    const COINBASE_MATURITY: u64 = 10;
 
+    // the network's rules: it starts at the default difficulty and follows
+    // the hashrate from there
     pub fn new(address: String) -> Self {
-        // create blockchain struct
-        let mut bc = BlockChain {
-            transaction_pool: Vec::<Vec<u8>>::new(),
-            chain: Vec::<Block>::new(),
-            blockchain_address: address,
-        };
+        let mut chain = BlockChain::with_difficulty(address, BlockChain::DIFFICULTY);
+        chain.set_retarget(Some(Retarget::default()));
+        chain
+    }
+
+    // a chain that is not compatible with the network's, its difficulty
+    // never changes. light clients and fraud proofs keep checking against
+    // the default difficulty
+    pub fn with_difficulty(address: String, difficulty: usize) -> Self {
+        BlockChain::with_chain_id(address, difficulty, DEFAULT_CHAIN_ID)
+    }
+
+    // a separate network, e.g. a testnet, whose transactions can't be
+    // replayed on the default one or the other way round
+    pub fn with_chain_id(address: String, difficulty: usize, chain_id: u64) -> Self {
+        let genesis = GenesisConfig::new().with_difficulty(difficulty).with_chain_id(chain_id);
+        BlockChain::with_genesis(address, &genesis)
+    }
+
+    // the chain `genesis` describes with its first block mined for
+    // `address`
+    pub fn with_genesis(address: String, genesis: &GenesisConfig) -> Self {
+        let mut bc = BlockChain::from_genesis(genesis);
+        bc.blockchain_address = address;
+
+        // mine the block to the blockchain
+        if let Err(e) = bc.mining() {
+            warn!(error = %e, "first block not mined");
+        }
+
+        bc
+    }
+
+    // only the genesis block, the same on every node built from `genesis`,
+    // for a node that gets the rest of the chain from its peers. blocks it
+    // mines pay nobody until `set_reward_address`
+    pub fn from_genesis(genesis: &GenesisConfig) -> Self {
+        let mut bc = BlockChain::empty(String::new(), genesis.difficulty, genesis.chain_id);
 
         // create block struct (genesis)
         // TODO: separate block and blockchain into two files
-        let b: Block = Block::new(0, vec![0 as u8]);
+        let mut b: Block = Block::new(0, Hash32::ZERO);
+        b.header.time_stamp = genesis.time_stamp;
+        for tx in genesis.transactions() {
+            let receipt = bc.state.apply_transaction(&tx, 0).expect("a genesis allocation is only a payment");
+            b.transactions.push(tx.serialization());
+            b.receipts.push(receipt);
+        }
+        b.seal_transactions();
+        bc.state.end_block(0, None).expect("the genesis block has no miner to pay");
+        bc.balances.add_block(bc.state.balance_changes());
+        b.header.state_root = bc.state.root();
+        b.header.receipts_root = receipt::receipts_root(&b.txids(), &b.receipts);
+        b.header.mmr_root = bc.header_mmr.root();
+        // the genesis block is not mined
+        b.seal();
 
         // add the block to the blockchain
+        bc.audit_log.record(AuditEvent::BlockAppended { height: 0, hash: b.hash() }, "genesis");
+        bc.header_mmr.push(&b.hash());
+        bc.index.add_block(0, &b);
         bc.chain.push(b);
+        bc
+    }
 
-        // mine the block to the blockchain
-        bc.mining();
+    // not even a genesis block, for whoever fills in the blocks next
+    fn empty(address: String, difficulty: usize, chain_id: u64) -> Self {
+        BlockChain {
+            transaction_pool: Mempool::default(),
+            tx_pipeline: TxPipeline::default(),
+            template_cache: None,
+            state: State::new(),
+            header_mmr: MerkleMountainRange::new(),
+            filter_headers: FilterHeaders::new(),
+            index: ChainIndex::new(),
+            balances: BalanceIndex::new(),
+            metrics: Metrics::new(),
+            audit_log: AuditLog::new(),
+            events: EventBus::new(),
+            clock: NetworkClock::new(),
+            sync: SyncTracker::new(),
+            added_nodes: BTreeSet::<String>::new(),
+            banlist: BanList::new(),
+            difficulty,
+            retarget: None,
+            mining_threads: 0,
+            cancel_mining: Arc::new(AtomicBool::new(false)),
+            config: ChainConfig::default(),
+            profile: BlockProfile::default(),
+            chain_id,
+            chain: Vec::<Block>::new(),
+            blockchain_address: address,
+            storage: None,
+            confirmations: ConfirmationTracker::new(),
+            wallets: wallets::Wallets::new(),
+            keystore: keystore::Keystore::new(),
+        }
+    }
 
-        bc
+    pub fn mining(&mut self) -> Result<(), BlockchainError> {
+        let recipient: String = self.blockchain_address.clone();
+        self.mining_to(&recipient)
+    }
+
+    // mines one block paying the reward to `recipient` instead of the
+    // configured address, e.g. a pool member or another wallet of the node
+    pub fn mining_to(&mut self, recipient: &str) -> Result<(), BlockchainError> {
+        let _span = info_span!("mining", height = self.chain.len(), recipient).entered();
+        let pending: PendingBlock = self.prepare_mining(recipient)?;
+        self.mine_pending(pending)
     }
 
-    pub fn mining(&mut self) -> bool {
+    // the coinbase into the pool and the next block built on the tip,
+    // everything mining does before proof of work
+    pub(crate) fn prepare_mining(&mut self, recipient: &str) -> Result<PendingBlock, BlockchainError> {
+        // blocks stamped by a clock the network disagrees with would be
+        // rejected by peers (and skew anything that reads block times)
+        if let Err(skew) = self.clock.check() {
+            warn!(%skew, "not mining");
+            self.record_error(&skew);
+            return Err(skew.into());
+        }
+
+        // hash all the block field's using sha256
+        let hash = self.last_block()?.hash();
+
         // if a block is mined, we need to create a transaction to
         // rewards to the miner when proof of work was done
         let tx: Transaction = Transaction::new(
             BlockChain::MINING_SENDER.into(),       // sender address
-            self.blockchain_address.clone().into(), // reciever address
-            BlockChain::MINING_REWARD,              // reward amount
-        );
-        self.add_transaction(&tx);
+            recipient.into(),                       // reciever address
+            self.state.parameters().mining_reward,  // reward amount, governance may change it
+        )
+        .with_chain_id(self.chain_id);
+        if let Err(e) = self.submit_transaction(tx, true) {
+            warn!(error = %e, "coinbase rejected, not mining");
+            self.record_error(&e);
+            return Err(BlockchainError::CoinbaseRejected(e));
+        }
 
-        // hash all the block field's using sha256
-        let hash = &self.last_block().hash();
-        self.create_block(hash);
+        self.prepare_block(&hash)
+    }
 
-        true
+    // mines the pool into a block on top of `previous_hash`, which has to
+    // be the tip's hash. the pool has to hold the block's coinbase, which
+    // `mining` puts there, a block without one is invalid
+    pub fn create_block(&mut self, previous_hash: &Hash32) -> Result<(), BlockchainError> {
+        let pending: PendingBlock = self.prepare_block(previous_hash)?;
+        self.mine_pending(pending)
     }
 
-    pub fn create_block(&mut self, previous_hash: &Vec<u8>) {
+    // proof of work on the calling thread, until a nonce is found or the
+    // cancel handle is set
+    fn mine_pending(&mut self, mut pending: PendingBlock) -> Result<(), BlockchainError> {
+        // a cancel that came before this block was for an earlier one
+        self.cancel_mining.store(false, Ordering::Relaxed);
+        let threads: usize = self.pow_threads(pending.block.header.difficulty);
+        let tried = AtomicU64::new(0);
+        let found: Option<Hash32> = pending
+            .profile
+            .time(Stage::ProofOfWork, || pow::work(&mut pending.block, threads, &self.cancel_mining, &tried));
+        if found.is_none() {
+            self.abandon_block(&pending);
+            return Err(BlockchainError::MiningCancelled);
+        }
+        self.finish_block(pending)
+    }
+
+    // the block that goes on top of `previous_hash`, with the pool's
+    // transactions applied to a copy of the state. the chain itself only
+    // changes once `finish_block` gets it with a nonce
+    pub(crate) fn prepare_block(&mut self, previous_hash: &Hash32) -> Result<PendingBlock, BlockchainError> {
+        if self.last_block()?.hash() != *previous_hash {
+            return Err(BlockchainError::UnknownParent {
+                previous_hash: *previous_hash,
+            });
+        }
         // TODO: consider to use reference and add the lifetime annotation
         // to the new contructor.
         let nonce: i32 = 0;
 
-        let mut b = Block::new(nonce, previous_hash.clone());
+        let mut b = Block::new(nonce, *previous_hash);
+        let height = self.chain.len() as u64;
+        // a clock that is behind the last blocks still stamps after them
+        let earliest: u128 = self.median_time_past(height as usize).map_or(0, |median| median + 1);
+        b.header.time_stamp = self.network_time().max(earliest);
+        b.header.difficulty = self.difficulty();
+        let mut profile: BlockProfile = BlockProfile::new(height);
+
+        // the coinbase opens the block, the pending transactions follow
+        b.transactions = profile.time(Stage::Template, || self.get_block_template().map(|t| t.transactions.clone()))?;
+        let coinbase: Vec<Vec<u8>> = self
+            .transaction_pool
+            .transactions()
+            .iter()
+            .filter(|tx| tx.sender_address == BlockChain::MINING_SENDER.as_bytes())
+            .map(|tx| tx.serialization())
+            .collect();
+        b.transactions.splice(0..0, coinbase);
+        let taken: Vec<Vec<u8>> = b.transactions.iter().map(|t| merkle::txid(t)).collect();
+
+        // move the account state forward and commit to the result, a
+        // transaction the state turns out to reject is left out of the block.
+        // what it did is only logged once the block is found. a block
+        // without its coinbase is invalid, one the state rejects stops it
+        let mut state: State = self.state.clone();
+        let index = &self.index;
+        let mut events = Vec::<(AuditEvent, String)>::new();
+        let mut receipts = Vec::<Receipt>::new();
+        let mut has_coinbase: bool = false;
+        let mut coinbase_error: Option<StateError> = None;
+        profile.time(Stage::Execute, || b.transactions.retain(|t| {
+            // the pool only holds transactions that decoded, bytes that
+            // don't are left out like any other the state rejects
+            let Ok(tx) = Transaction::deserialization(t) else {
+                return false;
+            };
+            // a block mints one reward, the template put its coinbase first
+            if tx.sender_address == BlockChain::MINING_SENDER.as_bytes() {
+                if has_coinbase {
+                    events.push((AuditEvent::TransactionDropped { txid: tx.id() }, "a second coinbase".to_string()));
+                    return false;
+                }
+                has_coinbase = true;
+            }
+            // balances the transaction can move, for the audit log
+            let mut addresses: Vec<Vec<u8>> = vec![tx.sender_address.clone()];
+            if tx.recipient_address != tx.sender_address {
+                addresses.push(tx.recipient_address.clone());
+            }
+            let before: Vec<i64> = addresses.iter().map(|a| state.balance(a)).collect();
 
-        // add the pending transactions to the block
-        for tx in self.transaction_pool.iter() {
-            b.transactions.push(tx.clone());
+            match state.apply_transaction(&tx, height) {
+                Ok(receipt) => {
+                    let cause = if receipt.success {
+                        "transaction mined"
+                    } else {
+                        "failed transaction paid for its gas"
+                    };
+                    for (address, before) in addresses.into_iter().zip(before) {
+                        let delta = state.balance(&address) - before;
+                        if delta != 0 && index.is_indexed(&address) {
+                            let event = AuditEvent::BalanceChanged {
+                                address,
+                                height,
+                                txid: tx.id(),
+                                delta,
+                            };
+                            events.push((event, cause.to_string()));
+                        }
+                    }
+                    receipts.push(receipt);
+                    true
+                }
+                Err(e) if tx.sender_address == BlockChain::MINING_SENDER.as_bytes() => {
+                    coinbase_error = Some(e);
+                    false
+                }
+                Err(e) => {
+                    warn!(error = %e, "transaction dropped from block");
+                    events.push((AuditEvent::TransactionDropped { txid: tx.id() }, e.to_string()));
+                    false
+                }
+            }
+        }));
+        if let Some(e) = coinbase_error {
+            let e = MempoolError::InvalidPayload(e);
+            warn!(error = %e, "coinbase rejected, not mining");
+            self.record_error(&e);
+            self.drop_coinbase();
+            return Err(BlockchainError::CoinbaseRejected(e));
         }
+        if !has_coinbase {
+            let missing = validation::ValidationError::MissingCoinbase { index: height as usize };
+            return Err(BlockchainError::Validation(missing));
+        }
+        let fees_collected: Result<i64, StateError> = profile.time(Stage::Commit, || {
+            b.seal_transactions();
+            // the fees that weren't burned go to whoever the coinbase pays
+            let miner: Option<Vec<u8>> = b.miner();
+            let miner_before: i64 = miner.as_ref().map_or(0, |m| state.balance(m));
+            state.end_block(height, miner.as_deref())?;
+            let fees_collected: i64 = miner.as_ref().map_or(0, |m| state.balance(m)) - miner_before;
+            if let Some(miner) = miner
+                && fees_collected != 0
+                && self.index.is_indexed(&miner)
+            {
+                // not paid by any one transaction
+                let event = AuditEvent::BalanceChanged {
+                    address: miner,
+                    height,
+                    txid: Vec::<u8>::new(),
+                    delta: fees_collected,
+                };
+                events.push((event, "collected transaction fees".to_string()));
+            }
+            b.header.state_root = state.root();
+            b.header.logs_bloom = receipt::logs_bloom(&receipts);
+            b.header.receipts_root = receipt::receipts_root(&b.txids(), &receipts);
+            b.receipts = receipts;
+            b.header.mmr_root = self.header_mmr.root();
+            Ok(fees_collected)
+        });
+        let fees_collected: i64 = match fees_collected {
+            Ok(fees_collected) => fees_collected,
+            Err(error) => {
+                let e = validation::ValidationError::InvalidFees { index: height as usize, error };
+                warn!(error = %e, "fees can't be paid, not mining");
+                self.record_error(&e);
+                self.drop_coinbase();
+                return Err(BlockchainError::Validation(e));
+            }
+        };
 
-        // all the trxs attached to the block needs to be cleared from the pool
-        self.transaction_pool.clear();
+        Ok(PendingBlock {
+            block: b,
+            state,
+            events,
+            taken,
+            fees_collected,
+            started: Instant::now(),
+            profile,
+        })
+    }
 
-        // resolve proof of work computation
-        // let now = Instant::now();
-        BlockChain::do_proof_of_work(&mut b);
-        // let elapsed = now.elapsed();
+    // mining stopped before a nonce was found: the coinbase leaves the
+    // pool, the rest waits for the next block
+    pub(crate) fn abandon_block(&mut self, pending: &PendingBlock) {
+        self.drop_coinbase();
+        info!(height = self.chain.len(), elapsed = ?pending.started.elapsed(), "mining cancelled");
+    }
 
-        // println!("compuse time: {:?}", elapsed);
-        // println!("proof of current block: {:?}", proof_hash);
+    // the next `mining` puts a new one in
+    fn drop_coinbase(&mut self) {
+        let coinbase: Vec<Vec<u8>> = self
+            .transaction_pool
+            .transactions()
+            .iter()
+            .filter(|tx| tx.sender_address == BlockChain::MINING_SENDER.as_bytes())
+            .map(|tx| tx.id())
+            .collect();
+        self.transaction_pool.remove_confirmed(&coinbase);
+    }
+
+    // appends a block whose proof of work is done. a block for the same
+    // height may have come in while it was mined, then it is abandoned
+    pub(crate) fn finish_block(&mut self, pending: PendingBlock) -> Result<(), BlockchainError> {
+        let tip: Hash32 = self.last_block()?.hash();
+        if pending.block.header.previous_hash != tip {
+            self.abandon_block(&pending);
+            return Err(BlockchainError::UnknownParent {
+                previous_hash: pending.block.header.previous_hash,
+            });
+        }
+        // e.g. proof of work ran out of nonces often enough to push the
+        // time stamp past what peers accept
+        if let Err(e) = self.check_time_stamp(&pending.block, self.chain.len()) {
+            self.abandon_block(&pending);
+            self.record_error(&e);
+            return Err(e.into());
+        }
+        let PendingBlock { block: mut b, state, events, taken, fees_collected, started, mut profile } = pending;
+        let height = self.chain.len() as u64;
+        b.seal();
+        info!(
+            height,
+            hash = %b.hash(),
+            nonce = b.header.nonce,
+            difficulty = b.header.difficulty,
+            transactions = b.transactions.len(),
+            fees = fees_collected,
+            elapsed = ?started.elapsed(),
+            "mined block"
+        );
+        self.state = state;
+        self.balances.add_block(self.state.balance_changes());
+        for (event, cause) in events {
+            self.audit_log.record(event, cause);
+        }
+
+        // the block's transactions are confirmed, the ones the state dropped
+        // would only be dropped again
+        let removed = self.transaction_pool.remove_confirmed(&taken);
+        self.metrics.record_mempool_removed(removed, self.transaction_pool.len());
 
+        let previous_time_stamp = self.last_block()?.header.time_stamp;
+        self.metrics.record_block_interval(Duration::from_nanos(
+            b.header.time_stamp.saturating_sub(previous_time_stamp) as u64,
+        ));
+
+        self.audit_log.record(
+            AuditEvent::BlockAppended { height, hash: b.hash() },
+            "mined locally",
+        );
+        self.header_mmr.push(&b.hash());
+        self.index.add_block(height, &b);
+        for (address, txid) in self.index.watched_activity(&b) {
+            self.audit_log.record(
+                AuditEvent::WatchedAddressActivity { address, height, txid },
+                "watched address in a mined block",
+            );
+        }
         self.chain.push(b);
+        self.confirmations.update(&self.chain);
+        self.publish_tip();
+
+        // the block stays mined even if it can't be saved, the error says
+        // the file is behind the chain
+        let saved = match (self.storage.as_mut(), self.chain.last()) {
+            (Some(store), Some(block)) => profile.time(Stage::Storage, || store.append(block)),
+            _ => Ok(()),
+        };
+        if self.storage.is_some() {
+            self.record_storage(&saved);
+        }
+        debug!(%profile, "block profile");
+        self.profile = profile;
+        saved?;
+        Ok(())
     }
 
-    fn do_proof_of_work(block: &mut Block) -> String {
-        const DIFFICULTY: usize = BlockChain::DIFFICULTY;
+    pub fn audit_log(&self) -> &AuditLog {
+        &self.audit_log
+    }
 
-        loop {
-            // create and transform hash to hex
-            let hash: Vec<u8> = block.hash();
-            let hash_str: String = hex::encode(&hash);
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
 
-            // check if the hash starts with the required number of zeros
-            if hash_str[0..DIFFICULTY] == "0".repeat(DIFFICULTY) {
-                return hash_str;
-            }
+    // proof that the block at `height` is under the mountain range root
+    // committed by the tip
+    pub fn mmr_proof(&self, height: u64) -> Option<mmr::MmrProof> {
+        let tip = self.chain.len() as u64 - 1;
+        self.header_mmr.prove(height, tip)
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn resolve_name(&self, name: &str) -> Option<Vec<u8>> {
+        // names are checked against the height of the next block, the one
+        // a transaction sent now would land in
+        self.state
+            .resolve_name(name.as_bytes(), self.chain.len() as u64)
+            .cloned()
+    }
 
-            // increment nonce
-            *block += 1;
+    pub fn get_block_template(&mut self) -> Result<&BlockTemplate, BlockchainError> {
+        let tip_hash: Hash32 = self.last_block()?.hash();
+
+        let is_current = match &self.template_cache {
+            Some(template) => template.is_current(&tip_hash, &self.transaction_pool),
+            None => false,
+        };
+
+        if !is_current {
+            let template = BlockTemplate::assemble(tip_hash, &self.transaction_pool);
+            self.template_cache = Some(template);
         }
+
+        Ok(self.template_cache.as_ref().unwrap())
+    }
+
+    // what the next block has to meet
+    pub fn difficulty(&self) -> usize {
+        self.expected_difficulty(self.chain.len() as u64)
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    pub fn meets_difficulty(hash: &[u8]) -> bool {
+        BlockChain::meets_target(hash, BlockChain::DIFFICULTY)
+    }
+
+    pub fn meets_target(hash: &[u8], difficulty: usize) -> bool {
+        let hash_str: String = hex::encode(hash);
+        hash_str.starts_with(&"0".repeat(difficulty))
     }
 
     pub fn print(&self) {
         for (i, block) in self.chain.iter().enumerate() {
-            println!("{} chain {} {}", "=".repeat(25), i, "=".repeat(25));
+            let _span = info_span!("chain", height = i).entered();
             block.print();
         }
-        println!("{}", "=".repeat(60));
     }
 
-    pub fn last_block(&self) -> &Block {
-        if self.chain.is_empty() {
-            panic!("Blockchain is empty");
-        }
+    // every block from genesis to the tip
+    pub fn blocks(&self) -> &[Block] {
+        &self.chain
+    }
 
-        if self.chain.len() == 1 {
-            return &self.chain[self.chain.len() - 1];
-        }
+    // the header of every block from genesis to the tip, all a peer needs
+    // to check the links and the proof of work before it asks for bodies
+    pub fn headers(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.chain.iter().map(|block| &block.header)
+    }
+
+    // the block at `height`, None past the tip. there's no `chain[height]`,
+    // a library shouldn't panic because a caller asked for a block it
+    // doesn't have
+    pub fn get_block(&self, height: usize) -> Option<&Block> {
+        self.chain.get(height)
+    }
 
-        self.chain.last().unwrap()
+    // the blocks in `range` the chain has, cut off at the tip: a range
+    // past it gives fewer blocks, or none
+    pub fn get_block_range(&self, range: Range<usize>) -> &[Block] {
+        let end = range.end.min(self.chain.len());
+        let start = range.start.min(end);
+        &self.chain[start..end]
     }
 
-    pub fn search_block(&self, search: BlockSearch) -> BlockSearchResult {
+    // waiting in the pool for the next block
+    pub fn pending_transactions(&self) -> &[Transaction] {
+        self.transaction_pool.transactions()
+    }
+
+    pub fn mempool(&self) -> &Mempool {
+        &self.transaction_pool
+    }
+
+    pub fn last_block(&self) -> Result<&Block, BlockchainError> {
+        self.chain.last().ok_or(BlockchainError::EmptyChain)
+    }
+
+    pub fn search_block(&self, search: BlockSearch) -> BlockSearchResult<'_> {
         // Check if the chain is empty first
         if self.chain.is_empty() {
             return BlockSearchResult::FailOfEmptyBlocks;
@@ -254,21 +1021,21 @@ impl BlockChain {
 
         // Handle SearchByIndex separately since it has different logic
         if let BlockSearch::SearchByIndex(index) = search {
-            if index >= self.chain.len() {
-                return BlockSearchResult::FailOfIndex(index);
-            }
-            return BlockSearchResult::Success(&self.chain[index]);
+            return match self.get_block(index) {
+                Some(block) => BlockSearchResult::Success(block),
+                None => BlockSearchResult::FailOfIndex(index),
+            };
         }
 
         // For other search types, iterate through the chain
-        for (idx, block) in self.chain.iter().enumerate() {
+        for block in self.chain.iter() {
             match search {
                 BlockSearch::SearchByIndex(_) => {
                     // This case is already handled above
                     unreachable!()
                 }
                 BlockSearch::SearchByPreviousHash(ref hash) => {
-                    if block.previous_hash == *hash {
+                    if block.header.previous_hash == *hash {
                         return BlockSearchResult::Success(block);
                     }
                 }
@@ -278,12 +1045,12 @@ impl BlockChain {
                     }
                 }
                 BlockSearch::SearchByNonce(nonce) => {
-                    if block.nonce == nonce {
+                    if block.header.nonce == nonce {
                         return BlockSearchResult::Success(block);
                     }
                 }
                 BlockSearch::SearchByTimestamp(time_stamp) => {
-                    if block.time_stamp == time_stamp {
+                    if block.header.time_stamp == time_stamp {
                         return BlockSearchResult::Success(block);
                     }
                 }
@@ -308,44 +1075,272 @@ impl BlockChain {
         }
     }
 
-    pub fn add_transaction(&mut self, tx: &impl Serialization<Transaction>) {
-        // detects duplicate
-        for tx_in_pool in self.transaction_pool.iter() {
-            if *tx_in_pool == tx.serialization() {
-                return;
+    pub fn add_transaction(&mut self, tx: &impl Serialization<Transaction>) -> Result<(), MempoolError> {
+        // the pool keeps decoded transactions so it can compare fees and
+        // nonces, duplicates and replacements are resolved there
+        let decoded_tx: Transaction = Transaction::deserialization(&tx.serialization()).map_err(MempoolError::Malformed)?;
+        self.submit_transaction(decoded_tx, false)
+    }
+
+    // the coinbase is the one transaction nobody signs, only the miner
+    // itself adds it
+    fn submit_transaction(&mut self, decoded_tx: Transaction, coinbase: bool) -> Result<(), MempoolError> {
+        let txid: Vec<u8> = decoded_tx.id();
+        match self.admit_transaction(decoded_tx, coinbase) {
+            Ok(Admitted::Replaced { evicted, .. }) => {
+                self.metrics.record_mempool_replaced();
+                self.audit_log
+                    .record(AuditEvent::TransactionReplaced { txid: txid.clone() }, "replaced a pooled transaction");
+                self.events.publish(ChainEvent::NewTransaction { txid });
+                if !evicted.is_empty() {
+                    self.record_evicted(&evicted);
+                }
+                Ok(())
+            }
+            Ok(Admitted::Added { evicted }) => {
+                self.metrics.record_mempool_added(self.transaction_pool.len());
+                self.audit_log
+                    .record(AuditEvent::TransactionAdded { txid: txid.clone() }, "accepted into the pool");
+                // the miner's own coinbase isn't news to anyone
+                if !coinbase {
+                    self.events.publish(ChainEvent::NewTransaction { txid });
+                }
+                if !evicted.is_empty() {
+                    self.record_evicted(&evicted);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.metrics.record_mempool_rejected();
+                self.audit_log
+                    .record(AuditEvent::TransactionRejected { txid }, e.to_string());
+                Err(e)
             }
         }
-        let serialized_tx = tx.serialization();
-        // println!("holis: {:?}", serialized_tx);
-        self.transaction_pool.push(serialized_tx);
     }
 
-    pub fn calculate_total_amount(&self, address: String) -> i64 {
-        let mut total_amount: i64 = 0;
-        for i in 0..self.chain.len() {
-            let block = &self[i];
+    // the block just added at the tip, to the subscribers
+    fn publish_tip(&mut self) {
+        if let Some(block) = self.chain.last() {
+            let event = ChainEvent::NewBlock {
+                height: self.chain.len() as u64 - 1,
+                hash: block.hash(),
+                transactions: block.transactions.len(),
+            };
+            self.events.publish(event);
+        }
+    }
 
-            for t in block.transactions.iter() {
-                let tx: Transaction = Transaction::deserialization(&t.clone());
-                let value = tx.value;
+    fn record_evicted(&mut self, evicted: &[Transaction]) {
+        self.metrics.record_mempool_removed(evicted.len(), self.transaction_pool.len());
+        for tx in evicted.iter() {
+            self.audit_log
+                .record(AuditEvent::TransactionEvicted { txid: tx.id() }, "the pool was full");
+        }
+    }
+
+    fn admit_transaction(&mut self, decoded_tx: Transaction, coinbase: bool) -> Result<Admitted, MempoolError> {
+        let context = TxContext {
+            chain: self,
+            height: self.chain.len() as u64,
+            coinbase,
+        };
+        self.tx_pipeline.validate(&decoded_tx, &context)?;
+        self.transaction_pool.add(decoded_tx)
+    }
 
-                // into() is a trait used for converting one type into another,
-                // String implement many type of into trait, such as into<str>, into<i32>
-                // into<u64> ..., into<Vec<u8>>
-                // So, we need to tell the compiler which trait we should use that is
-                // into<Vec<u8>>
+    // the funds of an address are guarded by the locking script of the most
+    // recent transaction it sent with one, none means unlocked
+    pub fn locking_script_for(&self, address: &[u8]) -> Option<Vec<u8>> {
+        self.state
+            .account(address)
+            .map(|account| account.locking_script.clone())
+            .filter(|script| !script.is_empty())
+    }
 
-                // increase amount
-                if <String as Into<Vec<u8>>>::into(address.clone()) == tx.recipient_address {
-                    total_amount += value as i64;
-                }
+    // the nonce a new transaction from `address` gets: the next one after
+    // its mined transactions and the ones waiting in the pool
+    pub fn next_nonce(&self, address: &[u8]) -> u64 {
+        let pooled: BTreeSet<u64> = self
+            .transaction_pool
+            .transactions()
+            .iter()
+            .filter(|tx| tx.sender_address == address)
+            .map(|tx| tx.nonce)
+            .collect();
+        let mut nonce: u64 = self.state.next_nonce(address);
+        while pooled.contains(&nonce) {
+            nonce += 1;
+        }
+        nonce
+    }
 
-                // decrease amount
-                if <String as Into<Vec<u8>>>::into(address.clone()) == tx.sender_address {
-                    total_amount -= value as i64;
-                }
-            }
+    // the scripts have to fit in the gas the transaction pays for
+    pub fn verify_spending_conditions(&self, tx: &Transaction) -> Result<(), ScriptError> {
+        let mut meter = GasMeter::new(tx.gas_limit);
+        self.state.verify_spending_conditions(tx, &mut meter)
+    }
+
+    // every block mined with mining() from now on pays `address`
+    pub fn set_reward_address(&mut self, address: String) {
+        self.blockchain_address = address;
+    }
+
+    pub fn reward_address(&self) -> &str {
+        &self.blockchain_address
+    }
+
+    pub fn set_replacement_policy(&mut self, policy: ReplacementPolicy) {
+        self.transaction_pool.set_policy(policy);
+    }
+
+    // the most weight the pool holds, see `Mempool::set_max_weight`
+    pub fn set_mempool_max_weight(&mut self, max_weight: u64) {
+        let evicted = self.transaction_pool.set_max_weight(max_weight);
+        self.record_evicted(&evicted);
+    }
+
+    // only blocks with at least `min_confirmations` count, a block has one
+    // confirmation when it is the tip and one more for each block on top.
+    // 0 and 1 both count every block, the pool is never included.
+    //
+    // adding up the values sent and received isn't enough once transactions
+    // pay fees: the sender pays the fee on top of the value, and the miner
+    // of the block collects what wasn't burned (the coinbase only carries
+    // the reward, the state pays the fees out when the block ends). so the
+    // amount is the balance the state had at that block
+    pub fn calculate_total_amount(&self, address: &Address, min_confirmations: u64) -> i64 {
+        let height = self.confirmed_blocks(min_confirmations) as u64;
+        match height.checked_sub(1) {
+            Some(height) => self.balance_at(address.as_bytes(), height),
+            None => 0,
+        }
+    }
+
+    // from the balance index, the state as of `height` if the index
+    // doesn't go back that far (a fast synced node). zero past the tip
+    fn balance_at(&self, address: &[u8], height: u64) -> i64 {
+        match self.balances.balance_at(address, height) {
+            Some(balance) => balance.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            None => self.state_at(height).map_or(0, |state| state.balance(address)),
         }
-        total_amount
+    }
+
+    // how many blocks from genesis have `min_confirmations` or more
+    fn confirmed_blocks(&self, min_confirmations: u64) -> usize {
+        let buried = min_confirmations.saturating_sub(1).min(self.chain.len() as u64);
+        self.chain.len() - buried as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_coinbase_the_state_rejects_stops_the_block() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        let height: usize = chain.blocks().len();
+        // the first reward and this fill the miner's balance, the next
+        // reward would overflow it
+        let full = Transaction::new(genesis::GENESIS_SENDER.into(), "miner".into(), i64::MAX as u64 - 1);
+        chain.state.apply_transaction(&full, 1).unwrap();
+
+        let rejected = MempoolError::InvalidPayload(StateError::AmountOverflow);
+        assert_eq!(chain.mining(), Err(BlockchainError::CoinbaseRejected(rejected)));
+        assert_eq!(chain.blocks().len(), height);
+        assert!(chain.pending_transactions().is_empty());
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn blocks_past_the_tip_are_none_not_a_panic() {
+        let chain = BlockChain::with_difficulty("miner".into(), 0);
+        assert_eq!(chain.get_block(1).map(|b| b.hash()), Some(chain.last_block().unwrap().hash()));
+        assert!(chain.get_block(2).is_none());
+
+        assert_eq!(chain.get_block_range(0..2).len(), 2);
+        assert_eq!(chain.get_block_range(1..100).len(), 1);
+        assert!(chain.get_block_range(5..10).is_empty());
+    }
+
+    #[test]
+    fn a_block_changed_after_sealing_still_fails_validation() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        let hash = chain.chain[1].hash();
+
+        chain.chain[1].header.state_root = Hash32::new([1u8; 32]);
+        assert_eq!(chain.chain[1].hash(), hash);
+        assert_ne!(chain.chain[1].compute_hash(), hash);
+        assert!(chain.validate_chain().is_err());
+    }
+
+    #[test]
+    fn blocks_are_equal_when_their_contents_are() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        let block: Block = chain.chain[2].clone();
+        let mut unsealed: Block = block.clone();
+        unsealed.hash = None;
+        assert_eq!(unsealed, block);
+        assert_eq!(storage::decode_block(&storage::encode_block(&block)), Ok(block.clone()));
+
+        // the cached hash is still the old one, the block isn't
+        let mut changed: Block = block.clone();
+        changed.header.state_root = Hash32::new([1u8; 32]);
+        assert_ne!(changed, block);
+        let blocks: std::collections::HashSet<Block> = chain.chain.iter().cloned().chain([unsealed]).collect();
+        assert_eq!(blocks.len(), chain.chain.len());
+    }
+
+    #[test]
+    fn headers_are_synced_before_the_bodies() {
+        let mut chain = BlockChain::new("miner".into());
+        chain.mining().unwrap();
+        chain.mining().unwrap();
+
+        // the links and the proof of work, from the headers alone
+        let headers: Vec<BlockHeader> = chain.headers().cloned().collect();
+        let mut client = light::LightClient::new(headers[0].clone());
+        assert_eq!(client.add_headers(1, headers[1..].to_vec()), Ok(chain.blocks().len() as u64 - 1));
+
+        // then each body, which has to be the one its header commits to
+        let tip: &Block = chain.last_block().unwrap();
+        let body: Vec<Vec<u8>> = tip.transactions.clone();
+        let block: Block = Block::from_parts(client.tip().clone(), body.clone()).unwrap();
+        assert_eq!(block.hash(), tip.hash());
+        assert!(Block::from_parts(client.tip().clone(), body[1..].to_vec()).is_none());
+
+        // a version this node doesn't know isn't read
+        let mut header: BlockHeader = client.tip().clone();
+        header.version = BLOCK_VERSION + 1;
+        let block: Block = Block::from_parts(header, tip.transactions.clone()).unwrap();
+        let index: usize = chain.blocks().len() - 1;
+        chain.chain[index] = block;
+        assert!(matches!(chain.validate_chain(), Err(validation::ValidationError::UnsupportedVersion { version, .. }) if version == BLOCK_VERSION + 1));
+    }
+
+    #[test]
+    fn senders_pay_fees_and_the_miner_collects_them() {
+        let wallet = test_utils::miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 2 {
+            chain.mining().unwrap();
+        }
+        let sent_before = chain.calculate_total_amount(&wallet.address(), 1);
+
+        let recipient: Address = test_utils::test_wallet("B").address();
+        let collector: Address = test_utils::test_wallet("collector").address();
+        let tx = Transaction::new(wallet.address().into_bytes(), recipient.clone().into_bytes(), 1).with_fee(2).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.set_reward_address(collector.clone().into());
+        chain.mining().unwrap();
+
+        assert_eq!(chain.calculate_total_amount(&wallet.address(), 1), sent_before - 3);
+        assert_eq!(chain.calculate_total_amount(&recipient, 1), 1);
+        // the reward and the fee
+        assert_eq!(chain.calculate_total_amount(&collector, 1), 3);
+        assert_eq!(chain.calculate_total_amount(&collector, 2), 0);
     }
 }
@@ -0,0 +1,85 @@
+use sha2::{Digest, Sha256};
+
+// a name stays with its owner for this many blocks after a claim or an
+// update, after that anyone can claim it again
+pub const NAME_REGISTRATION_PERIOD: u64 = 1000;
+pub const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameRecord {
+    // who can update or renew the name
+    pub owner: Vec<u8>,
+    // the address the name resolves to
+    pub target: Vec<u8>,
+    // first height at which the name is free again
+    pub expires_at: u64,
+}
+
+impl NameRecord {
+    pub fn is_expired(&self, height: u64) -> bool {
+        height >= self.expires_at
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update((self.owner.len() as u64).to_be_bytes());
+        hasher.update(&self.owner);
+        hasher.update((self.target.len() as u64).to_be_bytes());
+        hasher.update(&self.target);
+        hasher.update(self.expires_at.to_be_bytes());
+        hasher.finalize().to_vec()
+    }
+}
+
+// lowercase letters, digits and dashes only, so names can't be made to
+// look like each other with case or unicode tricks
+pub fn is_valid_name(name: &[u8]) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .iter()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == b'-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::state::{State, StateError};
+    use crate::blockchain::transaction::{Payload, Transaction};
+
+    fn send(sender: &str, nonce: u64, payload: Payload) -> Transaction {
+        Transaction::new(sender.into(), sender.into(), 0).with_nonce(nonce).with_payload(payload)
+    }
+
+    fn claim(name: &str, target: &str) -> Payload {
+        Payload::ClaimName { name: name.into(), target: target.into() }
+    }
+
+    #[test]
+    fn a_name_resolves_to_its_target_until_it_expires() {
+        assert!(is_valid_name(b"alice-2"));
+        assert!(!is_valid_name(b"Alice") && !is_valid_name(b"") && !is_valid_name(&[b'a'; MAX_NAME_LENGTH + 1]));
+
+        let mut state = State::new();
+        assert_eq!(state.apply_transaction(&send("A", 0, claim("Alice", "A")), 1), Err(StateError::InvalidName));
+        state.apply_transaction(&send("A", 0, claim("alice", "A")), 1).unwrap();
+        assert_eq!(state.resolve_name(b"alice", 2), Some(&b"A".to_vec()));
+        assert_eq!(state.resolve_name(b"bob", 2), None);
+
+        let expires_at: u64 = 1 + NAME_REGISTRATION_PERIOD;
+        assert_eq!(
+            state.apply_transaction(&send("B", 0, claim("alice", "B")), 2),
+            Err(StateError::NameTaken { expires_at })
+        );
+        let repoint = |target: &str| Payload::UpdateName { name: b"alice".to_vec(), target: target.into() };
+        assert_eq!(state.apply_transaction(&send("B", 0, repoint("B")), 2), Err(StateError::NotNameOwner));
+        state.apply_transaction(&send("A", 1, repoint("A2")), 2).unwrap();
+        // an update renews the name from the block it is in
+        assert_eq!(state.resolve_name(b"alice", expires_at), Some(&b"A2".to_vec()));
+
+        let expired: u64 = 2 + NAME_REGISTRATION_PERIOD;
+        assert_eq!(state.resolve_name(b"alice", expired), None);
+        state.apply_transaction(&send("B", 0, claim("alice", "B")), expired).unwrap();
+        assert_eq!(state.resolve_name(b"alice", expired), Some(&b"B".to_vec()));
+    }
+}
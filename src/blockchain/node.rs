@@ -0,0 +1,270 @@
+use crate::blockchain::difficulty::Retarget;
+use crate::blockchain::health::NodeStatus;
+use crate::blockchain::storage::StorageError;
+use crate::blockchain::transaction::DEFAULT_CHAIN_ID;
+use crate::blockchain::BlockChain;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{info, warn};
+
+// one process hosting several networks side by side, e.g. the main one
+// and a devnet to try things on. each chain is a BlockChain of its own
+// with its own files, chain id and json-rpc endpoints: they share the
+// process and its threads, never a state, a pool or a file. two chains
+// with the same chain id would take each other's transactions, so a node
+// refuses to host them together
+
+// the devnet's, transactions signed for it don't replay on the main
+// network and the other way around
+pub const DEVNET_CHAIN_ID: u64 = 2;
+
+// how a hosted chain is opened and reached
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainSpec {
+    pub name: String,
+    pub chain_id: u64,
+    pub difficulty: usize,
+    // the network's retarget rules, none for a difficulty that never changes
+    pub retarget: Option<Retarget>,
+    // where its files go, none for a chain only kept in memory
+    pub data_dir: Option<PathBuf>,
+    // a json-rpc port of its own, if it has one
+    pub rpc_address: Option<String>,
+    // its path on the node's shared json-rpc port
+    pub rpc_prefix: String,
+}
+
+impl ChainSpec {
+    // fixed difficulty, reached at /{name}
+    pub fn new(name: &str, chain_id: u64, difficulty: usize) -> Self {
+        ChainSpec {
+            name: name.to_string(),
+            chain_id,
+            difficulty,
+            retarget: None,
+            data_dir: None,
+            rpc_address: None,
+            rpc_prefix: format!("/{}", name),
+        }
+    }
+
+    // the network's rules, what `BlockChain::open` uses
+    pub fn mainnet(name: &str) -> Self {
+        ChainSpec {
+            retarget: Some(Retarget::default()),
+            ..ChainSpec::new(name, DEFAULT_CHAIN_ID, BlockChain::DIFFICULTY)
+        }
+    }
+
+    // blocks in a moment, for trying things out
+    pub fn devnet(name: &str) -> Self {
+        ChainSpec::new(name, DEVNET_CHAIN_ID, 1)
+    }
+
+    pub fn with_data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    pub fn with_rpc_address(mut self, address: &str) -> Self {
+        self.rpc_address = Some(address.to_string());
+        self
+    }
+
+    pub fn with_rpc_prefix(mut self, prefix: &str) -> Self {
+        self.rpc_prefix = prefix.to_string();
+        self
+    }
+}
+
+// why a chain can't be hosted next to the ones already there
+#[derive(Debug, PartialEq)]
+pub enum HostError {
+    DuplicateName(String),
+    SharedChainId { chain_id: u64, with: String },
+    SharedDataDir { dir: PathBuf, with: String },
+    SharedRpcAddress { address: String, with: String },
+    SharedRpcPrefix { prefix: String, with: String },
+    Storage { name: String, error: StorageError },
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostError::DuplicateName(name) => write!(f, "a chain called {} is already hosted", name),
+            HostError::SharedChainId { chain_id, with } => {
+                write!(f, "chain id {} is already {}'s, their transactions would mix", chain_id, with)
+            }
+            HostError::SharedDataDir { dir, with } => write!(f, "{} already holds {}'s files", dir.display(), with),
+            HostError::SharedRpcAddress { address, with } => write!(f, "{} is already {}'s json-rpc port", address, with),
+            HostError::SharedRpcPrefix { prefix, with } => write!(f, "json-rpc prefix {} is already {}'s", prefix, with),
+            HostError::Storage { name, error } => write!(f, "{} not opened: {}", name, error),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HostedChain {
+    pub spec: ChainSpec,
+    pub chain: Arc<Mutex<BlockChain>>,
+}
+
+impl HostedChain {
+    // a thread that panicked holding the chain left it as consistent as
+    // any other, the chain only changes between blocks
+    pub fn lock(&self) -> MutexGuard<'_, BlockChain> {
+        self.chain.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// the chains a process hosts, in the order they were added. cloning it
+// shares the chains, e.g. with the threads serving them
+#[derive(Debug, Clone, Default)]
+pub struct Node {
+    chains: Vec<HostedChain>,
+}
+
+impl Node {
+    pub fn new() -> Self {
+        Node::default()
+    }
+
+    // opens the chain `spec` describes, paying its rewards to `reward`
+    pub fn add_chain(&mut self, spec: ChainSpec, reward: String) -> Result<&HostedChain, HostError> {
+        self.check_isolated(&spec)?;
+        let chain: BlockChain = match &spec.data_dir {
+            Some(dir) => BlockChain::open_with_rules(dir, reward, spec.difficulty, spec.chain_id, spec.retarget)
+                .map_err(|error| HostError::Storage { name: spec.name.clone(), error })?,
+            None => {
+                let mut chain = BlockChain::with_chain_id(reward, spec.difficulty, spec.chain_id);
+                chain.set_retarget(spec.retarget);
+                chain
+            }
+        };
+        info!(chain = spec.name.as_str(), chain_id = spec.chain_id, prefix = spec.rpc_prefix.as_str(), "hosting a chain");
+        self.chains.push(HostedChain {
+            spec,
+            chain: Arc::new(Mutex::new(chain)),
+        });
+        Ok(self.chains.last().expect("just pushed"))
+    }
+
+    // nothing `spec` would open or listen on is another chain's already
+    fn check_isolated(&self, spec: &ChainSpec) -> Result<(), HostError> {
+        for hosted in self.chains.iter() {
+            let other: &ChainSpec = &hosted.spec;
+            let with: String = other.name.clone();
+            if other.name == spec.name {
+                return Err(HostError::DuplicateName(spec.name.clone()));
+            }
+            if other.chain_id == spec.chain_id {
+                return Err(HostError::SharedChainId { chain_id: spec.chain_id, with });
+            }
+            if let Some(dir) = spec.data_dir.as_ref().filter(|dir| other.data_dir.as_ref() == Some(*dir)) {
+                return Err(HostError::SharedDataDir { dir: dir.clone(), with });
+            }
+            if let Some(address) = spec.rpc_address.as_ref().filter(|address| other.rpc_address.as_ref() == Some(*address)) {
+                return Err(HostError::SharedRpcAddress { address: address.clone(), with });
+            }
+            if other.rpc_prefix.trim_end_matches('/') == spec.rpc_prefix.trim_end_matches('/') {
+                return Err(HostError::SharedRpcPrefix { prefix: spec.rpc_prefix.clone(), with });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn chains(&self) -> &[HostedChain] {
+        &self.chains
+    }
+
+    pub fn chain(&self, name: &str) -> Option<&HostedChain> {
+        self.chains.iter().find(|hosted| hosted.spec.name == name)
+    }
+
+    // the chain a request to `path` on the shared json-rpc port is for:
+    // the longest prefix the path is under, so a chain at "/" only gets
+    // what no other prefix claims
+    pub fn by_prefix(&self, path: &str) -> Option<&HostedChain> {
+        let path: &str = path.split(['?', '#']).next().unwrap_or("");
+        self.chains
+            .iter()
+            .filter(|hosted| {
+                let prefix: &str = hosted.spec.rpc_prefix.trim_end_matches('/');
+                path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|hosted| hosted.spec.rpc_prefix.trim_end_matches('/').len())
+    }
+
+    // every chain's status, by name
+    pub fn status(&self) -> Vec<(String, NodeStatus)> {
+        self.chains
+            .iter()
+            .map(|hosted| (hosted.spec.name.clone(), hosted.lock().status()))
+            .collect()
+    }
+
+    // shuts every chain down, one that can't be saved doesn't keep the
+    // others from closing cleanly. the ones that failed
+    pub fn shutdown(&self) -> Vec<(String, StorageError)> {
+        let mut failed = Vec::<(String, StorageError)>::new();
+        for hosted in self.chains.iter() {
+            if let Err(e) = hosted.lock().shutdown() {
+                warn!(chain = hosted.spec.name.as_str(), error = %e, "shutdown not saved");
+                failed.push((hosted.spec.name.clone(), e));
+            }
+        }
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::mempool::MempoolError;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::transaction::Transaction;
+
+    #[test]
+    fn hosted_chains_never_share_state_files_or_endpoints() {
+        let dir = std::env::temp_dir().join(format!("blockchain-node-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let wallet = miner();
+        let mut node = Node::new();
+        node.add_chain(ChainSpec::new("main", DEFAULT_CHAIN_ID, 0).with_rpc_prefix("/"), wallet.address().into())
+            .unwrap();
+        node.add_chain(ChainSpec::devnet("dev").with_data_dir(&dir), wallet.address().into()).unwrap();
+
+        // the same ids, files or endpoints again
+        let refused = |node: &mut Node, spec: ChainSpec| node.add_chain(spec, "miner".into()).err();
+        assert!(matches!(refused(&mut node, ChainSpec::devnet("dev")), Some(HostError::DuplicateName(_))));
+        assert!(matches!(refused(&mut node, ChainSpec::devnet("test")), Some(HostError::SharedChainId { .. })));
+        let same_dir = ChainSpec::new("test", 3, 0).with_data_dir(&dir);
+        assert!(matches!(refused(&mut node, same_dir), Some(HostError::SharedDataDir { .. })));
+        let same_prefix = ChainSpec::new("test", 3, 0).with_rpc_prefix("/dev");
+        assert!(matches!(refused(&mut node, same_prefix), Some(HostError::SharedRpcPrefix { .. })));
+
+        let main = node.chain("main").unwrap();
+        let dev = node.chain("dev").unwrap();
+        for _ in 0..BlockChain::COINBASE_MATURITY + 3 {
+            main.lock().mining().unwrap();
+        }
+        dev.lock().mining().unwrap();
+        assert_ne!(main.lock().blocks().len(), dev.lock().blocks().len());
+        assert_eq!(dev.lock().storage_path().map(|path| path.starts_with(&dir)), Some(true));
+
+        // signed for the main network, the devnet won't take it
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        main.lock().add_transaction(&tx).unwrap();
+        let wrong_chain = MempoolError::WrongChain { expected: DEVNET_CHAIN_ID, found: DEFAULT_CHAIN_ID };
+        assert_eq!(dev.lock().add_transaction(&tx), Err(wrong_chain));
+        assert_eq!(dev.lock().pending_transactions().len(), 0);
+
+        assert_eq!(node.by_prefix("/dev").map(|hosted| hosted.spec.name.as_str()), Some("dev"));
+        assert_eq!(node.by_prefix("/dev/").map(|hosted| hosted.spec.name.as_str()), Some("dev"));
+        assert_eq!(node.by_prefix("/devnet").map(|hosted| hosted.spec.name.as_str()), Some("main"));
+        assert_eq!(node.by_prefix("/").map(|hosted| hosted.spec.name.as_str()), Some("main"));
+        assert_eq!(node.shutdown(), Vec::new());
+        assert_eq!(node.status().len(), 2);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::blockchain::clock::local_time;
+use crate::blockchain::transaction::{put_bytes, take_array, take_bytes};
+use crate::blockchain::BlockChain;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use tracing::{info, warn};
+
+// how long `set_ban` keeps a peer out when no time is given, a day like
+// bitcoind's default
+pub const DEFAULT_BAN_TIME: u128 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeerError {
+    // neither connected nor added with `add_node`
+    UnknownPeer(String),
+    Banned { peer: String, until: u128 },
+    NotBanned(String),
+}
+
+impl fmt::Display for PeerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerError::UnknownPeer(peer) => write!(f, "{} is not a peer of this node", peer),
+            PeerError::Banned { peer, until } => write!(f, "{} is banned until {}", peer, until),
+            PeerError::NotBanned(peer) => write!(f, "{} is not banned", peer),
+        }
+    }
+}
+
+impl std::error::Error for PeerError {}
+
+// peers the node refuses to talk to, each until a local time in
+// nanoseconds. kept next to the block file, so a restart doesn't let them
+// straight back in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BanList {
+    bans: BTreeMap<String, u128>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        BanList {
+            bans: BTreeMap::<String, u128>::new(),
+        }
+    }
+
+    // banning a peer again moves the end of its ban, it doesn't add up
+    pub fn ban(&mut self, peer: &str, until: u128) {
+        self.bans.insert(peer.to_string(), until);
+    }
+
+    // whether it was banned
+    pub fn unban(&mut self, peer: &str) -> bool {
+        self.bans.remove(peer).is_some()
+    }
+
+    pub fn banned_until(&self, peer: &str, now: u128) -> Option<u128> {
+        self.bans.get(peer).copied().filter(|until| *until > now)
+    }
+
+    // bans still running at `now`, by peer
+    pub fn banned(&self, now: u128) -> Vec<(String, u128)> {
+        self.bans
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(peer, until)| (peer.clone(), *until))
+            .collect()
+    }
+
+    // forgets the bans that ran out, whether there were any
+    pub fn expire(&mut self, now: u128) -> bool {
+        let before = self.bans.len();
+        self.bans.retain(|_, until| *until > now);
+        self.bans.len() != before
+    }
+
+    // each ban the peer's length and bytes, then the end of the ban
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        for (peer, until) in self.bans.iter() {
+            put_bytes(&mut bin, peer.as_bytes());
+            bin.extend(until.to_be_bytes());
+        }
+        bin
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<BanList> {
+        let mut banlist = BanList::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let peer = String::from_utf8(take_bytes(bytes, &mut pos).ok()?).ok()?;
+            let until = u128::from_be_bytes(take_array(bytes, &mut pos).ok()?);
+            banlist.bans.insert(peer, until);
+        }
+        Some(banlist)
+    }
+}
+
+impl BlockChain {
+    // the operator's side of the connections, like bitcoind's addnode,
+    // disconnectnode, setban and listbanned. the node doesn't open sockets
+    // itself: whatever drives the connections reads `added_nodes` and asks
+    // `is_banned` before answering a handshake
+
+    // keeps trying to stay connected to `peer`
+    pub fn add_node(&mut self, peer: &str) -> Result<(), PeerError> {
+        if let Some(until) = self.banlist.banned_until(peer, local_time()) {
+            return Err(PeerError::Banned { peer: peer.to_string(), until });
+        }
+        if self.added_nodes.insert(peer.to_string()) {
+            info!(peer, "added a peer");
+        }
+        Ok(())
+    }
+
+    pub fn added_nodes(&self) -> &BTreeSet<String> {
+        &self.added_nodes
+    }
+
+    // drops the connection and the peer from the added nodes. returns the
+    // heights it still owed, to ask another peer for
+    pub fn disconnect_node(&mut self, peer: &str) -> Result<Vec<u64>, PeerError> {
+        let added = self.added_nodes.remove(peer);
+        if !added && self.sync.peer(peer).is_none() {
+            return Err(PeerError::UnknownPeer(peer.to_string()));
+        }
+        info!(peer, "disconnected a peer");
+        Ok(self.disconnect_peer(peer))
+    }
+
+    // bans `peer` for `duration` nanoseconds, `DEFAULT_BAN_TIME` when none,
+    // and disconnects it. returns the heights it still owed
+    pub fn set_ban(&mut self, peer: &str, duration: Option<u128>) -> Vec<u64> {
+        let until = local_time().saturating_add(duration.unwrap_or(DEFAULT_BAN_TIME));
+        self.banlist.ban(peer, until);
+        self.added_nodes.remove(peer);
+        self.save_banlist();
+        warn!(peer, until, "banned a peer");
+        self.disconnect_peer(peer)
+    }
+
+    pub fn remove_ban(&mut self, peer: &str) -> Result<(), PeerError> {
+        if !self.banlist.unban(peer) {
+            return Err(PeerError::NotBanned(peer.to_string()));
+        }
+        self.save_banlist();
+        info!(peer, "lifted a ban");
+        Ok(())
+    }
+
+    // running bans, by peer, with the local time each one ends at
+    pub fn list_banned(&self) -> Vec<(String, u128)> {
+        self.banlist.banned(local_time())
+    }
+
+    pub fn is_banned(&self, peer: &str) -> bool {
+        self.banlist.banned_until(peer, local_time()).is_some()
+    }
+
+    // a ban still holds if it can't be saved, only until the next start
+    fn save_banlist(&mut self) {
+        self.banlist.expire(local_time());
+        if let Some(store) = self.storage.as_ref() {
+            let saved = store.save_banlist(&self.banlist);
+            if let Err(e) = &saved {
+                warn!(error = %e, "banlist not saved");
+            }
+            self.record_storage(&saved);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_banned_peer_is_dropped_and_cant_be_added_again() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.add_node("10.0.0.1:8333").unwrap();
+        chain.add_node("10.0.0.2:8333").unwrap();
+        assert_eq!(chain.disconnect_node("10.0.0.2:8333"), Ok(vec![]));
+        assert_eq!(chain.disconnect_node("10.0.0.2:8333"), Err(PeerError::UnknownPeer("10.0.0.2:8333".into())));
+
+        chain.set_ban("10.0.0.1:8333", None);
+        assert!(chain.is_banned("10.0.0.1:8333"));
+        assert!(chain.added_nodes().is_empty());
+        assert!(matches!(chain.add_node("10.0.0.1:8333"), Err(PeerError::Banned { .. })));
+        assert_eq!(chain.list_banned().len(), 1);
+
+        assert_eq!(BanList::decode(&chain.banlist.encode()).as_ref(), Some(&chain.banlist));
+        chain.remove_ban("10.0.0.1:8333").unwrap();
+        assert_eq!(chain.remove_ban("10.0.0.1:8333"), Err(PeerError::NotBanned("10.0.0.1:8333".into())));
+        chain.add_node("10.0.0.1:8333").unwrap();
+    }
+
+    #[test]
+    fn bans_run_out() {
+        let mut banlist = BanList::new();
+        banlist.ban("a", 10);
+        banlist.ban("b", 20);
+        assert_eq!(banlist.banned_until("a", 5), Some(10));
+        assert_eq!(banlist.banned(10), vec![("b".to_string(), 20)]);
+        assert!(banlist.expire(15));
+        assert!(!banlist.expire(15));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::blockchain::difficulty::expected_hashes;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::{Block, BlockChain, BlockHeader};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tracing::{field, info_span};
+
+// below this many expected hashes a block is found before threads would
+// even have started, it is mined on the calling thread
+const PARALLEL_THRESHOLD: f64 = 65_536.0;
+
+// hashes a thread tries before adding them to the count everyone reads
+const COUNT_EVERY: u64 = 1024;
+
+// searches nonces from the header's one on until the hash meets the
+// header's difficulty, split over `threads`: thread i tries the nonces
+// i, i + threads, i + 2 * threads... after the first. every thread stops
+// as soon as one finds a hash, or `cancel` is set, e.g. because a block
+// for the same height arrived from the network. the hashes tried are added
+// to `tried`. the nonce and the hash, none if cancelled or not one of the
+// 2^32 nonces was enough
+pub fn solve(header: &BlockHeader, threads: usize, cancel: &AtomicBool, tried: &AtomicU64) -> Option<(i32, Hash32)> {
+    let threads = threads.max(1);
+    let found = AtomicBool::new(false);
+    let search = |first: u64| -> Option<(i32, Hash32)> {
+        let mut header = header.clone();
+        let start: i32 = header.nonce;
+        let mut offset: u64 = first;
+        let mut hashes: u64 = 0;
+        let mut result: Option<(i32, Hash32)> = None;
+        while offset <= u32::MAX as u64 && !found.load(Ordering::Relaxed) && !cancel.load(Ordering::Relaxed) {
+            header.nonce = start.wrapping_add(offset as u32 as i32);
+            let hash: Hash32 = header.hash();
+            hashes += 1;
+            if BlockChain::meets_target(&hash, header.difficulty) {
+                found.store(true, Ordering::Relaxed);
+                result = Some((header.nonce, hash));
+                break;
+            }
+            if hashes == COUNT_EVERY {
+                tried.fetch_add(hashes, Ordering::Relaxed);
+                hashes = 0;
+            }
+            offset += threads as u64;
+        }
+        tried.fetch_add(hashes, Ordering::Relaxed);
+        result
+    };
+
+    if threads == 1 {
+        return search(0);
+    }
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads as u64).map(|first| scope.spawn(move || search(first))).collect();
+        // two threads can both find one before they see the other's, the
+        // lowest thread's wins so an easy block always gets the same nonce
+        workers
+            .into_iter()
+            .filter_map(|worker| worker.join().expect("a proof of work thread panicked"))
+            .next()
+    })
+}
+
+// solves `block` in place, moving its time stamp on whenever every nonce
+// failed. its hash, none if cancelled. the search runs in a `pow` span
+// that gets the hashes tried and the time stamps used once it is over
+pub fn work(block: &mut Block, threads: usize, cancel: &AtomicBool, tried: &AtomicU64) -> Option<Hash32> {
+    let span = info_span!("pow", difficulty = block.header.difficulty, threads, hashes = field::Empty, rounds = field::Empty);
+    let _entered = span.enter();
+    let before: u64 = tried.load(Ordering::Relaxed);
+    let mut rounds: u64 = 0;
+    let found: Option<Hash32> = loop {
+        rounds += 1;
+        match solve(&block.header, threads, cancel, tried) {
+            Some((nonce, hash)) => {
+                block.header.nonce = nonce;
+                break Some(hash);
+            }
+            None if cancel.load(Ordering::Relaxed) => break None,
+            // a later time stamp gives new hashes to try
+            None => block.header.time_stamp += 1,
+        }
+    };
+    span.record("hashes", tried.load(Ordering::Relaxed) - before);
+    span.record("rounds", rounds);
+    found
+}
+
+impl BlockChain {
+    // threads proof of work runs on, 0 (the default) is one per core
+    pub fn set_mining_threads(&mut self, threads: usize) {
+        self.mining_threads = threads;
+    }
+
+    // setting it stops the block being mined, `mining` then returns
+    // `MiningCancelled` and leaves the chain as it was. kept by whatever
+    // receives blocks, which can't take the chain while it is mining.
+    // cancels `mine_async` too
+    pub fn mining_cancel_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel_mining)
+    }
+
+    // the threads a block at `difficulty` is worth
+    pub(crate) fn pow_threads(&self, difficulty: usize) -> usize {
+        if expected_hashes(difficulty) < PARALLEL_THRESHOLD {
+            return 1;
+        }
+        match self.mining_threads {
+            0 => thread::available_parallelism().map_or(1, |cores| cores.get()),
+            threads => threads,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::error::BlockchainError;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::transaction::Transaction;
+    use crate::blockchain::Block;
+    use std::time::Duration;
+
+    #[test]
+    fn threads_share_the_search_and_stop_when_cancelled() {
+        let mut block = Block::new(0, Hash32::digest(b"parent"));
+        block.header.difficulty = 3;
+        let tried = AtomicU64::new(0);
+        let (nonce, hash) = solve(&block.header(), 4, &AtomicBool::new(false), &tried).unwrap();
+        block.header.nonce = nonce;
+        assert_eq!(block.compute_hash(), hash);
+        assert!(BlockChain::meets_target(&hash, 3));
+        assert!(tried.load(Ordering::Relaxed) > 0);
+
+        assert_eq!(solve(&block.header(), 4, &AtomicBool::new(true), &tried), None);
+    }
+
+    #[test]
+    fn a_cancelled_block_leaves_the_chain_as_it_was() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        let (height, root) = (chain.blocks().len(), chain.state().root());
+
+        // nobody finds 12 zeros in a test, the block only ends when cancelled
+        chain.difficulty = 12;
+        chain.set_mining_threads(2);
+        let cancel = chain.mining_cancel_handle();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(chain.mining(), Err(BlockchainError::MiningCancelled));
+        canceller.join().unwrap();
+        assert_eq!((chain.blocks().len(), chain.state().root()), (height, root));
+        assert_eq!(chain.pending_transactions().len(), 1);
+
+        chain.difficulty = 0;
+        chain.mining().unwrap();
+        assert_eq!(chain.blocks().len(), height + 1);
+        assert!(chain.pending_transactions().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::blockchain::gas;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// ed25519 signatures can't be used to recover the public key the way
+// secp256k1 ones can, so there is no ecrecover here: the key is part of
+// the input and ED25519_VERIFY checks against it instead
+pub const SHA256: u8 = 0x01;
+pub const ED25519_VERIFY: u8 = 0x02;
+
+#[derive(Debug, PartialEq)]
+pub enum PrecompileError {
+    Unknown(u8),
+    InvalidInput(&'static str),
+}
+
+impl fmt::Display for PrecompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrecompileError::Unknown(id) => write!(f, "no precompile with id 0x{:02x}", id),
+            PrecompileError::InvalidInput(reason) => write!(f, "invalid precompile input: {}", reason),
+        }
+    }
+}
+
+// a function run natively by the node instead of by the script or the
+// contract calling it, at a fixed price known before running it
+pub struct Precompile {
+    pub id: u8,
+    pub name: &'static str,
+    base_gas: u64,
+    // charged per started 32 byte word of input
+    word_gas: u64,
+    function: fn(&[u8]) -> Result<Vec<u8>, PrecompileError>,
+}
+
+impl Precompile {
+    pub fn gas(&self, input_len: usize) -> u64 {
+        let words = input_len.div_ceil(32) as u64;
+        self.base_gas + self.word_gas.saturating_mul(words)
+    }
+
+    pub fn run(&self, input: &[u8]) -> Result<Vec<u8>, PrecompileError> {
+        (self.function)(input)
+    }
+}
+
+pub const PRECOMPILES: &[Precompile] = &[
+    Precompile {
+        id: SHA256,
+        name: "sha256",
+        base_gas: gas::SHA256_GAS,
+        word_gas: gas::SHA256_WORD_GAS,
+        function: sha256,
+    },
+    Precompile {
+        id: ED25519_VERIFY,
+        name: "ed25519_verify",
+        base_gas: gas::ED25519_VERIFY_GAS,
+        word_gas: gas::ED25519_VERIFY_WORD_GAS,
+        function: ed25519_verify,
+    },
+];
+
+pub fn precompile(id: u8) -> Result<&'static Precompile, PrecompileError> {
+    PRECOMPILES
+        .iter()
+        .find(|p| p.id == id)
+        .ok_or(PrecompileError::Unknown(id))
+}
+
+fn sha256(input: &[u8]) -> Result<Vec<u8>, PrecompileError> {
+    Ok(Sha256::digest(input).to_vec())
+}
+
+// input: public key (32 bytes) || signature (64 bytes) || message
+// output: [1] for a valid signature, [0] otherwise
+fn ed25519_verify(input: &[u8]) -> Result<Vec<u8>, PrecompileError> {
+    if input.len() < 96 {
+        return Err(PrecompileError::InvalidInput("expected a 32 byte key and a 64 byte signature"));
+    }
+
+    let public_key: [u8; 32] = input[..32].try_into().unwrap();
+    let signature: [u8; 64] = input[32..96].try_into().unwrap();
+    let valid = match VerifyingKey::from_bytes(&public_key) {
+        Ok(key) => key.verify(&input[96..], &Signature::from_bytes(&signature)).is_ok(),
+        Err(_) => false,
+    };
+
+    Ok(vec![valid as u8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn precompiles_give_their_outputs_at_their_price() {
+        let sha = precompile(SHA256).unwrap();
+        assert_eq!(sha.run(b"abc").unwrap(), Sha256::digest(b"abc").to_vec());
+        // a started word costs as much as a full one
+        assert_eq!(sha.gas(0), gas::SHA256_GAS);
+        assert_eq!(sha.gas(33), gas::SHA256_GAS + 2 * gas::SHA256_WORD_GAS);
+        assert_eq!(precompile(0x09).err(), Some(PrecompileError::Unknown(0x09)));
+    }
+
+    #[test]
+    fn ed25519_verify_answers_one_or_zero() {
+        let wallet = test_wallet("signer");
+        let message: &[u8] = b"pay B 10";
+        let input = |message: &[u8]| -> Vec<u8> {
+            [&wallet.public_key()[..], &wallet.sign(b"pay B 10")[..], message].concat()
+        };
+        let verify = precompile(ED25519_VERIFY).unwrap();
+        assert_eq!(verify.run(&input(message)), Ok(vec![1]));
+        assert_eq!(verify.run(&input(b"pay B 99")), Ok(vec![0]));
+        assert!(matches!(verify.run(&input(message)[..95]), Err(PrecompileError::InvalidInput(_))));
+    }
+}
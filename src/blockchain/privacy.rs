@@ -0,0 +1,129 @@
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::wallets::WalletError;
+use crate::blockchain::BlockChain;
+
+// what an observer of the chain can learn about one of the node's wallets.
+// this chain has accounts, not coins, so every payment to a wallet lands on
+// the same address and there is no change output to spot. the heuristics
+// below are the account versions of the usual ones:
+//
+// - reuse: every transaction after the first ties more activity to the
+//   same address
+// - linked wallets: a payment between two of the node's own wallets is the
+//   closest thing to change, it tells anyone watching both belong together
+// - round amounts: a payment of 500 was probably chosen by a person, which
+//   says which side of the transaction is the payment
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrivacyReport {
+    pub address: String,
+    pub transactions: usize,
+    // transactions that reused the address, all but the first
+    pub reused: usize,
+    // names of the node's other wallets it paid or was paid by
+    pub linked_wallets: Vec<String>,
+    // payments it sent with a round value
+    pub round_amounts: usize,
+    pub sent: usize,
+    // 100 leaks nothing the heuristics find, 0 leaks everything
+    pub score: u8,
+}
+
+// two trailing zeros or more, like a price a person picked
+pub fn is_round(value: u64) -> bool {
+    value >= 100 && value.is_multiple_of(100)
+}
+
+impl PrivacyReport {
+    // each heuristic can take away a fixed share of the score, so one
+    // bad habit doesn't hide the others
+    fn score(&self) -> u8 {
+        let reuse = (self.reused as u64 * 5).min(40);
+        let linked = (self.linked_wallets.len() as u64 * 15).min(30);
+        let round = (self.round_amounts as u64 * 30).checked_div(self.sent as u64).unwrap_or(0);
+        100u64.saturating_sub(reuse + linked + round) as u8
+    }
+}
+
+impl BlockChain {
+    // built on the address index, a wallet on a node that only indexes
+    // watched addresses is watched from the moment it is loaded
+    pub fn privacy_report(&self, name: &str) -> Result<PrivacyReport, WalletError> {
+        let address: Vec<u8> = self.wallet(name)?.address().into_bytes();
+        let history = self.index.address_history(&address);
+
+        let mut linked_wallets = Vec::<String>::new();
+        let mut round_amounts: usize = 0;
+        let mut sent: usize = 0;
+        for location in history {
+            let block = &self.chain[location.height as usize];
+            let Some(tx) = Transaction::decode(&block.transactions[location.index]) else {
+                continue;
+            };
+            if tx.sender_address == address {
+                sent += 1;
+                if is_round(tx.value) {
+                    round_amounts += 1;
+                }
+            }
+            let other: &[u8] = if tx.sender_address == address { &tx.recipient_address } else { &tx.sender_address };
+            for (other_name, wallet) in self.wallets.iter() {
+                if other_name != name && wallet.address().as_bytes() == other && !linked_wallets.contains(other_name) {
+                    linked_wallets.push(other_name.clone());
+                }
+            }
+        }
+
+        let mut report = PrivacyReport {
+            address: String::from_utf8_lossy(&address).into_owned(),
+            transactions: history.len(),
+            reused: history.len().saturating_sub(1),
+            linked_wallets,
+            round_amounts,
+            sent,
+            score: 0,
+        };
+        report.score = report.score();
+        Ok(report)
+    }
+
+    // one report for every loaded wallet, by name
+    pub fn privacy_reports(&self) -> Vec<(String, PrivacyReport)> {
+        self.wallets
+            .keys()
+            .filter_map(|name| Some((name.clone(), self.privacy_report(name).ok()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn paying_your_own_wallet_costs_privacy() {
+        let mut chain = BlockChain::with_difficulty(test_wallet("miner").address().into(), 0);
+        chain.load_wallet("savings", test_wallet("savings")).unwrap();
+        chain.load_wallet("spending", test_wallet("spending")).unwrap();
+        chain.load_wallet("cold", test_wallet("cold")).unwrap();
+        chain.set_reward_wallet("savings").unwrap();
+        for _ in 0..BlockChain::COINBASE_MATURITY + 2 {
+            chain.mining().unwrap();
+        }
+        let spending = chain.wallet("spending").unwrap().address().into_bytes();
+        chain.send_from("savings", &spending, 1, 0).unwrap();
+        chain.mining().unwrap();
+
+        let savings = chain.privacy_report("savings").unwrap();
+        assert_eq!(savings.linked_wallets, vec!["spending".to_string()]);
+        assert_eq!((savings.sent, savings.round_amounts), (1, 0));
+        // 13 rewards and a payment on one address
+        assert_eq!(savings.reused, savings.transactions - 1);
+        assert_eq!(savings.score, 100 - 40 - 15);
+
+        let cold = chain.privacy_report("cold").unwrap();
+        assert_eq!((cold.transactions, cold.score), (0, 100));
+        assert_eq!(chain.privacy_reports().len(), 3);
+        assert!(is_round(500) && !is_round(50) && !is_round(501));
+    }
+}
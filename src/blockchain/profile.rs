@@ -0,0 +1,117 @@
+use crate::blockchain::BlockChain;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tracing::{field, info_span};
+
+// where the time of a block goes. every stage runs in a `stage` span
+// with the height and, once it is over, the time it took in
+// `elapsed_us`, so any subscriber can collect them. the node keeps the
+// stages of the last block it mined or took from a peer in a BlockProfile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    // picking the pool's transactions for a block
+    Template,
+    // hashing the block, checking its proof of work and decoding and
+    // checking the signatures of its transactions, for a block from a peer
+    Prepare,
+    // applying the transactions to the state
+    Execute,
+    // the state root, the receipts and the other commitments
+    Commit,
+    ProofOfWork,
+    // writing the block to the block file
+    Storage,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Template => "template",
+            Stage::Prepare => "prepare",
+            Stage::Execute => "execute",
+            Stage::Commit => "commit",
+            Stage::ProofOfWork => "proof-of-work",
+            Stage::Storage => "storage",
+        }
+    }
+}
+
+// how long each stage of one block took, in the order they ran
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BlockProfile {
+    pub height: u64,
+    pub stages: Vec<(Stage, Duration)>,
+}
+
+impl BlockProfile {
+    pub fn new(height: u64) -> Self {
+        BlockProfile { height, stages: Vec::new() }
+    }
+
+    // runs `work` as `stage` of the block and adds the time it took
+    pub(crate) fn time<T>(&mut self, stage: Stage, work: impl FnOnce() -> T) -> T {
+        let span = info_span!("stage", stage = stage.name(), height = self.height, elapsed_us = field::Empty);
+        let started = Instant::now();
+        let result = span.in_scope(work);
+        let elapsed = started.elapsed();
+        span.record("elapsed_us", elapsed.as_micros() as u64);
+        self.record(stage, elapsed);
+        result
+    }
+
+    // a stage that runs twice, e.g. proof of work again after a block
+    // for the same height came first, adds up
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        match self.stages.iter_mut().find(|(s, _)| *s == stage) {
+            Some((_, total)) => *total += elapsed,
+            None => self.stages.push((stage, elapsed)),
+        }
+    }
+
+    pub fn get(&self, stage: Stage) -> Option<Duration> {
+        self.stages.iter().find(|(s, _)| *s == stage).map(|(_, elapsed)| *elapsed)
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, elapsed)| *elapsed).sum()
+    }
+}
+
+// one line per stage, with its share of the block's total
+impl fmt::Display for BlockProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total: Duration = self.total();
+        writeln!(f, "block {}: {:.3} ms", self.height, total.as_secs_f64() * 1e3)?;
+        for (stage, elapsed) in self.stages.iter() {
+            let share: f64 = elapsed.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON) * 100.0;
+            writeln!(f, "  {:<14} {:>10.3} ms {:>5.1}%", stage.name(), elapsed.as_secs_f64() * 1e3, share)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockChain {
+    // the stages of the last block this node mined or took from a peer,
+    // empty before the first one
+    pub fn last_profile(&self) -> &BlockProfile {
+        &self.profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_mined_block_is_profiled_stage_by_stage() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+
+        let profile: &BlockProfile = chain.last_profile();
+        let tip: u64 = chain.blocks().len() as u64 - 1;
+        assert_eq!(profile.height, tip);
+        let stages: Vec<Stage> = profile.stages.iter().map(|(stage, _)| *stage).collect();
+        assert_eq!(stages, vec![Stage::Template, Stage::Execute, Stage::Commit, Stage::ProofOfWork]);
+        assert!(profile.to_string().starts_with(&format!("block {}: ", tip)));
+    }
+}
@@ -0,0 +1,434 @@
+use crate::blockchain::announcement::BlockAnnouncement;
+use crate::blockchain::error::BlockchainError;
+use crate::blockchain::bloom::Bloom;
+use crate::blockchain::filter::BlockFilter;
+use crate::blockchain::fraud::FraudProof;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::merkle::{MerkleProof, MerkleStep};
+use crate::blockchain::transaction::{
+    put_bytes, take_array, take_bool, take_bytes, take_end, take_hash, take_u64, take_u8,
+};
+use crate::blockchain::{BlockChain, BlockHeader, DeserializeError, Serialization};
+
+// most headers sent in one message, a client asks again from the last one
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+
+// blocks a locator lists one by one back from the tip before it starts
+// skipping
+const LOCATOR_DENSE: usize = 10;
+
+// the heights a locator lists for a chain from `base` to `tip`, newest
+// first: the last ten, then twice as far back at every step, and `base`
+// last. about 10 + log2(tip) of them however long the chain is, and the
+// fork with a peer is never further than twice its distance from the tip
+// from one of them, so one round trip finds it
+pub fn locator_heights(tip: u64, base: u64) -> Vec<u64> {
+    let mut heights = Vec::<u64>::new();
+    let mut height: u64 = tip;
+    let mut step: u64 = 1;
+    while height > base {
+        heights.push(height);
+        if heights.len() >= LOCATOR_DENSE {
+            step = step.saturating_mul(2);
+        }
+        height = height.saturating_sub(step).max(base);
+    }
+    heights.push(base);
+    heights
+}
+
+// the messages a full node answers for light clients
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    GetMerkleProof { txid: Vec<u8> },
+    // proof that `txid` is in the block at `height`
+    MerkleProof { txid: Vec<u8>, height: u64, proof: MerkleProof },
+    // hashes of blocks the client has, newest first. the node answers with
+    // the headers after the first one it knows
+    GetHeaders { locator: Vec<Hash32> },
+    Headers { start_height: u64, headers: Vec<BlockHeader> },
+    NotFound,
+    // relayed as soon as a node rejects a block, so peers and light
+    // clients stop following it
+    FraudProof(Box<FraudProof>),
+    // compact filters for `count` blocks from `start_height`
+    GetFilters { start_height: u64, count: u64 },
+    Filters { start_height: u64, filters: Vec<BlockFilter> },
+    // first message both sides send on a new connection, the time lets
+    // each side see how far off its own clock is
+    Version { time_stamp: u128, height: u64 },
+    // a block the sender produced, signed with its node key
+    Announce(Box<BlockAnnouncement>),
+}
+
+impl BlockChain {
+    // what to send in `GetHeaders`: hashes of our blocks at
+    // `locator_heights`, ending with genesis
+    pub fn get_locator(&self) -> Vec<Hash32> {
+        let Some(tip) = (self.chain.len() as u64).checked_sub(1) else {
+            return Vec::<Hash32>::new();
+        };
+        locator_heights(tip, 0)
+            .into_iter()
+            .map(|height| self.chain[height as usize].hash())
+            .collect()
+    }
+
+    // the answer to a request, none for messages that aren't requests
+    pub fn handle_message(&self, message: &Message) -> Option<Message> {
+        match message {
+            Message::GetMerkleProof { txid } => {
+                let response = self
+                    .chain
+                    .iter()
+                    .enumerate()
+                    .find_map(|(height, block)| {
+                        let proof = block.merkle_proof(txid)?;
+                        Some(Message::MerkleProof {
+                            txid: txid.clone(),
+                            height: height as u64,
+                            proof,
+                        })
+                    })
+                    .unwrap_or(Message::NotFound);
+                Some(response)
+            }
+            Message::GetHeaders { locator } => {
+                // nothing in common means the client is on another chain
+                // entirely, it gets ours from after genesis. the first
+                // hashes are the client's newest blocks, searched from our
+                // tip down they are found quickly if we have them
+                let fork_height = locator
+                    .iter()
+                    .find_map(|hash| self.chain.iter().rposition(|block| block.hash() == *hash))
+                    .unwrap_or(0);
+
+                let headers: Vec<BlockHeader> = self
+                    .chain
+                    .iter()
+                    .skip(fork_height + 1)
+                    .take(MAX_HEADERS_PER_MESSAGE)
+                    .map(|block| block.header())
+                    .collect();
+                Some(Message::Headers {
+                    start_height: fork_height as u64 + 1,
+                    headers,
+                })
+            }
+            Message::GetFilters { start_height, count } => {
+                let filters: Vec<BlockFilter> = (*start_height..start_height.saturating_add(*count))
+                    .take(MAX_HEADERS_PER_MESSAGE)
+                    .map_while(|height| self.block_filter(height))
+                    .collect();
+                Some(Message::Filters {
+                    start_height: *start_height,
+                    filters,
+                })
+            }
+            Message::Version { .. } => Some(self.version_message()),
+            Message::MerkleProof { .. }
+            | Message::Headers { .. }
+            | Message::Filters { .. }
+            | Message::NotFound
+            | Message::FraudProof(_)
+            | Message::Announce(_) => None,
+        }
+    }
+}
+
+impl Serialization<BlockHeader> for BlockHeader {
+    fn serialization(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        self.put(&mut bin);
+        bin
+    }
+
+    fn deserialization(bytes: &[u8]) -> Result<BlockHeader, DeserializeError> {
+        let mut pos = 0;
+        let header = BlockHeader::take(bytes, &mut pos)?;
+        take_end(bytes, pos)?;
+        Ok(header)
+    }
+}
+
+impl BlockHeader {
+    // a fixed size, the block file puts the body right after it
+    pub(crate) fn put(&self, bin: &mut Vec<u8>) {
+        bin.extend(self.version.to_be_bytes());
+        bin.extend(self.nonce.to_be_bytes());
+        bin.extend(self.previous_hash.as_bytes());
+        bin.extend(self.time_stamp.to_be_bytes());
+        bin.extend((self.difficulty as u64).to_be_bytes());
+        bin.extend(self.merkle_root.as_bytes());
+        bin.extend(self.state_root.as_bytes());
+        bin.extend(self.logs_bloom.0);
+        bin.extend(self.receipts_root.as_bytes());
+        bin.extend(self.mmr_root.as_bytes());
+    }
+
+    pub(crate) fn take(bytes: &[u8], pos: &mut usize) -> Result<BlockHeader, DeserializeError> {
+        Ok(BlockHeader {
+            version: u32::from_be_bytes(take_array(bytes, pos)?),
+            nonce: i32::from_be_bytes(take_array(bytes, pos)?),
+            previous_hash: take_hash(bytes, pos)?,
+            time_stamp: u128::from_be_bytes(take_array(bytes, pos)?),
+            difficulty: take_u64(bytes, pos)? as usize,
+            merkle_root: take_hash(bytes, pos)?,
+            state_root: take_hash(bytes, pos)?,
+            logs_bloom: Bloom(take_array(bytes, pos)?),
+            receipts_root: take_hash(bytes, pos)?,
+            mmr_root: take_hash(bytes, pos)?,
+        })
+    }
+
+    // `decode` for callers that want to pass the error on
+    pub fn from_bytes(bytes: &[u8]) -> Result<BlockHeader, BlockchainError> {
+        BlockHeader::decode(bytes).ok_or(BlockchainError::MalformedHeader)
+    }
+
+    // none if the bytes aren't exactly one header, `deserialization` says
+    // why
+    pub fn decode(bytes: &[u8]) -> Option<BlockHeader> {
+        BlockHeader::deserialization(bytes).ok()
+    }
+}
+
+impl Serialization<MerkleProof> for MerkleProof {
+    fn serialization(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        bin.extend((self.steps.len() as u64).to_be_bytes());
+        for step in self.steps.iter() {
+            bin.push(step.is_left as u8);
+            put_bytes(&mut bin, &step.hash);
+        }
+        bin
+    }
+
+    fn deserialization(bytes: &[u8]) -> Result<MerkleProof, DeserializeError> {
+        let mut pos = 0;
+        let count = take_u64(bytes, &mut pos)?;
+        let mut steps = Vec::<MerkleStep>::new();
+        for _ in 0..count {
+            let is_left = take_bool(bytes, &mut pos)?;
+            let hash = take_bytes(bytes, &mut pos)?;
+            steps.push(MerkleStep { hash, is_left });
+        }
+        take_end(bytes, pos)?;
+        Ok(MerkleProof { steps })
+    }
+}
+
+impl Serialization<Message> for Message {
+    fn serialization(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        match self {
+            Message::GetMerkleProof { txid } => {
+                bin.push(0);
+                put_bytes(&mut bin, txid);
+            }
+            Message::MerkleProof { txid, height, proof } => {
+                bin.push(1);
+                put_bytes(&mut bin, txid);
+                bin.extend(height.to_be_bytes());
+                put_bytes(&mut bin, &proof.serialization());
+            }
+            Message::GetHeaders { locator } => {
+                bin.push(2);
+                bin.extend((locator.len() as u64).to_be_bytes());
+                for hash in locator.iter() {
+                    bin.extend(hash.as_bytes());
+                }
+            }
+            Message::Headers { start_height, headers } => {
+                bin.push(3);
+                bin.extend(start_height.to_be_bytes());
+                bin.extend((headers.len() as u64).to_be_bytes());
+                for header in headers.iter() {
+                    put_bytes(&mut bin, &header.serialization());
+                }
+            }
+            Message::NotFound => {
+                bin.push(4);
+            }
+            Message::FraudProof(fraud_proof) => match fraud_proof.as_ref() {
+                FraudProof::InvalidProofOfWork { header } => {
+                    bin.push(5);
+                    put_bytes(&mut bin, &header.serialization());
+                }
+                FraudProof::InvalidTransaction {
+                    header,
+                    transaction,
+                    proof,
+                } => {
+                    bin.push(6);
+                    put_bytes(&mut bin, &header.serialization());
+                    put_bytes(&mut bin, transaction);
+                    put_bytes(&mut bin, &proof.serialization());
+                }
+            },
+            Message::GetFilters { start_height, count } => {
+                bin.push(7);
+                bin.extend(start_height.to_be_bytes());
+                bin.extend(count.to_be_bytes());
+            }
+            Message::Filters { start_height, filters } => {
+                bin.push(8);
+                bin.extend(start_height.to_be_bytes());
+                bin.extend((filters.len() as u64).to_be_bytes());
+                for filter in filters.iter() {
+                    bin.extend(filter.n.to_be_bytes());
+                    put_bytes(&mut bin, &filter.data);
+                }
+            }
+            Message::Version { time_stamp, height } => {
+                bin.push(9);
+                bin.extend(time_stamp.to_be_bytes());
+                bin.extend(height.to_be_bytes());
+            }
+            Message::Announce(announcement) => {
+                bin.push(10);
+                bin.extend(announcement.height.to_be_bytes());
+                put_bytes(&mut bin, &announcement.header.serialization());
+                put_bytes(&mut bin, &announcement.producer);
+                put_bytes(&mut bin, &announcement.signature);
+            }
+        }
+        bin
+    }
+
+    fn deserialization(bytes: &[u8]) -> Result<Message, DeserializeError> {
+        let mut pos = 0;
+        let message = match take_u8(bytes, &mut pos)? {
+            0 => Message::GetMerkleProof {
+                txid: take_bytes(bytes, &mut pos)?,
+            },
+            1 => {
+                let txid = take_bytes(bytes, &mut pos)?;
+                let height = take_u64(bytes, &mut pos)?;
+                let proof = MerkleProof::deserialization(&take_bytes(bytes, &mut pos)?)?;
+                Message::MerkleProof { txid, height, proof }
+            }
+            2 => {
+                let count = take_count(bytes, &mut pos)?;
+                let locator = (0..count)
+                    .map(|_| take_hash(bytes, &mut pos))
+                    .collect::<Result<Vec<Hash32>, DeserializeError>>()?;
+                Message::GetHeaders { locator }
+            }
+            3 => {
+                let start_height = take_u64(bytes, &mut pos)?;
+                let count = take_count(bytes, &mut pos)?;
+                let headers = (0..count)
+                    .map(|_| BlockHeader::deserialization(&take_bytes(bytes, &mut pos)?))
+                    .collect::<Result<Vec<BlockHeader>, DeserializeError>>()?;
+                Message::Headers { start_height, headers }
+            }
+            4 => Message::NotFound,
+            5 => {
+                let header = BlockHeader::deserialization(&take_bytes(bytes, &mut pos)?)?;
+                Message::FraudProof(Box::new(FraudProof::InvalidProofOfWork { header }))
+            }
+            6 => {
+                let header = BlockHeader::deserialization(&take_bytes(bytes, &mut pos)?)?;
+                let transaction = take_bytes(bytes, &mut pos)?;
+                let proof = MerkleProof::deserialization(&take_bytes(bytes, &mut pos)?)?;
+                Message::FraudProof(Box::new(FraudProof::InvalidTransaction {
+                    header,
+                    transaction,
+                    proof,
+                }))
+            }
+            7 => {
+                let start_height = take_u64(bytes, &mut pos)?;
+                let count = take_u64(bytes, &mut pos)?;
+                Message::GetFilters { start_height, count }
+            }
+            8 => {
+                let start_height = take_u64(bytes, &mut pos)?;
+                let count = take_count(bytes, &mut pos)?;
+                let filters = (0..count)
+                    .map(|_| {
+                        let n = take_u64(bytes, &mut pos)?;
+                        let data = take_bytes(bytes, &mut pos)?;
+                        Ok(BlockFilter { n, data })
+                    })
+                    .collect::<Result<Vec<BlockFilter>, DeserializeError>>()?;
+                Message::Filters { start_height, filters }
+            }
+            9 => {
+                let time_stamp = u128::from_be_bytes(take_array(bytes, &mut pos)?);
+                let height = take_u64(bytes, &mut pos)?;
+                Message::Version { time_stamp, height }
+            }
+            10 => {
+                let height = take_u64(bytes, &mut pos)?;
+                let header = BlockHeader::deserialization(&take_bytes(bytes, &mut pos)?)?;
+                let producer = take_bytes(bytes, &mut pos)?;
+                let signature = take_bytes(bytes, &mut pos)?;
+                Message::Announce(Box::new(BlockAnnouncement {
+                    height,
+                    header,
+                    producer,
+                    signature,
+                }))
+            }
+            tag => return Err(DeserializeError::UnknownTag { kind: "message", tag }),
+        };
+        take_end(bytes, pos)?;
+        Ok(message)
+    }
+}
+
+impl Message {
+    // none if the bytes are truncated, have an unknown tag or carry more
+    // items than a message may hold, `deserialization` says which
+    pub fn decode(bytes: &[u8]) -> Option<Message> {
+        Message::deserialization(bytes).ok()
+    }
+}
+
+// lists in a message are never longer than a headers message
+fn take_count(bytes: &[u8], pos: &mut usize) -> Result<u64, DeserializeError> {
+    let count = take_u64(bytes, pos)?;
+    let max = MAX_HEADERS_PER_MESSAGE as u64;
+    if count > max {
+        return Err(DeserializeError::TooLong { count, max });
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_locator_is_dense_near_the_tip_and_sparse_behind_it() {
+        assert_eq!(locator_heights(0, 0), vec![0]);
+        assert_eq!(locator_heights(3, 0), vec![3, 2, 1, 0]);
+        let heights: Vec<u64> = locator_heights(1_000_000, 0);
+        assert_eq!(heights[..LOCATOR_DENSE], (999_991..=1_000_000).rev().collect::<Vec<u64>>()[..]);
+        assert!(heights.len() < 40);
+        assert_eq!(heights.last(), Some(&0));
+        assert_eq!(locator_heights(100, 90).last(), Some(&90));
+
+        // a peer that forked off after block 20 gets the headers from 21
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        for _ in 0..20 {
+            chain.mining().unwrap();
+        }
+        let mut peer = BlockChain::empty("peer".into(), 0, chain.chain_id());
+        peer.chain = chain.blocks().to_vec();
+        peer.reindex().unwrap();
+        for _ in 0..15 {
+            chain.mining().unwrap();
+        }
+        peer.mining().unwrap();
+        // 22 to 13, then 11, 7 and genesis
+        let locator: Vec<Hash32> = peer.get_locator();
+        assert_eq!(locator.len(), 13);
+        let Some(Message::Headers { start_height, headers }) = chain.handle_message(&Message::GetHeaders { locator }) else {
+            panic!("headers expected");
+        };
+        assert_eq!((start_height, headers.len()), (22, 15));
+    }
+}
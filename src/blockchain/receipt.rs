@@ -0,0 +1,168 @@
+use crate::blockchain::bloom::Bloom;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::light::{LightClient, LightClientError};
+use crate::blockchain::merkle::{self, MerkleProof};
+use crate::blockchain::transaction::put_bytes;
+use crate::blockchain::BlockChain;
+
+// an event emitted by a contract. topics are what logs get searched by,
+// data is free form
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Log {
+    // the contract that emitted it
+    pub address: Vec<u8>,
+    pub topics: Vec<Vec<u8>>,
+    pub data: Vec<u8>,
+}
+
+// the outcome of applying one transaction. a transaction that failed is
+// still mined: its nonce is used and the gas it burned is paid for, but
+// everything else it did is reverted, logs included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Receipt {
+    pub success: bool,
+    pub gas_used: u64,
+    pub logs: Vec<Log>,
+}
+
+impl Receipt {
+    pub fn bloom(&self) -> Bloom {
+        let mut bloom = Bloom::default();
+        for log in self.logs.iter() {
+            bloom.accrue(&log.address);
+            for topic in log.topics.iter() {
+                bloom.accrue(topic);
+            }
+        }
+        bloom
+    }
+
+    // the bytes the receipts root commits to, also how the block file
+    // stores a receipt
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        bin.push(self.success as u8);
+        bin.extend(self.gas_used.to_be_bytes());
+        bin.extend((self.logs.len() as u64).to_be_bytes());
+        for log in self.logs.iter() {
+            put_bytes(&mut bin, &log.address);
+            bin.extend((log.topics.len() as u64).to_be_bytes());
+            for topic in log.topics.iter() {
+                put_bytes(&mut bin, topic);
+            }
+            put_bytes(&mut bin, &log.data);
+        }
+        bin
+    }
+}
+
+// a leaf of the receipts tree. the txid goes in with the receipt, so a
+// proof shows which transaction the receipt is for and two transfers with
+// the same outcome are still different leaves
+fn receipt_leaf(txid: &[u8], receipt: &Receipt) -> Vec<u8> {
+    let mut leaf: Vec<u8> = txid.to_vec();
+    leaf.extend(receipt.encode());
+    leaf
+}
+
+// the merkle root over the receipts of a block, in transaction order.
+// `txids` and `receipts` line up, one receipt per transaction
+pub fn receipts_root(txids: &[Vec<u8>], receipts: &[Receipt]) -> Hash32 {
+    let leaves: Vec<Vec<u8>> = txids.iter().zip(receipts).map(|(txid, receipt)| receipt_leaf(txid, receipt)).collect();
+    merkle::merkle_root(&leaves)
+}
+
+// the receipt of a mined transaction and the path from it to the receipts
+// root of the block at `height`, so a light client can tell whether a
+// transaction succeeded and what it logged without executing anything
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceiptProof {
+    pub txid: Vec<u8>,
+    pub height: u64,
+    pub receipt: Receipt,
+    pub proof: MerkleProof,
+}
+
+impl ReceiptProof {
+    pub fn verify(&self, receipts_root: &Hash32) -> bool {
+        merkle::verify_merkle_proof(receipts_root.as_bytes(), &self.proof, &receipt_leaf(&self.txid, &self.receipt))
+    }
+}
+
+impl BlockChain {
+    // none if the transaction isn't mined, or is in a block known only by
+    // its header
+    pub fn prove_receipt(&self, txid: &[u8]) -> Option<ReceiptProof> {
+        let (block, location) = self.find_transaction(txid)?;
+        let txids: Vec<Vec<u8>> = block.txids();
+        let position: usize = txids.iter().position(|id| id == txid)?;
+        let receipt: Receipt = block.receipts.get(position)?.clone();
+        let leaves: Vec<Vec<u8>> = txids
+            .iter()
+            .zip(block.receipts.iter())
+            .map(|(id, receipt)| receipt_leaf(id, receipt))
+            .collect();
+        let proof: MerkleProof = merkle::merkle_proof(&leaves, &receipt_leaf(txid, &receipt))?;
+
+        Some(ReceiptProof {
+            txid: txid.to_vec(),
+            height: location.height,
+            receipt,
+            proof,
+        })
+    }
+}
+
+impl LightClient {
+    // checks the proof against the receipts root of our own header at the
+    // proof's height and returns the receipt it shows
+    pub fn verify_receipt<'a>(&self, proof: &'a ReceiptProof) -> Result<&'a Receipt, LightClientError> {
+        let header = self
+            .header(proof.height)
+            .ok_or(LightClientError::UnknownBlock { height: proof.height })?;
+        if !proof.verify(&header.receipts_root) {
+            return Err(LightClientError::InvalidReceiptProof);
+        }
+        Ok(&proof.receipt)
+    }
+}
+
+// the bloom a block commits to, covering the logs of all its receipts
+pub fn logs_bloom(receipts: &[Receipt]) -> Bloom {
+    let mut bloom = Bloom::default();
+    for receipt in receipts.iter() {
+        bloom.union(&receipt.bloom());
+    }
+    bloom
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::transaction::Transaction;
+
+    #[test]
+    fn a_light_client_checks_a_receipt_against_the_header() {
+        let wallet = miner();
+        let mut chain = BlockChain::new(wallet.address().into());
+        for _ in 0..BlockChain::COINBASE_MATURITY + 3 {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), test_wallet("bob").address().into_bytes(), 2).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.mining().unwrap();
+
+        let mut client = LightClient::new(chain.get_block(0).unwrap().header());
+        client.sync(&chain).unwrap();
+        let proof: ReceiptProof = chain.prove_receipt(&tx.id()).unwrap();
+        assert!(client.verify_receipt(&proof).unwrap().success);
+
+        // a node can't claim the transaction failed
+        let mut forged: ReceiptProof = proof.clone();
+        forged.receipt.success = false;
+        assert_eq!(client.verify_receipt(&forged), Err(LightClientError::InvalidReceiptProof));
+    }
+}
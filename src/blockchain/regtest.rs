@@ -0,0 +1,107 @@
+use crate::blockchain::genesis::GenesisConfig;
+use crate::blockchain::BlockChain;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// regtest, like bitcoind's: a local chain for demos and end to end tests.
+// blocks need no work, so they come when something asks for one, by hand
+// or from a `BlockTimer`, instead of when some miner gets lucky
+
+// its own network, transactions signed for it don't replay anywhere else
+pub const REGTEST_CHAIN_ID: u64 = 1337;
+
+impl BlockChain {
+    // a regtest chain paying `address`, with `genesis` funding the accounts
+    // a demo starts with. its difficulty and chain id don't count, the
+    // difficulty is 0 and never retargets
+    pub fn regtest(address: String, genesis: &GenesisConfig) -> BlockChain {
+        BlockChain::with_genesis(address, &genesis.clone().with_difficulty(0).with_chain_id(REGTEST_CHAIN_ID))
+    }
+
+    pub fn is_regtest(&self) -> bool {
+        self.chain_id == REGTEST_CHAIN_ID
+    }
+}
+
+// mines a block every `interval` on a thread of its own, like a network
+// with a steady block time. on a chain with real difficulty a block takes
+// as long as it takes, the next one is due an interval after it
+#[derive(Debug)]
+pub struct BlockTimer {
+    thread: JoinHandle<u64>,
+    stop: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl BlockTimer {
+    pub fn start(chain: &Arc<Mutex<BlockChain>>, interval: Duration) -> BlockTimer {
+        let cancel: Arc<AtomicBool> = chain
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .mining_cancel_handle();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let (chain, stop) = (Arc::clone(chain), Arc::clone(&stop));
+            thread::spawn(move || {
+                let mut mined: u64 = 0;
+                let mut due: Instant = Instant::now() + interval;
+                loop {
+                    // `stop` wakes the thread early
+                    while !stop.load(Ordering::Relaxed) && Instant::now() < due {
+                        thread::park_timeout(due.saturating_duration_since(Instant::now()));
+                    }
+                    if stop.load(Ordering::Relaxed) {
+                        return mined;
+                    }
+                    match BlockChain::mine_async(&chain).wait() {
+                        Ok(_) => mined += 1,
+                        Err(e) => warn!(error = %e, "block not mined on the timer"),
+                    }
+                    // a late block doesn't make the next ones come faster
+                    due = (due + interval).max(Instant::now());
+                }
+            })
+        };
+
+        BlockTimer { thread, stop, cancel }
+    }
+
+    // stops the timer, a block being mined is cancelled. how many blocks
+    // the timer mined
+    pub fn stop(self) -> u64 {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.store(true, Ordering::Relaxed);
+        self.thread.thread().unpark();
+        self.thread.join().expect("the block timer panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn the_timer_mines_until_it_is_stopped() {
+        let alice = test_wallet("alice");
+        let chain = BlockChain::regtest("miner".into(), &GenesisConfig::new().with_allocation(alice.address(), 100));
+        assert!(chain.is_regtest());
+        let height: usize = chain.blocks().len();
+        let chain = Arc::new(Mutex::new(chain));
+
+        let timer = BlockTimer::start(&chain, Duration::from_millis(10));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while chain.lock().unwrap().blocks().len() < height + 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let mined: u64 = timer.stop();
+        let chain = chain.lock().unwrap();
+        assert!(mined >= 3);
+        assert_eq!(chain.blocks().len(), height + mined as usize);
+        assert_eq!(chain.calculate_total_amount(&alice.address(), 1), 100);
+    }
+}
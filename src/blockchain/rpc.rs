@@ -0,0 +1,294 @@
+use crate::blockchain::error_code::{ErrorCode, HasErrorCode};
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::json::{block_json, json_string};
+use crate::blockchain::node::Node;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::BlockChain;
+use serde_json::Value;
+use std::io::Read;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+// json-rpc 2.0 over http, the protocol bitcoin-cli, curl scripts and most
+// blockchain tooling already speak. a request is a POST with a
+// {"jsonrpc":"2.0","method","params","id"} body, params by position or by
+// name, to any path of a chain's own port or under the chain's prefix on
+// the port a node shares between its chains. the methods:
+//
+//     getblockcount                   the height of the tip
+//     getblock [blockhash]            a block and its transactions, by
+//                                     hash or by height
+//     getbalance [address]            balance of an address at the tip
+//     sendrawtransaction [hexstring]  a signed transaction's bytes in hex
+//                                     into the pool. the txid
+//     getmempool                      the txids waiting for a block, best
+//                                     fee first
+//
+// a batch is an array of requests and gets an array of answers back.
+// requests without an id are notifications and get no answer at all
+
+// most of a request body that is read, a transaction is far smaller
+const MAX_BODY: u64 = 1 << 20;
+
+// the errors json-rpc itself defines
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+// anything the node refuses, with its error code's name and number in
+// the error's data
+pub const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<ErrorCode>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: &str) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    fn node(code: ErrorCode, message: &str) -> Self {
+        RpcError {
+            code: SERVER_ERROR,
+            message: message.to_string(),
+            data: Some(code),
+        }
+    }
+
+    fn json(&self) -> String {
+        let data: String = match self.data {
+            Some(code) => format!(
+                ",\"data\":{{\"code\":{},\"number\":{}}}",
+                json_string(code.as_str()),
+                code.number()
+            ),
+            None => String::new(),
+        };
+        format!("{{\"code\":{},\"message\":{}{}}}", self.code, json_string(&self.message), data)
+    }
+}
+
+// answers a request body, a single request or a batch. none when there is
+// nothing to send back, the body only held notifications
+pub fn handle(chain: &mut BlockChain, body: &[u8]) -> Option<String> {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return Some(response(&Value::Null, Err(RpcError::new(PARSE_ERROR, &e.to_string())))),
+    };
+    match request {
+        Value::Array(batch) if batch.is_empty() => {
+            Some(response(&Value::Null, Err(RpcError::new(INVALID_REQUEST, "the batch is empty"))))
+        }
+        Value::Array(batch) => {
+            let answers: Vec<String> = batch.iter().filter_map(|request| call(chain, request)).collect();
+            (!answers.is_empty()).then(|| format!("[{}]", answers.join(",")))
+        }
+        request => call(chain, &request),
+    }
+}
+
+// one request of a batch, or the only one
+fn call(chain: &mut BlockChain, request: &Value) -> Option<String> {
+    let id: Option<&Value> = request.get("id");
+    let method: Option<&str> = request.get("method").and_then(Value::as_str);
+    let result: Result<String, RpcError> = match (request.get("jsonrpc").and_then(Value::as_str), method) {
+        (Some("2.0"), Some(method)) => dispatch(chain, method, request.get("params").unwrap_or(&Value::Null)),
+        _ => Err(RpcError::new(INVALID_REQUEST, "not a json-rpc 2.0 request")),
+    };
+    // a notification is answered only when it isn't a request at all
+    match (id, &result) {
+        (None, Err(RpcError { code: INVALID_REQUEST, .. })) => Some(response(&Value::Null, result)),
+        (None, _) => None,
+        (Some(id), _) => Some(response(id, result)),
+    }
+}
+
+fn response(id: &Value, result: Result<String, RpcError>) -> String {
+    match result {
+        Ok(result) => format!("{{\"jsonrpc\":\"2.0\",\"result\":{},\"id\":{}}}", result, id),
+        Err(error) => format!("{{\"jsonrpc\":\"2.0\",\"error\":{},\"id\":{}}}", error.json(), id),
+    }
+}
+
+// the parameter at `index` of a positional list, or called `name` in an
+// object of named ones
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> Option<&'a Value> {
+    match params {
+        Value::Array(params) => params.get(index),
+        Value::Object(params) => params.get(name),
+        _ => None,
+    }
+}
+
+fn string_param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a str, RpcError> {
+    param(params, index, name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, &format!("{} is missing, or not a string", name)))
+}
+
+fn dispatch(chain: &mut BlockChain, method: &str, params: &Value) -> Result<String, RpcError> {
+    match method {
+        "getblockcount" => Ok((chain.blocks().len() - 1).to_string()),
+        "getblock" => {
+            let height: Option<usize> = match param(params, 0, "blockhash") {
+                Some(Value::Number(height)) => height.as_u64().map(|height| height as usize),
+                Some(Value::String(hash)) => {
+                    let hash: Hash32 = hash
+                        .parse()
+                        .map_err(|_| RpcError::new(INVALID_PARAMS, "blockhash is not a block hash"))?;
+                    chain.blocks().iter().position(|block| block.hash() == hash)
+                }
+                _ => return Err(RpcError::new(INVALID_PARAMS, "blockhash is missing, or not a hash or a height")),
+            };
+            height
+                .and_then(|height| chain.get_block(height).map(|block| block_json(height, block)))
+                .ok_or_else(|| RpcError::node(ErrorCode::UnknownBlock, "block not found"))
+        }
+        "getbalance" => {
+            let address: &str = string_param(params, 0, "address")?;
+            Ok(chain.state().balance(address.as_bytes()).to_string())
+        }
+        "sendrawtransaction" => {
+            let tx: Transaction = hex::decode(string_param(params, 0, "hexstring")?)
+                .ok()
+                .and_then(|bytes| Transaction::decode(&bytes))
+                .ok_or_else(|| RpcError::node(ErrorCode::MalformedTransaction, "hexstring isn't a transaction in hex"))?;
+            chain
+                .add_transaction(&tx)
+                .map_err(|e| RpcError::node(e.code(), &e.to_string()))?;
+            Ok(json_string(&hex::encode(tx.id())))
+        }
+        "getmempool" => {
+            let txids: Vec<String> = chain
+                .mempool()
+                .pending()
+                .iter()
+                .map(|tx| json_string(&hex::encode(tx.id())))
+                .collect();
+            Ok(format!("[{}]", txids.join(",")))
+        }
+        _ => Err(RpcError::new(METHOD_NOT_FOUND, &format!("no method {}", method))),
+    }
+}
+
+// serves json-rpc until the process is stopped, one request at a time. the
+// chain is only locked while a request is answered
+pub fn serve(chain: &Mutex<BlockChain>, address: &str) -> std::io::Result<()> {
+    listen(address, |_| Some(chain))
+}
+
+// every chain the node hosts on its own port if it has one, and under its
+// prefix on the shared `address` if there is one. a request never reaches
+// a chain it isn't addressed to, a path no chain claims is a 404
+pub fn serve_node(node: &Node, address: Option<&str>) -> std::io::Result<()> {
+    for hosted in node.chains().iter().filter(|hosted| hosted.spec.rpc_address.is_some()) {
+        let hosted = hosted.clone();
+        std::thread::spawn(move || {
+            let address: &str = hosted.spec.rpc_address.as_deref().expect("only chains with a port");
+            if let Err(e) = serve(&hosted.chain, address) {
+                warn!(chain = hosted.spec.name.as_str(), error = %e, "json-rpc stopped");
+            }
+        });
+    }
+    match address {
+        Some(address) => listen(address, |path| node.by_prefix(path).map(|hosted| &*hosted.chain)),
+        None => Ok(()),
+    }
+}
+
+// answers what is POSTed to the chain `route` finds for the request's path
+fn listen<'a>(address: &str, route: impl Fn(&str) -> Option<&'a Mutex<BlockChain>>) -> std::io::Result<()> {
+    let server = tiny_http::Server::http(address).map_err(std::io::Error::other)?;
+    info!(address, "json-rpc listening");
+
+    for mut request in server.incoming_requests() {
+        let mut body = Vec::<u8>::new();
+        let chain: Option<&Mutex<BlockChain>> = route(request.url());
+        let reply = if *request.method() != tiny_http::Method::Post {
+            tiny_http::Response::from_string("json-rpc requests are POSTed").with_status_code(405)
+        } else if let Some(chain) = chain {
+            let answer: Option<String> = match request.as_reader().take(MAX_BODY).read_to_end(&mut body) {
+                Ok(_) => handle(&mut chain.lock().unwrap(), &body),
+                Err(e) => Some(response(&Value::Null, Err(RpcError::new(PARSE_ERROR, &e.to_string())))),
+            };
+            let header = tiny_http::Header::from_bytes("Content-Type", "application/json")
+                .expect("content types are valid header values");
+            match answer {
+                Some(answer) => tiny_http::Response::from_string(answer).with_header(header),
+                // only notifications, nothing to answer
+                None => tiny_http::Response::from_string("").with_status_code(204),
+            }
+        } else {
+            tiny_http::Response::from_string("no chain at this path").with_status_code(404)
+        };
+        if let Err(e) = request.respond(reply) {
+            warn!(error = %e, "json-rpc response not sent");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::Serialization;
+
+    fn rpc(chain: &mut BlockChain, request: &str) -> Value {
+        serde_json::from_str(&handle(chain, request.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn tooling_sends_a_transaction_and_queries_the_node() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let height = chain.blocks().len() - 1;
+        let count = rpc(&mut chain, r#"{"jsonrpc":"2.0","method":"getblockcount","id":1}"#);
+        assert_eq!(count, serde_json::json!({"jsonrpc": "2.0", "result": height, "id": 1}));
+
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        let raw = hex::encode(tx.serialization());
+        let send = format!(r#"{{"jsonrpc":"2.0","method":"sendrawtransaction","params":["{}"],"id":"a"}}"#, raw);
+        assert_eq!(rpc(&mut chain, &send)["result"], hex::encode(tx.id()));
+        let again = rpc(&mut chain, &send);
+        assert_eq!((again["error"]["code"].as_i64(), again["id"].as_str()), (Some(SERVER_ERROR), Some("a")));
+        let pool = rpc(&mut chain, r#"{"jsonrpc":"2.0","method":"getmempool","id":2}"#);
+        assert_eq!(pool["result"], serde_json::json!([hex::encode(tx.id())]));
+
+        chain.mining().unwrap();
+        let hash = chain.last_block().unwrap().hash();
+        let by_name = format!(r#"{{"jsonrpc":"2.0","method":"getblock","params":{{"blockhash":"{}"}},"id":3}}"#, hash);
+        let block = rpc(&mut chain, &by_name);
+        assert_eq!(block["result"]["height"], height + 1);
+        assert_eq!(block["result"]["transactions"][1]["txid"], hex::encode(tx.id()));
+        let balance = rpc(&mut chain, r#"{"jsonrpc":"2.0","method":"getbalance","params":["B"],"id":4}"#);
+        assert_eq!(balance["result"], 1);
+    }
+
+    #[test]
+    fn bad_requests_get_json_rpc_errors() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        assert_eq!(rpc(&mut chain, "{not json")["error"]["code"], PARSE_ERROR);
+        assert_eq!(rpc(&mut chain, r#"{"method":"getblockcount","id":1}"#)["error"]["code"], INVALID_REQUEST);
+        assert_eq!(rpc(&mut chain, r#"{"jsonrpc":"2.0","method":"stop","id":1}"#)["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(rpc(&mut chain, r#"{"jsonrpc":"2.0","method":"getblock","id":1}"#)["error"]["code"], INVALID_PARAMS);
+        let missing = rpc(&mut chain, r#"{"jsonrpc":"2.0","method":"getblock","params":[99],"id":1}"#);
+        assert_eq!(missing["error"]["data"], serde_json::json!({"code": "unknown-block", "number": 3000}));
+
+        // a batch, the notification in it isn't answered
+        let batch = r#"[{"jsonrpc":"2.0","method":"getblockcount","id":1},{"jsonrpc":"2.0","method":"getmempool"}]"#;
+        assert_eq!(rpc(&mut chain, batch).as_array().map(Vec::len), Some(1));
+        assert_eq!(handle(&mut chain, br#"{"jsonrpc":"2.0","method":"getblockcount"}"#), None);
+    }
+}
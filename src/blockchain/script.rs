@@ -0,0 +1,334 @@
+use crate::blockchain::gas::{self, GasMeter};
+use crate::blockchain::precompile::{self, PrecompileError};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// opcode values follow bitcoin's numbering so scripts look familiar
+// when dumped as hex, bytes 0x01..=0x4b push that many bytes of data
+const OP_PUSH_MAX: u8 = 0x4b;
+const OP_DUP: u8 = 0x76;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_HASH: u8 = 0xa8;
+const OP_CHECKSIG: u8 = 0xac;
+// one of bitcoin's unused nop slots
+const OP_PRECOMPILE: u8 = 0xb0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpCode {
+    Push(Vec<u8>),
+    Dup,
+    Hash,
+    EqualVerify,
+    CheckSig,
+    // pops a one byte precompile id and then its input, pushes the output
+    Precompile,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    UnknownOpCode(u8),
+    TruncatedPush,
+    PushTooLarge(usize),
+    StackUnderflow,
+    EqualVerifyFailed,
+    EvaluatedToFalse,
+    MissingUnlockingScript,
+    OutOfGas,
+    Precompile(PrecompileError),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::UnknownOpCode(op) => write!(f, "unknown opcode 0x{:02x}", op),
+            ScriptError::TruncatedPush => write!(f, "push runs past the end of the script"),
+            ScriptError::PushTooLarge(len) => {
+                write!(f, "cannot push {} bytes, the limit is {}", len, OP_PUSH_MAX)
+            }
+            ScriptError::StackUnderflow => write!(f, "not enough items on the stack"),
+            ScriptError::EqualVerifyFailed => write!(f, "equalverify failed"),
+            ScriptError::EvaluatedToFalse => write!(f, "script evaluated to false"),
+            ScriptError::MissingUnlockingScript => {
+                write!(f, "sender funds are locked but no unlocking script was given")
+            }
+            ScriptError::OutOfGas => write!(f, "ran out of gas while evaluating the script"),
+            ScriptError::Precompile(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Script {
+    pub ops: Vec<OpCode>,
+}
+
+impl Script {
+    pub fn new(ops: Vec<OpCode>) -> Self {
+        Script { ops }
+    }
+
+    // pay to public key hash: the spender shows a public key hashing to
+    // `pubkey_hash` and a signature made with it
+    pub fn p2pkh(pubkey_hash: &[u8]) -> Self {
+        Script::new(vec![
+            OpCode::Dup,
+            OpCode::Hash,
+            OpCode::Push(pubkey_hash.to_vec()),
+            OpCode::EqualVerify,
+            OpCode::CheckSig,
+        ])
+    }
+
+    // unlocking counterpart of p2pkh
+    pub fn p2pkh_unlock(signature: &[u8], public_key: &[u8]) -> Self {
+        Script::new(vec![
+            OpCode::Push(signature.to_vec()),
+            OpCode::Push(public_key.to_vec()),
+        ])
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ScriptError> {
+        let mut bin = Vec::<u8>::new();
+        for op in self.ops.iter() {
+            match op {
+                OpCode::Push(data) => {
+                    if data.is_empty() || data.len() > OP_PUSH_MAX as usize {
+                        return Err(ScriptError::PushTooLarge(data.len()));
+                    }
+                    bin.push(data.len() as u8);
+                    bin.extend(data);
+                }
+                OpCode::Dup => bin.push(OP_DUP),
+                OpCode::Hash => bin.push(OP_HASH),
+                OpCode::EqualVerify => bin.push(OP_EQUALVERIFY),
+                OpCode::CheckSig => bin.push(OP_CHECKSIG),
+                OpCode::Precompile => bin.push(OP_PRECOMPILE),
+            }
+        }
+        Ok(bin)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ScriptError> {
+        let mut ops = Vec::<OpCode>::new();
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            let op = bytes[pos];
+            pos += 1;
+
+            match op {
+                1..=OP_PUSH_MAX => {
+                    let len = op as usize;
+                    if pos + len > bytes.len() {
+                        return Err(ScriptError::TruncatedPush);
+                    }
+                    ops.push(OpCode::Push(bytes[pos..pos + len].to_vec()));
+                    pos += len;
+                }
+                OP_DUP => ops.push(OpCode::Dup),
+                OP_HASH => ops.push(OpCode::Hash),
+                OP_EQUALVERIFY => ops.push(OpCode::EqualVerify),
+                OP_CHECKSIG => ops.push(OpCode::CheckSig),
+                OP_PRECOMPILE => ops.push(OpCode::Precompile),
+                _ => return Err(ScriptError::UnknownOpCode(op)),
+            }
+        }
+
+        Ok(Script { ops })
+    }
+}
+
+pub struct ScriptInterpreter<'a> {
+    stack: Vec<Vec<u8>>,
+    // the bytes a checksig signature has to commit to
+    message: &'a [u8],
+    meter: &'a mut GasMeter,
+}
+
+impl<'a> ScriptInterpreter<'a> {
+    pub fn new(message: &'a [u8], meter: &'a mut GasMeter) -> Self {
+        ScriptInterpreter {
+            stack: Vec::<Vec<u8>>::new(),
+            message,
+            meter,
+        }
+    }
+
+    // the unlocking script runs first and leaves its data on the stack,
+    // then the locking script consumes it
+    pub fn verify(
+        unlocking: &Script,
+        locking: &Script,
+        message: &'a [u8],
+        meter: &'a mut GasMeter,
+    ) -> Result<(), ScriptError> {
+        let mut interpreter = ScriptInterpreter::new(message, meter);
+        interpreter.run(unlocking)?;
+        interpreter.run(locking)?;
+
+        match interpreter.stack.last() {
+            Some(top) if is_true(top) => Ok(()),
+            _ => Err(ScriptError::EvaluatedToFalse),
+        }
+    }
+
+    pub fn run(&mut self, script: &Script) -> Result<(), ScriptError> {
+        for op in script.ops.iter() {
+            let cost = match op {
+                OpCode::Hash => gas::SCRIPT_HASH_GAS,
+                OpCode::CheckSig => gas::SCRIPT_CHECKSIG_GAS,
+                _ => gas::SCRIPT_OP_GAS,
+            };
+            self.meter.charge(cost).map_err(|_| ScriptError::OutOfGas)?;
+
+            match op {
+                OpCode::Push(data) => {
+                    self.stack.push(data.clone());
+                }
+                OpCode::Dup => {
+                    let top = self.stack.last().ok_or(ScriptError::StackUnderflow)?;
+                    self.stack.push(top.clone());
+                }
+                OpCode::Hash => {
+                    let top = self.pop()?;
+                    self.stack.push(Sha256::digest(&top).to_vec());
+                }
+                OpCode::EqualVerify => {
+                    let a = self.pop()?;
+                    let b = self.pop()?;
+                    if a != b {
+                        return Err(ScriptError::EqualVerifyFailed);
+                    }
+                }
+                OpCode::CheckSig => {
+                    let public_key = self.pop()?;
+                    let signature = self.pop()?;
+                    let valid = check_signature(&public_key, &signature, self.message);
+                    self.stack.push(if valid { vec![1] } else { vec![] });
+                }
+                // the flat opcode cost above covers the dispatch, the
+                // precompile's own price depends on its input
+                OpCode::Precompile => {
+                    let id = self.pop()?;
+                    let input = self.pop()?;
+                    let [id] = id[..] else {
+                        return Err(ScriptError::Precompile(PrecompileError::InvalidInput(
+                            "precompile id must be a single byte",
+                        )));
+                    };
+
+                    let precompile = precompile::precompile(id).map_err(ScriptError::Precompile)?;
+                    self.meter
+                        .charge(precompile.gas(input.len()))
+                        .map_err(|_| ScriptError::OutOfGas)?;
+                    let output = precompile.run(&input).map_err(ScriptError::Precompile)?;
+                    self.stack.push(output);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+}
+
+// like bitcoin, an empty item or an item made only of zeros is false
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|b| *b != 0)
+}
+
+// malformed keys or signatures are not an error, they just fail the check
+pub(crate) fn check_signature(public_key: &[u8], signature: &[u8], message: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(signature) = <[u8; 64]>::try_from(signature) else {
+        return false;
+    };
+    let Ok(verifying_key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+
+    verifying_key
+        .verify(message, &Signature::from_bytes(&signature))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    // what a p2pkh spend costs: the two unlocking pushes, then dup, hash,
+    // push, equalverify and checksig
+    const P2PKH_GAS: u64 = 5 * gas::SCRIPT_OP_GAS + gas::SCRIPT_HASH_GAS + gas::SCRIPT_CHECKSIG_GAS;
+
+    fn p2pkh_spend(signer: &str, owner: &str, message: &[u8]) -> (Script, Script) {
+        let signer = test_wallet(signer);
+        let unlocking = Script::p2pkh_unlock(&signer.sign(message), &signer.public_key());
+        let locking = Script::from_bytes(&test_wallet(owner).locking_script()).unwrap();
+        (unlocking, locking)
+    }
+
+    #[test]
+    fn only_the_owners_signature_unlocks() {
+        let mut meter = GasMeter::new(gas::MAX_TX_GAS);
+        let (unlocking, locking) = p2pkh_spend("A", "A", b"spend");
+        assert_eq!(ScriptInterpreter::verify(&unlocking, &locking, b"spend", &mut meter), Ok(()));
+        assert_eq!(meter.used(), P2PKH_GAS);
+
+        // signed for something else
+        let (unlocking, locking) = p2pkh_spend("A", "A", b"another spend");
+        assert_eq!(
+            ScriptInterpreter::verify(&unlocking, &locking, b"spend", &mut meter),
+            Err(ScriptError::EvaluatedToFalse)
+        );
+        // somebody else's key doesn't hash to the owner's
+        let (unlocking, locking) = p2pkh_spend("B", "A", b"spend");
+        assert_eq!(
+            ScriptInterpreter::verify(&unlocking, &locking, b"spend", &mut meter),
+            Err(ScriptError::EqualVerifyFailed)
+        );
+    }
+
+    #[test]
+    fn missing_items_underflow_the_stack() {
+        let mut meter = GasMeter::new(gas::MAX_TX_GAS);
+        let (_, locking) = p2pkh_spend("A", "A", b"spend");
+        assert_eq!(
+            ScriptInterpreter::verify(&Script::default(), &locking, b"spend", &mut meter),
+            Err(ScriptError::StackUnderflow)
+        );
+        // each of these takes two items
+        for op in [OpCode::EqualVerify, OpCode::CheckSig, OpCode::Precompile] {
+            let mut interpreter = ScriptInterpreter::new(b"spend", &mut meter);
+            let script = Script::new(vec![OpCode::Push(vec![1]), op]);
+            assert_eq!(interpreter.run(&script), Err(ScriptError::StackUnderflow));
+        }
+        // an empty stack at the end is false, not an underflow
+        assert_eq!(
+            ScriptInterpreter::verify(&Script::default(), &Script::default(), b"spend", &mut meter),
+            Err(ScriptError::EvaluatedToFalse)
+        );
+    }
+
+    #[test]
+    fn scripts_stay_within_their_limits() {
+        let too_long = Script::new(vec![OpCode::Push(vec![1; OP_PUSH_MAX as usize + 1])]);
+        assert_eq!(too_long.to_bytes(), Err(ScriptError::PushTooLarge(OP_PUSH_MAX as usize + 1)));
+        assert_eq!(Script::new(vec![OpCode::Push(Vec::new())]).to_bytes(), Err(ScriptError::PushTooLarge(0)));
+        assert_eq!(Script::from_bytes(&[5, 1, 2]), Err(ScriptError::TruncatedPush));
+        assert_eq!(Script::from_bytes(&[0xff]), Err(ScriptError::UnknownOpCode(0xff)));
+
+        // every op is paid for, the checksig at the end is one too many
+        let (unlocking, locking) = p2pkh_spend("A", "A", b"spend");
+        let mut meter = GasMeter::new(P2PKH_GAS - 1);
+        assert_eq!(
+            ScriptInterpreter::verify(&unlocking, &locking, b"spend", &mut meter),
+            Err(ScriptError::OutOfGas)
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use crate::blockchain::error_code::{ErrorCode, HasErrorCode};
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::json::{block_json, json_string, percent_decode};
+use crate::blockchain::sync::SyncStatus;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::wallets::{WalletCall, WalletError, WalletReply};
+use crate::blockchain::{BlockChain, Serialization};
+use std::io::Read;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+// most of a request body that is read, a transaction is far smaller
+const MAX_BODY: u64 = 1 << 20;
+
+// always json: what was asked for, or {"error", "code", "number"}
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl ApiResponse {
+    fn ok(body: String) -> Self {
+        ApiResponse { status: 200, body }
+    }
+
+    fn error(status: u16, code: ErrorCode, message: &str) -> Self {
+        ApiResponse {
+            status,
+            body: format!(
+                "{{\"error\":{},\"code\":{},\"number\":{}}}",
+                json_string(message),
+                json_string(code.as_str()),
+                code.number()
+            ),
+        }
+    }
+
+    // a path the api doesn't have, there's no error code for that
+    fn not_found() -> Self {
+        ApiResponse {
+            status: 404,
+            body: "{\"error\":\"not found\"}".to_string(),
+        }
+    }
+}
+
+// answers one request, everything the node's api does:
+//
+//     GET  /chain                  height and every block, oldest first
+//     GET  /blocks/{index}         one block and its transactions, by
+//                                  height or by hash
+//     POST /transactions           a signed transaction, its bytes in hex
+//                                  as the body. answers with the txid
+//     POST /mine                   mines the pool into a block, answers
+//                                  with the block
+//     GET  /balance/{address}      balance at the tip, and how much of it
+//                                  can be spent
+//     GET  /sync                   how far behind the peers the node is,
+//                                  and what each of them has sent
+//     GET  /health                 tip, sync, peers, pool, storage and
+//                                  the last error. 503 while the node is
+//                                  behind or can't save, for load
+//                                  balancers and probes
+//     POST /peers/{peer}           addnode: keep connected to the peer
+//     DELETE /peers/{peer}         disconnectnode: drop it, and forget it
+//                                  was added
+//     GET  /bans                   listbanned: running bans and when each
+//                                  one ends
+//     POST /bans/{peer}            setban: ban the peer, for the seconds
+//                                  in the body or a day
+//     DELETE /bans/{peer}          lift the ban
+//     GET  /wallets/{name}/{call}  address, balance or history of one of
+//                                  the node's wallets
+//     POST /wallets/{name}/{call}  the other wallet calls, the parameters
+//                                  one per line in the body: send,
+//                                  walletlock, walletunlock (passphrase
+//                                  and seconds), signmessage and
+//                                  signtransaction (in hex)
+//
+// transactions come signed, the node never sees the sender's key. the
+// node's own wallets sign here, no call answers with a key
+pub fn handle(chain: &mut BlockChain, method: &str, path: &str, body: &[u8]) -> ApiResponse {
+    let path = path.split(['?', '#']).next().unwrap_or("");
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", ["chain"]) => {
+            let blocks: Vec<String> = chain
+                .blocks()
+                .iter()
+                .enumerate()
+                .map(|(height, block)| block_json(height, block))
+                .collect();
+            ApiResponse::ok(format!(
+                "{{\"height\":{},\"blocks\":[{}]}}",
+                chain.blocks().len() - 1,
+                blocks.join(",")
+            ))
+        }
+        ("GET", ["blocks", index]) => match index.parse::<usize>() {
+            Ok(index) if index < chain.blocks().len() => {
+                ApiResponse::ok(block_json(index, &chain.blocks()[index]))
+            }
+            Ok(_) => ApiResponse::error(404, ErrorCode::UnknownBlock, "no block at that index"),
+            Err(_) => block_by_hash(chain, index),
+        },
+        ("POST", ["transactions"]) => {
+            let tx = std::str::from_utf8(body)
+                .ok()
+                .and_then(|text| hex::decode(text.trim()).ok())
+                .and_then(|bytes| Transaction::decode(&bytes));
+            let Some(tx) = tx else {
+                return ApiResponse::error(400, ErrorCode::MalformedTransaction, "the body isn't a transaction in hex");
+            };
+            match chain.add_transaction(&tx) {
+                Ok(()) => ApiResponse::ok(format!("{{\"txid\":{}}}", json_string(&hex::encode(tx.id())))),
+                Err(e) => ApiResponse::error(400, e.code(), &e.to_string()),
+            }
+        }
+        ("POST", ["mine"]) => match chain.mining() {
+            Ok(()) => {
+                let height = chain.blocks().len() - 1;
+                ApiResponse::ok(block_json(height, &chain.blocks()[height]))
+            }
+            Err(e) => ApiResponse::error(500, e.code(), &e.to_string()),
+        },
+        ("GET", ["balance", address]) => {
+            let address: Vec<u8> = percent_decode(address);
+            let height = chain.blocks().len() as u64;
+            ApiResponse::ok(format!(
+                "{{\"address\":{},\"balance\":{},\"spendable\":{}}}",
+                json_string(&String::from_utf8_lossy(&address)),
+                chain.state().balance(&address),
+                chain.state().spendable_balance(&address, height)
+            ))
+        }
+        ("GET", ["sync"]) => ApiResponse::ok(sync_json(&chain.sync_status())),
+        ("GET", ["health"]) => {
+            let status = chain.status();
+            ApiResponse {
+                status: if status.is_healthy() { 200 } else { 503 },
+                body: status.to_json(),
+            }
+        }
+        ("POST", ["peers", peer]) => {
+            let peer = String::from_utf8_lossy(&percent_decode(peer)).into_owned();
+            match chain.add_node(&peer) {
+                Ok(()) => ApiResponse::ok(format!("{{\"added\":{}}}", json_string(&peer))),
+                Err(e) => ApiResponse::error(403, e.code(), &e.to_string()),
+            }
+        }
+        ("DELETE", ["peers", peer]) => {
+            let peer = String::from_utf8_lossy(&percent_decode(peer)).into_owned();
+            match chain.disconnect_node(&peer) {
+                Ok(_) => ApiResponse::ok(format!("{{\"disconnected\":{}}}", json_string(&peer))),
+                Err(e) => ApiResponse::error(404, e.code(), &e.to_string()),
+            }
+        }
+        ("GET", ["bans"]) => {
+            let bans: Vec<String> = chain
+                .list_banned()
+                .iter()
+                .map(|(peer, until)| format!("{{\"peer\":{},\"until\":{}}}", json_string(peer), until))
+                .collect();
+            ApiResponse::ok(format!("[{}]", bans.join(",")))
+        }
+        ("POST", ["bans", peer]) => {
+            let peer = String::from_utf8_lossy(&percent_decode(peer)).into_owned();
+            let text = String::from_utf8_lossy(body);
+            let duration: Option<u128> = match text.trim() {
+                "" => None,
+                seconds => match seconds.parse::<u128>() {
+                    Ok(seconds) => Some(seconds.saturating_mul(1_000_000_000)),
+                    Err(_) => return ApiResponse::error(400, ErrorCode::PolicyRejected, "the body is the ban in seconds"),
+                },
+            };
+            chain.set_ban(&peer, duration);
+            ApiResponse::ok(format!("{{\"banned\":{}}}", json_string(&peer)))
+        }
+        ("DELETE", ["bans", peer]) => {
+            let peer = String::from_utf8_lossy(&percent_decode(peer)).into_owned();
+            match chain.remove_ban(&peer) {
+                Ok(()) => ApiResponse::ok(format!("{{\"unbanned\":{}}}", json_string(&peer))),
+                Err(e) => ApiResponse::error(404, e.code(), &e.to_string()),
+            }
+        }
+        ("GET", ["wallets", name, call]) => wallet_call(chain, name, call, &[]),
+        ("POST", ["wallets", name, call]) => {
+            let text = String::from_utf8_lossy(body);
+            let params: Vec<&str> = text.lines().filter(|line| !line.is_empty()).collect();
+            wallet_call(chain, name, call, &params)
+        }
+        _ => ApiResponse::not_found(),
+    }
+}
+
+fn wallet_call(chain: &mut BlockChain, name: &str, call: &str, params: &[&str]) -> ApiResponse {
+    let name = String::from_utf8_lossy(&percent_decode(name)).into_owned();
+    let Some(call) = WalletCall::parse(call, params) else {
+        return ApiResponse::not_found();
+    };
+    match chain.call_wallet(&name, call) {
+        Ok(reply) => ApiResponse::ok(wallet_reply_json(&reply)),
+        Err(e) => {
+            let status = match e {
+                WalletError::UnknownWallet(_) => 404,
+                WalletError::Locked(_) | WalletError::WrongPassphrase | WalletError::NotSender(_) => 403,
+                WalletError::AlreadyLoaded(_) | WalletError::Rejected(_) => 400,
+            };
+            ApiResponse::error(status, e.code(), &e.to_string())
+        }
+    }
+}
+
+fn wallet_reply_json(reply: &WalletReply) -> String {
+    match reply {
+        WalletReply::Address(address) => format!("{{\"address\":{}}}", json_string(&address.to_string())),
+        WalletReply::Balance(balance) => format!("{{\"balance\":{}}}", balance),
+        WalletReply::History(history) => {
+            let locations: Vec<String> = history
+                .iter()
+                .map(|location| format!("{{\"height\":{},\"index\":{}}}", location.height, location.index))
+                .collect();
+            format!("[{}]", locations.join(","))
+        }
+        WalletReply::Sent { txid } => format!("{{\"txid\":{}}}", json_string(&hex::encode(txid))),
+        WalletReply::Locked => "{\"locked\":true}".to_string(),
+        WalletReply::Unlocked { until } => format!("{{\"locked\":false,\"until\":{}}}", until),
+        WalletReply::Signature(signature) => format!("{{\"signature\":{}}}", json_string(&hex::encode(signature))),
+        WalletReply::Signed(tx) => format!(
+            "{{\"txid\":{},\"transaction\":{}}}",
+            json_string(&hex::encode(tx.id())),
+            json_string(&hex::encode(tx.serialization()))
+        ),
+    }
+}
+
+fn block_by_hash(chain: &BlockChain, hash: &str) -> ApiResponse {
+    let Ok(hash) = hash.parse::<Hash32>() else {
+        return ApiResponse::error(404, ErrorCode::UnknownBlock, "not a height or a block hash");
+    };
+    match chain.blocks().iter().position(|block| block.hash() == hash) {
+        Some(height) => ApiResponse::ok(block_json(height, &chain.blocks()[height])),
+        None => ApiResponse::error(404, ErrorCode::UnknownBlock, "no block with that hash"),
+    }
+}
+
+fn sync_json(status: &SyncStatus) -> String {
+    let peers: Vec<String> = status
+        .peers
+        .iter()
+        .map(|(peer, sync)| {
+            format!(
+                "{{\"peer\":{},\"height\":{},\"headers_received\":{},\"blocks_requested\":{},\"blocks_received\":{},\"in_flight\":{},\"stalled\":{}}}",
+                json_string(peer),
+                sync.height,
+                sync.headers_received,
+                sync.blocks_requested,
+                sync.blocks_received,
+                sync.in_flight.len(),
+                sync.stalled
+            )
+        })
+        .collect();
+    format!(
+        "{{\"height\":{},\"best_peer_height\":{},\"synced\":{},\"peers\":[{}]}}",
+        status.height,
+        status.best_peer_height,
+        status.is_synced(),
+        peers.join(",")
+    )
+}
+
+// serves the api until the process is stopped, one request at a time. the
+// chain is shared with whatever else runs on the node and only locked
+// while a request is answered
+pub fn serve(chain: &Mutex<BlockChain>, address: &str) -> std::io::Result<()> {
+    let server = tiny_http::Server::http(address).map_err(std::io::Error::other)?;
+    info!(address, "api listening");
+
+    for mut request in server.incoming_requests() {
+        let mut body = Vec::<u8>::new();
+        let response = match request.as_reader().take(MAX_BODY).read_to_end(&mut body) {
+            Ok(_) => {
+                let method = request.method().to_string();
+                handle(&mut chain.lock().unwrap(), &method, request.url(), &body)
+            }
+            Err(e) => ApiResponse::error(400, ErrorCode::MalformedTransaction, &e.to_string()),
+        };
+        let header = tiny_http::Header::from_bytes("Content-Type", "application/json")
+            .expect("content types are valid header values");
+        let reply = tiny_http::Response::from_string(response.body)
+            .with_status_code(response.status)
+            .with_header(header);
+        if let Err(e) = request.respond(reply) {
+            warn!(error = %e, "api response not sent");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::Serialization;
+
+    #[test]
+    fn a_client_sends_a_transaction_mines_it_and_sees_the_balance() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        let body = hex::encode(tx.serialization());
+        let sent = handle(&mut chain, "POST", "/transactions", body.as_bytes());
+        assert_eq!(sent, ApiResponse::ok(format!("{{\"txid\":\"{}\"}}", hex::encode(tx.id()))));
+        // the same one again
+        assert_eq!(handle(&mut chain, "POST", "/transactions", body.as_bytes()).status, 400);
+
+        let mined = handle(&mut chain, "POST", "/mine", b"");
+        assert_eq!(mined.status, 200);
+        assert!(mined.body.contains(&hex::encode(tx.id())));
+        let height = chain.blocks().len() - 1;
+        assert_eq!(handle(&mut chain, "GET", &format!("/blocks/{}", height), b"").body, mined.body);
+        let hash = chain.blocks()[height].hash();
+        assert_eq!(handle(&mut chain, "GET", &format!("/blocks/{}", hash), b"").body, mined.body);
+        assert!(handle(&mut chain, "GET", "/chain", b"").body.starts_with(&format!("{{\"height\":{},", height)));
+        assert_eq!(
+            handle(&mut chain, "GET", "/balance/B", b"").body,
+            "{\"address\":\"B\",\"balance\":1,\"spendable\":1}"
+        );
+
+        let missing = handle(&mut chain, "GET", "/blocks/100", b"");
+        assert_eq!((missing.status, missing.body.contains("\"code\":\"unknown-block\"")), (404, true));
+        assert_eq!(handle(&mut chain, "POST", "/transactions", b"not hex").status, 400);
+        assert_eq!(handle(&mut chain, "DELETE", "/chain", b""), ApiResponse::not_found());
+        assert_eq!(
+            handle(&mut chain, "GET", "/sync", b"").body,
+            format!("{{\"height\":{},\"best_peer_height\":0,\"synced\":true,\"peers\":[]}}", height)
+        );
+        let health = handle(&mut chain, "GET", "/health", b"");
+        assert_eq!(health.status, 200);
+        assert!(health.body.contains("\"storage\":{\"status\":\"memory\"},\"last_error\":null"));
+        chain.sync.add_peer("ahead", height as u64 + 1, 0);
+        assert_eq!(handle(&mut chain, "GET", "/health", b"").status, 503);
+    }
+
+    #[test]
+    fn an_encrypted_wallet_signs_over_the_api_only_while_unlocked() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.load_wallet("cold", wallet.clone()).unwrap();
+        chain.encrypt_wallet("cold", "correct horse").unwrap();
+        let secret: String = hex::encode(wallet.secret());
+
+        let address = handle(&mut chain, "GET", "/wallets/cold/address", b"");
+        assert_eq!(address.body, format!("{{\"address\":\"{}\"}}", wallet.address()));
+        let locked = handle(&mut chain, "POST", "/wallets/cold/signmessage", b"hello");
+        assert_eq!((locked.status, locked.body.contains("\"code\":\"wallet-locked\"")), (403, true));
+        let wrong = handle(&mut chain, "POST", "/wallets/cold/walletunlock", b"battery staple\n60");
+        assert_eq!((wrong.status, wrong.body.contains("\"code\":\"wrong-passphrase\"")), (403, true));
+
+        let unlocked = handle(&mut chain, "POST", "/wallets/cold/walletunlock", b"correct horse\n60");
+        assert!(unlocked.body.starts_with("{\"locked\":false,\"until\":"));
+        let signed = handle(&mut chain, "POST", "/wallets/cold/signmessage", b"hello");
+        assert_eq!(signed.body, format!("{{\"signature\":\"{}\"}}", hex::encode(wallet.sign(b"hello"))));
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1);
+        let body = hex::encode(tx.serialization());
+        let signed = handle(&mut chain, "POST", "/wallets/cold/signtransaction", body.as_bytes());
+        assert!(signed.body.contains(&hex::encode(tx.sign(&wallet).serialization())));
+        assert!(!signed.body.contains(&secret) && !unlocked.body.contains(&secret));
+
+        assert_eq!(handle(&mut chain, "POST", "/wallets/cold/walletlock", b"").body, "{\"locked\":true}");
+        assert_eq!(handle(&mut chain, "POST", "/wallets/cold/signmessage", b"hello").status, 403);
+        assert_eq!(handle(&mut chain, "GET", "/wallets/hot/address", b"").status, 404);
+    }
+
+    #[test]
+    fn an_operator_adds_and_bans_peers() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        assert_eq!(handle(&mut chain, "POST", "/peers/10.0.0.1:8333", b"").status, 200);
+        assert_eq!(handle(&mut chain, "DELETE", "/peers/10.0.0.1:8333", b"").status, 200);
+        let unknown = handle(&mut chain, "DELETE", "/peers/10.0.0.1:8333", b"");
+        assert_eq!((unknown.status, unknown.body.contains("\"code\":\"unknown-peer\"")), (404, true));
+
+        assert_eq!(handle(&mut chain, "POST", "/bans/10.0.0.2:8333", b"60").status, 200);
+        assert_eq!(handle(&mut chain, "POST", "/bans/10.0.0.3:8333", b"an hour").status, 400);
+        let refused = handle(&mut chain, "POST", "/peers/10.0.0.2:8333", b"");
+        assert_eq!((refused.status, refused.body.contains("\"code\":\"peer-banned\"")), (403, true));
+        let bans = handle(&mut chain, "GET", "/bans", b"").body;
+        assert!(bans.starts_with("[{\"peer\":\"10.0.0.2:8333\",\"until\":"));
+        assert_eq!(handle(&mut chain, "DELETE", "/bans/10.0.0.2:8333", b"").status, 200);
+        assert_eq!(handle(&mut chain, "GET", "/bans", b"").body, "[]");
+    }
+}
@@ -0,0 +1,296 @@
+use crate::blockchain::bloom::Bloom;
+use crate::blockchain::chaos::SplitMix64;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::light::{LightClient, LightClientError};
+use crate::blockchain::{BlockChain, BlockHeader, BLOCK_VERSION};
+use std::collections::{BTreeMap, HashMap};
+
+// a miner taking part in a scenario. `rate` is its chance, in parts per
+// thousand, of finding a block in any one tick: its share of the hashrate
+#[derive(Debug, Clone)]
+pub struct SimulatedMiner {
+    pub name: String,
+    pub rate: u32,
+}
+
+// a tip switch that dropped blocks the miner had followed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedReorg {
+    pub tick: u64,
+    pub miner: String,
+    // how many blocks were taken off the miner's chain
+    pub depth: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationOutcome {
+    pub blocks_found: u64,
+    // found but not on the chain the miners ended up following
+    pub stale_blocks: u64,
+    pub reorgs: Vec<SimulatedReorg>,
+    // height of the best chain at the end
+    pub height: u64,
+    // every miner follows the same tip at the end. a tie between two
+    // chains of the same length can leave them split
+    pub converged: bool,
+    // blocks each miner has on the best chain
+    pub blocks_on_chain: BTreeMap<String, u64>,
+}
+
+// miners finding blocks at their own rates and hearing about each other's
+// blocks `latency` ticks later. every miner follows the longest chain it
+// knows, through the same fork choice a light client uses, so forks happen
+// when blocks are found faster than they spread. the seed drives
+// everything, a scenario always plays out the same way.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    seed: u64,
+    miners: Vec<SimulatedMiner>,
+    latency: u64,
+    ticks: u64,
+}
+
+// one miner's view of the network
+struct MinerState {
+    name: String,
+    rate: u32,
+    client: LightClient,
+}
+
+impl Scenario {
+    pub fn new(seed: u64) -> Self {
+        Scenario {
+            seed,
+            miners: Vec::<SimulatedMiner>::new(),
+            latency: 1,
+            ticks: 100,
+        }
+    }
+
+    pub fn with_miner(mut self, name: &str, rate: u32) -> Self {
+        self.miners.push(SimulatedMiner {
+            name: name.to_string(),
+            rate: rate.min(1000),
+        });
+        self
+    }
+
+    // ticks between a miner finding a block and everybody else having it.
+    // even at 0 the others only build on it from the next tick, blocks
+    // found in the same tick always race
+    pub fn with_latency(mut self, latency: u64) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    pub fn with_ticks(mut self, ticks: u64) -> Self {
+        self.ticks = ticks;
+        self
+    }
+
+    pub fn run(&self) -> SimulationOutcome {
+        let mut rng = SplitMix64(self.seed);
+        let genesis = BlockHeader {
+            version: BLOCK_VERSION,
+            nonce: 0,
+            previous_hash: Hash32::ZERO,
+            time_stamp: 0,
+            difficulty: 0,
+            merkle_root: Hash32::ZERO,
+            state_root: Hash32::ZERO,
+            logs_bloom: Bloom::default(),
+            receipts_root: Hash32::ZERO,
+            mmr_root: Hash32::ZERO,
+        };
+
+        let mut miners: Vec<MinerState> = self
+            .miners
+            .iter()
+            .map(|miner| MinerState {
+                name: miner.name.clone(),
+                rate: miner.rate,
+                client: LightClient::new(genesis.clone()),
+            })
+            .collect();
+
+        // every block found, by hash: its header, height and who found it
+        let mut blocks: HashMap<Hash32, (BlockHeader, u64, usize)> = HashMap::new();
+        // blocks on their way, with the tick they arrive at and who sent them
+        let mut in_flight: Vec<(u64, usize, Hash32)> = Vec::new();
+        let mut reorgs = Vec::<SimulatedReorg>::new();
+
+        let mut tick: u64 = 0;
+        loop {
+            // deliveries first, a block found this tick builds on them
+            let (arrived, waiting): (Vec<_>, Vec<_>) =
+                in_flight.into_iter().partition(|(at, _, _)| *at <= tick);
+            in_flight = waiting;
+            for (_, sender, hash) in arrived {
+                for (index, miner) in miners.iter_mut().enumerate() {
+                    if index != sender
+                        && let Some(depth) = receive(&mut miner.client, &blocks, hash)
+                    {
+                        reorgs.push(SimulatedReorg {
+                            tick,
+                            miner: miner.name.clone(),
+                            depth,
+                        });
+                    }
+                }
+            }
+
+            if tick >= self.ticks {
+                if in_flight.is_empty() {
+                    break;
+                }
+                tick += 1;
+                continue;
+            }
+
+            for (index, miner) in miners.iter_mut().enumerate() {
+                if rng.below(1000) >= miner.rate as u64 {
+                    continue;
+                }
+                let header = mine(miner.client.tip(), &miner.name, tick);
+                let hash = header.hash();
+                let height = miner.client.height() + 1;
+                miner
+                    .client
+                    .add_headers(height, vec![header.clone()])
+                    .expect("a block on our own tip extends our chain");
+                blocks.insert(hash, (header, height, index));
+                in_flight.push((tick + self.latency, index, hash));
+            }
+            tick += 1;
+        }
+
+        let best = miners
+            .iter()
+            .max_by_key(|miner| miner.client.height())
+            .map(|miner| &miner.client);
+        let height = best.map_or(0, |client| client.height());
+        let converged = miners
+            .iter()
+            .all(|miner| best.is_some_and(|best| miner.client.tip() == best.tip()));
+
+        let mut blocks_on_chain: BTreeMap<String, u64> =
+            miners.iter().map(|miner| (miner.name.clone(), 0)).collect();
+        if let Some(best) = best {
+            for header in best.headers().iter().skip(1) {
+                let (_, _, index) = &blocks[&header.hash()];
+                *blocks_on_chain.get_mut(&miners[*index].name).unwrap() += 1;
+            }
+        }
+
+        SimulationOutcome {
+            blocks_found: blocks.len() as u64,
+            stale_blocks: blocks.len() as u64 - height,
+            reorgs,
+            height,
+            converged,
+            blocks_on_chain,
+        }
+    }
+}
+
+// a header on `parent` that meets the network difficulty. the miner's
+// name stands in for the transactions, so two miners never find the
+// same block
+fn mine(parent: &BlockHeader, miner: &str, tick: u64) -> BlockHeader {
+    let mut header = BlockHeader {
+        version: BLOCK_VERSION,
+        nonce: 0,
+        previous_hash: parent.hash(),
+        time_stamp: tick as u128,
+        difficulty: BlockChain::DIFFICULTY,
+        merkle_root: Hash32::digest(miner.as_bytes()),
+        state_root: Hash32::ZERO,
+        logs_bloom: Bloom::default(),
+        receipts_root: Hash32::ZERO,
+        mmr_root: Hash32::ZERO,
+    };
+    while !BlockChain::meets_difficulty(&header.hash()) {
+        header.nonce += 1;
+    }
+    header
+}
+
+// hands a block to a miner along with the branch it is on, like a node
+// fetching the headers it's missing. returns the depth of the reorg if
+// the miner had to drop blocks to follow it
+fn receive(
+    client: &mut LightClient,
+    blocks: &HashMap<Hash32, (BlockHeader, u64, usize)>,
+    hash: Hash32,
+) -> Option<u64> {
+    // walk back until a block the miner already has
+    let mut branch = Vec::<BlockHeader>::new();
+    let mut cursor: Hash32 = hash;
+    let fork_height = loop {
+        let (header, height, _) = &blocks[&cursor];
+        if client.header(*height).is_some_and(|known| known.hash() == cursor) {
+            break *height;
+        }
+        branch.push(header.clone());
+        cursor = header.previous_hash;
+        if *height == 1 {
+            break 0;
+        }
+    };
+    if branch.is_empty() {
+        return None;
+    }
+    branch.reverse();
+
+    let old_height = client.height();
+    match client.add_headers(fork_height + 1, branch) {
+        Ok(_) if old_height > fork_height => Some(old_height - fork_height),
+        Ok(_) | Err(LightClientError::NotBestChain) => None,
+        Err(e) => panic!("simulated blocks are always valid: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_lone_miner_never_forks() {
+        let outcome = Scenario::new(7).with_miner("alice", 300).with_ticks(40).run();
+        assert!(outcome.blocks_found > 0);
+        assert_eq!(outcome.height, outcome.blocks_found);
+        assert_eq!(outcome.stale_blocks, 0);
+        assert!(outcome.reorgs.is_empty());
+        assert!(outcome.converged);
+    }
+
+    #[test]
+    fn instant_propagation_with_rare_blocks_does_not_fork() {
+        let outcome = Scenario::new(1)
+            .with_miner("alice", 20)
+            .with_miner("bob", 20)
+            .with_latency(0)
+            .with_ticks(200)
+            .run();
+        assert_eq!(outcome.stale_blocks, 0);
+        assert!(outcome.reorgs.is_empty());
+    }
+
+    #[test]
+    fn slow_propagation_leaves_stale_blocks_and_reorgs() {
+        let scenario = Scenario::new(42)
+            .with_miner("alice", 300)
+            .with_miner("bob", 300)
+            .with_miner("carol", 100)
+            .with_latency(3)
+            .with_ticks(60);
+        let outcome = scenario.run();
+
+        assert!(outcome.stale_blocks > 0);
+        assert!(!outcome.reorgs.is_empty());
+        assert_eq!(outcome.blocks_found, outcome.height + outcome.stale_blocks);
+        assert_eq!(outcome.blocks_on_chain.values().sum::<u64>(), outcome.height);
+        // same seed, same story
+        assert_eq!(scenario.run(), outcome);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::blockchain::mempool::{fee_rate, MempoolError};
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::validator::{TxContext, TxValidator};
+use crate::blockchain::{BlockChain, Serialization};
+use sha2::{Digest, Sha256};
+
+// hashcash for transactions: instead of a fee, a sender can pay for
+// relay with a little proof of work. a spammer sending thousands of free
+// transactions has to do the work thousands of times, someone sending
+// one barely notices. the stamp only counts for getting into the pool,
+// blocks don't check it, so it's policy like the minimum fee rate
+
+impl Transaction {
+    // what the stamp is worked on: everything but the key, the signature
+    // and the unlocking script, so the stamp can be found before signing
+    // and covers the stamp itself
+    pub fn stamp_hash(&self) -> Vec<u8> {
+        let mut unsigned = self.clone();
+        unsigned.public_key.clear();
+        unsigned.signature.clear();
+        unsigned.unlocking_script.clear();
+
+        let mut hasher = Sha256::new();
+        hasher.update(unsigned.serialization());
+        hasher.finalize().to_vec()
+    }
+
+    // whether the stamp hash starts with `difficulty` zero hex digits, the
+    // same measure blocks use
+    pub fn has_stamp(&self, difficulty: usize) -> bool {
+        BlockChain::meets_target(&self.stamp_hash(), difficulty)
+    }
+
+    // tries stamps until one meets `difficulty`, about 16^difficulty
+    // hashes. goes before `sign`, the signature covers the stamp
+    pub fn with_stamp(mut self, difficulty: usize) -> Self {
+        self.stamp = 0;
+        while !self.has_stamp(difficulty) {
+            self.stamp += 1;
+        }
+        self
+    }
+}
+
+// a policy rule: transactions paying a fee rate below `min_fee_rate` (per
+// 1000 weight units, like `mempool::fee_rate`) get in only with a stamp of
+// `difficulty`. those paying enough don't need one, the miner's reward
+// never does
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StampRequired {
+    pub min_fee_rate: u64,
+    pub difficulty: usize,
+}
+
+impl TxValidator for StampRequired {
+    fn name(&self) -> &str {
+        "stamp"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        let rate = fee_rate(tx);
+        if context.coinbase || rate >= self.min_fee_rate || tx.has_stamp(self.difficulty) {
+            return Ok(());
+        }
+        Err(MempoolError::Rejected {
+            validator: self.name().to_string(),
+            reason: format!(
+                "fee rate {} is below {} and the stamp doesn't meet difficulty {}",
+                rate, self.min_fee_rate, self.difficulty
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+
+    #[test]
+    fn free_transactions_need_a_stamp() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 3 {
+            chain.mining().unwrap();
+        }
+        chain.add_tx_validator(StampRequired {
+            min_fee_rate: 1,
+            difficulty: 2,
+        });
+        let tx = |nonce: u64| Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).with_nonce(nonce);
+
+        assert!(matches!(
+            chain.add_transaction(&tx(0).sign(&wallet)),
+            Err(MempoolError::Rejected { validator, .. }) if validator == "stamp"
+        ));
+        let stamped: Transaction = tx(0).with_stamp(2).sign(&wallet);
+        assert!(stamped.has_stamp(2));
+        chain.add_transaction(&stamped).unwrap();
+        // paying the fee is the other way in
+        chain.add_transaction(&tx(1).with_fee(2).sign(&wallet)).unwrap();
+        chain.mining().unwrap();
+        assert!(chain.pending_transactions().is_empty());
+    }
+}
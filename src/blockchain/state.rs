@@ -0,0 +1,1159 @@
+use crate::blockchain::asset::{self, Asset};
+use crate::blockchain::balances::BalanceIndex;
+use crate::blockchain::gas::{self, GasMeter};
+use crate::blockchain::genesis::GENESIS_SENDER;
+use crate::blockchain::governance::{self, ChainParameters, Parameter, Proposal, ProposalStatus};
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::names::{self, NameRecord};
+use crate::blockchain::receipt::{Log, Receipt};
+use crate::blockchain::script::{Script, ScriptError, ScriptInterpreter};
+use crate::blockchain::transaction::{Payload, Transaction};
+use crate::blockchain::trie::{SparseMerkleTree, TrieProof};
+use crate::blockchain::utxo::{self, OutPoint, TxOutput, UtxoSet};
+use crate::blockchain::BlockChain;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(feature = "vm")]
+use crate::blockchain::vm;
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    UnsupportedPayload,
+    EmptyAssetSupply,
+    AssetAlreadyExists,
+    UnknownAsset,
+    InsufficientAssetBalance { available: u64, requested: u64 },
+    InvalidName,
+    NameTaken { expires_at: u64 },
+    NameNotFound,
+    NotNameOwner,
+    NameExpired,
+    ProposalExists,
+    // a proposal to set a parameter above its maximum
+    ParameterOutOfRange { parameter: Parameter, value: u64 },
+    UnknownProposal,
+    VotingClosed,
+    NoVotingPower,
+    GasLimitTooHigh { limit: u64 },
+    // the sender can't pay value, fee and gas out of what it has
+    InsufficientBalance { available: i64, requested: u64 },
+    // value, fee and gas add up to more than a balance can hold
+    AmountOverflow,
+    // the transaction would spend mining rewards that haven't matured
+    ImmatureCoinbase { spendable: i64, requested: u64 },
+    // a spend of an output that was spent already, or never created
+    MissingOutput,
+    NotOutputOwner,
+    // one spend naming the same output twice
+    DuplicateInput,
+    // the sender's transactions are applied in nonce order, each exactly
+    // once: `expected` is how many it sent before
+    WrongNonce { expected: u64, found: u64 },
+    Script(ScriptError),
+    #[cfg(feature = "vm")]
+    Vm(vm::VmError),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnsupportedPayload => {
+                write!(f, "contract transactions need the `vm` feature")
+            }
+            StateError::EmptyAssetSupply => write!(f, "an asset needs a supply greater than zero"),
+            StateError::AssetAlreadyExists => {
+                write!(f, "an asset was already created by this sender and nonce")
+            }
+            StateError::UnknownAsset => write!(f, "no asset with this id"),
+            StateError::InsufficientAssetBalance { available, requested } => {
+                write!(
+                    f,
+                    "asset balance too low, {} requested but only {} available",
+                    requested, available
+                )
+            }
+            StateError::InvalidName => write!(
+                f,
+                "names are 1 to {} lowercase letters, digits or dashes",
+                names::MAX_NAME_LENGTH
+            ),
+            StateError::NameTaken { expires_at } => {
+                write!(f, "name is registered until height {}", expires_at)
+            }
+            StateError::NameNotFound => write!(f, "name is not registered"),
+            StateError::NotNameOwner => write!(f, "only the owner can update a name"),
+            StateError::NameExpired => write!(f, "name expired, it has to be claimed again"),
+            StateError::ProposalExists => {
+                write!(f, "a proposal was already made by this sender and nonce")
+            }
+            StateError::ParameterOutOfRange { parameter, value } => {
+                write!(f, "{:?} can be at most {}, {} was proposed", parameter, parameter.max(), value)
+            }
+            StateError::UnknownProposal => write!(f, "no proposal with this id"),
+            StateError::VotingClosed => write!(f, "voting on this proposal is closed"),
+            StateError::NoVotingPower => {
+                write!(f, "the voter had no balance when the proposal was made")
+            }
+            StateError::GasLimitTooHigh { limit } => write!(
+                f,
+                "gas limit {} is above the maximum of {}",
+                limit,
+                gas::MAX_TX_GAS
+            ),
+            StateError::InsufficientBalance { available, requested } => {
+                write!(f, "balance too low, {} requested but only {} available", requested, available)
+            }
+            StateError::AmountOverflow => write!(f, "the amounts of the transaction overflow a balance"),
+            StateError::ImmatureCoinbase { spendable, requested } => write!(
+                f,
+                "{} requested but only {} is spendable, mining rewards need {} blocks to mature",
+                requested,
+                spendable,
+                BlockChain::COINBASE_MATURITY
+            ),
+            StateError::MissingOutput => write!(f, "the output is spent or was never created"),
+            StateError::NotOutputOwner => write!(f, "only the owner of an output can spend it"),
+            StateError::DuplicateInput => write!(f, "the transaction spends the same output twice"),
+            StateError::WrongNonce { expected, found } => {
+                write!(f, "nonce {} is out of order, the sender's next transaction has nonce {}", found, expected)
+            }
+            StateError::Script(e) => write!(f, "spending conditions not met: {}", e),
+            #[cfg(feature = "vm")]
+            StateError::Vm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Account {
+    // signed like calculate_total_amount: the coinbase and genesis
+    // senders go below zero by what they issued, any other account is
+    // refused a transaction it can't pay for
+    pub balance: i64,
+    // number of transactions sent from this account
+    pub nonce: u64,
+    // sha256 of the contract code, empty for plain accounts
+    pub code_hash: Vec<u8>,
+    pub storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    // units held of each user issued asset, by asset id
+    pub assets: BTreeMap<Vec<u8>, u64>,
+    // script guarding the funds, set by the last transaction this account
+    // sent with one. empty means unlocked
+    pub locking_script: Vec<u8>,
+    // mining rewards paid to this account as (height, amount), oldest
+    // first. matured ones are dropped whenever a new reward comes in
+    pub immature_rewards: Vec<(u64, u64)>,
+}
+
+impl Account {
+    pub fn is_contract(&self) -> bool {
+        !self.code_hash.is_empty()
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.balance.to_be_bytes());
+        hasher.update(self.nonce.to_be_bytes());
+        hasher.update(&self.code_hash);
+        for (key, value) in self.storage.iter() {
+            hasher.update((key.len() as u64).to_be_bytes());
+            hasher.update(key);
+            hasher.update((value.len() as u64).to_be_bytes());
+            hasher.update(value);
+        }
+        for (asset_id, amount) in self.assets.iter() {
+            hasher.update(asset_id);
+            hasher.update(amount.to_be_bytes());
+        }
+        hasher.update(&self.locking_script);
+        for (height, amount) in self.immature_rewards.iter() {
+            hasher.update(height.to_be_bytes());
+            hasher.update(amount.to_be_bytes());
+        }
+        hasher.finalize().to_vec()
+    }
+
+    // a reward paid by the block at `height`, spendable once it matured
+    fn add_reward(&mut self, height: u64, amount: u64) {
+        self.immature_rewards
+            .retain(|(mined, _)| mined + BlockChain::COINBASE_MATURITY > height);
+        self.immature_rewards.push((height, amount));
+    }
+
+    // rewards that can't be spent yet in a block at `height`
+    pub fn immature_balance(&self, height: u64) -> u64 {
+        self.immature_rewards
+            .iter()
+            .filter(|(mined, _)| mined + BlockChain::COINBASE_MATURITY > height)
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Supply {
+    pub issued: u64,
+    pub circulating: i64,
+    pub burned: i64,
+}
+
+// how many blocks back the state can be rolled back, deeper reorgs need
+// a replay from genesis
+pub const MAX_ROLLBACK_BLOCKS: usize = 100;
+
+// where a piece of state lives in the trie
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StateKey {
+    Account(Vec<u8>),
+    Code(Vec<u8>),
+    Asset(Vec<u8>),
+    Name(Vec<u8>),
+    Proposal(Vec<u8>),
+    Parameters,
+    Output(OutPoint),
+}
+
+impl StateKey {
+    // hashing spreads the keys over the whole tree, the tag keeps keys of
+    // different kinds from ever colliding
+    pub fn trie_key(&self) -> [u8; 32] {
+        let outpoint: Vec<u8>;
+        let (tag, id): (u8, &[u8]) = match self {
+            StateKey::Account(address) => (0, address),
+            StateKey::Code(code_hash) => (1, code_hash),
+            StateKey::Asset(asset_id) => (2, asset_id),
+            StateKey::Name(name) => (3, name),
+            StateKey::Proposal(proposal_id) => (4, proposal_id),
+            StateKey::Parameters => (5, &[]),
+            StateKey::Output(out) => {
+                outpoint = out.encode();
+                (6, &outpoint)
+            }
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update([tag]);
+        hasher.update(id);
+        hasher.finalize().into()
+    }
+}
+
+// the previous value of a key, kept to undo a block
+#[derive(Debug, Clone)]
+enum Entry {
+    Account(Account),
+    Code(Vec<u8>),
+    Asset(Asset),
+    Name(NameRecord),
+    Proposal(Proposal),
+    Parameters(ChainParameters),
+    Output(TxOutput),
+}
+
+// the world state after applying a sequence of blocks. every entry is a
+// leaf of a sparse merkle tree, its root is what blocks commit to.
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    accounts: BTreeMap<Vec<u8>, Account>,
+    // contract code by code hash
+    code: BTreeMap<Vec<u8>, Vec<u8>>,
+    // user issued assets by asset id
+    assets: BTreeMap<Vec<u8>, Asset>,
+    // registered names, expired records stay until someone claims them
+    names: BTreeMap<Vec<u8>, NameRecord>,
+    // governance proposals by id, decided ones are kept as a record
+    proposals: BTreeMap<Vec<u8>, Proposal>,
+    // current values of the parameters governance can change
+    parameters: ChainParameters,
+    // outputs of spends nobody has spent yet
+    utxos: UtxoSet,
+    trie: SparseMerkleTree,
+    // keys changed by the block being applied, with their value before it
+    touched: BTreeMap<StateKey, Option<Entry>>,
+    // same, but for the transaction being applied, used to revert it
+    tx_touched: BTreeMap<StateKey, Option<Entry>>,
+    // the touched sets of the last blocks, newest last
+    undo: Vec<BTreeMap<StateKey, Option<Entry>>>,
+    // the fees of the block being applied that weren't burned, the miner
+    // gets them when the block ends
+    tips: u64,
+    // balances over the last blocks of a voting period, what a vote
+    // weighs. one history for every proposal instead of a copy of the
+    // balances in each
+    history: BalanceIndex,
+}
+
+impl State {
+    pub fn new() -> Self {
+        State {
+            accounts: BTreeMap::new(),
+            code: BTreeMap::new(),
+            assets: BTreeMap::new(),
+            names: BTreeMap::new(),
+            proposals: BTreeMap::new(),
+            parameters: ChainParameters::default(),
+            utxos: UtxoSet::new(),
+            trie: SparseMerkleTree::new(),
+            touched: BTreeMap::new(),
+            tx_touched: BTreeMap::new(),
+            undo: Vec::new(),
+            tips: 0,
+            history: BalanceIndex::new(),
+        }
+    }
+
+    pub fn account(&self, address: &[u8]) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    pub fn balance(&self, address: &[u8]) -> i64 {
+        self.account(address).map(|a| a.balance).unwrap_or(0)
+    }
+
+    // the nonce the next transaction from `address` has to carry
+    pub fn next_nonce(&self, address: &[u8]) -> u64 {
+        self.account(address).map(|a| a.nonce).unwrap_or(0)
+    }
+
+    // coins in existence, worked out from the balances: everything the
+    // mining sender and the genesis allocations ever paid out was issued,
+    // whatever no account or output holds any more was burned (gas, burned
+    // fees). overdrafts count negative
+    pub fn supply(&self) -> Supply {
+        let issuers: [&[u8]; 2] = [BlockChain::MINING_SENDER.as_bytes(), GENESIS_SENDER.as_bytes()];
+        let issued = issuers.iter().map(|issuer| (-self.balance(issuer)).max(0) as u64).sum();
+        let circulating: i64 = self
+            .accounts
+            .iter()
+            .filter(|(address, _)| !issuers.contains(&address.as_slice()))
+            .map(|(_, account)| account.balance)
+            .sum::<i64>()
+            + self.utxos.outputs().map(|(_, output)| output.value as i64).sum::<i64>();
+        Supply {
+            issued,
+            circulating,
+            burned: issued as i64 - circulating,
+        }
+    }
+
+    // what a transaction in a block at `height` can spend, the balance
+    // without the rewards that haven't matured yet
+    pub fn spendable_balance(&self, address: &[u8], height: u64) -> i64 {
+        match self.account(address) {
+            Some(account) => {
+                let immature: i64 = i64::try_from(account.immature_balance(height)).unwrap_or(i64::MAX);
+                account.balance.saturating_sub(immature)
+            }
+            None => 0,
+        }
+    }
+
+    pub fn code(&self, code_hash: &[u8]) -> Option<&Vec<u8>> {
+        self.code.get(code_hash)
+    }
+
+    pub fn asset(&self, asset_id: &[u8]) -> Option<&Asset> {
+        self.assets.get(asset_id)
+    }
+
+    pub fn asset_balance(&self, address: &[u8], asset_id: &[u8]) -> u64 {
+        self.account(address)
+            .and_then(|a| a.assets.get(asset_id).copied())
+            .unwrap_or(0)
+    }
+
+    pub fn name_record(&self, name: &[u8]) -> Option<&NameRecord> {
+        self.names.get(name)
+    }
+
+    // the address a name points to at `height`, expired names resolve to
+    // nothing
+    pub fn resolve_name(&self, name: &[u8], height: u64) -> Option<&Vec<u8>> {
+        self.name_record(name)
+            .filter(|record| !record.is_expired(height))
+            .map(|record| &record.target)
+    }
+
+    pub fn proposal(&self, proposal_id: &[u8]) -> Option<&Proposal> {
+        self.proposals.get(proposal_id)
+    }
+
+    // what a vote on `proposal` from `voter` counts for: the voter's
+    // balance when the proposal was made
+    pub fn voting_weight(&self, proposal: &Proposal, voter: &[u8]) -> u64 {
+        let balance: i128 = self.history.balance_at(voter, proposal.snapshot_height).unwrap_or(0);
+        balance.clamp(0, u64::MAX as i128) as u64
+    }
+
+    // (yes, no) weight of the votes on a proposal so far
+    pub fn tally(&self, proposal_id: &[u8]) -> Option<(u64, u64)> {
+        let proposal: &Proposal = self.proposals.get(proposal_id)?;
+        Some(proposal.tally(|voter| self.voting_weight(proposal, voter)))
+    }
+
+    pub fn parameters(&self) -> &ChainParameters {
+        &self.parameters
+    }
+
+    pub fn utxos(&self) -> &UtxoSet {
+        &self.utxos
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&Vec<u8>, &Account)> {
+        self.accounts.iter()
+    }
+
+    fn account_mut(&mut self, address: &[u8]) -> &mut Account {
+        self.touch(StateKey::Account(address.to_vec()));
+        self.accounts.entry(address.to_vec()).or_default()
+    }
+
+    fn entry(&self, key: &StateKey) -> Option<Entry> {
+        match key {
+            StateKey::Account(address) => self.accounts.get(address).cloned().map(Entry::Account),
+            StateKey::Code(code_hash) => self.code.get(code_hash).cloned().map(Entry::Code),
+            StateKey::Asset(asset_id) => self.assets.get(asset_id).cloned().map(Entry::Asset),
+            StateKey::Name(name) => self.names.get(name).cloned().map(Entry::Name),
+            StateKey::Proposal(id) => self.proposals.get(id).cloned().map(Entry::Proposal),
+            StateKey::Parameters => Some(Entry::Parameters(self.parameters)),
+            StateKey::Output(outpoint) => self.utxos.get(outpoint).cloned().map(Entry::Output),
+        }
+    }
+
+    fn set_entry(&mut self, key: StateKey, entry: Option<Entry>) {
+        match (key, entry) {
+            (StateKey::Account(address), Some(Entry::Account(account))) => {
+                self.accounts.insert(address, account);
+            }
+            (StateKey::Account(address), _) => {
+                self.accounts.remove(&address);
+            }
+            (StateKey::Code(code_hash), Some(Entry::Code(code))) => {
+                self.code.insert(code_hash, code);
+            }
+            (StateKey::Code(code_hash), _) => {
+                self.code.remove(&code_hash);
+            }
+            (StateKey::Asset(asset_id), Some(Entry::Asset(asset))) => {
+                self.assets.insert(asset_id, asset);
+            }
+            (StateKey::Asset(asset_id), _) => {
+                self.assets.remove(&asset_id);
+            }
+            (StateKey::Name(name), Some(Entry::Name(record))) => {
+                self.names.insert(name, record);
+            }
+            (StateKey::Name(name), _) => {
+                self.names.remove(&name);
+            }
+            (StateKey::Proposal(id), Some(Entry::Proposal(proposal))) => {
+                self.proposals.insert(id, proposal);
+            }
+            (StateKey::Proposal(id), _) => {
+                self.proposals.remove(&id);
+            }
+            (StateKey::Parameters, Some(Entry::Parameters(parameters))) => {
+                self.parameters = parameters;
+            }
+            (StateKey::Parameters, _) => {
+                self.parameters = ChainParameters::default();
+            }
+            (StateKey::Output(outpoint), Some(Entry::Output(output))) => {
+                self.utxos.insert(outpoint, output);
+            }
+            (StateKey::Output(outpoint), _) => {
+                self.utxos.remove(&outpoint);
+            }
+        }
+    }
+
+    // has to be called before changing the value behind `key`
+    fn touch(&mut self, key: StateKey) {
+        if !self.tx_touched.contains_key(&key) {
+            let old = self.entry(&key);
+            self.touched.entry(key.clone()).or_insert_with(|| old.clone());
+            self.tx_touched.insert(key, old);
+        }
+    }
+
+    // puts back everything the current transaction changed
+    fn revert_transaction(&mut self) {
+        let changes = std::mem::take(&mut self.tx_touched);
+        for (key, old) in changes {
+            self.set_entry(key, old);
+        }
+    }
+
+    fn entry_hash(&self, key: &StateKey) -> Option<[u8; 32]> {
+        let hash: Vec<u8> = match key {
+            StateKey::Account(address) => self.accounts.get(address)?.hash(),
+            StateKey::Code(code_hash) => self.code.get(code_hash).map(|_| code_hash.clone())?,
+            StateKey::Asset(asset_id) => self.assets.get(asset_id)?.hash(),
+            StateKey::Name(name) => self.names.get(name)?.hash(),
+            StateKey::Proposal(id) => self.proposals.get(id)?.hash(),
+            StateKey::Parameters => self.parameters.hash(),
+            StateKey::Output(outpoint) => self.utxos.get(outpoint)?.hash(),
+        };
+        hash.try_into().ok()
+    }
+
+    // writes the keys touched by the block into the trie and remembers how
+    // to undo them
+    fn commit(&mut self) {
+        let touched = std::mem::take(&mut self.touched);
+        self.tx_touched.clear();
+        for key in touched.keys() {
+            let value = self.entry_hash(key);
+            self.trie.update(key.trie_key(), value);
+        }
+        self.trie.commit();
+        self.undo.push(touched);
+        // a proposal is tallied a voting period after its snapshot
+        self.history.add_block(self.balance_changes());
+        self.history.prune(governance::VOTING_PERIOD as usize + 1);
+
+        self.trie.truncate_journal(MAX_ROLLBACK_BLOCKS);
+        if self.undo.len() > MAX_ROLLBACK_BLOCKS {
+            self.undo.remove(0);
+        }
+    }
+
+    // how much the last applied block moved each balance it changed, by
+    // account. what the balance index keeps per block
+    pub fn balance_changes(&self) -> Vec<(Vec<u8>, i64)> {
+        let Some(changes) = self.undo.last() else {
+            return Vec::new();
+        };
+        changes
+            .iter()
+            .filter_map(|(key, old)| {
+                let StateKey::Account(address) = key else {
+                    return None;
+                };
+                let before: i64 = match old {
+                    Some(Entry::Account(account)) => account.balance,
+                    _ => 0,
+                };
+                let delta: i64 = self.balance(address) - before;
+                (delta != 0).then(|| (address.clone(), delta))
+            })
+            .collect()
+    }
+
+    // undoes the last applied block, false if it is too old to undo
+    pub fn rollback_block(&mut self) -> bool {
+        let Some(changes) = self.undo.pop() else {
+            return false;
+        };
+
+        // anything applied after the last block is dropped too
+        self.tips = 0;
+        self.history.rollback_block();
+        let pending = std::mem::take(&mut self.touched);
+        for (key, old) in pending.into_iter().chain(changes) {
+            self.set_entry(key, old);
+        }
+        self.trie.rollback(1)
+    }
+
+    // merkle proof for a key against the current root
+    pub fn prove(&self, key: &StateKey) -> (Option<[u8; 32]>, TrieProof) {
+        let trie_key = key.trie_key();
+        (self.trie.get(&trie_key).copied(), self.trie.prove(&trie_key))
+    }
+
+    pub fn root(&self) -> Hash32 {
+        Hash32::new(self.trie.root())
+    }
+
+    // runs the unlocking script of `tx` against the locking script
+    // guarding the sender's funds, charging the opcodes to `meter`
+    pub fn verify_spending_conditions(
+        &self,
+        tx: &Transaction,
+        meter: &mut GasMeter,
+    ) -> Result<(), ScriptError> {
+        let locking_bytes: &[u8] = match self.account(&tx.sender_address) {
+            Some(account) if !account.locking_script.is_empty() => &account.locking_script,
+            _ => return Ok(()),
+        };
+
+        if tx.unlocking_script.is_empty() {
+            return Err(ScriptError::MissingUnlockingScript);
+        }
+
+        let locking: Script = Script::from_bytes(locking_bytes)?;
+        let unlocking: Script = Script::from_bytes(&tx.unlocking_script)?;
+        ScriptInterpreter::verify(&unlocking, &locking, &tx.signature_hash(), meter)
+    }
+
+    // `height` is the height of the block the transaction is part of. an
+    // error means the transaction can't be in a block at all, a failed
+    // execution is reported in the receipt instead.
+    pub fn apply_transaction(&mut self, tx: &Transaction, height: u64) -> Result<Receipt, StateError> {
+        State::check_stateless(tx)?;
+        self.check_nonce(tx)?;
+        self.check_funds(tx, height)?;
+        // a payload that can't apply, a spend of an output that is gone say,
+        // makes the whole block invalid rather than a failed receipt the
+        // sender still pays for. only running the code of a contract fails
+        // inside the block
+        self.check_payload(tx, height)?;
+
+        let mut meter = GasMeter::new(tx.gas_limit);
+        self.verify_spending_conditions(tx, &mut meter)
+            .map_err(StateError::Script)?;
+
+        self.tx_touched.clear();
+        let (success, logs) = match self.execute(tx, height, &mut meter) {
+            Ok(logs) => (true, logs),
+            // nobody pays for a failed coinbase, a reward that can't be paid
+            // out makes the block invalid instead
+            Err(e) if tx.sender_address == BlockChain::MINING_SENDER.as_bytes() => {
+                self.revert_transaction();
+                return Err(e);
+            }
+            Err(_) => {
+                self.revert_transaction();
+                (false, Vec::new())
+            }
+        };
+
+        // paid either way, gas left in the meter is never taken (refunded)
+        // and running out used the whole limit. gas is always burned, the
+        // fee is split between the burn and the miner. check_funds made sure
+        // the sender has it, a charge that still doesn't fit undoes the
+        // transaction rather than leave it half applied
+        let gas_used = meter.used();
+        let charged: Option<i64> = gas_used
+            .checked_mul(tx.gas_price)
+            .and_then(|gas| gas.checked_add(tx.fee))
+            .and_then(|charged| i64::try_from(charged).ok());
+        let sender = self.account_mut(&tx.sender_address);
+        let Some(balance) = charged.and_then(|charged| sender.balance.checked_sub(charged)) else {
+            self.revert_transaction();
+            return Err(StateError::AmountOverflow);
+        };
+        sender.balance = balance;
+        sender.nonce += 1;
+        self.tx_touched.clear();
+
+        let burned = (tx.fee as u128 * self.parameters.fee_burn_percent as u128 / 100) as u64;
+        self.tips = self.tips.saturating_add(tx.fee - burned);
+
+        Ok(Receipt {
+            success,
+            gas_used,
+            logs,
+        })
+    }
+
+    fn execute(
+        &mut self,
+        tx: &Transaction,
+        height: u64,
+        meter: &mut GasMeter,
+    ) -> Result<Vec<Log>, StateError> {
+        let value: i64 = i64::try_from(tx.value).map_err(|_| StateError::AmountOverflow)?;
+        let sender = self.account_mut(&tx.sender_address);
+        sender.balance = sender.balance.checked_sub(value).ok_or(StateError::AmountOverflow)?;
+
+        // only the owner locks its funds, it had to open the old lock to
+        // get here. a lock set by whoever pays an account could freeze it
+        if !tx.locking_script.is_empty() {
+            sender.locking_script = tx.locking_script.clone();
+        }
+
+        let recipient = self.account_mut(&tx.recipient_address);
+        recipient.balance = recipient.balance.checked_add(value).ok_or(StateError::AmountOverflow)?;
+        if tx.sender_address == BlockChain::MINING_SENDER.as_bytes() {
+            recipient.add_reward(height, tx.value);
+        }
+
+        self.apply_payload(tx, height, meter)
+    }
+
+    // runs after all the transactions of the block at `height`: the miner
+    // collects the fees that weren't burned (burned too if the block has
+    // no miner), proposals whose voting period is over get tallied and, if
+    // passed, applied. then the block's changes are committed to the trie.
+    // fees the miner's balance can't hold make the block invalid, nothing
+    // is committed then
+    pub fn end_block(&mut self, height: u64, miner: Option<&[u8]>) -> Result<(), StateError> {
+        let tips = std::mem::take(&mut self.tips);
+        if let Some(miner) = miner
+            && tips > 0
+        {
+            let tips: i64 = i64::try_from(tips).map_err(|_| StateError::AmountOverflow)?;
+            let miner = self.account_mut(miner);
+            miner.balance = miner.balance.checked_add(tips).ok_or(StateError::AmountOverflow)?;
+            // fees mature like the reward they come with, a reorg can take
+            // both away
+            miner.add_reward(height, tips as u64);
+        }
+
+        let finished: Vec<Vec<u8>> = self
+            .proposals
+            .iter()
+            .filter(|(_, p)| p.status == ProposalStatus::Open && height >= p.voting_ends_at)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in finished {
+            let (yes, no) = self.tally(&id).unwrap();
+            self.touch(StateKey::Proposal(id.clone()));
+            let proposal = self.proposals.get_mut(&id).unwrap();
+            if yes > no {
+                proposal.status = ProposalStatus::Passed;
+                let (parameter, value) = (proposal.parameter, proposal.value);
+                self.touch(StateKey::Parameters);
+                self.parameters.set(parameter, value);
+            } else {
+                proposal.status = ProposalStatus::Rejected;
+            }
+        }
+
+        self.commit();
+        Ok(())
+    }
+
+    // checks a payload against the current state without applying it,
+    // used to keep transactions that can only fail out of the pool and out
+    // of blocks. whether the sender can pay is check_funds'
+    pub fn check_payload(&self, tx: &Transaction, height: u64) -> Result<(), StateError> {
+        if tx.gas_limit > gas::MAX_TX_GAS {
+            return Err(StateError::GasLimitTooHigh { limit: tx.gas_limit });
+        }
+
+        match &tx.payload {
+            Payload::Transfer => Ok(()),
+            Payload::CreateAsset { supply, .. } => {
+                if *supply == 0 {
+                    return Err(StateError::EmptyAssetSupply);
+                }
+                if self.asset(&asset::asset_id(&tx.sender_address, tx.nonce)).is_some() {
+                    return Err(StateError::AssetAlreadyExists);
+                }
+                Ok(())
+            }
+            Payload::TransferAsset { asset_id, amount } => {
+                if self.asset(asset_id).is_none() {
+                    return Err(StateError::UnknownAsset);
+                }
+                let available = self.asset_balance(&tx.sender_address, asset_id);
+                if available < *amount {
+                    return Err(StateError::InsufficientAssetBalance {
+                        available,
+                        requested: *amount,
+                    });
+                }
+                Ok(())
+            }
+            Payload::ClaimName { name, .. } => {
+                if !names::is_valid_name(name) {
+                    return Err(StateError::InvalidName);
+                }
+                match self.name_record(name) {
+                    Some(record) if !record.is_expired(height) => Err(StateError::NameTaken {
+                        expires_at: record.expires_at,
+                    }),
+                    _ => Ok(()),
+                }
+            }
+            Payload::UpdateName { name, .. } => {
+                let record = self.name_record(name).ok_or(StateError::NameNotFound)?;
+                if record.is_expired(height) {
+                    return Err(StateError::NameExpired);
+                }
+                if record.owner != tx.sender_address {
+                    return Err(StateError::NotNameOwner);
+                }
+                Ok(())
+            }
+            Payload::Propose { parameter, value } => {
+                if *value > parameter.max() {
+                    return Err(StateError::ParameterOutOfRange { parameter: *parameter, value: *value });
+                }
+                if self.proposal(&governance::proposal_id(&tx.sender_address, tx.nonce)).is_some() {
+                    return Err(StateError::ProposalExists);
+                }
+                Ok(())
+            }
+            Payload::Vote { proposal_id, .. } => {
+                let proposal = self.proposal(proposal_id).ok_or(StateError::UnknownProposal)?;
+                if !proposal.is_open(height) {
+                    return Err(StateError::VotingClosed);
+                }
+                if self.voting_weight(proposal, &tx.sender_address) == 0 {
+                    return Err(StateError::NoVotingPower);
+                }
+                Ok(())
+            }
+            Payload::Spend { inputs, .. } => {
+                for (i, input) in inputs.iter().enumerate() {
+                    if inputs[..i].contains(input) {
+                        return Err(StateError::DuplicateInput);
+                    }
+                    let output = self.utxos.get(input).ok_or(StateError::MissingOutput)?;
+                    if output.owner != tx.sender_address {
+                        return Err(StateError::NotOutputOwner);
+                    }
+                }
+                Ok(())
+            }
+            #[cfg(feature = "vm")]
+            Payload::Deploy { .. } | Payload::Call { .. } => Ok(()),
+            #[cfg(not(feature = "vm"))]
+            Payload::Deploy { .. } | Payload::Call { .. } => Err(StateError::UnsupportedPayload),
+        }
+    }
+
+    // replay protection: a signed transaction carries its sender's nonce,
+    // once it is applied the nonce moves on and the same bytes can't be
+    // applied again. the coinbase and the genesis allocations are sent by
+    // nobody, every block has one with nonce 0
+    fn check_nonce(&self, tx: &Transaction) -> Result<(), StateError> {
+        let issuers: [&[u8]; 2] = [BlockChain::MINING_SENDER.as_bytes(), GENESIS_SENDER.as_bytes()];
+        if issuers.contains(&tx.sender_address.as_slice()) {
+            return Ok(());
+        }
+        let expected = self.next_nonce(&tx.sender_address);
+        if tx.nonce != expected {
+            return Err(StateError::WrongNonce { expected, found: tx.nonce });
+        }
+        Ok(())
+    }
+
+    // everything a transaction can take from its sender's account: value,
+    // fee, all the gas it may burn and what a spend's outputs hold beyond
+    // its inputs. none when that is more than any balance can hold
+    fn requested_amount(&self, tx: &Transaction) -> Option<u64> {
+        let funding: u64 = u64::try_from(-self.spend_change(tx)).unwrap_or(0);
+        let requested: u64 = tx
+            .value
+            .checked_add(tx.fee)?
+            .checked_add(tx.gas_limit.checked_mul(tx.gas_price)?)?
+            .checked_add(funding)?;
+        i64::try_from(requested).is_ok().then_some(requested)
+    }
+
+    // no overdrafts: the sender pays for all of it out of what it can spend
+    // in a block at `height`. that leaves out mining rewards that haven't
+    // matured, a reorg could take the block paying them away while what
+    // they paid for stays on the other branch. the coinbase and the genesis
+    // allocations issue new coins, they are the only senders allowed below
+    // zero
+    pub fn check_funds(&self, tx: &Transaction, height: u64) -> Result<(), StateError> {
+        let requested: u64 = self.requested_amount(tx).ok_or(StateError::AmountOverflow)?;
+        let issuers: [&[u8]; 2] = [BlockChain::MINING_SENDER.as_bytes(), GENESIS_SENDER.as_bytes()];
+        if issuers.contains(&tx.sender_address.as_slice()) {
+            return Ok(());
+        }
+        let available: i64 = self.balance(&tx.sender_address);
+        if i128::from(requested) > i128::from(available) {
+            return Err(StateError::InsufficientBalance { available, requested });
+        }
+        // the balance would cover it, only not before the rewards mature
+        let spendable: i64 = self.spendable_balance(&tx.sender_address, height);
+        if i128::from(requested) > i128::from(spendable) {
+            return Err(StateError::ImmatureCoinbase { spendable, requested });
+        }
+        Ok(())
+    }
+
+    // what a spend's inputs hold beyond its outputs, the change its sender's
+    // account gets. negative when the account pays for part of the outputs.
+    // inputs that don't exist count nothing
+    fn spend_change(&self, tx: &Transaction) -> i128 {
+        let Payload::Spend { inputs, outputs } = &tx.payload else {
+            return 0;
+        };
+        let spent: i128 = inputs.iter().filter_map(|input| self.utxos.get(input)).map(|output| output.value as i128).sum();
+        let created: i128 = outputs.iter().map(|output| output.value as i128).sum();
+        spent - created
+    }
+
+    #[cfg_attr(not(feature = "vm"), allow(unused_variables))]
+    fn apply_payload(
+        &mut self,
+        tx: &Transaction,
+        height: u64,
+        meter: &mut GasMeter,
+    ) -> Result<Vec<Log>, StateError> {
+        match &tx.payload {
+            Payload::Transfer => Ok(Vec::new()),
+            Payload::CreateAsset { name, supply } => {
+                let asset_id = asset::asset_id(&tx.sender_address, tx.nonce);
+                self.touch(StateKey::Asset(asset_id.clone()));
+                self.assets.insert(
+                    asset_id.clone(),
+                    Asset {
+                        issuer: tx.sender_address.clone(),
+                        name: name.clone(),
+                        total_supply: *supply,
+                    },
+                );
+                self.account_mut(&tx.sender_address).assets.insert(asset_id, *supply);
+                Ok(Vec::new())
+            }
+            Payload::TransferAsset { asset_id, amount } => {
+                let sender = self.account_mut(&tx.sender_address);
+                let remaining = sender.assets.get(asset_id).copied().unwrap_or(0) - amount;
+                if remaining == 0 {
+                    sender.assets.remove(asset_id);
+                } else {
+                    sender.assets.insert(asset_id.clone(), remaining);
+                }
+
+                let recipient = self.account_mut(&tx.recipient_address);
+                *recipient.assets.entry(asset_id.clone()).or_insert(0) += amount;
+                Ok(Vec::new())
+            }
+            // claiming and updating both (re)start the registration period
+            Payload::ClaimName { name, target } | Payload::UpdateName { name, target } => {
+                self.touch(StateKey::Name(name.clone()));
+                self.names.insert(
+                    name.clone(),
+                    NameRecord {
+                        owner: tx.sender_address.clone(),
+                        target: target.clone(),
+                        expires_at: height + names::NAME_REGISTRATION_PERIOD,
+                    },
+                );
+                Ok(Vec::new())
+            }
+            Payload::Propose { parameter, value } => {
+                let proposal_id = governance::proposal_id(&tx.sender_address, tx.nonce);
+                self.touch(StateKey::Proposal(proposal_id.clone()));
+                self.proposals.insert(
+                    proposal_id,
+                    Proposal {
+                        proposer: tx.sender_address.clone(),
+                        parameter: *parameter,
+                        value: *value,
+                        // whoever held coins before this block gets a say
+                        snapshot_height: height.saturating_sub(1),
+                        voting_ends_at: height + governance::VOTING_PERIOD,
+                        votes: BTreeMap::new(),
+                        status: ProposalStatus::Open,
+                    },
+                );
+                Ok(Vec::new())
+            }
+            Payload::Vote { proposal_id, approve } => {
+                self.touch(StateKey::Proposal(proposal_id.clone()));
+                if let Some(proposal) = self.proposals.get_mut(proposal_id) {
+                    proposal.votes.insert(tx.sender_address.clone(), *approve);
+                }
+                Ok(Vec::new())
+            }
+            Payload::Spend { inputs, .. } => {
+                let change: i64 = i64::try_from(self.spend_change(tx)).map_err(|_| StateError::AmountOverflow)?;
+                for input in inputs.iter() {
+                    self.touch(StateKey::Output(input.clone()));
+                    self.utxos.remove(input);
+                }
+                for (outpoint, output) in utxo::outpoints(tx) {
+                    self.touch(StateKey::Output(outpoint.clone()));
+                    self.utxos.insert(outpoint, output);
+                }
+                let sender = self.account_mut(&tx.sender_address);
+                sender.balance = sender.balance.checked_add(change).ok_or(StateError::AmountOverflow)?;
+                Ok(Vec::new())
+            }
+            #[cfg(feature = "vm")]
+            Payload::Deploy { code, input } => {
+                let address = vm::contract_address(&tx.sender_address, tx.nonce);
+                if self.account(&address).is_some_and(|a| a.is_contract()) {
+                    return Err(StateError::Vm(vm::VmError::AlreadyDeployed));
+                }
+
+                meter
+                    .charge(gas::DEPLOY_GAS_PER_BYTE.saturating_mul(code.len() as u64))
+                    .map_err(|_| StateError::Vm(vm::VmError::OutOfGas))?;
+                let execution = vm::deploy(&address, code, input.clone(), meter)
+                    .map_err(StateError::Vm)?;
+                let code_hash: Vec<u8> = Sha256::digest(code).to_vec();
+                self.touch(StateKey::Code(code_hash.clone()));
+                self.code.insert(code_hash.clone(), code.clone());
+
+                let contract = self.account_mut(&address);
+                contract.code_hash = code_hash;
+                contract.storage = execution.storage;
+                Ok(execution.logs)
+            }
+            #[cfg(feature = "vm")]
+            Payload::Call { input } => {
+                let contract = self
+                    .account(&tx.recipient_address)
+                    .filter(|a| a.is_contract())
+                    .ok_or(StateError::Vm(vm::VmError::UnknownContract))?;
+                let code = self
+                    .code(&contract.code_hash)
+                    .ok_or(StateError::Vm(vm::VmError::UnknownContract))?;
+
+                meter
+                    .charge(gas::CONTRACT_CALL_GAS)
+                    .map_err(|_| StateError::Vm(vm::VmError::OutOfGas))?;
+                let execution = vm::call(
+                    &tx.recipient_address,
+                    code,
+                    contract.storage.clone(),
+                    input.clone(),
+                    meter,
+                )
+                .map_err(StateError::Vm)?;
+                self.account_mut(&tx.recipient_address).storage = execution.storage;
+                Ok(execution.logs)
+            }
+            #[cfg(not(feature = "vm"))]
+            Payload::Deploy { .. } | Payload::Call { .. } => Err(StateError::UnsupportedPayload),
+        }
+    }
+
+    // the rules a transaction has to follow whatever the state is, a block
+    // breaking one of them can be shown invalid with just the transaction
+    pub fn check_stateless(tx: &Transaction) -> Result<(), StateError> {
+        if !State::supports(&tx.payload) {
+            return Err(StateError::UnsupportedPayload);
+        }
+        if tx.gas_limit > gas::MAX_TX_GAS {
+            return Err(StateError::GasLimitTooHigh { limit: tx.gas_limit });
+        }
+        // a lock nothing can open would leave the funds stuck for good
+        if !tx.locking_script.is_empty() {
+            Script::from_bytes(&tx.locking_script).map_err(StateError::Script)?;
+        }
+        Ok(())
+    }
+
+    // without the vm contract payloads never make it into the pool, but
+    // blocks received from elsewhere might still carry them
+    pub fn supports(payload: &Payload) -> bool {
+        cfg!(feature = "vm") || !matches!(payload, Payload::Deploy { .. } | Payload::Call { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn rewards_can_only_be_spent_once_matured() {
+        // `with_difficulty` mines block 1, paying the first reward
+        let miner = test_wallet("miner");
+        let mut chain = BlockChain::with_difficulty(miner.address().into(), 0);
+        let spend = Transaction::new(miner.address().into_bytes(), "B".into(), 1).sign(&miner);
+        assert!(chain.add_transaction(&spend).is_err());
+
+        while (chain.blocks().len() as u64) < 1 + BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let height = chain.blocks().len() as u64;
+        assert_eq!(chain.state().spendable_balance(miner.address().as_bytes(), height), 1);
+        assert!(chain.add_transaction(&spend).is_ok());
+    }
+
+    #[test]
+    fn nobody_spends_more_than_they_have() {
+        let mut state = State::new();
+        state.apply_transaction(&Transaction::new(GENESIS_SENDER.into(), "A".into(), 100), 0).unwrap();
+        let overdraft = Transaction::new("A".into(), "B".into(), 95).with_fee(10);
+        assert_eq!(
+            state.apply_transaction(&overdraft, 1),
+            Err(StateError::InsufficientBalance { available: 100, requested: 105 })
+        );
+        // amounts a balance can't hold are refused, not wrapped around
+        let huge = Transaction::new("A".into(), "B".into(), 1 << 63);
+        assert_eq!(state.apply_transaction(&huge, 1), Err(StateError::AmountOverflow));
+        let gas = Transaction::new("A".into(), "B".into(), 1).with_gas(gas::MAX_TX_GAS, u64::MAX);
+        assert_eq!(state.apply_transaction(&gas, 1), Err(StateError::AmountOverflow));
+
+        state.apply_transaction(&Transaction::new("A".into(), "B".into(), 90).with_fee(10), 1).unwrap();
+        state.end_block(1, None).unwrap();
+        assert_eq!((state.balance(b"A"), state.balance(b"B")), (0, 90));
+    }
+
+    #[test]
+    fn rewards_pay_for_nothing_until_they_mature() {
+        let mut state = State::new();
+        state.apply_transaction(&Transaction::new(GENESIS_SENDER.into(), "A".into(), 3), 0).unwrap();
+        state.apply_transaction(&Transaction::new(BlockChain::MINING_SENDER.into(), "A".into(), 5), 1).unwrap();
+        state.end_block(1, None).unwrap();
+        assert_eq!((state.balance(b"A"), state.spendable_balance(b"A", 2)), (8, 3));
+
+        let tx = |value: u64| Transaction::new("A".into(), "B".into(), value);
+        assert_eq!(
+            state.apply_transaction(&tx(4), 2),
+            Err(StateError::ImmatureCoinbase { spendable: 3, requested: 4 })
+        );
+        // more than the balance holds, matured or not
+        assert_eq!(
+            state.apply_transaction(&tx(9), 2),
+            Err(StateError::InsufficientBalance { available: 8, requested: 9 })
+        );
+        state.apply_transaction(&tx(3), 2).unwrap();
+
+        let mature: u64 = 1 + BlockChain::COINBASE_MATURITY;
+        assert!(state.apply_transaction(&tx(5).with_nonce(1), mature).is_ok());
+    }
+
+    #[test]
+    fn only_the_owner_locks_its_funds() {
+        let (owner, stranger) = (test_wallet("owner"), test_wallet("stranger"));
+        let (a, b): (Vec<u8>, Vec<u8>) = (owner.address().into_bytes(), stranger.address().into_bytes());
+        let mut state = State::new();
+        state.apply_transaction(&Transaction::new(GENESIS_SENDER.into(), a.clone(), 10), 0).unwrap();
+
+        // bytes that are no script would freeze whoever they lock
+        let garbage = Transaction::new(b.clone(), a.clone(), 0).with_locking_script(vec![0xff]);
+        assert_eq!(
+            state.apply_transaction(&garbage, 1),
+            Err(StateError::Script(ScriptError::UnknownOpCode(0xff)))
+        );
+        // a payment with a lock locks its sender, not the one it pays
+        let payment = Transaction::new(b.clone(), a.clone(), 0).with_locking_script(stranger.locking_script());
+        state.apply_transaction(&payment, 1).unwrap();
+        assert!(state.account(&a).unwrap().locking_script.is_empty());
+        assert_eq!(state.account(&b).unwrap().locking_script, stranger.locking_script());
+        state.apply_transaction(&Transaction::new(a.clone(), b.clone(), 1), 1).unwrap();
+
+        let lock = Transaction::new(a.clone(), a.clone(), 0).with_nonce(1).with_locking_script(owner.locking_script());
+        state.apply_transaction(&lock, 1).unwrap();
+        let unlocked = Transaction::new(a.clone(), b.clone(), 1).with_nonce(2).with_gas(5_000, 0);
+        assert_eq!(
+            state.apply_transaction(&unlocked, 1),
+            Err(StateError::Script(ScriptError::MissingUnlockingScript))
+        );
+        let unlocking: Vec<u8> = owner.unlocking_script(&unlocked.signature_hash());
+        state.apply_transaction(&unlocked.with_unlocking_script(unlocking), 1).unwrap();
+        assert_eq!(state.balance(&b), 2);
+    }
+
+    #[test]
+    fn fees_are_split_between_the_burn_and_the_miner() {
+        let mut state = State::new();
+        state.parameters.fee_burn_percent = 30;
+
+        state.apply_transaction(&Transaction::new(GENESIS_SENDER.into(), "A".into(), 10), 0).unwrap();
+        let tx = Transaction::new("A".into(), "B".into(), 0).with_fee(10);
+        state.apply_transaction(&tx, 1).unwrap();
+        state.end_block(1, Some(b"miner")).unwrap();
+
+        assert_eq!(state.balance(b"A"), 0);
+        assert_eq!(state.balance(b"miner"), 7);
+        assert_eq!(state.supply().burned, 3);
+        // the tip waits to mature like the reward
+        let matured: u64 = 1 + BlockChain::COINBASE_MATURITY;
+        assert_eq!(state.spendable_balance(b"miner", matured - 1), 0);
+        assert_eq!(state.spendable_balance(b"miner", matured), 7);
+
+        // fees no balance can hold stop the block, they used to be capped
+        state.tips = u64::MAX;
+        assert_eq!(state.end_block(2, Some(b"miner")), Err(StateError::AmountOverflow));
+        assert_eq!(state.balance(b"miner"), 7);
+    }
+}
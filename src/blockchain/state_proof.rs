@@ -0,0 +1,135 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::light::{LightClient, LightClientError};
+use crate::blockchain::state::{Account, State, StateKey};
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::trie::{self, TrieProof};
+use crate::blockchain::{BlockChain, Serialization};
+
+// an account as of some block, with the path from its leaf to the state
+// root that block commits to. a missing account proves a zero balance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceProof {
+    pub address: Vec<u8>,
+    pub height: u64,
+    pub account: Option<Account>,
+    pub proof: TrieProof,
+}
+
+impl BalanceProof {
+    pub fn balance(&self) -> i64 {
+        self.account.as_ref().map(|a| a.balance).unwrap_or(0)
+    }
+
+    pub fn verify(&self, state_root: &Hash32) -> bool {
+        let leaf: Option<[u8; 32]> = match &self.account {
+            Some(account) => match account.hash().try_into() {
+                Ok(hash) => Some(hash),
+                Err(_) => return false,
+            },
+            None => None,
+        };
+
+        let key = StateKey::Account(self.address.clone()).trie_key();
+        trie::verify_proof(state_root.as_bytes(), &key, leaf.as_ref(), &self.proof)
+    }
+}
+
+impl BlockChain {
+    // the state right after the block at `height`. recent blocks are
+    // undone from the tip, older ones replayed from genesis
+    pub fn state_at(&self, height: u64) -> Option<State> {
+        let tip = self.chain.len().checked_sub(1)? as u64;
+        if height > tip {
+            return None;
+        }
+
+        let mut state: State = self.state.clone();
+        if (0..tip - height).all(|_| state.rollback_block()) {
+            return Some(state);
+        }
+
+        let mut state: State = State::new();
+        for (index, block) in self.chain.iter().enumerate().take(height as usize + 1) {
+            for t in block.transactions.iter() {
+                state
+                    .apply_transaction(&Transaction::deserialization(t).ok()?, index as u64)
+                    .ok()?;
+            }
+            state.end_block(index as u64, block.miner().as_deref()).ok()?;
+        }
+        Some(state)
+    }
+
+    // the balance as of the newest block with `min_confirmations`, so
+    // funds in blocks a short reorg could still undo don't count. zero
+    // when no block is buried that deep
+    pub fn balance(&self, address: &[u8], min_confirmations: u64) -> i64 {
+        let tip = self.chain.len() as u64 - 1;
+        match tip.checked_sub(min_confirmations.saturating_sub(1)) {
+            Some(height) => self.balance_at(address, height),
+            None => 0,
+        }
+    }
+
+    pub fn prove_balance(&self, address: &[u8], height: u64) -> Option<BalanceProof> {
+        let state: State = self.state_at(height)?;
+        let (_, proof) = state.prove(&StateKey::Account(address.to_vec()));
+
+        Some(BalanceProof {
+            address: address.to_vec(),
+            height,
+            account: state.account(address).cloned(),
+            proof,
+        })
+    }
+}
+
+impl LightClient {
+    // checks the proof against the state root of our own header at the
+    // proof's height and returns the balance it shows
+    pub fn verify_balance(&self, proof: &BalanceProof) -> Result<i64, LightClientError> {
+        let header = self
+            .header(proof.height)
+            .ok_or(LightClientError::UnknownBlock { height: proof.height })?;
+        if !proof.verify(&header.state_root) {
+            return Err(LightClientError::InvalidStateProof);
+        }
+        Ok(proof.balance())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::BlockHeader;
+
+    #[test]
+    fn a_light_client_checks_balances_with_and_without_an_account() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 1);
+        for _ in 0..3 {
+            chain.mining().unwrap();
+        }
+        let genesis: BlockHeader = chain.headers().next().unwrap().clone();
+        let mut client = LightClient::new(genesis).with_rules(1, None);
+        client.sync(&chain).unwrap();
+        let height: u64 = client.height();
+
+        let proof: BalanceProof = chain.prove_balance(b"miner", height).unwrap();
+        assert_eq!(client.verify_balance(&proof), Ok(chain.balance_at(b"miner", height)));
+        // nobody ever paid this one, and the proof says so
+        let nobody: BalanceProof = chain.prove_balance(b"nobody", height).unwrap();
+        assert_eq!(nobody.account, None);
+        assert_eq!(client.verify_balance(&nobody), Ok(0));
+
+        let mut richer: BalanceProof = proof.clone();
+        richer.account.as_mut().unwrap().balance += 1;
+        assert_eq!(client.verify_balance(&richer), Err(LightClientError::InvalidStateProof));
+        // an account that is there can't be passed off as missing
+        let mut hidden: BalanceProof = proof.clone();
+        hidden.account = None;
+        assert_eq!(client.verify_balance(&hidden), Err(LightClientError::InvalidStateProof));
+        let mut older: BalanceProof = proof;
+        older.height = height - 1;
+        assert_eq!(client.verify_balance(&older), Err(LightClientError::InvalidStateProof));
+    }
+}
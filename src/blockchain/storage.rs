@@ -0,0 +1,466 @@
+use crate::blockchain::difficulty::Retarget;
+use crate::blockchain::keystore::Keystore;
+use crate::blockchain::peers::BanList;
+use crate::blockchain::receipt::{Log, Receipt};
+use crate::blockchain::transaction::{
+    put_bytes, take_bool, take_bytes, take_end, take_u64, Transaction, DEFAULT_CHAIN_ID,
+};
+use crate::blockchain::validation::ValidationError;
+use crate::blockchain::{Block, BlockChain, BlockHeader, DeserializeError, Serialization};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, info_span, warn};
+
+// the one file a chain directory holds: every block, oldest first, each
+// one a length and then the block's bytes. blocks are only ever appended,
+// so a crash can at worst leave the last one half written
+pub const BLOCK_FILE: &str = "blocks.dat";
+
+// written next to the block file by `BlockChain::shutdown`: how many blocks
+// the file had and the tip's hash. a start that finds it matching the file
+// knows nothing was torn or changed since and skips checking signatures
+// again. it is removed as soon as the chain is open, so a crash afterwards
+// leaves none
+pub const SHUTDOWN_MARKER: &str = "clean-shutdown";
+
+// the pool at shutdown, each transaction a length and then its bytes
+pub const MEMPOOL_FILE: &str = "mempool.dat";
+
+// the banned peers, rewritten whenever a ban is set or lifted
+pub const BANLIST_FILE: &str = "banlist.dat";
+
+// the encrypted wallets, rewritten whenever one is encrypted
+pub const KEYSTORE_FILE: &str = "keystore.dat";
+
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    // the message of the io error, with the path it happened on
+    Io(String),
+    // a whole record that isn't a block, at this byte offset of the file
+    Corrupt { offset: u64 },
+    // the blocks read fine but aren't a valid chain
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(message) => write!(f, "{}", message),
+            StorageError::Corrupt { offset } => write!(f, "block file is corrupt at byte {}", offset),
+            StorageError::Invalid(e) => write!(f, "stored chain is invalid: {}", e),
+        }
+    }
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> StorageError {
+    StorageError::Io(format!("{}: {}", path.display(), e))
+}
+
+// the block file of one chain, open for appending
+#[derive(Debug)]
+pub struct BlockStore {
+    path: PathBuf,
+    file: File,
+    // the last run closed the file with `close` and it wasn't touched since
+    clean_shutdown: bool,
+}
+
+// written next to `path` and moved over it, a crash leaves either the old
+// file or the new one
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), StorageError> {
+    let staged = path.with_extension("tmp");
+    let mut file = File::create(&staged).map_err(|e| io_error(&staged, e))?;
+    file.write_all(bytes).map_err(|e| io_error(&staged, e))?;
+    file.sync_data().map_err(|e| io_error(&staged, e))?;
+    fs::rename(&staged, path).map_err(|e| io_error(path, e))
+}
+
+fn shutdown_marker(blocks: &[Block]) -> Vec<u8> {
+    let mut marker = (blocks.len() as u64).to_be_bytes().to_vec();
+    if let Some(tip) = blocks.last() {
+        marker.extend(tip.hash().as_bytes());
+    }
+    marker
+}
+
+impl BlockStore {
+    // opens, or creates, the block file in `dir` and reads the blocks it
+    // has. a half written last block is cut off, it was never confirmed
+    // as saved
+    pub fn open(dir: &Path) -> Result<(BlockStore, Vec<Block>), StorageError> {
+        let _span = info_span!("storage", op = "open").entered();
+        fs::create_dir_all(dir).map_err(|e| io_error(dir, e))?;
+        let path = dir.join(BLOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| io_error(&path, e))?;
+
+        let mut bytes = Vec::<u8>::new();
+        file.read_to_end(&mut bytes).map_err(|e| io_error(&path, e))?;
+
+        let (blocks, torn) = decode_records(&bytes)?;
+        if let Some(offset) = torn {
+            warn!(offset, "dropping a half written block at the end of the block file");
+            file.set_len(offset as u64).map_err(|e| io_error(&path, e))?;
+        }
+
+        let marker_path = dir.join(SHUTDOWN_MARKER);
+        let clean_shutdown = match fs::read(&marker_path) {
+            Ok(marker) => {
+                fs::remove_file(&marker_path).map_err(|e| io_error(&marker_path, e))?;
+                torn.is_none() && marker == shutdown_marker(&blocks)
+            }
+            Err(_) => false,
+        };
+
+        Ok((BlockStore { path, file, clean_shutdown }, blocks))
+    }
+
+    pub fn clean_shutdown(&self) -> bool {
+        self.clean_shutdown
+    }
+
+    // saves the pool, makes sure every appended block is on the disk and
+    // leaves the marker saying so. `blocks` are the ones in the file
+    pub fn close(&mut self, blocks: &[Block], pool: &[Transaction]) -> Result<(), StorageError> {
+        let _span = info_span!("storage", op = "close", pending = pool.len()).entered();
+        let mut bytes = Vec::<u8>::new();
+        for tx in pool.iter() {
+            put_bytes(&mut bytes, &tx.serialization());
+        }
+        write_atomically(&self.path.with_file_name(MEMPOOL_FILE), &bytes)?;
+        self.file.sync_all().map_err(|e| io_error(&self.path, e))?;
+        write_atomically(&self.path.with_file_name(SHUTDOWN_MARKER), &shutdown_marker(blocks))
+    }
+
+    // the pool saved by the last `close`, the file is removed once read.
+    // transactions that don't decode are left out
+    pub fn take_mempool(&self) -> Result<Vec<Transaction>, StorageError> {
+        let path = self.path.with_file_name(MEMPOOL_FILE);
+        let Ok(bytes) = fs::read(&path) else {
+            return Ok(Vec::<Transaction>::new());
+        };
+        fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+
+        let mut pool = Vec::<Transaction>::new();
+        let mut pos = 0;
+        while let Ok(record) = take_bytes(&bytes, &mut pos) {
+            pool.extend(Transaction::decode(&record));
+        }
+        Ok(pool)
+    }
+
+    // the bans saved with the chain, none if there's no file yet. a file
+    // that doesn't decode is ignored, the node starts without bans
+    pub fn load_banlist(&self) -> BanList {
+        let path = self.path.with_file_name(BANLIST_FILE);
+        let Ok(bytes) = fs::read(&path) else {
+            return BanList::new();
+        };
+        BanList::decode(&bytes).unwrap_or_else(|| {
+            warn!(path = %path.display(), "ignoring a banlist that doesn't decode");
+            BanList::new()
+        })
+    }
+
+    pub fn save_banlist(&self, banlist: &BanList) -> Result<(), StorageError> {
+        write_atomically(&self.path.with_file_name(BANLIST_FILE), &banlist.encode())
+    }
+
+    // the encrypted wallets saved with the chain, none if there's no file
+    // yet. one that doesn't decode is left alone, not overwritten, until
+    // a wallet is encrypted
+    pub fn load_keystore(&self) -> Keystore {
+        let path = self.path.with_file_name(KEYSTORE_FILE);
+        let Ok(bytes) = fs::read(&path) else {
+            return Keystore::new();
+        };
+        Keystore::decode(&bytes).unwrap_or_else(|| {
+            warn!(path = %path.display(), "ignoring a keystore that doesn't decode");
+            Keystore::new()
+        })
+    }
+
+    pub fn save_keystore(&self, keystore: &Keystore) -> Result<(), StorageError> {
+        write_atomically(&self.path.with_file_name(KEYSTORE_FILE), &keystore.encode())
+    }
+
+    // written through to the disk before it returns, a block that was
+    // appended survives a crash
+    pub fn append(&mut self, block: &Block) -> Result<(), StorageError> {
+        let mut record = Vec::<u8>::new();
+        put_bytes(&mut record, &encode_block(block));
+        let _span = info_span!("storage", op = "append", bytes = record.len()).entered();
+        self.file.write_all(&record).map_err(|e| io_error(&self.path, e))?;
+        self.file.sync_data().map_err(|e| io_error(&self.path, e))
+    }
+
+    // replaces every block in the file, after a reorg. a crash leaves
+    // either the old chain or the new one
+    pub fn rewrite(&mut self, blocks: &[Block]) -> Result<(), StorageError> {
+        let _span = info_span!("storage", op = "rewrite", blocks = blocks.len()).entered();
+        let mut bytes = Vec::<u8>::new();
+        for block in blocks.iter() {
+            put_bytes(&mut bytes, &encode_block(block));
+        }
+        write_atomically(&self.path, &bytes)?;
+
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| io_error(&self.path, e))?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// the blocks of a block file, e.g. one written by `BlockChain::export`,
+// without opening it for appending. a half written last block is an error
+// here, nothing is cut off
+pub fn read_blocks(path: &Path) -> Result<Vec<Block>, StorageError> {
+    let bytes: Vec<u8> = fs::read(path).map_err(|e| io_error(path, e))?;
+    match decode_records(&bytes)? {
+        (blocks, None) => Ok(blocks),
+        (_, Some(offset)) => Err(StorageError::Corrupt { offset: offset as u64 }),
+    }
+}
+
+// the blocks, and where the half written one at the end starts if there
+// is one
+fn decode_records(bytes: &[u8]) -> Result<(Vec<Block>, Option<usize>), StorageError> {
+    let mut blocks = Vec::<Block>::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let Ok(record) = take_bytes(bytes, &mut pos) else {
+            return Ok((blocks, Some(start)));
+        };
+        let block = decode_block(&record).map_err(|_| StorageError::Corrupt { offset: start as u64 })?;
+        blocks.push(block);
+    }
+    Ok((blocks, None))
+}
+
+// everything in the block, receipts included so they don't have to be
+// rebuilt on every start
+pub fn encode_block(block: &Block) -> Vec<u8> {
+    let mut bin = Vec::<u8>::new();
+    block.header.put(&mut bin);
+    bin.extend((block.transactions.len() as u64).to_be_bytes());
+    for tx in block.transactions.iter() {
+        put_bytes(&mut bin, tx);
+    }
+    bin.extend((block.receipts.len() as u64).to_be_bytes());
+    for receipt in block.receipts.iter() {
+        bin.extend(receipt.encode());
+    }
+    bin
+}
+
+// an error if the bytes aren't exactly one block
+pub fn decode_block(bytes: &[u8]) -> Result<Block, DeserializeError> {
+    let mut pos = 0;
+    let header = BlockHeader::take(bytes, &mut pos)?;
+    let transactions = (0..take_u64(bytes, &mut pos)?)
+        .map(|_| take_bytes(bytes, &mut pos))
+        .collect::<Result<Vec<Vec<u8>>, DeserializeError>>()?;
+    let mut receipts = Vec::<Receipt>::new();
+    for _ in 0..take_u64(bytes, &mut pos)? {
+        let success = take_bool(bytes, &mut pos)?;
+        let gas_used = take_u64(bytes, &mut pos)?;
+        let mut logs = Vec::<Log>::new();
+        for _ in 0..take_u64(bytes, &mut pos)? {
+            let address = take_bytes(bytes, &mut pos)?;
+            let topics = (0..take_u64(bytes, &mut pos)?)
+                .map(|_| take_bytes(bytes, &mut pos))
+                .collect::<Result<Vec<Vec<u8>>, DeserializeError>>()?;
+            let data = take_bytes(bytes, &mut pos)?;
+            logs.push(Log { address, topics, data });
+        }
+        receipts.push(Receipt { success, gas_used, logs });
+    }
+    take_end(bytes, pos)?;
+
+    let mut block = Block {
+        header,
+        transactions,
+        receipts,
+        hash: None,
+    };
+    block.seal();
+    Ok(block)
+}
+
+impl BlockChain {
+    // the chain saved in `dir`, replayed and checked, or a new one saved
+    // there from now on. every block mined afterwards is appended to it
+    pub fn open(dir: impl AsRef<Path>, address: String) -> Result<BlockChain, StorageError> {
+        let retarget = Some(Retarget::default());
+        BlockChain::open_with_rules(dir.as_ref(), address, BlockChain::DIFFICULTY, DEFAULT_CHAIN_ID, retarget)
+    }
+
+    // `difficulty` and `chain_id` have to be what the saved chain was
+    // mined with, otherwise it doesn't validate. the difficulty never
+    // changes, like on a chain made with `with_chain_id`
+    pub fn open_with_chain_id(
+        dir: impl AsRef<Path>,
+        address: String,
+        difficulty: usize,
+        chain_id: u64,
+    ) -> Result<BlockChain, StorageError> {
+        BlockChain::open_with_rules(dir.as_ref(), address, difficulty, chain_id, None)
+    }
+
+    pub(crate) fn open_with_rules(
+        dir: &Path,
+        address: String,
+        difficulty: usize,
+        chain_id: u64,
+        retarget: Option<Retarget>,
+    ) -> Result<BlockChain, StorageError> {
+        let (mut store, blocks) = BlockStore::open(dir)?;
+
+        if blocks.is_empty() {
+            let mut chain = BlockChain::with_chain_id(address, difficulty, chain_id);
+            chain.set_retarget(retarget);
+            chain.banlist = store.load_banlist();
+            chain.keystore = store.load_keystore();
+            for block in chain.chain.iter() {
+                store.append(block)?;
+            }
+            chain.storage = Some(store);
+            info!(path = %chain.storage_path().unwrap().display(), "saving a new chain");
+            return Ok(chain);
+        }
+
+        let mut chain = BlockChain::empty(address, difficulty, chain_id);
+        chain.set_retarget(retarget);
+        chain.banlist = store.load_banlist();
+        chain.keystore = store.load_keystore();
+        chain.chain = blocks;
+        // the blocks are still replayed, the state is only kept in memory
+        let clean_shutdown = store.clean_shutdown();
+        chain.rebuild(!clean_shutdown).map_err(StorageError::Invalid)?;
+        // checked again like any new transaction, the chain may have moved
+        // on since they were saved
+        for tx in store.take_mempool()? {
+            let _ = chain.submit_transaction(tx, false);
+        }
+        chain.storage = Some(store);
+        info!(blocks = chain.chain.len(), clean_shutdown, pending = chain.transaction_pool.len(), "loaded the chain from disk");
+        Ok(chain)
+    }
+
+    // for a node that is stopping: saves the pool and marks the block file
+    // as cleanly closed, so the next start skips checking it again. nothing
+    // is mined afterwards, whatever calls this holds the chain until the
+    // process exits
+    pub fn shutdown(&mut self) -> Result<(), StorageError> {
+        let Some(store) = self.storage.as_mut() else {
+            return Ok(());
+        };
+        store.close(&self.chain, self.transaction_pool.transactions())?;
+        info!(blocks = self.chain.len(), pending = self.transaction_pool.len(), "shut down cleanly");
+        Ok(())
+    }
+
+    // the whole chain as a block file at `path`, in the format `open` reads
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        let mut bytes = Vec::<u8>::new();
+        for block in self.chain.iter() {
+            put_bytes(&mut bytes, &encode_block(block));
+        }
+        fs::write(path.as_ref(), bytes).map_err(|e| io_error(path.as_ref(), e))
+    }
+
+    // where the chain is saved, none for a chain only kept in memory
+    pub fn storage_path(&self) -> Option<&Path> {
+        self.storage.as_ref().map(|store| store.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blockchain-storage-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn a_reopened_chain_has_every_block_it_mined() {
+        let dir = temp_dir("reopen");
+        let wallet = miner();
+        let mut chain = BlockChain::open_with_chain_id(&dir, wallet.address().into(), 0, DEFAULT_CHAIN_ID).unwrap();
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), test_wallet("B").address().into_bytes(), 1);
+        chain.add_transaction(&tx.sign(&wallet)).unwrap();
+        chain.mining().unwrap();
+        let (height, root) = (chain.blocks().len(), chain.state().root());
+        drop(chain);
+
+        let reopened = BlockChain::open_with_chain_id(&dir, wallet.address().into(), 0, DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(reopened.blocks().len(), height);
+        assert_eq!(reopened.state().root(), root);
+        assert_eq!(reopened.blocks()[height - 1].receipts.len(), 2);
+
+        // a crash in the middle of writing the next block
+        let path = dir.join(BLOCK_FILE);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0, 0, 0, 0, 0, 0, 1, 0, 42]).unwrap();
+        let mut reopened = BlockChain::open_with_chain_id(&dir, wallet.address().into(), 0, DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(reopened.blocks().len(), height);
+        reopened.mining().unwrap();
+        drop(reopened);
+        let reopened = BlockChain::open_with_chain_id(&dir, wallet.address().into(), 0, DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(reopened.blocks().len(), height + 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_clean_shutdown_keeps_the_pool_and_is_trusted_once() {
+        let dir = temp_dir("shutdown");
+        let wallet = miner();
+        let mut chain = BlockChain::open_with_chain_id(&dir, wallet.address().into(), 0, DEFAULT_CHAIN_ID).unwrap();
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        chain.set_ban("10.0.0.1:8333", None);
+        chain.shutdown().unwrap();
+        drop(chain);
+
+        let chain = BlockChain::open_with_chain_id(&dir, wallet.address().into(), 0, DEFAULT_CHAIN_ID).unwrap();
+        assert_eq!(chain.pending_transactions()[0].id(), tx.id());
+        assert!(chain.is_banned("10.0.0.1:8333"));
+        // opening takes the marker away, a crash now is noticed next time
+        assert!(!dir.join(SHUTDOWN_MARKER).exists());
+        let (mut store, blocks) = BlockStore::open(&dir).unwrap();
+        assert!(!store.clean_shutdown());
+
+        store.close(&blocks, &[]).unwrap();
+        assert!(BlockStore::open(&dir).unwrap().0.clean_shutdown());
+        // a block the marker doesn't know about
+        let (mut store, blocks) = BlockStore::open(&dir).unwrap();
+        store.close(&blocks, &[]).unwrap();
+        store.append(&blocks[1]).unwrap();
+        assert!(!BlockStore::open(&dir).unwrap().0.clean_shutdown());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
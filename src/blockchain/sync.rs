@@ -0,0 +1,237 @@
+use crate::blockchain::clock::local_time;
+use crate::blockchain::protocol::Message;
+use crate::blockchain::BlockChain;
+use std::collections::BTreeMap;
+use tracing::warn;
+
+// a peer with blocks in flight that sends nothing for this long is stalled,
+// in nanoseconds like block timestamps (30 seconds)
+pub const STALL_TIMEOUT: u128 = 30 * 1_000_000_000;
+
+// what syncing from one peer looks like so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerSync {
+    // the tip the peer said it had when we shook hands
+    pub height: u64,
+    pub headers_received: u64,
+    pub blocks_requested: u64,
+    pub blocks_received: u64,
+    // heights asked for that haven't arrived yet, oldest request first
+    pub in_flight: Vec<u64>,
+    // local time of the last header or block it sent, or of the handshake
+    pub last_progress: u128,
+    // it sat on requested blocks past the timeout, nothing more is asked of
+    // it until it sends something
+    pub stalled: bool,
+}
+
+// the blocks a stalled peer was sitting on and who they are asked from
+// now, none if no other peer can serve them
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerSwitch {
+    pub stalled: String,
+    pub to: Option<String>,
+    pub heights: Vec<u64>,
+}
+
+// how the initial download is going, for an operator wondering why it is slow
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncStatus {
+    pub height: u64,
+    // the highest tip any peer claims, 0 without peers
+    pub best_peer_height: u64,
+    pub peers: Vec<(String, PeerSync)>,
+}
+
+impl SyncStatus {
+    pub fn is_synced(&self) -> bool {
+        self.height >= self.best_peer_height
+    }
+}
+
+// progress per peer. it doesn't send anything: whatever drives the
+// connections reports what was asked and what arrived, and acts on the
+// switches `check_stalls` returns
+#[derive(Debug, Clone, Default)]
+pub struct SyncTracker {
+    peers: BTreeMap<String, PeerSync>,
+}
+
+impl SyncTracker {
+    pub fn new() -> Self {
+        SyncTracker::default()
+    }
+
+    pub fn peer(&self, peer: &str) -> Option<&PeerSync> {
+        self.peers.get(peer)
+    }
+
+    pub fn add_peer(&mut self, peer: &str, height: u64, now: u128) {
+        let sync = self.peers.entry(peer.to_string()).or_insert(PeerSync {
+            height,
+            headers_received: 0,
+            blocks_requested: 0,
+            blocks_received: 0,
+            in_flight: Vec::<u64>::new(),
+            last_progress: now,
+            stalled: false,
+        });
+        sync.height = sync.height.max(height);
+    }
+
+    // the heights it still owed, to be asked from someone else
+    pub fn remove_peer(&mut self, peer: &str) -> Vec<u64> {
+        self.peers.remove(peer).map(|sync| sync.in_flight).unwrap_or_default()
+    }
+
+    pub fn headers_received(&mut self, peer: &str, count: u64, now: u128) {
+        if let Some(sync) = self.peers.get_mut(peer) {
+            sync.headers_received += count;
+            sync.last_progress = now;
+            sync.stalled = false;
+        }
+    }
+
+    pub fn blocks_requested(&mut self, peer: &str, heights: &[u64], now: u128) {
+        if let Some(sync) = self.peers.get_mut(peer) {
+            // the stall clock starts with the first request, not the handshake
+            if sync.in_flight.is_empty() {
+                sync.last_progress = now;
+            }
+            sync.blocks_requested += heights.len() as u64;
+            sync.in_flight.extend_from_slice(heights);
+        }
+    }
+
+    pub fn block_received(&mut self, peer: &str, height: u64, now: u128) {
+        if let Some(sync) = self.peers.get_mut(peer) {
+            sync.in_flight.retain(|h| *h != height);
+            sync.blocks_received += 1;
+            sync.height = sync.height.max(height);
+            sync.last_progress = now;
+            sync.stalled = false;
+        }
+    }
+
+    // marks peers that sat on their requests for longer than `timeout` as
+    // stalled and hands their blocks to the peer with the highest tip and
+    // the fewest blocks in flight, if there is one that has them
+    pub fn check_stalls(&mut self, now: u128, timeout: u128) -> Vec<PeerSwitch> {
+        let stalled: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|(_, sync)| !sync.stalled && !sync.in_flight.is_empty())
+            .filter(|(_, sync)| now.saturating_sub(sync.last_progress) > timeout)
+            .map(|(peer, _)| peer.clone())
+            .collect();
+
+        let mut switches = Vec::<PeerSwitch>::new();
+        for peer in stalled {
+            let sync = self.peers.get_mut(&peer).expect("only known peers are stalled");
+            sync.stalled = true;
+            let heights: Vec<u64> = std::mem::take(&mut sync.in_flight);
+            let highest: u64 = heights.iter().copied().max().unwrap_or(0);
+
+            let to: Option<String> = self
+                .peers
+                .iter()
+                .filter(|(_, sync)| !sync.stalled && sync.height >= highest)
+                .min_by_key(|(_, sync)| (sync.in_flight.len(), u64::MAX - sync.height))
+                .map(|(peer, _)| peer.clone());
+            if let Some(to) = &to {
+                self.blocks_requested(to, &heights, now);
+            }
+            switches.push(PeerSwitch { stalled: peer, to, heights });
+        }
+        switches
+    }
+
+    pub fn status(&self, height: u64) -> SyncStatus {
+        SyncStatus {
+            height,
+            best_peer_height: self.peers.values().map(|sync| sync.height).max().unwrap_or(0),
+            peers: self
+                .peers
+                .iter()
+                .map(|(peer, sync)| (peer.clone(), sync.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl BlockChain {
+    // the rest is reported by whatever drives the connections, like
+    // `record_peer_version`, which also starts tracking the peer's sync
+    pub fn record_headers_received(&mut self, peer: &str, message: &Message) {
+        if let Message::Headers { headers, .. } = message {
+            self.sync.headers_received(peer, headers.len() as u64, local_time());
+        }
+    }
+
+    pub fn record_blocks_requested(&mut self, peer: &str, heights: &[u64]) {
+        self.sync.blocks_requested(peer, heights, local_time());
+    }
+
+    pub fn record_block_received(&mut self, peer: &str, height: u64) {
+        self.sync.block_received(peer, height, local_time());
+    }
+
+    // the heights the peer still owed
+    pub fn disconnect_peer(&mut self, peer: &str) -> Vec<u64> {
+        self.sync.remove_peer(peer)
+    }
+
+    // call every few seconds, then ask the new peers for the blocks
+    pub fn check_sync_stalls(&mut self) -> Vec<PeerSwitch> {
+        let switches = self.sync.check_stalls(local_time(), STALL_TIMEOUT);
+        for switch in switches.iter() {
+            warn!(
+                peer = %switch.stalled,
+                to = switch.to.as_deref().unwrap_or("nobody"),
+                blocks = switch.heights.len(),
+                "peer stalled, asking another one"
+            );
+        }
+        switches
+    }
+
+    pub fn sync_status(&self) -> SyncStatus {
+        self.sync.status(self.chain.len() as u64 - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_a_stalled_peer_sits_on_move_to_another_one() {
+        let mut tracker = SyncTracker::new();
+        tracker.add_peer("slow", 100, 0);
+        tracker.add_peer("fast", 100, 0);
+        tracker.add_peer("behind", 10, 0);
+        tracker.headers_received("fast", 100, 1);
+        tracker.blocks_requested("slow", &[1, 2, 3], 1);
+        tracker.blocks_requested("fast", &[4, 5], 1);
+        tracker.block_received("slow", 1, 2);
+        tracker.block_received("fast", 4, 2);
+
+        // fast keeps delivering, slow doesn't
+        tracker.block_received("fast", 5, 8);
+        assert!(tracker.check_stalls(7, 5).is_empty());
+        let switches = tracker.check_stalls(8, 5);
+        assert_eq!(
+            switches,
+            vec![PeerSwitch { stalled: "slow".into(), to: Some("fast".into()), heights: vec![2, 3] }]
+        );
+
+        let status = tracker.status(5);
+        assert_eq!(status.best_peer_height, 100);
+        assert!(!status.is_synced());
+        let fast = tracker.peer("fast").unwrap();
+        assert_eq!((fast.headers_received, fast.blocks_requested, fast.blocks_received), (100, 4, 2));
+        assert_eq!(fast.in_flight, vec![2, 3]);
+        assert!(tracker.peer("slow").unwrap().stalled);
+        assert_eq!(tracker.remove_peer("fast"), vec![2, 3]);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::{mempool::Mempool, transaction::Transaction, BlockChain, Serialization};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap};
+
+// the most weight a template takes from the pool, the coinbase aside. a
+// pool bigger than a block waits for the next ones, the best paying
+// packages go first
+pub const MAX_TEMPLATE_WEIGHT: u64 = 400_000;
+
+// the candidate content of the next block: which transactions a miner
+// should include on top of the current tip
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub previous_hash: Hash32,
+    pub transactions: Vec<Vec<u8>>,
+    pub total_fees: u64,
+    // mempool generation the template was assembled from
+    pub mempool_generation: u64,
+}
+
+// fees per 1000 weight units, `fee_rate` for one transaction or a package
+fn rate(fees: u128, weight: u128) -> u64 {
+    (fees * 1000 / weight.max(1)).min(u64::MAX as u128) as u64
+}
+
+// how a package ranks: its fee rate, then the transaction's own, then
+// nonce order. the index last makes every key different
+type Score = (u64, u64, Reverse<u64>, Reverse<usize>);
+
+// a transaction with the ancestors it still waits for: all of them
+// have to be mined for it to be
+struct Package {
+    members: BTreeSet<usize>,
+    fees: u128,
+    weight: u64,
+}
+
+// the pool as a dependency graph, built once per template. weights and
+// fees are worked out once too, a weight serializes the transaction
+struct Graph<'a> {
+    pool: Vec<&'a Transaction>,
+    weights: Vec<u64>,
+    // transactions that have to be mined before each one: the same
+    // sender's previous nonce, and payments to its sender, which may be
+    // the coins it spends
+    parents: Vec<Vec<usize>>,
+    children: Vec<Vec<usize>>,
+    // none for a package heavier than a block. it never fits: whatever
+    // of it is in the block already counts toward the block's weight
+    packages: Vec<Option<Package>>,
+}
+
+impl<'a> Graph<'a> {
+    fn new(pool: Vec<&'a Transaction>) -> Self {
+        let mut by_sender: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        let mut by_recipient: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (i, tx) in pool.iter().enumerate() {
+            by_sender.entry(&tx.sender_address).or_default().push(i);
+            by_recipient.entry(&tx.recipient_address).or_default().push(i);
+        }
+
+        let mut parents: Vec<Vec<usize>> = vec![Vec::new(); pool.len()];
+        for sent in by_sender.values_mut() {
+            sent.sort_by_key(|i| (pool[*i].nonce, *i));
+            // the previous nonce waits for the ones before it in turn
+            for pair in sent.windows(2) {
+                parents[pair[1]].push(pair[0]);
+            }
+        }
+        for (i, tx) in pool.iter().enumerate() {
+            let funders = by_recipient.get(tx.sender_address.as_slice()).into_iter().flatten();
+            parents[i].extend(funders.filter(|f| pool[**f].sender_address != tx.sender_address));
+        }
+
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); pool.len()];
+        for (i, waits_for) in parents.iter().enumerate() {
+            for parent in waits_for.iter() {
+                children[*parent].push(i);
+            }
+        }
+
+        let weights: Vec<u64> = pool.iter().map(|tx| tx.weight()).collect();
+        let packages: Vec<Option<Package>> =
+            (0..pool.len()).map(|i| package(&pool, &weights, &parents, i)).collect();
+        Graph {
+            pool,
+            weights,
+            parents,
+            children,
+            packages,
+        }
+    }
+
+    fn score(&self, index: usize) -> Option<Score> {
+        let package: &Package = self.packages[index].as_ref()?;
+        let own: u64 = rate(self.pool[index].fee as u128, self.weights[index] as u128);
+        let package_rate: u64 = rate(package.fees, package.weight as u128);
+        Some((package_rate, own, Reverse(self.pool[index].nonce), Reverse(index)))
+    }
+
+    // `included` went into the block: the packages waiting on them lose
+    // them. returns those transactions, their scores changed
+    fn remove(&mut self, included: &[usize]) -> BTreeSet<usize> {
+        let mut descendants = BTreeSet::<usize>::new();
+        let mut queue: Vec<usize> = included.to_vec();
+        while let Some(i) = queue.pop() {
+            for child in self.children[i].iter() {
+                // past a package too big everything is too big
+                if self.packages[*child].is_some() && descendants.insert(*child) {
+                    queue.push(*child);
+                }
+            }
+        }
+        for d in descendants.iter() {
+            let Some(package) = self.packages[*d].as_mut() else { continue };
+            for i in included.iter() {
+                if package.members.remove(i) {
+                    package.fees -= self.pool[*i].fee as u128;
+                    package.weight -= self.weights[*i];
+                }
+            }
+        }
+        descendants
+    }
+}
+
+// the package of `index` when nothing is in the block yet, none as soon
+// as it weighs more than a block
+fn package(pool: &[&Transaction], weights: &[u64], parents: &[Vec<usize>], index: usize) -> Option<Package> {
+    let mut package = Package {
+        members: BTreeSet::new(),
+        fees: 0,
+        weight: 0,
+    };
+    let mut queue: Vec<usize> = vec![index];
+    while let Some(i) = queue.pop() {
+        if !package.members.insert(i) {
+            continue;
+        }
+        package.fees += pool[i].fee as u128;
+        package.weight += weights[i];
+        if package.weight > MAX_TEMPLATE_WEIGHT {
+            return None;
+        }
+        queue.extend(parents[i].iter().copied());
+    }
+    Some(package)
+}
+
+impl BlockTemplate {
+    // child pays for parent: a transaction is ranked by the fee rate of
+    // its whole package, so a well paying child pulls in the cheap parent
+    // it spends from. the package goes in ancestors first, so the block
+    // never spends what it hasn't paid yet. packages paying the same rate
+    // keep nonce order. the coinbase isn't part of it, the miner puts its
+    // own in front
+    pub fn assemble(previous_hash: Hash32, mempool: &Mempool) -> Self {
+        let pool: Vec<&Transaction> = mempool
+            .transactions()
+            .iter()
+            .filter(|tx| tx.sender_address != BlockChain::MINING_SENDER.as_bytes())
+            .collect();
+        let mut graph = Graph::new(pool);
+        let count: usize = graph.pool.len();
+
+        // best package first. a score goes stale when part of the package
+        // is included, the descendants of what was included are scored
+        // again and the scores from before are skipped by their version
+        let mut selected: Vec<bool> = vec![false; count];
+        let mut versions: Vec<u64> = vec![0; count];
+        let mut queue: BinaryHeap<(Score, u64)> = (0..count).filter_map(|i| Some((graph.score(i)?, 0))).collect();
+        let mut order = Vec::<usize>::with_capacity(count);
+        let mut weight: u64 = 0;
+        while let Some((score, version)) = queue.pop() {
+            let Reverse(best) = score.3;
+            if selected[best] || version != versions[best] {
+                continue;
+            }
+            // too big for what's left, something smaller may still fit
+            let package_weight: u64 = graph.packages[best].as_ref().map_or(u64::MAX, |p| p.weight);
+            if weight.saturating_add(package_weight) > MAX_TEMPLATE_WEIGHT {
+                continue;
+            }
+            weight += package_weight;
+
+            let before: usize = order.len();
+            include(&graph.parents, &mut selected, &mut order, best);
+            for i in graph.remove(&order[before..]) {
+                versions[i] += 1;
+                if let Some(score) = graph.score(i) {
+                    queue.push((score, versions[i]));
+                }
+            }
+        }
+
+        let mut total_fees: u64 = 0;
+        let mut transactions = Vec::<Vec<u8>>::new();
+        for tx in order.iter().map(|i| graph.pool[*i]) {
+            total_fees = total_fees.saturating_add(tx.fee);
+            transactions.push(tx.serialization());
+        }
+
+        BlockTemplate {
+            previous_hash,
+            transactions,
+            total_fees,
+            mempool_generation: mempool.generation(),
+        }
+    }
+
+    // a template stays valid until the pool changes or a new block
+    // moves the tip
+    pub fn is_current(&self, previous_hash: &Hash32, mempool: &Mempool) -> bool {
+        self.mempool_generation == mempool.generation() && self.previous_hash == *previous_hash
+    }
+}
+
+// adds `index` after its ancestors. it is marked before them, so two
+// transactions paying each other can't recurse forever, one of them just
+// goes first
+fn include(parents: &[Vec<usize>], selected: &mut [bool], order: &mut Vec<usize>, index: usize) {
+    if selected[index] {
+        return;
+    }
+    selected[index] = true;
+    for parent in parents[index].iter() {
+        include(parents, selected, order, *parent);
+    }
+    order.push(index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::mempool::ReplacementPolicy;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn a_child_pays_for_its_parent_and_comes_after_it() {
+        let (alice, bob) = (test_wallet("alice"), test_wallet("bob"));
+        let (carol, dave) = (test_wallet("carol"), test_wallet("dave"));
+        let parent = Transaction::new(alice.address().into(), bob.address().into(), 5).sign(&alice);
+        let child = Transaction::new(bob.address().into(), carol.address().into(), 5).with_fee(10).sign(&bob);
+        let other = Transaction::new(dave.address().into(), carol.address().into(), 1).with_fee(4).sign(&dave);
+
+        let mut mempool = Mempool::new(ReplacementPolicy::default());
+        for tx in [child.clone(), other.clone(), parent.clone()] {
+            mempool.add(tx).unwrap();
+        }
+        // alone the child pays 10 and the parent nothing, as a package
+        // they pay 5 each, still more than 4
+        let template = BlockTemplate::assemble(Hash32::ZERO, &mempool);
+        let expected: Vec<Vec<u8>> = vec![parent.serialization(), child.serialization(), other.serialization()];
+        assert_eq!(template.transactions, expected);
+        assert_eq!(template.total_fees, 14);
+    }
+
+    #[test]
+    fn a_template_holds_a_block_not_the_whole_pool() {
+        let (alice, bob, carol) = (test_wallet("alice"), test_wallet("bob"), test_wallet("carol"));
+        let mut mempool = Mempool::new(ReplacementPolicy::default()).with_max_weight(4 * MAX_TEMPLATE_WEIGHT);
+        let mut nonce: u64 = 0;
+        while mempool.weight() <= 2 * MAX_TEMPLATE_WEIGHT {
+            let tx = Transaction::new(alice.address().into(), bob.address().into(), 1).with_nonce(nonce).with_fee(1);
+            mempool.add(tx).unwrap();
+            nonce += 1;
+        }
+        let rich = Transaction::new(carol.address().into(), bob.address().into(), 1).with_fee(1_000).sign(&carol);
+        mempool.add(rich.clone()).unwrap();
+        // the coinbase isn't the template's, it doesn't make it stale
+        let template = BlockTemplate::assemble(Hash32::ZERO, &mempool);
+        mempool.add(Transaction::new(BlockChain::MINING_SENDER.into(), alice.address().into(), 1)).unwrap();
+        assert!(template.is_current(&Hash32::ZERO, &mempool));
+
+        let weight: u64 = template
+            .transactions
+            .iter()
+            .map(|t| Transaction::deserialization(t).unwrap().weight())
+            .sum();
+        assert!(weight <= MAX_TEMPLATE_WEIGHT && weight > MAX_TEMPLATE_WEIGHT / 2);
+        // the best rate first, then alice's in nonce order as far as they fit
+        assert_eq!(template.transactions[0], rich.serialization());
+        let nonces: Vec<u64> = template.transactions[1..]
+            .iter()
+            .map(|t| Transaction::deserialization(t).unwrap().nonce)
+            .collect();
+        assert_eq!(nonces, (0..nonces.len() as u64).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,406 @@
+use crate::blockchain::chaos::SplitMix64;
+use crate::blockchain::governance::Parameter;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::state::State;
+use crate::blockchain::transaction::{Payload, Transaction};
+use crate::blockchain::utxo::{OutPoint, TxOutput};
+use crate::blockchain::wallet::Wallet;
+use crate::blockchain::*;
+use proptest::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+// a wallet whose keys only depend on `name`, so tests can sign for the
+// same address every run
+pub fn test_wallet(name: &str) -> Wallet {
+    Wallet::from_secret(Sha256::digest(name.as_bytes()).into())
+}
+
+// the wallet generated chains mine to
+pub fn miner() -> Wallet {
+    test_wallet("miner")
+}
+
+// a handful of addresses, so generated transactions keep running into
+// each other instead of all touching fresh accounts
+pub fn arb_address() -> impl Strategy<Value = Vec<u8>> {
+    prop::sample::select(vec!["alice", "bob", "carol", "dave"])
+        .prop_map(|address| address.as_bytes().to_vec())
+}
+
+fn arb_bytes(max_len: usize) -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..max_len)
+}
+
+// mostly plain transfers, the payloads only need to be well formed and
+// may well be rejected by the state
+pub fn arb_payload() -> impl Strategy<Value = Payload> {
+    prop_oneof![
+        4 => Just(Payload::Transfer),
+        1 => (arb_bytes(64), arb_bytes(16)).prop_map(|(code, input)| Payload::Deploy { code, input }),
+        1 => arb_bytes(16).prop_map(|input| Payload::Call { input }),
+        1 => (arb_bytes(16), any::<u64>()).prop_map(|(name, supply)| Payload::CreateAsset { name, supply }),
+        1 => (arb_bytes(32), any::<u64>())
+            .prop_map(|(asset_id, amount)| Payload::TransferAsset { asset_id, amount }),
+        1 => (arb_bytes(16), arb_address()).prop_map(|(name, target)| Payload::ClaimName { name, target }),
+        1 => (arb_bytes(16), arb_address()).prop_map(|(name, target)| Payload::UpdateName { name, target }),
+        1 => (prop_oneof![Just(Parameter::MiningReward), Just(Parameter::FeeBurnPercent)], any::<u64>())
+            .prop_map(|(parameter, value)| Payload::Propose { parameter, value }),
+        1 => (arb_bytes(32), any::<bool>())
+            .prop_map(|(proposal_id, approve)| Payload::Vote { proposal_id, approve }),
+        1 => (
+            prop::collection::vec((arb_bytes(32), any::<u64>()), 0..3),
+            prop::collection::vec((arb_address(), any::<u64>()), 0..3),
+        )
+            .prop_map(|(inputs, outputs)| Payload::Spend {
+                inputs: inputs.into_iter().map(|(txid, index)| OutPoint::new(txid, index)).collect(),
+                outputs: outputs.into_iter().map(|(owner, value)| TxOutput::new(owner, value)).collect(),
+            }),
+    ]
+}
+
+// any field can take any value, good for decoders but not for mining
+pub fn arb_transaction() -> impl Strategy<Value = Transaction> {
+    (
+        arb_address(),
+        arb_address(),
+        any::<u64>(),
+        any::<u64>(),
+        any::<u64>(),
+        any::<bool>(),
+        // scripts, then the key and signature
+        (arb_bytes(32), arb_bytes(32), arb_bytes(33), arb_bytes(65)),
+        any::<u64>(),
+        any::<u64>(),
+        any::<u64>(),
+        arb_payload(),
+    )
+        .prop_map(
+            |(sender, recipient, value, fee, nonce, replaceable, (locking, unlocking, public_key, signature), gas_limit, gas_price, chain_id, payload)| {
+                let mut tx = Transaction::new(sender, recipient, value)
+                    .with_fee(fee)
+                    .with_nonce(nonce)
+                    .with_locking_script(locking)
+                    .with_unlocking_script(unlocking)
+                    .with_gas(gas_limit, gas_price)
+                    .with_chain_id(chain_id)
+                    .with_payload(payload);
+                tx.replaceable = replaceable;
+                tx.public_key = public_key;
+                tx.signature = signature;
+                tx
+            },
+        )
+}
+
+// a block on its own, not linked to anything and without proof of work
+pub fn arb_block() -> impl Strategy<Value = Block> {
+    (
+        any::<i32>(),
+        any::<[u8; 32]>(),
+        any::<u128>(),
+        prop::collection::vec(arb_transaction(), 0..8),
+        any::<[u8; 32]>(),
+        any::<[u8; 32]>(),
+    )
+        .prop_map(|(nonce, previous_hash, time_stamp, transactions, state_root, mmr_root)| {
+            let mut block = Block::new(nonce, Hash32::new(previous_hash));
+            block.header.time_stamp = time_stamp;
+            block.transactions = transactions.iter().map(|tx| tx.serialization()).collect();
+            block.seal_transactions();
+            block.header.state_root = Hash32::new(state_root);
+            block.header.mmr_root = Hash32::new(mmr_root);
+            block
+        })
+}
+
+// up to `max_blocks` blocks mined on top of `BlockChain::new`, each paying
+// a few generated amounts out of the miner's rewards
+pub fn arb_chain(max_blocks: usize) -> impl Strategy<Value = BlockChain> {
+    let payments = prop::collection::vec((arb_address(), 1..=2u64), 0..3);
+    prop::collection::vec(payments, 0..max_blocks).prop_map(build_chain)
+}
+
+// the miner only spends the matured rewards it had at the start of each
+// block, so the chain is valid and no balance goes negative
+pub fn build_chain(blocks: Vec<Vec<(Vec<u8>, u64)>>) -> BlockChain {
+    let wallet = miner();
+    let mut chain = BlockChain::new(wallet.address().into());
+    let miner: Vec<u8> = wallet.address().into_bytes();
+
+    for payments in blocks {
+        let height = chain.blocks().len() as u64;
+        let mut balance = chain.state().spendable_balance(&miner, height);
+        let mut nonce = chain.state().account(&miner).map_or(0, |account| account.nonce);
+        for (recipient, value) in payments {
+            if value as i64 > balance {
+                continue;
+            }
+            let tx = Transaction::new(miner.clone(), recipient, value).with_nonce(nonce).sign(&wallet);
+            if chain.add_transaction(&tx).is_ok() {
+                balance -= value as i64;
+                nonce += 1;
+            }
+        }
+        chain.mining().unwrap();
+    }
+
+    chain
+}
+
+// builds realistic chains quickly for tests and examples:
+//
+//     ChainBuilder::new().with_difficulty(1).with_blocks(50).with_random_txs(500).build()
+//
+// blocks are mined at difficulty 0 unless told otherwise, which accepts
+// any hash (instant mining). the same seed always gives the same chain,
+// apart from timestamps.
+#[derive(Debug, Clone)]
+pub struct ChainBuilder {
+    difficulty: usize,
+    blocks: usize,
+    random_txs: usize,
+    accounts: usize,
+    seed: u64,
+}
+
+impl Default for ChainBuilder {
+    fn default() -> Self {
+        ChainBuilder {
+            difficulty: 0,
+            blocks: 1,
+            random_txs: 0,
+            accounts: 8,
+            seed: 0,
+        }
+    }
+}
+
+impl ChainBuilder {
+    pub fn new() -> Self {
+        ChainBuilder::default()
+    }
+
+    pub fn with_difficulty(mut self, difficulty: usize) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    // blocks mined after genesis, `BlockChain::new` already mines the first
+    pub fn with_blocks(mut self, blocks: usize) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    // transfers spread over the blocks. they are paid for out of mining
+    // rewards, so early blocks hold fewer and whatever the rewards can't
+    // cover by the last block is left out
+    pub fn with_random_txs(mut self, random_txs: usize) -> Self {
+        self.random_txs = random_txs;
+        self
+    }
+
+    // how many addresses besides the miner send and receive, the wallets
+    // are `test_wallet("account 0")` and so on
+    pub fn with_accounts(mut self, accounts: usize) -> Self {
+        self.accounts = accounts.max(1);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn build(self) -> BlockChain {
+        let mut wallets: Vec<Wallet> = vec![miner()];
+        wallets.extend((0..self.accounts).map(|i| test_wallet(&format!("account {}", i))));
+        let addresses: Vec<Vec<u8>> = wallets.iter().map(|w| w.address().into_bytes()).collect();
+
+        let mut chain = BlockChain::with_difficulty(wallets[0].address().into(), self.difficulty);
+        let mut rng = SplitMix64(self.seed);
+
+        let mut remaining = self.random_txs;
+        for block in 0..self.blocks {
+            // spread what's left evenly over the blocks still to come
+            let wanted = remaining.div_ceil(self.blocks - block);
+
+            // only what each address could spend at the start of the
+            // block is spent, so no balance ever dips below zero
+            let height = chain.blocks().len() as u64;
+            let mut spendable: Vec<i64> = addresses
+                .iter()
+                .map(|a| chain.state().spendable_balance(a, height))
+                .collect();
+            let mut nonces: Vec<u64> = addresses
+                .iter()
+                .map(|a| chain.state().account(a).map_or(0, |account| account.nonce))
+                .collect();
+
+            let mut added = 0;
+            while added < wanted {
+                let funded: Vec<usize> = (0..addresses.len()).filter(|i| spendable[*i] > 0).collect();
+                if funded.is_empty() {
+                    break;
+                }
+                let sender = funded[rng.below(funded.len() as u64) as usize];
+                let recipient = (sender + 1 + rng.below(addresses.len() as u64 - 1) as usize) % addresses.len();
+                let value = 1 + rng.below(spendable[sender].min(3) as u64);
+
+                let tx = Transaction::new(addresses[sender].clone(), addresses[recipient].clone(), value)
+                    .with_nonce(nonces[sender])
+                    .sign(&wallets[sender]);
+                if chain.add_transaction(&tx).is_err() {
+                    break;
+                }
+                spendable[sender] -= value as i64;
+                nonces[sender] += 1;
+                added += 1;
+            }
+
+            remaining -= added;
+            chain.mining().unwrap();
+        }
+
+        chain
+    }
+}
+
+// a wallet a default ChainBuilder sends from, the miner or one of its
+// accounts, that can pay `amount` in the next block and isn't `except`.
+// which of them still can is up to the random transactions
+pub fn funded_wallet(chain: &BlockChain, amount: i64, except: &[u8]) -> Option<Wallet> {
+    let height = chain.blocks().len() as u64;
+    std::iter::once(miner())
+        .chain((0..ChainBuilder::default().accounts).map(|i| test_wallet(&format!("account {}", i))))
+        .filter(|wallet| wallet.address().as_bytes() != except)
+        .find(|wallet| chain.state().spendable_balance(wallet.address().as_bytes(), height) >= amount)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InvariantViolation {
+    BrokenLink { height: u64 },
+    InsufficientWork { height: u64 },
+    NegativeBalance { address: Vec<u8>, balance: i64 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::BrokenLink { height } => {
+                write!(f, "block {} does not point at the hash of block {}", height, height - 1)
+            }
+            InvariantViolation::InsufficientWork { height } => {
+                write!(f, "block {} does not meet the difficulty target", height)
+            }
+            InvariantViolation::NegativeBalance { address, balance } => write!(
+                f,
+                "{} has a negative balance of {}",
+                String::from_utf8_lossy(address),
+                balance
+            ),
+        }
+    }
+}
+
+// every block points at the hash of the one before it and, past genesis,
+// carries enough proof of work
+pub fn check_links(chain: &BlockChain) -> Result<(), InvariantViolation> {
+    for (height, pair) in chain.chain.windows(2).enumerate() {
+        let height = height as u64 + 1;
+        if pair[1].header.previous_hash != pair[0].hash() {
+            return Err(InvariantViolation::BrokenLink { height });
+        }
+        if !BlockChain::meets_target(&pair[1].hash(), pair[1].header.difficulty) {
+            return Err(InvariantViolation::InsufficientWork { height });
+        }
+    }
+    Ok(())
+}
+
+// the coinbase sender issues every reward and is the only account allowed
+// to be in the red
+pub fn check_balances(state: &State) -> Result<(), InvariantViolation> {
+    for (address, account) in state.accounts() {
+        if account.balance < 0 && address.as_slice() != BlockChain::MINING_SENDER.as_bytes() {
+            return Err(InvariantViolation::NegativeBalance {
+                address: address.clone(),
+                balance: account.balance,
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn check_invariants(chain: &BlockChain) -> Result<(), InvariantViolation> {
+    check_links(chain)?;
+    check_balances(chain.state())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::merkle;
+    use crate::blockchain::protocol::Message;
+
+    proptest! {
+        #[test]
+        fn transaction_serialization_round_trips(tx in arb_transaction()) {
+            prop_assert_eq!(Transaction::deserialization(&tx.serialization()), Ok(tx));
+        }
+
+        #[test]
+        fn truncated_transactions_do_not_decode(tx in arb_transaction(), cut in any::<prop::sample::Index>()) {
+            let bytes = tx.serialization();
+            prop_assert!(Transaction::deserialization(&bytes[..cut.index(bytes.len())]).is_err());
+        }
+
+        #[test]
+        fn garbage_is_an_error_not_a_panic(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = Transaction::deserialization(&bytes);
+            let _ = BlockHeader::deserialization(&bytes);
+            let _ = Message::deserialization(&bytes);
+        }
+
+        #[test]
+        fn header_serialization_round_trips(block in arb_block()) {
+            let header = block.header();
+            prop_assert_eq!(BlockHeader::deserialization(&header.serialization()), Ok(header));
+        }
+
+        #[test]
+        fn every_transaction_has_a_merkle_proof(block in arb_block()) {
+            let root = block.header.merkle_root;
+            for txid in block.txids() {
+                let proof = block.merkle_proof(&txid).unwrap();
+                prop_assert!(merkle::verify_merkle_proof(&root, &proof, &txid));
+            }
+        }
+    }
+
+    proptest! {
+        // every case mines real blocks
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn generated_chains_hold_invariants(chain in arb_chain(4)) {
+            prop_assert_eq!(check_invariants(&chain), Ok(()));
+            prop_assert!(chain.validate_chain().is_ok());
+        }
+
+        #[test]
+        fn built_chains_hold_invariants(seed in any::<u64>(), blocks in 1..20usize, txs in 0..200usize) {
+            let chain = ChainBuilder::new().with_blocks(blocks).with_random_txs(txs).with_seed(seed).build();
+            prop_assert_eq!(chain.chain.len(), blocks + 2);
+            prop_assert_eq!(check_invariants(&chain), Ok(()));
+            prop_assert!(chain.validate_chain().is_ok());
+        }
+
+        #[test]
+        fn relinked_block_breaks_the_chain(chain in arb_chain(3)) {
+            let mut chain = chain;
+            chain.chain[1].header.previous_hash = Hash32::ZERO;
+            prop_assert_eq!(check_links(&chain), Err(InvariantViolation::BrokenLink { height: 1 }));
+        }
+    }
+}
@@ -1,11 +1,302 @@
+use crate::blockchain::address::Address;
+use crate::blockchain::error::BlockchainError;
+use crate::blockchain::governance::Parameter;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::script;
+use crate::blockchain::utxo::{OutPoint, TxOutput};
+use crate::blockchain::wallet::{self, Wallet};
 use crate::blockchain::*;
+use sha2::{Digest, Sha256};
 use std::fmt;
 
-#[derive(Debug)]
+// what a transaction does besides moving `value` to the recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Payload {
+    Transfer,
+    // deploy wasm `code`, its `init` export runs once with `input`
+    Deploy { code: Vec<u8>, input: Vec<u8> },
+    // run the `call` export of the contract at the recipient address
+    Call { input: Vec<u8> },
+    // issue a new asset, the whole supply goes to the sender
+    CreateAsset { name: Vec<u8>, supply: u64 },
+    // move `amount` units of an asset from the sender to the recipient
+    TransferAsset { asset_id: Vec<u8>, amount: u64 },
+    // register a free (or expired) name pointing to `target`
+    ClaimName { name: Vec<u8>, target: Vec<u8> },
+    // owner only: point the name somewhere else, this also renews it
+    UpdateName { name: Vec<u8>, target: Vec<u8> },
+    // propose changing a chain parameter, balances at this point weigh the votes
+    Propose { parameter: Parameter, value: u64 },
+    Vote { proposal_id: Vec<u8>, approve: bool },
+    // spend the sender's `inputs` and create `outputs`, see utxo.rs. what
+    // the inputs hold beyond the outputs goes to the sender's account, what
+    // they hold less is taken from it
+    Spend { inputs: Vec<OutPoint>, outputs: Vec<TxOutput> },
+}
+
+pub(crate) fn put_bytes(bin: &mut Vec<u8>, bytes: &[u8]) {
+    bin.extend(bytes.len().to_be_bytes().to_vec());
+    bin.extend(bytes);
+}
+
+// the take_* helpers read at `pos` and move it past what they read. they
+// never index out of bounds, bytes that end too early are an error
+pub(crate) fn take_slice<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], DeserializeError> {
+    let slice = pos
+        .checked_add(len)
+        .and_then(|end| bytes.get(*pos..end))
+        .ok_or(DeserializeError::Truncated { offset: *pos, needed: len })?;
+    *pos += len;
+    Ok(slice)
+}
+
+// a field of a fixed size, no length in front
+pub(crate) fn take_array<const N: usize>(bytes: &[u8], pos: &mut usize) -> Result<[u8; N], DeserializeError> {
+    let mut array = [0u8; N];
+    array.copy_from_slice(take_slice(bytes, pos, N)?);
+    Ok(array)
+}
+
+pub(crate) fn take_u8(bytes: &[u8], pos: &mut usize) -> Result<u8, DeserializeError> {
+    take_array::<1>(bytes, pos).map(|array| array[0])
+}
+
+pub(crate) fn take_bool(bytes: &[u8], pos: &mut usize) -> Result<bool, DeserializeError> {
+    let offset = *pos;
+    match take_u8(bytes, pos)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        byte => Err(DeserializeError::InvalidBool { offset, byte }),
+    }
+}
+
+pub(crate) fn take_u64(bytes: &[u8], pos: &mut usize) -> Result<u64, DeserializeError> {
+    take_array(bytes, pos).map(u64::from_be_bytes)
+}
+
+pub(crate) fn take_bytes(bytes: &[u8], pos: &mut usize) -> Result<Vec<u8>, DeserializeError> {
+    // a length no memory could hold is as truncated as any other too long one
+    let len = usize::try_from(take_u64(bytes, pos)?).unwrap_or(usize::MAX);
+    take_slice(bytes, pos, len).map(|slice| slice.to_vec())
+}
+
+// hashes are always 32 bytes, they go without a length
+pub(crate) fn take_hash(bytes: &[u8], pos: &mut usize) -> Result<Hash32, DeserializeError> {
+    take_array(bytes, pos).map(Hash32::new)
+}
+
+// the value has to end where the bytes do
+pub(crate) fn take_end(bytes: &[u8], pos: usize) -> Result<(), DeserializeError> {
+    match bytes.len().saturating_sub(pos) {
+        0 => Ok(()),
+        count => Err(DeserializeError::TrailingBytes { count }),
+    }
+}
+
+// the amounts carry their own length, always 8
+fn take_amount(bytes: &[u8], pos: &mut usize) -> Result<u64, DeserializeError> {
+    let offset = *pos;
+    let amount = take_bytes(bytes, pos)?;
+    let array: [u8; 8] = amount.as_slice().try_into().map_err(|_| DeserializeError::InvalidLength {
+        offset,
+        expected: 8,
+        found: amount.len(),
+    })?;
+    Ok(u64::from_be_bytes(array))
+}
+
+impl Serialization<Payload> for Payload {
+    fn serialization(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        match self {
+            Payload::Transfer => {
+                bin.push(0);
+            }
+            Payload::Deploy { code, input } => {
+                bin.push(1);
+                put_bytes(&mut bin, code);
+                put_bytes(&mut bin, input);
+            }
+            Payload::Call { input } => {
+                bin.push(2);
+                put_bytes(&mut bin, input);
+            }
+            Payload::CreateAsset { name, supply } => {
+                bin.push(3);
+                put_bytes(&mut bin, name);
+                bin.extend(supply.to_be_bytes());
+            }
+            Payload::TransferAsset { asset_id, amount } => {
+                bin.push(4);
+                put_bytes(&mut bin, asset_id);
+                bin.extend(amount.to_be_bytes());
+            }
+            Payload::ClaimName { name, target } => {
+                bin.push(5);
+                put_bytes(&mut bin, name);
+                put_bytes(&mut bin, target);
+            }
+            Payload::UpdateName { name, target } => {
+                bin.push(6);
+                put_bytes(&mut bin, name);
+                put_bytes(&mut bin, target);
+            }
+            Payload::Propose { parameter, value } => {
+                bin.push(7);
+                bin.push(parameter.to_byte());
+                bin.extend(value.to_be_bytes());
+            }
+            Payload::Vote { proposal_id, approve } => {
+                bin.push(8);
+                put_bytes(&mut bin, proposal_id);
+                bin.push(*approve as u8);
+            }
+            Payload::Spend { inputs, outputs } => {
+                bin.push(9);
+                bin.extend((inputs.len() as u64).to_be_bytes());
+                for input in inputs.iter() {
+                    input.put(&mut bin);
+                }
+                bin.extend((outputs.len() as u64).to_be_bytes());
+                for output in outputs.iter() {
+                    output.put(&mut bin);
+                }
+            }
+        }
+        bin
+    }
+
+    fn deserialization(bytes: &[u8]) -> Result<Payload, DeserializeError> {
+        let mut pos = 0;
+        let payload = match take_u8(bytes, &mut pos)? {
+            0 => Payload::Transfer,
+            1 => {
+                let code = take_bytes(bytes, &mut pos)?;
+                let input = take_bytes(bytes, &mut pos)?;
+                Payload::Deploy { code, input }
+            }
+            2 => {
+                let input = take_bytes(bytes, &mut pos)?;
+                Payload::Call { input }
+            }
+            3 => {
+                let name = take_bytes(bytes, &mut pos)?;
+                let supply = take_u64(bytes, &mut pos)?;
+                Payload::CreateAsset { name, supply }
+            }
+            4 => {
+                let asset_id = take_bytes(bytes, &mut pos)?;
+                let amount = take_u64(bytes, &mut pos)?;
+                Payload::TransferAsset { asset_id, amount }
+            }
+            5 => {
+                let name = take_bytes(bytes, &mut pos)?;
+                let target = take_bytes(bytes, &mut pos)?;
+                Payload::ClaimName { name, target }
+            }
+            6 => {
+                let name = take_bytes(bytes, &mut pos)?;
+                let target = take_bytes(bytes, &mut pos)?;
+                Payload::UpdateName { name, target }
+            }
+            7 => {
+                let tag = take_u8(bytes, &mut pos)?;
+                let parameter = Parameter::from_byte(tag).ok_or(DeserializeError::UnknownTag { kind: "parameter", tag })?;
+                let value = take_u64(bytes, &mut pos)?;
+                Payload::Propose { parameter, value }
+            }
+            8 => {
+                let proposal_id = take_bytes(bytes, &mut pos)?;
+                let approve = take_bool(bytes, &mut pos)?;
+                Payload::Vote { proposal_id, approve }
+            }
+            9 => {
+                let inputs = (0..take_u64(bytes, &mut pos)?)
+                    .map(|_| OutPoint::take(bytes, &mut pos))
+                    .collect::<Result<Vec<OutPoint>, DeserializeError>>()?;
+                let outputs = (0..take_u64(bytes, &mut pos)?)
+                    .map(|_| TxOutput::take(bytes, &mut pos))
+                    .collect::<Result<Vec<TxOutput>, DeserializeError>>()?;
+                Payload::Spend { inputs, outputs }
+            }
+            tag => return Err(DeserializeError::UnknownTag { kind: "payload", tag }),
+        };
+        take_end(bytes, pos)?;
+        Ok(payload)
+    }
+}
+
+impl Payload {
+    // none if the bytes are truncated or name an unknown payload,
+    // `deserialization` says which
+    pub fn decode(bytes: &[u8]) -> Option<Payload> {
+        Payload::deserialization(bytes).ok()
+    }
+}
+
+// why a transaction's signature doesn't hold up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureError {
+    Unsigned,
+    // the sender address is not the one the public key derives
+    KeyMismatch,
+    InvalidSignature,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Unsigned => write!(f, "transaction is not signed"),
+            SignatureError::KeyMismatch => {
+                write!(f, "the signing key does not belong to the sender address")
+            }
+            SignatureError::InvalidSignature => write!(f, "signature does not match the transaction"),
+        }
+    }
+}
+
+// the network transactions are for unless they say otherwise. test
+// networks and forks pick another one
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+// how much more a byte of the transaction weighs than a byte of its witness
+pub const WITNESS_SCALE_FACTOR: u64 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transaction {
     pub sender_address: Vec<u8>,
     pub recipient_address: Vec<u8>,
     pub value: u64,
+    pub fee: u64,
+    // per-sender sequence number, two transactions from the same sender
+    // with the same nonce are in conflict and only one can be mined
+    pub nonce: u64,
+    // the sender signals that this transaction may be replaced in the pool
+    // by another one paying a higher fee (opt-in replace-by-fee)
+    pub replaceable: bool,
+    // condition (script bytes) the sender locks its own funds with from
+    // now on, empty leaves its lock as it is
+    pub locking_script: Vec<u8>,
+    // data satisfying the locking script that guards the sender's funds
+    pub unlocking_script: Vec<u8>,
+    // most gas the sender is willing to pay for scripts and contracts,
+    // each unit used costs `gas_price`
+    pub gas_limit: u64,
+    pub gas_price: u64,
+    // the network the transaction is meant for. it's signed with the rest,
+    // so a transaction from a test network or the other side of a fork
+    // can't be replayed on this chain
+    pub chain_id: u64,
+    // a hashcash stamp, the nonce that gives the signature hash leading
+    // zeros. it costs the sender work instead of fee, see `stamp.rs`
+    pub stamp: u64,
+    // the sender's ed25519 public key, the sender address has to be the
+    // address it derives
+    pub public_key: Vec<u8>,
+    // by that key, over the signature hash
+    pub signature: Vec<u8>,
+    pub payload: Payload,
 }
 
 impl Transaction {
@@ -14,7 +305,141 @@ impl Transaction {
             sender_address: sender,
             recipient_address: recipient,
             value,
+            fee: 0,
+            nonce: 0,
+            replaceable: false,
+            locking_script: Vec::<u8>::new(),
+            unlocking_script: Vec::<u8>::new(),
+            gas_limit: 0,
+            gas_price: 0,
+            chain_id: DEFAULT_CHAIN_ID,
+            stamp: 0,
+            public_key: Vec::<u8>::new(),
+            signature: Vec::<u8>::new(),
+            payload: Payload::Transfer,
+        }
+    }
+
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn replaceable(mut self) -> Self {
+        self.replaceable = true;
+        self
+    }
+
+    pub fn with_locking_script(mut self, script: Vec<u8>) -> Self {
+        self.locking_script = script;
+        self
+    }
+
+    pub fn with_unlocking_script(mut self, script: Vec<u8>) -> Self {
+        self.unlocking_script = script;
+        self
+    }
+
+    pub fn with_gas(mut self, gas_limit: u64, gas_price: u64) -> Self {
+        self.gas_limit = gas_limit;
+        self.gas_price = gas_price;
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn with_payload(mut self, payload: Payload) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    // signs with the wallet's key, last: changing anything but the
+    // unlocking script afterwards breaks the signature
+    pub fn sign(mut self, wallet: &Wallet) -> Self {
+        self.public_key = wallet.public_key().to_vec();
+        self.signature = wallet.sign(&self.signature_hash()).to_vec();
+        self
+    }
+
+    // the sender as an address, none for the coinbase sender, a contract
+    // or a name
+    pub fn sender(&self) -> Option<Address> {
+        Address::from_account(&self.sender_address)
+    }
+
+    pub fn recipient(&self) -> Option<Address> {
+        Address::from_account(&self.recipient_address)
+    }
+
+    // the sender signed it with the key its address comes from
+    pub fn verify(&self) -> Result<(), SignatureError> {
+        if self.public_key.is_empty() || self.signature.is_empty() {
+            return Err(SignatureError::Unsigned);
+        }
+        if self.sender() != Some(wallet::address_from_public_key(&self.public_key)) {
+            return Err(SignatureError::KeyMismatch);
         }
+        if !script::check_signature(&self.public_key, &self.signature, &self.signature_hash()) {
+            return Err(SignatureError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    // the message signatures, and checksig in scripts, are made over: the
+    // whole transaction except the signature and the unlocking script,
+    // which is where a script's signature lives
+    pub fn signature_hash(&self) -> Vec<u8> {
+        let mut unsigned = self.clone();
+        unsigned.unlocking_script.clear();
+        unsigned.signature.clear();
+
+        let mut hasher = Sha256::new();
+        hasher.update(unsigned.serialization());
+        hasher.finalize().to_vec()
+    }
+
+    pub fn id(&self) -> Vec<u8> {
+        merkle::txid(&self.serialization())
+    }
+
+    // bytes that only prove the sender may spend: the public key, the
+    // signature and the unlocking script. nothing reads them again once
+    // the transaction is deep in the chain
+    pub fn witness_size(&self) -> u64 {
+        (self.public_key.len() + self.signature.len() + self.unlocking_script.len()) as u64
+    }
+
+    // what the transaction costs the space in a block. witness bytes weigh
+    // a quarter of the rest, so signing doesn't cost a sender much more
+    // than not having to
+    pub fn weight(&self) -> u64 {
+        let size = self.serialization().len() as u64;
+        let witness = self.witness_size();
+        (size - witness) * WITNESS_SCALE_FACTOR + witness
+    }
+
+    // two transactions conflict when they spend the same sender nonce
+    pub fn conflicts_with(&self, other: &Transaction) -> bool {
+        self.sender_address == other.sender_address && self.nonce == other.nonce
+    }
+
+    // `decode` for callers that want to pass the error on
+    pub fn from_bytes(bytes: &[u8]) -> Result<Transaction, BlockchainError> {
+        Transaction::decode(bytes).ok_or(BlockchainError::MalformedTransaction)
+    }
+
+    // none if the bytes aren't exactly one transaction, `deserialization`
+    // says why
+    pub fn decode(bytes: &[u8]) -> Option<Transaction> {
+        Transaction::deserialization(bytes).ok()
     }
 }
 
@@ -34,36 +459,79 @@ impl Serialization<Transaction> for Transaction {
         bin.extend(len_value.to_be_bytes().to_vec());
         bin.extend(&self.value.to_be_bytes().to_vec());
 
+        let len_fee = self.fee.to_be_bytes().len();
+        bin.extend(len_fee.to_be_bytes().to_vec());
+        bin.extend(&self.fee.to_be_bytes().to_vec());
+
+        let len_nonce = self.nonce.to_be_bytes().len();
+        bin.extend(len_nonce.to_be_bytes().to_vec());
+        bin.extend(&self.nonce.to_be_bytes().to_vec());
+
+        bin.push(self.replaceable as u8);
+
+        let len_locking = self.locking_script.len();
+        bin.extend(len_locking.to_be_bytes().to_vec());
+        bin.extend(&self.locking_script);
+
+        let len_unlocking = self.unlocking_script.len();
+        bin.extend(len_unlocking.to_be_bytes().to_vec());
+        bin.extend(&self.unlocking_script);
+
+        bin.extend(self.gas_limit.to_be_bytes());
+        bin.extend(self.gas_price.to_be_bytes());
+        bin.extend(self.chain_id.to_be_bytes());
+        bin.extend(self.stamp.to_be_bytes());
+        put_bytes(&mut bin, &self.public_key);
+        put_bytes(&mut bin, &self.signature);
+
+        // the payload goes last, it takes the rest of the bytes
+        bin.extend(self.payload.serialization());
+
         bin
     }
 
-    fn deserialization(bytes: &Vec<u8>) -> Transaction {
+    fn deserialization(bytes: &[u8]) -> Result<Transaction, DeserializeError> {
         let mut pos = 0;
 
-        // try_into is a trait used to convert slice into array
-        let len_sender = usize::from_be_bytes(bytes[pos..pos+8].try_into().unwrap());
-        let mut sender_address = Vec::<u8>::new();
-        pos += 8;
-        sender_address.extend_from_slice(&bytes[pos..pos+len_sender]);
-        pos += len_sender;
+        let sender_address = take_bytes(bytes, &mut pos)?;
+        let recipient_address = take_bytes(bytes, &mut pos)?;
 
-        let len_recipient = usize::from_be_bytes(bytes[pos..pos+8].try_into().unwrap());
-        pos += 8;
-        let mut recipient_address = Vec::<u8>::new();
-        recipient_address.extend_from_slice(&bytes[pos..pos+len_recipient]);
-        pos += len_recipient;
+        let value = take_amount(bytes, &mut pos)?;
+        let fee = take_amount(bytes, &mut pos)?;
+        let nonce = take_amount(bytes, &mut pos)?;
 
-        let len_value = usize::from_be_bytes(bytes[pos..pos+8].try_into().unwrap());
-        pos += 8;
+        let replaceable = take_bool(bytes, &mut pos)?;
 
-        let value = u64::from_be_bytes(bytes[pos..pos+len_value].try_into().unwrap());
-        // pos += len_value;
+        let locking_script = take_bytes(bytes, &mut pos)?;
+        let unlocking_script = take_bytes(bytes, &mut pos)?;
 
-        Transaction {
+        let gas_limit = take_u64(bytes, &mut pos)?;
+        let gas_price = take_u64(bytes, &mut pos)?;
+        let chain_id = take_u64(bytes, &mut pos)?;
+        let stamp = take_u64(bytes, &mut pos)?;
+        let public_key = take_bytes(bytes, &mut pos)?;
+        let signature = take_bytes(bytes, &mut pos)?;
+
+        // the payload takes the rest of the bytes
+        let payload = Payload::deserialization(&bytes[pos..])?;
+
+        Ok(Transaction {
             sender_address,
             recipient_address,
             value,
-        }
+            fee,
+            nonce,
+            replaceable,
+            locking_script,
+            unlocking_script,
+            gas_limit,
+            gas_price,
+            chain_id,
+            stamp,
+            public_key,
+            signature,
+            payload,
+        })
     }
 }
 
@@ -74,12 +542,98 @@ impl fmt::Display for Transaction {
         // sender address: [67]
         write!(
             f,
-            "\n{}\nsender address: {:?} \nrecipient address: {:?}\nvalue: {}\n{}",
+            "\n{}\nsender address: {:?} \nrecipient address: {:?}\nvalue: {}\nfee: {}\nnonce: {}\ngas: {} at {}\n{}",
             "-".repeat(40),
             self.sender_address,
             self.recipient_address,
             self.value,
+            self.fee,
+            self.nonce,
+            self.gas_limit,
+            self.gas_price,
             "-".repeat(40),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::mempool::MempoolError;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::BlockChain;
+
+    #[test]
+    fn only_transactions_signed_by_the_sender_get_into_the_pool() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1);
+        assert_eq!((tx.sender(), tx.recipient()), (Some(wallet.address()), None));
+
+        assert_eq!(tx.verify(), Err(SignatureError::Unsigned));
+        assert_eq!(
+            chain.add_transaction(&tx),
+            Err(MempoolError::InvalidSignature(SignatureError::Unsigned))
+        );
+        // someone else's key doesn't hash to the sender's address
+        assert_eq!(tx.clone().sign(&test_wallet("thief")).verify(), Err(SignatureError::KeyMismatch));
+        // changed after signing
+        let mut tampered = tx.clone().sign(&wallet);
+        tampered.value = 1_000;
+        assert_eq!(tampered.verify(), Err(SignatureError::InvalidSignature));
+
+        let signed = tx.sign(&wallet);
+        assert_eq!(signed.verify(), Ok(()));
+        assert_eq!(Transaction::deserialization(&signed.serialization()).unwrap().verify(), Ok(()));
+        chain.add_transaction(&signed).unwrap();
+        chain.mining().unwrap();
+        assert_eq!(chain.validate_chain(), Ok(()));
+    }
+
+    #[test]
+    fn bytes_that_are_not_a_transaction_say_why() {
+        let bytes: Vec<u8> = Transaction::new(b"A".to_vec(), b"B".to_vec(), 1).serialization();
+        assert_eq!(
+            Transaction::deserialization(&bytes[..4]).unwrap_err(),
+            DeserializeError::Truncated { offset: 0, needed: 8 }
+        );
+        let mut longer: Vec<u8> = bytes.clone();
+        longer.push(0);
+        assert_eq!(Transaction::deserialization(&longer).unwrap_err(), DeserializeError::TrailingBytes { count: 1 });
+        // the payload tag is the last byte of a transfer
+        let mut unknown: Vec<u8> = bytes;
+        *unknown.last_mut().unwrap() = 200;
+        assert_eq!(
+            Transaction::deserialization(&unknown).unwrap_err(),
+            DeserializeError::UnknownTag { kind: "payload", tag: 200 }
+        );
+    }
+
+    #[test]
+    fn a_flag_is_0_or_1_and_nothing_else() {
+        let plain: Vec<u8> = Transaction::new(b"A".to_vec(), b"B".to_vec(), 1).serialization();
+        let replaceable: Vec<u8> = Transaction::new(b"A".to_vec(), b"B".to_vec(), 1).replaceable().serialization();
+        let offset: usize = (0..plain.len()).find(|i| plain[*i] != replaceable[*i]).unwrap();
+        // 2 used to decode as true, a second id for the same transaction
+        let mut two: Vec<u8> = replaceable.clone();
+        two[offset] = 2;
+        assert_eq!(Transaction::deserialization(&two).unwrap_err(), DeserializeError::InvalidBool { offset, byte: 2 });
+        assert!(Transaction::deserialization(&replaceable).unwrap().replaceable);
+
+        // a vote's approval is the last byte. the payload is decoded on its
+        // own, the offset counts from its tag: tag, id length, id
+        let vote = Payload::Vote {
+            proposal_id: vec![1; 32],
+            approve: true,
+        };
+        let mut bytes: Vec<u8> = Transaction::new(b"A".to_vec(), b"B".to_vec(), 0).with_payload(vote).serialization();
+        *bytes.last_mut().unwrap() = 255;
+        assert_eq!(
+            Transaction::deserialization(&bytes).unwrap_err(),
+            DeserializeError::InvalidBool { offset: 1 + 8 + 32, byte: 255 }
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// a sparse merkle tree: a full binary tree of depth 256 where the path to
+// a leaf is given by the bits of its key. almost every subtree is empty,
+// empty subtrees hash to zeros so they never need to be stored.
+pub const TREE_DEPTH: usize = 256;
+pub const EMPTY_HASH: [u8; 32] = [0u8; 32];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrieProof {
+    // bit i set means the sibling at depth i is not empty and is stored in
+    // `siblings`, in order from the root down
+    pub bitmap: [u8; 32],
+    pub siblings: Vec<[u8; 32]>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SparseMerkleTree {
+    leaves: BTreeMap<[u8; 32], [u8; 32]>,
+    // hashes of the subtrees holding two leaves or more, by depth and the
+    // first `depth` bits of their keys. kept up to date as leaves change, so
+    // the root is one lookup instead of hashing every leaf again. a subtree
+    // with a single leaf is one chain of hashes up from it, cheap to redo
+    // and not worth ~256 entries per leaf
+    branches: BTreeMap<(usize, [u8; 32]), [u8; 32]>,
+    // changes since the last commit: key and the value it had before
+    pending: BTreeMap<[u8; 32], Option<[u8; 32]>>,
+    // one entry per commit, what to write back to undo it
+    journal: Vec<BTreeMap<[u8; 32], Option<[u8; 32]>>>,
+}
+
+fn bit(key: &[u8; 32], depth: usize) -> bool {
+    key[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+fn leaf_hash(key: &[u8; 32], value: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(key);
+    hasher.update(value);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    if *left == EMPTY_HASH && *right == EMPTY_HASH {
+        return EMPTY_HASH;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// the first `depth` bits of `key`, the rest zeroed: the smallest key of
+// the subtree at `depth` that `key` is in
+fn prefix(key: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut prefix = [0u8; 32];
+    prefix[..depth / 8].copy_from_slice(&key[..depth / 8]);
+    if !depth.is_multiple_of(8) {
+        prefix[depth / 8] = key[depth / 8] & !(0xff >> (depth % 8));
+    }
+    prefix
+}
+
+// the largest key of the same subtree
+fn last_key(prefix: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut last = *prefix;
+    if !depth.is_multiple_of(8) {
+        last[depth / 8] |= 0xff >> (depth % 8);
+    }
+    for byte in last.iter_mut().skip(depth.div_ceil(8)) {
+        *byte = 0xff;
+    }
+    last
+}
+
+// the prefix of the other child of the subtree at `depth`, the sibling of
+// the one `key` goes down
+fn sibling_prefix(key: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut sibling = prefix(key, depth + 1);
+    sibling[depth / 8] ^= 0x80 >> (depth % 8);
+    sibling
+}
+
+// a subtree at `depth` holding nothing but this leaf
+fn single_leaf_root(key: &[u8; 32], value: &[u8; 32], depth: usize) -> [u8; 32] {
+    let mut hash = leaf_hash(key, value);
+    for level in (depth..TREE_DEPTH).rev() {
+        hash = if bit(key, level) {
+            node_hash(&EMPTY_HASH, &hash)
+        } else {
+            node_hash(&hash, &EMPTY_HASH)
+        };
+    }
+    hash
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        SparseMerkleTree::default()
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<&[u8; 32]> {
+        self.leaves.get(key)
+    }
+
+    // `None` removes the leaf
+    pub fn update(&mut self, key: [u8; 32], value: Option<[u8; 32]>) {
+        let old = match value {
+            Some(value) => self.leaves.insert(key, value),
+            None => self.leaves.remove(&key),
+        };
+        self.rehash_path(&key);
+
+        // only the value before the first change of this commit matters
+        self.pending.entry(key).or_insert(old);
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.subtree_root(0, &[0u8; 32])
+    }
+
+    fn subtree_root(&self, depth: usize, prefix: &[u8; 32]) -> [u8; 32] {
+        if let Some(hash) = self.branches.get(&(depth, *prefix)) {
+            return *hash;
+        }
+        match self.leaves.range(*prefix..=last_key(prefix, depth)).next() {
+            Some((key, value)) => single_leaf_root(key, value, depth),
+            None => EMPTY_HASH,
+        }
+    }
+
+    // brings the cached subtrees on the path of a changed leaf up to date,
+    // from the bottom up. only they can have changed
+    fn rehash_path(&mut self, key: &[u8; 32]) {
+        for depth in (0..TREE_DEPTH).rev() {
+            let prefix: [u8; 32] = prefix(key, depth);
+            let branching: bool = self.leaves.range(prefix..=last_key(&prefix, depth)).nth(1).is_some();
+            if !branching {
+                self.branches.remove(&(depth, prefix));
+                continue;
+            }
+            let ours = self.subtree_root(depth + 1, &self::prefix(key, depth + 1));
+            let theirs = self.subtree_root(depth + 1, &sibling_prefix(key, depth));
+            let hash = if bit(key, depth) {
+                node_hash(&theirs, &ours)
+            } else {
+                node_hash(&ours, &theirs)
+            };
+            self.branches.insert((depth, prefix), hash);
+        }
+    }
+
+    // closes the changes made since the last commit into one journal entry
+    pub fn commit(&mut self) {
+        let changes = std::mem::take(&mut self.pending);
+        self.journal.push(changes);
+    }
+
+    // drops the oldest journal entries so at most `keep` commits can be undone
+    pub fn truncate_journal(&mut self, keep: usize) {
+        if self.journal.len() > keep {
+            let excess = self.journal.len() - keep;
+            self.journal.drain(..excess);
+        }
+    }
+
+    // undoes the last `commits` commits (and anything not committed yet),
+    // returns false if the journal doesn't go back that far
+    pub fn rollback(&mut self, commits: usize) -> bool {
+        if commits > self.journal.len() {
+            return false;
+        }
+
+        let pending = std::mem::take(&mut self.pending);
+        self.restore(pending);
+        for _ in 0..commits {
+            let changes = self.journal.pop().unwrap();
+            self.restore(changes);
+        }
+        true
+    }
+
+    fn restore(&mut self, changes: BTreeMap<[u8; 32], Option<[u8; 32]>>) {
+        for (key, old) in changes {
+            match old {
+                Some(value) => self.leaves.insert(key, value),
+                None => self.leaves.remove(&key),
+            };
+            self.rehash_path(&key);
+        }
+    }
+
+    // the sibling hashes along the path of `key`, enough to rebuild the root
+    // from the leaf (or from an empty slot, for non-inclusion)
+    pub fn prove(&self, key: &[u8; 32]) -> TrieProof {
+        let mut bitmap = [0u8; 32];
+        let mut siblings = Vec::<[u8; 32]>::new();
+
+        for depth in 0..TREE_DEPTH {
+            let sibling = self.subtree_root(depth + 1, &sibling_prefix(key, depth));
+            if sibling != EMPTY_HASH {
+                bitmap[depth / 8] |= 0x80 >> (depth % 8);
+                siblings.push(sibling);
+            }
+        }
+
+        TrieProof { bitmap, siblings }
+    }
+}
+
+// checks that `key` holds `value` (or nothing, if `value` is None) in the
+// tree with the given root
+pub fn verify_proof(root: &[u8; 32], key: &[u8; 32], value: Option<&[u8; 32]>, proof: &TrieProof) -> bool {
+    let mut hash = match value {
+        Some(value) => leaf_hash(key, value),
+        None => EMPTY_HASH,
+    };
+
+    let mut remaining = proof.siblings.iter().rev();
+    for depth in (0..TREE_DEPTH).rev() {
+        let sibling = if proof.bitmap[depth / 8] & (0x80 >> (depth % 8)) != 0 {
+            match remaining.next() {
+                Some(sibling) => *sibling,
+                None => return false,
+            }
+        } else {
+            EMPTY_HASH
+        };
+
+        hash = if bit(key, depth) {
+            node_hash(&sibling, &hash)
+        } else {
+            node_hash(&hash, &sibling)
+        };
+    }
+
+    remaining.next().is_none() && hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what the root was before it was cached: every leaf hashed again.
+    // `leaves` are sorted and all share the first `depth` bits
+    fn rehash_everything(leaves: &[([u8; 32], [u8; 32])], depth: usize) -> [u8; 32] {
+        if leaves.is_empty() {
+            return EMPTY_HASH;
+        }
+        if depth == TREE_DEPTH {
+            let (key, value) = &leaves[0];
+            return leaf_hash(key, value);
+        }
+        let split = leaves.partition_point(|(key, _)| !bit(key, depth));
+        node_hash(&rehash_everything(&leaves[..split], depth + 1), &rehash_everything(&leaves[split..], depth + 1))
+    }
+
+    fn key(n: u8) -> [u8; 32] {
+        Sha256::digest([n]).into()
+    }
+
+    #[test]
+    fn the_cached_root_follows_updates_and_rollbacks() {
+        let mut tree = SparseMerkleTree::new();
+        assert_eq!(tree.root(), EMPTY_HASH);
+        for n in 0..40u8 {
+            tree.update(key(n), Some([n; 32]));
+        }
+        tree.commit();
+        let committed: [u8; 32] = tree.root();
+        // two keys sharing all but the last bit, and some removals
+        let mut twin: [u8; 32] = key(0);
+        twin[31] ^= 1;
+        tree.update(twin, Some([7; 32]));
+        for n in (0..40u8).step_by(3) {
+            tree.update(key(n), None);
+        }
+        tree.update(key(1), Some([0xaa; 32]));
+        let leaves: Vec<([u8; 32], [u8; 32])> = tree.leaves.iter().map(|(k, v)| (*k, *v)).collect();
+        assert_eq!(tree.root(), rehash_everything(&leaves, 0));
+
+        assert!(tree.rollback(0));
+        assert_eq!(tree.root(), committed);
+        assert!(tree.rollback(1));
+        assert_eq!(tree.root(), EMPTY_HASH);
+        assert!(tree.branches.is_empty());
+    }
+
+    #[test]
+    fn proofs_show_what_is_there_and_what_is_not() {
+        let mut tree = SparseMerkleTree::new();
+        for n in 0..10u8 {
+            tree.update(key(n), Some([n; 32]));
+        }
+        let root: [u8; 32] = tree.root();
+
+        let proof: TrieProof = tree.prove(&key(3));
+        assert!(verify_proof(&root, &key(3), Some(&[3; 32]), &proof));
+        assert!(!verify_proof(&root, &key(3), Some(&[4; 32]), &proof));
+        assert!(!verify_proof(&root, &key(3), None, &proof));
+
+        // a key that was never set proves to be empty, and nothing else
+        let absent: TrieProof = tree.prove(&key(100));
+        assert!(verify_proof(&root, &key(100), None, &absent));
+        assert!(!verify_proof(&root, &key(100), Some(&[0; 32]), &absent));
+
+        let mut tampered: TrieProof = proof.clone();
+        tampered.siblings[0][0] ^= 1;
+        assert!(!verify_proof(&root, &key(3), Some(&[3; 32]), &tampered));
+        let mut dropped: TrieProof = absent.clone();
+        dropped.siblings.pop();
+        assert!(!verify_proof(&root, &key(100), None, &dropped));
+    }
+}
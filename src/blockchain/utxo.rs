@@ -0,0 +1,262 @@
+use crate::blockchain::coin_selection::{Coin, Selection};
+use crate::blockchain::transaction::{put_bytes, take_bytes, take_end, take_u64, Payload, Transaction};
+use crate::blockchain::{BlockChain, DeserializeError};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+// the output based model, next to the accounts: instead of a balance an
+// owner holds outputs, each created by some transaction and spent whole by
+// a later one. a spend names the outputs it consumes and creates new ones,
+// what it consumes and doesn't pay out is change to the sender's account.
+// an output can only be spent once, so two transactions spending the same
+// one can never both be mined, whatever order nodes see them in
+
+// an output of a transaction, by the txid and its position among the
+// transaction's outputs
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutPoint {
+    pub txid: Vec<u8>,
+    pub index: u64,
+}
+
+impl OutPoint {
+    pub fn new(txid: Vec<u8>, index: u64) -> Self {
+        OutPoint { txid, index }
+    }
+
+    // how a spend carries it and what a coin's outpoint is
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bin = Vec::<u8>::new();
+        self.put(&mut bin);
+        bin
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<OutPoint, DeserializeError> {
+        let mut pos = 0;
+        let outpoint = OutPoint::take(bytes, &mut pos)?;
+        take_end(bytes, pos)?;
+        Ok(outpoint)
+    }
+
+    pub(crate) fn put(&self, bin: &mut Vec<u8>) {
+        put_bytes(bin, &self.txid);
+        bin.extend(self.index.to_be_bytes());
+    }
+
+    pub(crate) fn take(bytes: &[u8], pos: &mut usize) -> Result<OutPoint, DeserializeError> {
+        let txid = take_bytes(bytes, pos)?;
+        let index = take_u64(bytes, pos)?;
+        Ok(OutPoint { txid, index })
+    }
+}
+
+// `value` coins only `owner` can spend
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TxOutput {
+    pub owner: Vec<u8>,
+    pub value: u64,
+}
+
+impl TxOutput {
+    pub fn new(owner: Vec<u8>, value: u64) -> Self {
+        TxOutput { owner, value }
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        hasher.update((self.owner.len() as u64).to_be_bytes());
+        hasher.update(&self.owner);
+        hasher.update(self.value.to_be_bytes());
+        hasher.finalize().to_vec()
+    }
+
+    pub(crate) fn put(&self, bin: &mut Vec<u8>) {
+        put_bytes(bin, &self.owner);
+        bin.extend(self.value.to_be_bytes());
+    }
+
+    pub(crate) fn take(bytes: &[u8], pos: &mut usize) -> Result<TxOutput, DeserializeError> {
+        let owner = take_bytes(bytes, pos)?;
+        let value = take_u64(bytes, pos)?;
+        Ok(TxOutput { owner, value })
+    }
+}
+
+// the outputs nobody has spent yet. part of the state, so it is committed
+// to by the state root and undone with the rest of a block
+#[derive(Debug, Clone, Default)]
+pub struct UtxoSet {
+    outputs: BTreeMap<OutPoint, TxOutput>,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        UtxoSet::default()
+    }
+
+    pub fn get(&self, outpoint: &OutPoint) -> Option<&TxOutput> {
+        self.outputs.get(outpoint)
+    }
+
+    pub fn contains(&self, outpoint: &OutPoint) -> bool {
+        self.outputs.contains_key(outpoint)
+    }
+
+    pub(crate) fn insert(&mut self, outpoint: OutPoint, output: TxOutput) {
+        self.outputs.insert(outpoint, output);
+    }
+
+    pub(crate) fn remove(&mut self, outpoint: &OutPoint) -> Option<TxOutput> {
+        self.outputs.remove(outpoint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    // a linear scan, there is no index by owner
+    pub fn owned_by<'a>(&'a self, owner: &'a [u8]) -> impl Iterator<Item = (&'a OutPoint, &'a TxOutput)> + 'a {
+        self.outputs.iter().filter(move |(_, output)| output.owner == owner)
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = (&OutPoint, &TxOutput)> {
+        self.outputs.iter()
+    }
+
+    pub fn balance(&self, owner: &[u8]) -> u64 {
+        self.owned_by(owner).map(|(_, output)| output.value).sum()
+    }
+}
+
+// the outputs of `tx`, the spend that created them has the txid they
+// point to
+pub fn outpoints(tx: &Transaction) -> Vec<(OutPoint, TxOutput)> {
+    let Payload::Spend { outputs, .. } = &tx.payload else {
+        return Vec::new();
+    };
+    let txid: Vec<u8> = tx.id();
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(index, output)| (OutPoint::new(txid.clone(), index as u64), output.clone()))
+        .collect()
+}
+
+impl Transaction {
+    // spends the coins `selection` picked: `amount` to `recipient` and the
+    // change back to `sender` as a new output. the fee comes out of the
+    // inputs. none if a coin's outpoint doesn't decode
+    pub fn spend(sender: Vec<u8>, recipient: Vec<u8>, amount: u64, selection: &Selection) -> Option<Transaction> {
+        let inputs: Vec<OutPoint> = selection
+            .coins
+            .iter()
+            .map(|coin| OutPoint::decode(&coin.outpoint).ok())
+            .collect::<Option<Vec<OutPoint>>>()?;
+        let mut outputs: Vec<TxOutput> = vec![TxOutput::new(recipient.clone(), amount)];
+        if selection.change > 0 {
+            outputs.push(TxOutput::new(sender.clone(), selection.change));
+        }
+
+        Some(
+            Transaction::new(sender, recipient, 0)
+                .with_fee(selection.fee)
+                .with_payload(Payload::Spend { inputs, outputs }),
+        )
+    }
+}
+
+impl BlockChain {
+    pub fn utxos(&self) -> &UtxoSet {
+        self.state.utxos()
+    }
+
+    // what `owner` can pick from for a spend, as of the tip. outputs that
+    // a transaction in the pool already spends are left out
+    pub fn coins(&self, owner: &[u8]) -> Vec<Coin> {
+        let pending: Vec<&OutPoint> = self
+            .transaction_pool
+            .transactions()
+            .iter()
+            .flat_map(|tx| tx.payload.inputs())
+            .collect();
+        self.utxos()
+            .owned_by(owner)
+            .filter(|(outpoint, _)| !pending.contains(outpoint))
+            .map(|(outpoint, output)| Coin {
+                outpoint: outpoint.encode(),
+                value: output.value,
+            })
+            .collect()
+    }
+}
+
+impl Payload {
+    // the outputs a transaction spends, none unless it is a spend
+    pub fn inputs(&self) -> &[OutPoint] {
+        match self {
+            Payload::Spend { inputs, .. } => inputs,
+            _ => &[],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::coin_selection::{CoinSelector, LargestFirst, Target};
+    use crate::blockchain::mempool::MempoolError;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::validation::ValidationError;
+    use crate::blockchain::Serialization;
+
+    #[test]
+    fn outputs_are_spent_once() {
+        let wallet = miner();
+        let bob = test_wallet("bob");
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 3 {
+            chain.mining().unwrap();
+        }
+
+        // from the account into two outputs of the miner's
+        let me: Vec<u8> = wallet.address().into_bytes();
+        let outputs = vec![TxOutput::new(me.clone(), 2), TxOutput::new(me.clone(), 1)];
+        let fund = Transaction::new(me.clone(), me.clone(), 0)
+            .with_payload(Payload::Spend { inputs: Vec::new(), outputs })
+            .sign(&wallet);
+        chain.add_transaction(&fund).unwrap();
+        chain.mining().unwrap();
+        assert_eq!(chain.utxos().balance(&me), 3);
+
+        let selection = LargestFirst.select(&chain.coins(&me), &Target::new(1).with_fees(1, 0)).unwrap();
+        let pay = Transaction::spend(me.clone(), bob.address().into_bytes(), 1, &selection)
+            .unwrap()
+            .with_nonce(1)
+            .sign(&wallet);
+        chain.add_transaction(&pay).unwrap();
+        // the pool won't take a second spend of the same output
+        let again = Transaction::spend(me.clone(), me.clone(), 1, &selection).unwrap().with_nonce(2).sign(&wallet);
+        assert!(matches!(chain.add_transaction(&again), Err(MempoolError::DoubleSpend { .. })));
+        assert!(chain.coins(&me).iter().all(|coin| coin.value == 1));
+
+        chain.mining().unwrap();
+        assert_eq!(chain.utxos().balance(bob.address().as_bytes()), 1);
+        assert_eq!(chain.utxos().balance(&me), 1);
+
+        // nor does a block with two spends of one output
+        let double = Transaction::spend(me.clone(), me.clone(), 1, &selection).unwrap().with_nonce(2).sign(&wallet);
+        let tip = chain.blocks().len() - 1;
+        chain.chain[tip].transactions.push(double.serialization());
+        chain.chain[tip].seal_transactions();
+        assert!(matches!(
+            chain.validate_chain(),
+            Err(ValidationError::DoubleSpend { index, .. }) if index == tip
+        ));
+    }
+}
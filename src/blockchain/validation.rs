@@ -0,0 +1,474 @@
+use crate::blockchain::audit::AuditEvent;
+use crate::blockchain::balances::BalanceIndex;
+use crate::blockchain::genesis;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::mmr::MerkleMountainRange;
+use crate::blockchain::profile::{BlockProfile, Stage};
+use crate::blockchain::receipt::{self, Receipt};
+use crate::blockchain::utxo::OutPoint;
+use crate::blockchain::{state::{State, StateError}, transaction::{Payload, Transaction}, Block, BlockChain, BLOCK_VERSION};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq)]
+pub enum ValidationError {
+    EmptyChain,
+    // a whole chain from a peer that doesn't start where ours does
+    GenesisMismatch,
+    BrokenLink { index: usize },
+    // the bytes of a transaction don't decode
+    MalformedTransaction { index: usize },
+    InvalidProofOfWork { index: usize },
+    // a header version this node doesn't know how to read
+    UnsupportedVersion { index: usize, version: u32 },
+    // the header claims a difficulty the retarget rules don't give
+    WrongDifficulty { index: usize, expected: usize },
+    UnsupportedPayload { index: usize },
+    InvalidTransaction { index: usize, error: StateError },
+    StateRootMismatch { index: usize },
+    LogsBloomMismatch { index: usize },
+    ReceiptsRootMismatch { index: usize },
+    MmrRootMismatch { index: usize },
+    // the header's merkle root isn't the root of the block's transactions
+    MerkleRootMismatch { index: usize },
+    // a transaction signed for another network
+    WrongChain { index: usize },
+    // two transactions of the block spend the same output
+    DoubleSpend { index: usize, outpoint: OutPoint },
+    InvalidSignature { index: usize },
+    // a genesis allocation in a block that isn't the genesis block
+    MisplacedAllocation { index: usize },
+    // the first transaction of a mined block isn't its coinbase
+    MissingCoinbase { index: usize },
+    // a second coinbase after the first, it would mint another reward
+    ExtraCoinbase { index: usize },
+    // the coinbase pays `found` instead of the mining reward, or pays
+    // itself a fee or gas
+    InvalidCoinbase { index: usize, expected: u64, found: u64 },
+    // the fees the block pays its miner don't fit the miner's balance
+    InvalidFees { index: usize, error: StateError },
+    // stamped `drift` ahead of the network's time, more than the config
+    // allows
+    TimestampTooFarInFuture { index: usize, drift: Duration },
+    // stamped at or before the median-time-past of the blocks before it
+    TimestampTooOld { index: usize, median: u128 },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyChain => write!(f, "the chain has no genesis block"),
+            ValidationError::GenesisMismatch => write!(f, "the chain starts from another genesis block"),
+            ValidationError::BrokenLink { index } => {
+                write!(f, "block {} does not point to the hash of block {}", index, index - 1)
+            }
+            ValidationError::MalformedTransaction { index } => {
+                write!(f, "block {} has a transaction that can't be decoded", index)
+            }
+            ValidationError::InvalidProofOfWork { index } => {
+                write!(f, "block {} does not meet the difficulty target", index)
+            }
+            ValidationError::UnsupportedVersion { index, version } => {
+                write!(f, "block {} has version {}, this node only knows version {}", index, version, BLOCK_VERSION)
+            }
+            ValidationError::WrongDifficulty { index, expected } => {
+                write!(f, "block {} should have been mined at difficulty {}", index, expected)
+            }
+            ValidationError::UnsupportedPayload { index } => {
+                write!(f, "block {} carries a transaction this node can't execute", index)
+            }
+            ValidationError::InvalidTransaction { index, error } => {
+                write!(f, "block {} has an invalid transaction: {}", index, error)
+            }
+            ValidationError::StateRootMismatch { index } => {
+                write!(f, "block {} commits to a state root that replaying it doesn't produce", index)
+            }
+            ValidationError::LogsBloomMismatch { index } => {
+                write!(f, "block {} has a logs bloom that doesn't match its receipts", index)
+            }
+            ValidationError::ReceiptsRootMismatch { index } => {
+                write!(f, "block {} commits to receipts that replaying it doesn't produce", index)
+            }
+            ValidationError::MmrRootMismatch { index } => {
+                write!(f, "block {} doesn't commit to the blocks before it", index)
+            }
+            ValidationError::MerkleRootMismatch { index } => {
+                write!(f, "block {} has transactions its merkle root doesn't commit to", index)
+            }
+            ValidationError::WrongChain { index } => {
+                write!(f, "block {} has a transaction signed for another chain", index)
+            }
+            ValidationError::DoubleSpend { index, outpoint } => {
+                write!(f, "block {} spends output {} of {} twice", index, outpoint.index, hex::encode(&outpoint.txid))
+            }
+            ValidationError::InvalidSignature { index } => {
+                write!(f, "block {} has a transaction without a valid signature", index)
+            }
+            ValidationError::MisplacedAllocation { index } => {
+                write!(f, "block {} pays out a genesis allocation, only the genesis block can", index)
+            }
+            ValidationError::MissingCoinbase { index } => {
+                write!(f, "block {} doesn't start with its coinbase", index)
+            }
+            ValidationError::ExtraCoinbase { index } => write!(f, "block {} has more than one coinbase", index),
+            ValidationError::InvalidFees { index, error } => {
+                write!(f, "block {} can't pay its miner the fees: {}", index, error)
+            }
+            ValidationError::InvalidCoinbase { index, expected, found } => write!(
+                f,
+                "block {} has a coinbase paying {}, the reward is {} and the coinbase pays no fee",
+                index, found, expected
+            ),
+            ValidationError::TimestampTooFarInFuture { index, drift } => {
+                write!(f, "block {} is stamped {} seconds ahead of the network's time", index, drift.as_secs())
+            }
+            ValidationError::TimestampTooOld { index, median } => write!(
+                f,
+                "block {} is stamped at or before {}, the median time of the blocks before it",
+                index, median
+            ),
+        }
+    }
+}
+
+impl BlockChain {
+    // full validation: links, proof of work, and replaying every
+    // transaction from an empty state to check each committed state root
+    pub fn validate_chain(&self) -> Result<(), ValidationError> {
+        let started = Instant::now();
+        let result = self.replay_chain(true);
+        self.metrics.record_validation(started.elapsed());
+        result.map(|_| ())
+    }
+
+    // checks a block received from a peer as the next one on our tip,
+    // without adding it
+    pub fn validate_block(&self, block: &Block) -> Result<(), ValidationError> {
+        let mut state: State = self.state.clone();
+        let index = self.chain.len();
+        self.check_time_stamp(block, index)?;
+        let mut profile: BlockProfile = BlockProfile::new(index as u64);
+        let prepared = profile.time(Stage::Prepare, || prepare_block(block, index, self.difficulty(), self.chain_id, true));
+        let previous_hash = self.chain.last().ok_or(ValidationError::EmptyChain)?.hash();
+        check_block(block, index, Some(&previous_hash), prepared, &mut state, &self.header_mmr, &mut profile)?;
+        Ok(())
+    }
+
+    // validates a block received from a peer as the next one on our tip
+    // and adds it. it was mined elsewhere, the pool is left as it is
+    pub(crate) fn apply_block(&mut self, mut block: Block) -> Result<(), ValidationError> {
+        let mut state: State = self.state.clone();
+        let index = self.chain.len();
+        self.check_time_stamp(&block, index)?;
+        let mut profile: BlockProfile = BlockProfile::new(index as u64);
+        let prepared = profile.time(Stage::Prepare, || prepare_block(&block, index, self.difficulty(), self.chain_id, true));
+        let previous_hash = self.chain.last().ok_or(ValidationError::EmptyChain)?.hash();
+        let hash = check_block(&block, index, Some(&previous_hash), prepared, &mut state, &self.header_mmr, &mut profile)?;
+        self.profile = profile;
+
+        self.state = state;
+        self.balances.add_block(self.state.balance_changes());
+        self.audit_log.record(
+            AuditEvent::BlockAppended { height: index as u64, hash },
+            "received from a peer",
+        );
+        self.header_mmr.push(&hash);
+        self.index.add_block(index as u64, &block);
+        self.template_cache = None;
+        block.seal();
+        self.chain.push(block);
+        self.confirmations.update(&self.chain);
+        self.publish_tip();
+        Ok(())
+    }
+
+    // the state and header mmr at the tip, as replaying the blocks builds
+    // them. without `check_signatures` the blocks are trusted to be signed
+    // by their senders, they were checked before this node wrote them
+    pub(crate) fn replay_chain(&self, check_signatures: bool) -> Result<(State, MerkleMountainRange, BalanceIndex), ValidationError> {
+        if self.chain.is_empty() {
+            return Err(ValidationError::EmptyChain);
+        }
+
+        // hashing and decoding don't depend on other blocks, so every block
+        // is prepared at once on all cores. executing the transactions does,
+        // that part walks the chain in order below. the difficulties only
+        // need timestamps, they are worked out first
+        let difficulties: Vec<usize> = (0..self.chain.len())
+            .map(|height| self.expected_difficulty(height as u64))
+            .collect();
+        let prepared: Vec<Result<PreparedBlock, ValidationError>> = self
+            .chain
+            .par_iter()
+            .zip(difficulties)
+            .enumerate()
+            .map(|(index, (block, difficulty))| prepare_block(block, index, difficulty, self.chain_id, check_signatures))
+            .collect();
+
+        let mut state: State = State::new();
+        let mut header_mmr: MerkleMountainRange = MerkleMountainRange::new();
+        let mut balances: BalanceIndex = BalanceIndex::new();
+        // the genesis block is not mined, it has nothing to link to
+        let mut previous_hash: Option<Hash32> = None;
+
+        for (index, (block, prepared)) in self.chain.iter().zip(prepared).enumerate() {
+            self.check_time_stamp(block, index)?;
+            // the blocks were prepared together, only the rest is per block
+            let mut profile: BlockProfile = BlockProfile::new(index as u64);
+            let hash = check_block(block, index, previous_hash.as_ref(), prepared, &mut state, &header_mmr, &mut profile)?;
+            header_mmr.push(&hash);
+            balances.add_block(state.balance_changes());
+            previous_hash = Some(hash);
+        }
+
+        Ok((state, header_mmr, balances))
+    }
+}
+
+// what can be checked about a block on its own, without the blocks before
+// it: its hash (which recomputes the merkle root of its transactions), the
+// proof of work against `difficulty`, the one the blocks before it call for,
+// and that every transaction decodes to something this node can execute and
+// was signed, for this chain, by its sender. checking the signatures is most
+// of the work, `check_signatures` skips it
+struct PreparedBlock {
+    hash: Hash32,
+    transactions: Vec<Transaction>,
+}
+
+fn prepare_block(
+    block: &Block,
+    index: usize,
+    difficulty: usize,
+    chain_id: u64,
+    check_signatures: bool,
+) -> Result<PreparedBlock, ValidationError> {
+    if block.header.version != BLOCK_VERSION {
+        return Err(ValidationError::UnsupportedVersion { index, version: block.header.version });
+    }
+    // never the cached hash, the block may have been changed since
+    let hash = block.compute_hash();
+    if block.header.difficulty != difficulty {
+        return Err(ValidationError::WrongDifficulty { index, expected: difficulty });
+    }
+    // the genesis block is not mined
+    if index > 0 && !BlockChain::meets_target(&hash, block.header.difficulty) {
+        return Err(ValidationError::InvalidProofOfWork { index });
+    }
+    // the hash only covers the transactions through the root
+    if block.header.merkle_root != block.compute_merkle_root() {
+        return Err(ValidationError::MerkleRootMismatch { index });
+    }
+
+    let mut transactions = Vec::<Transaction>::new();
+    // the state would refuse the second spend too, but only as a missing
+    // output
+    let mut spent = HashSet::<OutPoint>::new();
+    for t in block.transactions.iter() {
+        let tx: Transaction =
+            Transaction::decode(t).ok_or(ValidationError::MalformedTransaction { index })?;
+        if !State::supports(&tx.payload) {
+            return Err(ValidationError::UnsupportedPayload { index });
+        }
+        if tx.chain_id != chain_id {
+            return Err(ValidationError::WrongChain { index });
+        }
+        if genesis::is_allocation(&tx) && index > 0 {
+            return Err(ValidationError::MisplacedAllocation { index });
+        }
+        // the coinbase and the allocations pay out new coins, nobody owns
+        // their senders
+        let unsigned: bool = tx.sender_address == BlockChain::MINING_SENDER.as_bytes() || genesis::is_allocation(&tx);
+        if check_signatures && !unsigned && tx.verify().is_err() {
+            return Err(ValidationError::InvalidSignature { index });
+        }
+        for input in tx.payload.inputs() {
+            if !spent.insert(input.clone()) {
+                return Err(ValidationError::DoubleSpend { index, outpoint: input.clone() });
+            }
+        }
+        transactions.push(tx);
+    }
+
+    Ok(PreparedBlock { hash, transactions })
+}
+
+// every mined block starts with exactly one coinbase, the only
+// transaction that creates coins. it pays the mining reward of the state
+// the block starts from and nothing else: the fees of the block reach the
+// miner when the block ends, not through the coinbase
+fn check_coinbase(transactions: &[Transaction], index: usize, reward: u64) -> Result<(), ValidationError> {
+    let is_coinbase = |tx: &Transaction| tx.sender_address == BlockChain::MINING_SENDER.as_bytes();
+    let coinbase: &Transaction = transactions
+        .first()
+        .filter(|tx| is_coinbase(tx))
+        .ok_or(ValidationError::MissingCoinbase { index })?;
+    if transactions[1..].iter().any(is_coinbase) {
+        return Err(ValidationError::ExtraCoinbase { index });
+    }
+    let pays_only_the_reward: bool = coinbase.value == reward
+        && coinbase.fee == 0
+        && coinbase.gas_price == 0
+        && matches!(coinbase.payload, Payload::Transfer);
+    if !pays_only_the_reward {
+        return Err(ValidationError::InvalidCoinbase { index, expected: reward, found: coinbase.value });
+    }
+    Ok(())
+}
+
+// `state` is the state after the block at `index - 1`, whose hash is
+// `previous_hash`, and moves forward past `block`. `header_mmr` holds
+// every block before it. the stages are timed into `profile`. returns the
+// block's hash
+fn check_block(
+    block: &Block,
+    index: usize,
+    previous_hash: Option<&Hash32>,
+    prepared: Result<PreparedBlock, ValidationError>,
+    state: &mut State,
+    header_mmr: &MerkleMountainRange,
+    profile: &mut BlockProfile,
+) -> Result<Hash32, ValidationError> {
+    if let Some(previous_hash) = previous_hash
+        && block.header.previous_hash != *previous_hash
+    {
+        return Err(ValidationError::BrokenLink { index });
+    }
+    let prepared: PreparedBlock = prepared?;
+    // the genesis block is not mined, its coins are the allocations
+    if index > 0 {
+        check_coinbase(&prepared.transactions, index, state.parameters().mining_reward)?;
+    }
+
+    let receipts: Vec<Receipt> = profile.time(Stage::Execute, || {
+        let mut receipts = Vec::<Receipt>::new();
+        for tx in prepared.transactions.iter() {
+            let receipt = state
+                .apply_transaction(tx, index as u64)
+                .map_err(|error| ValidationError::InvalidTransaction { index, error })?;
+            receipts.push(receipt);
+        }
+        state
+            .end_block(index as u64, block.miner().as_deref())
+            .map_err(|error| ValidationError::InvalidFees { index, error })?;
+        Ok(receipts)
+    })?;
+
+    profile.time(Stage::Commit, || {
+        if state.root() != block.header.state_root {
+            return Err(ValidationError::StateRootMismatch { index });
+        }
+        if receipt::logs_bloom(&receipts) != block.header.logs_bloom {
+            return Err(ValidationError::LogsBloomMismatch { index });
+        }
+        if receipt::receipts_root(&block.txids(), &receipts) != block.header.receipts_root {
+            return Err(ValidationError::ReceiptsRootMismatch { index });
+        }
+        if block.header.mmr_root != header_mmr.root() {
+            return Err(ValidationError::MmrRootMismatch { index });
+        }
+        Ok(())
+    })?;
+
+    Ok(prepared.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::mempool::MempoolError;
+    use crate::blockchain::test_utils::test_wallet;
+    use crate::blockchain::transaction::DEFAULT_CHAIN_ID;
+    use crate::blockchain::Serialization;
+
+    #[test]
+    fn transactions_signed_for_another_chain_are_refused() {
+        let testnet_id: u64 = DEFAULT_CHAIN_ID + 1;
+        let mut testnet = BlockChain::with_chain_id("miner".into(), 0, testnet_id);
+        let mainnet = BlockChain::with_difficulty("miner".into(), 0);
+
+        // A has no coins, it sends nothing
+        let wallet = test_wallet("A");
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 0);
+        assert_eq!(
+            testnet.add_transaction(&tx.clone().sign(&wallet)),
+            Err(MempoolError::WrongChain { expected: testnet_id, found: DEFAULT_CHAIN_ID })
+        );
+        testnet.add_transaction(&tx.with_chain_id(testnet_id).sign(&wallet)).unwrap();
+        testnet.mining().unwrap();
+        assert_eq!(testnet.validate_chain(), Ok(()));
+
+        // a testnet block replayed on the main chain, relinked so only its
+        // transactions are wrong
+        let mut block: Block = testnet.last_block().unwrap().clone();
+        block.header.previous_hash = mainnet.last_block().unwrap().hash();
+        assert_eq!(mainnet.validate_block(&block), Err(ValidationError::WrongChain { index: 2 }));
+    }
+
+    #[test]
+    fn a_block_mints_exactly_one_reward() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        let mut peer = BlockChain::empty("peer".into(), chain.difficulty, chain.chain_id());
+        peer.chain = chain.blocks().to_vec();
+        peer.reindex().unwrap();
+        peer.mining().unwrap();
+        // the peer's block with an unsigned coinbase of its own added
+        let minted = Transaction::new(BlockChain::MINING_SENDER.into(), b"peer".to_vec(), 1_000_000_000).with_nonce(5);
+        let mut block: Block = peer.last_block().unwrap().clone();
+        block.transactions.push(minted.serialization());
+        block.seal_transactions();
+        let tip: usize = chain.blocks().len();
+        assert_eq!(chain.validate_block(&block), Err(ValidationError::ExtraCoinbase { index: tip }));
+
+        // only the one paying the reward, first
+        let reward: u64 = chain.state().parameters().mining_reward;
+        let coinbase: Transaction = Transaction::new(BlockChain::MINING_SENDER.into(), b"peer".to_vec(), reward);
+        let check = |transactions: &[Transaction]| check_coinbase(transactions, 2, reward);
+        let too_much = ValidationError::InvalidCoinbase { index: 2, expected: reward, found: 1_000_000_000 };
+        assert_eq!(check(&[minted]), Err(too_much));
+        assert!(check(&[coinbase.clone().with_fee(5)]).is_err());
+        assert_eq!(check(&[]), Err(ValidationError::MissingCoinbase { index: 2 }));
+        let payment = Transaction::new(b"A".to_vec(), b"B".to_vec(), 1);
+        assert_eq!(check(&[payment, coinbase.clone()]), Err(ValidationError::MissingCoinbase { index: 2 }));
+        assert_eq!(check(&[coinbase]), Ok(()));
+        chain.mining().unwrap();
+        assert_eq!(chain.validate_chain(), Ok(()));
+    }
+
+    #[test]
+    fn transactions_have_to_match_the_merkle_root() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        let tip = chain.chain.len() - 1;
+        let hash = chain.chain[tip].hash();
+
+        // the hash only sees the root, dropping a transaction keeps it
+        chain.chain[tip].transactions.pop();
+        assert_eq!(chain.chain[tip].hash(), hash);
+        assert_eq!(chain.validate_chain(), Err(ValidationError::MerkleRootMismatch { index: tip }));
+    }
+
+    #[test]
+    fn a_spend_of_a_missing_output_invalidates_the_block() {
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        let wallet = test_wallet("A");
+        let missing = OutPoint { txid: vec![7; 32], index: 0 };
+        let spend = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 0)
+            .with_payload(Payload::Spend { inputs: vec![missing], outputs: Vec::new() })
+            .sign(&wallet);
+        assert_eq!(chain.add_transaction(&spend), Err(MempoolError::InvalidPayload(StateError::MissingOutput)));
+
+        // a miner that let it in anyway made a block nobody accepts, not a
+        // failed receipt that still charges the sender
+        let tip: usize = chain.blocks().len() - 1;
+        chain.chain[tip].transactions.push(spend.serialization());
+        chain.chain[tip].seal_transactions();
+        assert_eq!(
+            chain.validate_chain(),
+            Err(ValidationError::InvalidTransaction { index: tip, error: StateError::MissingOutput })
+        );
+    }
+}
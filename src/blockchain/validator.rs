@@ -0,0 +1,387 @@
+use crate::blockchain::mempool::{fee_rate, MempoolError};
+use crate::blockchain::script::Script;
+use crate::blockchain::state::StateError;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::BlockChain;
+use std::collections::HashSet;
+use std::fmt;
+
+// what a validator gets to look at besides the transaction. the chain is
+// as it is before the transaction joins the pool
+pub struct TxContext<'a> {
+    pub chain: &'a BlockChain,
+    // the height of the block the transaction would go into
+    pub height: u64,
+    // the miner's own reward, nobody signs it
+    pub coinbase: bool,
+}
+
+// one rule a transaction has to pass to get into the pool. the node runs
+// the built in ones below, anything else (a blacklist, a kyc list, a
+// minimum fee) is one more validator added with
+// `BlockChain::add_tx_validator`, no need to change the crate for it.
+// rules that aren't about the transaction being valid should refuse it
+// with `MempoolError::Rejected`
+pub trait TxValidator: Send + Sync {
+    // shows up in rejections and the pipeline's debug output
+    fn name(&self) -> &str;
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError>;
+}
+
+// signed for this network
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChainIdCheck;
+
+impl TxValidator for ChainIdCheck {
+    fn name(&self) -> &str {
+        "chain-id"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        let expected = context.chain.chain_id();
+        if tx.chain_id != expected {
+            return Err(MempoolError::WrongChain { expected, found: tx.chain_id });
+        }
+        Ok(())
+    }
+}
+
+// signed by the sender
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignatureCheck;
+
+impl TxValidator for SignatureCheck {
+    fn name(&self) -> &str {
+        "signature"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        if context.coinbase {
+            return Ok(());
+        }
+        tx.verify().map_err(MempoolError::InvalidSignature)
+    }
+}
+
+// the sender's next nonce, or the nonce of one of its pooled transactions
+// it replaces
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NonceCheck;
+
+impl TxValidator for NonceCheck {
+    fn name(&self) -> &str {
+        "nonce"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        if context.coinbase {
+            return Ok(());
+        }
+        let mined: u64 = context.chain.state().next_nonce(&tx.sender_address);
+        if tx.nonce < mined {
+            return Err(MempoolError::NonceTooLow { expected: mined, found: tx.nonce });
+        }
+        let next: u64 = context.chain.next_nonce(&tx.sender_address);
+        if tx.nonce > next {
+            return Err(MempoolError::NonceTooHigh { expected: next, found: tx.nonce });
+        }
+        Ok(())
+    }
+}
+
+// the sender can pay for it and the payload can be applied, checked
+// against the state the next block starts from. paying for it means value,
+// fee and all the gas it may burn out of what the sender can spend then,
+// mining rewards that haven't matured don't count
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BalanceCheck;
+
+impl TxValidator for BalanceCheck {
+    fn name(&self) -> &str {
+        "balance"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        let state = context.chain.state();
+        if !context.coinbase {
+            let requested: u64 = tx
+                .gas_limit
+                .checked_mul(tx.gas_price)
+                .and_then(|gas| gas.checked_add(tx.value))
+                .and_then(|amount| amount.checked_add(tx.fee))
+                .ok_or(MempoolError::InvalidPayload(StateError::AmountOverflow))?;
+            let spendable: i64 = state.spendable_balance(&tx.sender_address, context.height);
+            if i128::from(requested) > i128::from(spendable) {
+                return Err(MempoolError::InsufficientFunds { spendable, requested });
+            }
+        }
+        state.check_payload(tx, context.height).map_err(MempoolError::InvalidPayload)
+    }
+}
+
+// the unlocking script opens the sender's locking script, and a new lock
+// the transaction sets is a script at all
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScriptCheck;
+
+impl TxValidator for ScriptCheck {
+    fn name(&self) -> &str {
+        "script"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        if !tx.locking_script.is_empty() {
+            Script::from_bytes(&tx.locking_script).map_err(MempoolError::InvalidScript)?;
+        }
+        context.chain.verify_spending_conditions(tx).map_err(MempoolError::InvalidScript)
+    }
+}
+
+// a policy rule: nothing from or to these addresses gets into this
+// node's pool. other nodes may still mine it, blocks aren't checked
+#[derive(Debug, Clone, Default)]
+pub struct Blacklist {
+    addresses: HashSet<Vec<u8>>,
+}
+
+impl Blacklist {
+    pub fn new() -> Self {
+        Blacklist::default()
+    }
+
+    pub fn with_address(mut self, address: &[u8]) -> Self {
+        self.addresses.insert(address.to_vec());
+        self
+    }
+
+    pub fn contains(&self, address: &[u8]) -> bool {
+        self.addresses.contains(address)
+    }
+}
+
+impl TxValidator for Blacklist {
+    fn name(&self) -> &str {
+        "blacklist"
+    }
+
+    fn validate(&self, tx: &Transaction, _context: &TxContext) -> Result<(), MempoolError> {
+        if self.contains(&tx.sender_address) || self.contains(&tx.recipient_address) {
+            return Err(MempoolError::Rejected {
+                validator: self.name().to_string(),
+                reason: "address is blacklisted".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// a policy rule: transactions paying less than `min_fee_rate` (per 1000
+// weight units, like `mempool::fee_rate`) aren't worth the pool space or
+// relaying. the miner's reward pays no fee and is always let through
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinFeeRate {
+    pub min_fee_rate: u64,
+}
+
+impl TxValidator for MinFeeRate {
+    fn name(&self) -> &str {
+        "min-fee-rate"
+    }
+
+    fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        let rate = fee_rate(tx);
+        if !context.coinbase && rate < self.min_fee_rate {
+            return Err(MempoolError::Rejected {
+                validator: self.name().to_string(),
+                reason: format!("fee rate {} is below {}", rate, self.min_fee_rate),
+            });
+        }
+        Ok(())
+    }
+}
+
+// the validators a transaction goes through, in order. the first one
+// to refuse it decides the error, the cheap checks come first
+pub struct TxPipeline {
+    validators: Vec<Box<dyn TxValidator>>,
+}
+
+impl Default for TxPipeline {
+    // the checks every node needs: chain id, signature, nonce, balance,
+    // scripts
+    fn default() -> Self {
+        TxPipeline {
+            validators: vec![
+                Box::new(ChainIdCheck),
+                Box::new(SignatureCheck),
+                Box::new(NonceCheck),
+                Box::new(BalanceCheck),
+                Box::new(ScriptCheck),
+            ],
+        }
+    }
+}
+
+impl fmt::Debug for TxPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl TxPipeline {
+    // no checks at all, for building a pipeline from scratch
+    pub fn empty() -> Self {
+        TxPipeline { validators: Vec::new() }
+    }
+
+    // runs after the ones already there
+    pub fn push(&mut self, validator: impl TxValidator + 'static) {
+        self.validators.push(Box::new(validator));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.validators.iter().map(|v| v.name()).collect()
+    }
+
+    pub fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+        for validator in &self.validators {
+            validator.validate(tx, context)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockChain {
+    // the rule runs on every transaction offered to the pool from now on,
+    // after the ones already there
+    pub fn add_tx_validator(&mut self, validator: impl TxValidator + 'static) {
+        self.tx_pipeline.push(validator);
+    }
+
+    // replaces the built in checks too, a pipeline without them lets
+    // invalid transactions in and they only fail when a block is mined
+    pub fn set_tx_pipeline(&mut self, pipeline: TxPipeline) {
+        self.tx_pipeline = pipeline;
+    }
+
+    pub fn tx_pipeline(&self) -> &TxPipeline {
+        &self.tx_pipeline
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::{miner, test_wallet};
+    use crate::blockchain::validation::ValidationError;
+    use crate::blockchain::Serialization;
+
+    // a demo kyc rule: only addresses someone has vouched for may send
+    struct Kyc {
+        verified: HashSet<Vec<u8>>,
+    }
+
+    impl TxValidator for Kyc {
+        fn name(&self) -> &str {
+            "kyc"
+        }
+
+        fn validate(&self, tx: &Transaction, context: &TxContext) -> Result<(), MempoolError> {
+            if context.coinbase || self.verified.contains(&tx.sender_address) {
+                return Ok(());
+            }
+            Err(MempoolError::Rejected {
+                validator: self.name().to_string(),
+                reason: "sender has not been verified".to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn custom_rules_run_after_the_built_in_checks() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let stranger = test_wallet("stranger").address().into_bytes();
+        chain.add_tx_validator(Blacklist::new().with_address(&stranger));
+        chain.add_tx_validator(Kyc {
+            verified: HashSet::from([test_wallet("account 0").address().into_bytes()]),
+        });
+        assert_eq!(chain.tx_pipeline().names(), vec!["chain-id", "signature", "nonce", "balance", "script", "blacklist", "kyc"]);
+
+        let to_stranger = Transaction::new(wallet.address().into_bytes(), stranger, 1).sign(&wallet);
+        assert_eq!(
+            chain.add_transaction(&to_stranger),
+            Err(MempoolError::Rejected {
+                validator: "blacklist".to_string(),
+                reason: "address is blacklisted".to_string()
+            })
+        );
+        // an unsigned transaction fails the signature check before kyc sees it
+        let tx = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1);
+        assert!(matches!(chain.add_transaction(&tx), Err(MempoolError::InvalidSignature(_))));
+        assert!(matches!(
+            chain.add_transaction(&tx.sign(&wallet)),
+            Err(MempoolError::Rejected { validator, .. }) if validator == "kyc"
+        ));
+        assert!(chain.pending_transactions().is_empty());
+
+        // the coinbase still gets through
+        chain.mining().unwrap();
+    }
+
+    #[test]
+    fn the_pool_refuses_what_the_sender_cannot_pay_for() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 3 {
+            chain.mining().unwrap();
+        }
+        let me: Vec<u8> = wallet.address().into_bytes();
+        let height: u64 = chain.blocks().len() as u64;
+        let spendable: i64 = chain.state().spendable_balance(&me, height);
+        // more than that is on the balance, the rest hasn't matured
+        assert!(chain.state().balance(&me) > spendable);
+
+        let value = spendable as u64;
+        let tx = Transaction::new(me.clone(), b"B".to_vec(), value).with_fee(1).sign(&wallet);
+        assert_eq!(
+            chain.add_transaction(&tx),
+            Err(MempoolError::InsufficientFunds { spendable, requested: value + 1 })
+        );
+        let huge = Transaction::new(me.clone(), b"B".to_vec(), 1).with_gas(2, u64::MAX).sign(&wallet);
+        assert_eq!(chain.add_transaction(&huge), Err(MempoolError::InvalidPayload(StateError::AmountOverflow)));
+        let tx = Transaction::new(me, b"B".to_vec(), value - 1).with_fee(1).sign(&wallet);
+        assert!(chain.add_transaction(&tx).is_ok());
+    }
+
+    #[test]
+    fn a_signed_transaction_is_only_accepted_once() {
+        let wallet = miner();
+        let mut chain = BlockChain::with_difficulty(wallet.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY + 3 {
+            chain.mining().unwrap();
+        }
+        let me: Vec<u8> = wallet.address().into_bytes();
+        let tx = Transaction::new(me.clone(), b"B".to_vec(), 1).sign(&wallet);
+        chain.add_transaction(&tx).unwrap();
+        assert_eq!(chain.next_nonce(&me), 1);
+        // a gap after the pooled transaction
+        let ahead = Transaction::new(me.clone(), b"B".to_vec(), 1).with_nonce(2).sign(&wallet);
+        assert_eq!(chain.add_transaction(&ahead), Err(MempoolError::NonceTooHigh { expected: 1, found: 2 }));
+        chain.mining().unwrap();
+
+        // once mined, the same bytes are a replay
+        assert_eq!(chain.add_transaction(&tx), Err(MempoolError::NonceTooLow { expected: 1, found: 0 }));
+        let tip = chain.blocks().len() - 1;
+        chain.chain[tip].transactions.push(tx.serialization());
+        chain.chain[tip].seal_transactions();
+        assert!(matches!(
+            chain.validate_chain(),
+            Err(ValidationError::InvalidTransaction { index, error: StateError::WrongNonce { expected: 1, found: 0 } }) if index == tip
+        ));
+    }
+}
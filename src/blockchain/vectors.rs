@@ -0,0 +1,178 @@
+use crate::blockchain::bloom::Bloom;
+use crate::blockchain::genesis::GenesisConfig;
+use crate::blockchain::hash32::Hash32;
+use crate::blockchain::merkle;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::wallet::Wallet;
+use crate::blockchain::{BlockChain, BlockHeader, Serialization, BLOCK_VERSION};
+
+// golden vectors: fixed inputs and the bytes and hashes this node makes of
+// them. they are what every node has to agree on, a change to any of them
+// is a fork, not a refactor. the tests pin them, so a change that moves
+// one fails until the vector is updated on purpose (with a new block
+// version or chain id). another implementation can check itself against
+// the same values, everything is hex
+
+// the key the vector transaction is signed with. ed25519 signatures are
+// deterministic, the same key and message always give the same signature
+pub const VECTOR_SECRET: [u8; 32] = [7u8; 32];
+
+// a header with every field set to something that isn't zero, so a field
+// left out or moved shows up in the bytes
+pub fn vector_header() -> BlockHeader {
+    let mut logs_bloom = Bloom::default();
+    logs_bloom.accrue(b"vector");
+    BlockHeader {
+        version: BLOCK_VERSION,
+        nonce: 42,
+        previous_hash: Hash32::digest(b"previous"),
+        time_stamp: 1_704_067_200_000_000_000,
+        difficulty: 3,
+        merkle_root: Hash32::digest(b"transactions"),
+        state_root: Hash32::digest(b"state"),
+        logs_bloom,
+        receipts_root: Hash32::digest(b"receipts"),
+        mmr_root: Hash32::digest(b"mountains"),
+    }
+}
+
+pub fn vector_wallet() -> Wallet {
+    Wallet::from_secret(VECTOR_SECRET)
+}
+
+// a transfer of 1000 with a fee of 10 and nonce 3 on the default network,
+// to the account "recipient", signed by the vector wallet
+pub fn vector_transaction() -> Transaction {
+    let wallet: Wallet = vector_wallet();
+    Transaction::new(wallet.address().into_bytes(), b"recipient".to_vec(), 1_000)
+        .with_fee(10)
+        .with_nonce(3)
+        .sign(&wallet)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vector {
+    pub name: &'static str,
+    // hex, or the address as it is written
+    pub expected: &'static str,
+}
+
+pub const VECTORS: &[Vector] = &[
+    // vector_header's bytes, as the block file and peers get them
+    Vector {
+        name: "header-bytes",
+        expected: concat!(
+            // version
+            "00000001",
+            // nonce
+            "0000002a",
+            // previous hash
+            "6da0633528deaa0144e7b058315f0b753ec0b945163a72bf96a0d18180f9de0d",
+            // time stamp
+            "000000000000000017a6101701650000",
+            // difficulty
+            "0000000000000003",
+            // merkle root
+            "81dc075c3d55230215300137991a25f90be4c243a55580fe2af7538774147bd6",
+            // state root
+            "4ba69735ca53765ed6a709edb56c6ea236b7193a3b29a6b390c346f0f4340e4e",
+            // logs bloom
+            "00000000000000000000000000000000000000000000000200002000000000000000000000000000000000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+            "00000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+            // receipts root
+            "3619a1d05b1fe41a17aeede95dca3b2075c283281e17af896b2116f207ee3495",
+            // mmr root
+            "5e23d5356b6462d0b2362c287dfdfc96244680f91b9a4c463fe1f72fc17ff0ec",
+        ),
+    },
+    // the block hash is the sha256 of those
+    Vector { name: "header-hash", expected: "e1b0b44344e8aefd53a4603049931305fdbfdbed30e5a79994805ab189c2f8ef" },
+    // the default network's genesis block, every node builds it itself
+    Vector { name: "genesis-hash", expected: "3fb09fc4d3fe2474599a049be6f92c697f32359fd34453d20339263de11e7c4f" },
+    // base58check of the vector wallet's public key hash
+    Vector { name: "address", expected: "yMvvDAfUZBKkmiRjzHrmMsdH2JhCje1cgcWugwvRnQ8dNkRH4T" },
+    // the signed vector transaction as a block stores it
+    Vector {
+        name: "transaction-bytes",
+        expected: concat!(
+            "0000000000000032794d7676444166555a424b6b6d69526a7a48726d4d736448324a68436a6531636763577567777652",
+            "6e5138644e6b524834540000000000000009726563697069656e74000000000000000800000000000003e80000000000",
+            "000008000000000000000a00000000000000080000000000000003000000000000000000000000000000000000000000",
+            "000000000000000000000000000000000000000100000000000000000000000000000020ea4a6c63e29c520abef5507b",
+            "132ec5f9954776aebebe7b92421eea691446d22c0000000000000040e87828b8c400e10cc3daf3c8afdff041ee1e6692",
+            "0cb2cc549b8ad891809f2351006fe5a58b758f1db735f0ccb2f8d55ea515b7c64440863832abf6369e1ad20b00",
+        ),
+    },
+    // what the vector wallet signs: the sha256 of the transaction without
+    // its signature and unlocking script
+    Vector { name: "signature-hash", expected: "3d0e1ca2aefd44a877ebbb4cede986561b89792152665e7f66ea68fda4f73feb" },
+    // the vector wallet's ed25519 signature over it
+    Vector {
+        name: "signature",
+        expected: concat!(
+            "e87828b8c400e10cc3daf3c8afdff041ee1e66920cb2cc549b8ad891809f2351",
+            "006fe5a58b758f1db735f0ccb2f8d55ea515b7c64440863832abf6369e1ad20b",
+        ),
+    },
+    // the sha256 of the transaction's bytes
+    Vector { name: "txid", expected: "62b8b6341722259ac2c20c50900578d706bd5d3da958c0ab67602cde5c8689ff" },
+    // over the vector transaction's txid and the sha256 of "coinbase",
+    // leaves and nodes hashed with their own prefixes
+    Vector { name: "merkle-root", expected: "b73f397e439230efc4fec81ecffc3a3233870f05d1df23d3a1e08c4605c81c79" },
+];
+
+// what this build makes of the vector's inputs, none for a name that isn't
+// one of VECTORS
+pub fn compute(name: &str) -> Option<String> {
+    let tx: Transaction = vector_transaction();
+    let value: String = match name {
+        "header-bytes" => hex::encode(vector_header().serialization()),
+        "header-hash" => vector_header().hash().to_string(),
+        "genesis-hash" => BlockChain::from_genesis(&GenesisConfig::new()).blocks()[0].hash().to_string(),
+        "address" => vector_wallet().address().to_string(),
+        "transaction-bytes" => hex::encode(tx.serialization()),
+        "signature-hash" => hex::encode(tx.signature_hash()),
+        "signature" => hex::encode(&tx.signature),
+        "txid" => hex::encode(tx.id()),
+        "merkle-root" => merkle::merkle_root(&[tx.id(), merkle::txid(b"coinbase")]).to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// the vectors this build doesn't reproduce, with what it makes instead
+pub fn mismatches() -> Vec<(Vector, String)> {
+    VECTORS
+        .iter()
+        .filter_map(|vector| {
+            let actual: String = compute(vector.name).unwrap_or_default();
+            (actual != vector.expected).then_some((*vector, actual))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consensus_outputs_match_the_golden_vectors() {
+        let changed: Vec<String> = mismatches()
+            .iter()
+            .map(|(vector, actual)| format!("{} changed, expected {} but got {}", vector.name, vector.expected, actual))
+            .collect();
+        assert!(changed.is_empty(), "{}", changed.join("\n"));
+    }
+
+    #[test]
+    fn the_vector_inputs_are_what_they_say() {
+        let header: BlockHeader = vector_header();
+        assert_eq!(BlockHeader::deserialization(&header.serialization()), Ok(header));
+        let tx: Transaction = vector_transaction();
+        assert_eq!(tx.verify(), Ok(()));
+        assert_eq!(Transaction::deserialization(&tx.serialization()), Ok(tx));
+        assert_eq!(compute("nothing"), None);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::blockchain::difficulty::Retarget;
+use crate::blockchain::error_code::{ErrorCode, HasErrorCode};
+use crate::blockchain::json::json_string;
+use crate::blockchain::storage::{self, StorageError};
+use crate::blockchain::transaction::DEFAULT_CHAIN_ID;
+use crate::blockchain::BlockChain;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// what the chain in the file was mined with, it isn't stored in the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifyOptions {
+    pub difficulty: usize,
+    pub chain_id: u64,
+    pub retarget: Option<Retarget>,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        VerifyOptions {
+            difficulty: BlockChain::DIFFICULTY,
+            chain_id: DEFAULT_CHAIN_ID,
+            retarget: Some(Retarget::default()),
+        }
+    }
+}
+
+// the outcome of checking a chain file, meant for scripts: `to_json` is one
+// line and `valid` decides the exit code
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    pub valid: bool,
+    pub blocks: usize,
+    pub transactions: usize,
+    // hex, empty when the file couldn't be read
+    pub tip: String,
+    pub state_root: String,
+    pub error: Option<(ErrorCode, String)>,
+    pub elapsed: Duration,
+}
+
+impl VerifyReport {
+    pub fn to_json(&self) -> String {
+        let (code, error) = match &self.error {
+            Some((code, message)) => (json_string(code.as_str()), json_string(message)),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        format!(
+            "{{\"valid\":{},\"blocks\":{},\"transactions\":{},\"tip\":{},\"state_root\":{},\"code\":{},\"error\":{},\"elapsed_ms\":{}}}",
+            self.valid,
+            self.blocks,
+            self.transactions,
+            json_string(&self.tip),
+            json_string(&self.state_root),
+            code,
+            error,
+            self.elapsed.as_millis()
+        )
+    }
+}
+
+// reads the block file at `path` and does everything a node does with a
+// chain before trusting it: every block is replayed from an empty state and
+// checked against its commitments, then the indexes are rebuilt. nothing is
+// mined and no peer is contacted
+pub fn verify_file(path: &Path, options: VerifyOptions) -> VerifyReport {
+    let started = Instant::now();
+    let mut report = VerifyReport {
+        valid: false,
+        blocks: 0,
+        transactions: 0,
+        tip: String::new(),
+        state_root: String::new(),
+        error: None,
+        elapsed: Duration::ZERO,
+    };
+
+    let blocks = match storage::read_blocks(path) {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            report.error = Some((e.code(), e.to_string()));
+            report.elapsed = started.elapsed();
+            return report;
+        }
+    };
+    report.blocks = blocks.len();
+    report.transactions = blocks.iter().map(|block| block.transactions.len()).sum();
+    if let Some(tip) = blocks.last() {
+        report.tip = tip.compute_hash().to_string();
+    }
+
+    let mut chain = BlockChain::empty(String::new(), options.difficulty, options.chain_id);
+    chain.set_retarget(options.retarget);
+    chain.chain = blocks;
+    match chain.reindex() {
+        Ok(()) => {
+            report.valid = true;
+            report.state_root = hex::encode(chain.state().root());
+        }
+        Err(e) => {
+            let e = StorageError::Invalid(e);
+            report.error = Some((e.code(), e.to_string()));
+        }
+    }
+    report.elapsed = started.elapsed();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::hash32::Hash32;
+    use std::fs;
+
+    #[test]
+    fn an_exported_chain_verifies_and_a_changed_one_does_not() {
+        let path = std::env::temp_dir().join(format!("blockchain-verify-{}.dat", std::process::id()));
+        let mut chain = BlockChain::with_difficulty("miner".into(), 0);
+        chain.mining().unwrap();
+        chain.export(&path).unwrap();
+
+        let options = VerifyOptions { difficulty: 0, retarget: None, ..VerifyOptions::default() };
+        let report = verify_file(&path, options);
+        assert!(report.valid, "{}", report.to_json());
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.state_root, hex::encode(chain.state().root()));
+        assert!(report.to_json().starts_with("{\"valid\":true,\"blocks\":3,\"transactions\":2,"));
+
+        // a file edited by hand
+        chain.chain[1].header.state_root = Hash32::ZERO;
+        chain.export(&path).unwrap();
+        let report = verify_file(&path, options);
+        assert!(!report.valid);
+        assert!(report.to_json().contains("\"valid\":false"));
+        assert_eq!(report.error.map(|(code, _)| code), Some(ErrorCode::InvalidBlock));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
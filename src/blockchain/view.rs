@@ -0,0 +1,97 @@
+use crate::blockchain::index::{ChainIndex, TxLocation};
+use crate::blockchain::state::State;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::{Block, BlockChain};
+
+// a copy of the chain as it was at one height. a query made of several
+// reads (a wallet's whole history next to its balance, say) gets answers
+// that agree with each other, even when the chain is shared with a miner
+// that appends blocks between the reads
+#[derive(Debug, Clone)]
+pub struct ReadView {
+    blocks: Vec<Block>,
+    state: State,
+    index: ChainIndex,
+}
+
+impl ReadView {
+    // the height every answer is pinned to
+    pub fn height(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    pub fn tip(&self) -> &Block {
+        &self.blocks[self.blocks.len() - 1]
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    pub fn block(&self, height: u64) -> Option<&Block> {
+        self.blocks.get(height as usize)
+    }
+
+    // the state after the tip block
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn balance(&self, address: &[u8]) -> i64 {
+        self.state.balance(address)
+    }
+
+    pub fn find_transaction(&self, txid: &[u8]) -> Option<(&Block, TxLocation)> {
+        let location = self.index.transaction(txid)?;
+        Some((&self.blocks[location.height as usize], location))
+    }
+
+    // every transaction the address sent or received, oldest first
+    pub fn history(&self, address: &[u8]) -> Vec<(TxLocation, Transaction)> {
+        self.index
+            .address_history(address)
+            .iter()
+            .filter_map(|location| {
+                let block = &self.blocks[location.height as usize];
+                Some((*location, Transaction::decode(&block.transactions[location.index])?))
+            })
+            .collect()
+    }
+}
+
+impl BlockChain {
+    // copies what the view needs, so the chain (and any lock around it) can
+    // be let go of right after. pending transactions are not part of it
+    pub fn read_view(&self) -> ReadView {
+        ReadView {
+            blocks: self.chain.clone(),
+            state: self.state.clone(),
+            index: self.index.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn a_view_does_not_see_blocks_appended_after_it() {
+        let chain = Mutex::new(BlockChain::with_difficulty("miner".into(), 0));
+        chain.lock().unwrap().mining().unwrap();
+        let view: ReadView = chain.lock().unwrap().read_view();
+        let height: u64 = view.height();
+        let history = view.history(b"miner");
+
+        chain.lock().unwrap().mining().unwrap();
+        chain.lock().unwrap().mining().unwrap();
+
+        assert_eq!(chain.lock().unwrap().blocks().len() as u64, height + 3);
+        assert_eq!(view.height(), height);
+        assert_eq!(view.history(b"miner").len(), history.len());
+        assert!(history.iter().all(|(location, _)| location.height <= view.height()));
+        let reward: i64 = history.iter().map(|(_, tx)| tx.value as i64).sum();
+        assert_eq!(view.balance(b"miner"), reward);
+    }
+}
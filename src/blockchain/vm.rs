@@ -0,0 +1,374 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::blockchain::gas::{self, GasMeter};
+use crate::blockchain::precompile;
+use crate::blockchain::receipt::Log;
+use wasmi::core::TrapCode;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store};
+
+// contracts talk to the chain only through these host functions, there is
+// nothing like time or randomness exposed so every node computes the same
+const HOST_MODULE: &str = "env";
+
+// topics are fixed size so they can be compared and put in the bloom
+pub const LOG_TOPIC_SIZE: usize = 32;
+pub const MAX_LOG_TOPICS: usize = 4;
+
+#[derive(Debug, PartialEq)]
+pub enum VmError {
+    InvalidModule(String),
+    AlreadyDeployed,
+    UnknownContract,
+    MissingExport(&'static str),
+    Trap(String),
+    OutOfGas,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::InvalidModule(e) => write!(f, "invalid contract module: {}", e),
+            VmError::AlreadyDeployed => write!(f, "a contract is already deployed at this address"),
+            VmError::UnknownContract => write!(f, "no contract deployed at this address"),
+            VmError::MissingExport(name) => write!(f, "contract does not export `{}`", name),
+            VmError::Trap(e) => write!(f, "contract execution failed: {}", e),
+            VmError::OutOfGas => write!(f, "contract ran out of gas"),
+        }
+    }
+}
+
+// what a running contract can see and touch
+struct HostState {
+    // the contract being run, logs are emitted under it
+    address: Vec<u8>,
+    input: Vec<u8>,
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    logs: Vec<Log>,
+}
+
+// what a successful execution leaves behind
+#[derive(Debug)]
+pub struct Execution {
+    pub storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub logs: Vec<Log>,
+}
+
+// the address of a contract only depends on who deployed it and with
+// which nonce, so it is known before the deploy transaction is mined
+pub fn contract_address(deployer: &[u8], nonce: u64) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(deployer);
+    hasher.update(nonce.to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+// contracts may export `init`, it runs once with the deploy input and
+// returns the initial storage of the contract
+pub fn deploy(
+    address: &[u8],
+    code: &[u8],
+    input: Vec<u8>,
+    meter: &mut GasMeter,
+) -> Result<Execution, VmError> {
+    execute(address, code, "init", input, BTreeMap::new(), true, meter)
+}
+
+// runs the `call` export against a copy of the storage, the caller only
+// writes it back if the execution finished without trapping
+pub fn call(
+    address: &[u8],
+    code: &[u8],
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    input: Vec<u8>,
+    meter: &mut GasMeter,
+) -> Result<Execution, VmError> {
+    execute(address, code, "call", input, storage, false, meter)
+}
+
+fn engine() -> Engine {
+    let mut config = Config::default();
+    // floats are the classic source of cross-platform differences
+    config.floats(false);
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+fn execute(
+    address: &[u8],
+    code: &[u8],
+    entry: &'static str,
+    input: Vec<u8>,
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    optional_entry: bool,
+    meter: &mut GasMeter,
+) -> Result<Execution, VmError> {
+    let engine = engine();
+    let module = Module::new(&engine, code).map_err(|e| VmError::InvalidModule(e.to_string()))?;
+
+    // one unit of fuel per unit of gas, whatever is left after the
+    // execution goes back to the meter
+    let fuel = meter.remaining();
+    let host = HostState {
+        address: address.to_vec(),
+        input,
+        storage,
+        logs: Vec::new(),
+    };
+    let mut store = Store::new(&engine, host);
+    store.set_fuel(fuel).map_err(|e| VmError::Trap(e.to_string()))?;
+
+    let mut linker = <Linker<HostState>>::new(&engine);
+    link_host_functions(&mut linker).map_err(|e| VmError::InvalidModule(e.to_string()))?;
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|pre| pre.start(&mut store))
+        .map_err(|e| VmError::InvalidModule(e.to_string()))?;
+
+    let result = match instance.get_typed_func::<(), ()>(&store, entry) {
+        Ok(func) => func.call(&mut store, ()),
+        Err(_) if optional_entry => Ok(()),
+        Err(_) => return Err(VmError::MissingExport(entry)),
+    };
+
+    let consumed = fuel - store.get_fuel().unwrap_or(0);
+    if meter.charge(consumed).is_err() {
+        return Err(VmError::OutOfGas);
+    }
+
+    match result {
+        Ok(()) => {
+            let host = store.into_data();
+            Ok(Execution {
+                storage: host.storage,
+                logs: host.logs,
+            })
+        }
+        // wasmi can stop with a little fuel left that didn't cover the next
+        // instruction, running out burns the whole limit either way
+        Err(e) if e.as_trap_code() == Some(TrapCode::OutOfFuel) => {
+            let _ = meter.charge(meter.remaining() + 1);
+            Err(VmError::OutOfGas)
+        }
+        Err(e) => Err(VmError::Trap(e.to_string())),
+    }
+}
+
+fn memory(caller: &Caller<'_, HostState>) -> Result<Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("contract does not export memory"))
+}
+
+// the range is checked against the memory before anything is copied, a
+// contract can't make the node allocate more than it has: a negative
+// length would be a 4 GiB buffer
+fn read_bytes(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+    let len = usize::try_from(len).map_err(|_| wasmi::Error::new("negative length"))?;
+    let start = ptr as u32 as usize;
+    memory(caller)?
+        .data(caller)
+        .get(start..start.saturating_add(len))
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| wasmi::Error::new("out of the contract's memory bounds"))
+}
+
+fn write_bytes(caller: &mut Caller<'_, HostState>, ptr: i32, bytes: &[u8]) -> Result<(), wasmi::Error> {
+    memory(caller)?
+        .write(caller, ptr as u32 as usize, bytes)
+        .map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+// host functions doing more than a few instructions worth of work pay
+// for it out of the same fuel
+fn charge_fuel(caller: &mut Caller<'_, HostState>, cost: u64) -> Result<(), wasmi::Error> {
+    let fuel = caller.get_fuel().map_err(|e| wasmi::Error::new(e.to_string()))?;
+    if cost > fuel {
+        return Err(TrapCode::OutOfFuel.into());
+    }
+    caller
+        .set_fuel(fuel - cost)
+        .map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+fn link_host_functions(linker: &mut Linker<HostState>) -> Result<(), wasmi::errors::LinkerError> {
+    linker.func_wrap(HOST_MODULE, "input_len", |caller: Caller<'_, HostState>| -> i32 {
+        caller.data().input.len() as i32
+    })?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "input_read",
+        |mut caller: Caller<'_, HostState>, ptr: i32| -> Result<(), wasmi::Error> {
+            let input = caller.data().input.clone();
+            write_bytes(&mut caller, ptr, &input)
+        },
+    )?;
+
+    // returns the length of the value, or -1 if the key is not set, at most
+    // `capacity` bytes are copied to `out_ptr`
+    linker.func_wrap(
+        HOST_MODULE,
+        "storage_read",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, out_ptr: i32, capacity: i32| -> Result<i32, wasmi::Error> {
+            let key = read_bytes(&caller, key_ptr, key_len)?;
+            let Some(value) = caller.data().storage.get(&key).cloned() else {
+                return Ok(-1);
+            };
+
+            let copied = value.len().min(capacity.max(0) as usize);
+            write_bytes(&mut caller, out_ptr, &value[..copied])?;
+            Ok(value.len() as i32)
+        },
+    )?;
+
+    linker.func_wrap(
+        HOST_MODULE,
+        "storage_write",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32| -> Result<(), wasmi::Error> {
+            let key = read_bytes(&caller, key_ptr, key_len)?;
+            let value = read_bytes(&caller, value_ptr, value_len)?;
+            // every node keeps what is written, the key and the value are
+            // paid per byte
+            let cost = gas::STORAGE_GAS_PER_BYTE.saturating_mul((key.len() + value.len()) as u64);
+            charge_fuel(&mut caller, cost)?;
+            caller.data_mut().storage.insert(key, value);
+            Ok(())
+        },
+    )?;
+
+    // `topic_count` topics of LOG_TOPIC_SIZE bytes each, back to back at
+    // `topics_ptr`
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        |mut caller: Caller<'_, HostState>, topics_ptr: i32, topic_count: i32, data_ptr: i32, data_len: i32| -> Result<(), wasmi::Error> {
+            let topic_count = topic_count as u32 as usize;
+            if topic_count > MAX_LOG_TOPICS {
+                return Err(wasmi::Error::new("too many log topics"));
+            }
+
+            let cost = gas::LOG_GAS
+                + gas::LOG_TOPIC_GAS * topic_count as u64
+                + gas::LOG_DATA_GAS_PER_BYTE * data_len as u32 as u64;
+            charge_fuel(&mut caller, cost)?;
+
+            let topics = read_bytes(&caller, topics_ptr, (topic_count * LOG_TOPIC_SIZE) as i32)?
+                .chunks(LOG_TOPIC_SIZE)
+                .map(|topic| topic.to_vec())
+                .collect();
+            let data = read_bytes(&caller, data_ptr, data_len)?;
+
+            let address = caller.data().address.clone();
+            caller.data_mut().logs.push(Log { address, topics, data });
+            Ok(())
+        },
+    )?;
+
+    // runs a precompile natively. returns the output length or -1 if the precompile rejected the
+    // input, at most `capacity` bytes are copied to `out_ptr`
+    linker.func_wrap(
+        HOST_MODULE,
+        "precompile",
+        |mut caller: Caller<'_, HostState>, id: i32, input_ptr: i32, input_len: i32, out_ptr: i32, capacity: i32| -> Result<i32, wasmi::Error> {
+            let precompile = u8::try_from(id)
+                .ok()
+                .and_then(|id| precompile::precompile(id).ok())
+                .ok_or_else(|| wasmi::Error::new(format!("no precompile with id {}", id)))?;
+
+            let cost = precompile.gas(input_len as u32 as usize);
+            charge_fuel(&mut caller, cost)?;
+
+            let input = read_bytes(&caller, input_ptr, input_len)?;
+            let Ok(output) = precompile.run(&input) else {
+                return Ok(-1);
+            };
+
+            let copied = output.len().min(capacity.max(0) as usize);
+            write_bytes(&mut caller, out_ptr, &output[..copied])?;
+            Ok(output.len() as i32)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sleb128(mut value: i32, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            let last = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            out.push(if last { byte } else { byte | 0x80 });
+            if last {
+                return;
+            }
+        }
+    }
+
+    // a contract with a page of memory whose `call` does nothing but one
+    // storage_write with these arguments. assembled by hand, there is no
+    // wat compiler among the dependencies
+    fn storage_writer(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32) -> Vec<u8> {
+        let mut body: Vec<u8> = vec![0x00];
+        for argument in [key_ptr, key_len, value_ptr, value_len] {
+            body.push(0x41);
+            sleb128(argument, &mut body);
+        }
+        body.extend([0x10, 0x00, 0x0b]);
+
+        let mut code: Vec<u8> = b"\0asm".to_vec();
+        code.extend([0x01, 0x00, 0x00, 0x00]);
+        // types: (i32 i32 i32 i32) -> () and () -> ()
+        code.extend([0x01, 0x0b, 0x02, 0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x00, 0x60, 0x00, 0x00]);
+        code.extend([0x02, 0x15, 0x01, 0x03]);
+        code.extend(b"env");
+        code.push(0x0d);
+        code.extend(b"storage_write");
+        code.extend([0x00, 0x00]);
+        code.extend([0x03, 0x02, 0x01, 0x01]);
+        code.extend([0x05, 0x03, 0x01, 0x00, 0x01]);
+        code.extend([0x07, 0x11, 0x02, 0x06]);
+        code.extend(b"memory");
+        code.extend([0x02, 0x00, 0x04]);
+        code.extend(b"call");
+        code.extend([0x00, 0x01]);
+        code.extend([0x0a, body.len() as u8 + 2, 0x01, body.len() as u8]);
+        code.extend(body);
+        code
+    }
+
+    fn run(code: &[u8], meter: &mut GasMeter) -> Result<Execution, VmError> {
+        call(b"contract", code, BTreeMap::new(), Vec::new(), meter)
+    }
+
+    #[test]
+    fn storage_is_paid_per_byte() {
+        let mut short = GasMeter::new(gas::MAX_TX_GAS);
+        let execution = run(&storage_writer(0, 4, 0, 10), &mut short).unwrap();
+        assert_eq!(execution.storage.get(&vec![0u8; 4]), Some(&vec![0u8; 10]));
+        let mut long = GasMeter::new(gas::MAX_TX_GAS);
+        run(&storage_writer(0, 4, 0, 110), &mut long).unwrap();
+        assert_eq!(long.used() - short.used(), 100 * gas::STORAGE_GAS_PER_BYTE);
+
+        // short of the last byte, the whole limit is burned
+        let mut meter = GasMeter::new(long.used() - 1);
+        assert_eq!(run(&storage_writer(0, 4, 0, 110), &mut meter).map(|_| ()), Err(VmError::OutOfGas));
+        assert_eq!(meter.remaining(), 0);
+    }
+
+    #[test]
+    fn reads_outside_the_memory_trap_without_allocating() {
+        let page: i32 = 65536;
+        for (key_ptr, key_len) in [(page - 2, 4), (0, page + 1), (0, -1), (-1, 1)] {
+            let mut meter = GasMeter::new(gas::MAX_TX_GAS);
+            let result = run(&storage_writer(key_ptr, key_len, 0, 1), &mut meter);
+            assert!(matches!(result, Err(VmError::Trap(_))), "{} bytes at {}", key_len, key_ptr);
+        }
+    }
+}
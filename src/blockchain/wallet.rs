@@ -0,0 +1,107 @@
+use crate::blockchain::address::Address;
+use crate::blockchain::script::Script;
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+
+// an address is the public key's sha256 in base58check, the same hash a
+// p2pkh locking script checks the spender's key against
+pub fn address_from_public_key(public_key: &[u8]) -> Address {
+    Address::from_public_key(public_key)
+}
+
+fn pubkey_hash(public_key: &[u8]) -> Vec<u8> {
+    Sha256::digest(public_key).to_vec()
+}
+
+// an ed25519 key pair and the address derived from it
+#[derive(Debug, Clone)]
+pub struct Wallet {
+    signing_key: SigningKey,
+}
+
+impl Wallet {
+    // a new key pair from the operating system's random number generator
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        getrandom::fill(&mut secret).expect("the operating system has no random number generator");
+        Wallet::from_secret(secret)
+    }
+
+    // the same secret always gives the same keys and address, it's all a
+    // backup needs
+    pub fn from_secret(secret: [u8; 32]) -> Self {
+        Wallet {
+            signing_key: SigningKey::from_bytes(&secret),
+        }
+    }
+
+    pub fn secret(&self) -> [u8; 32] {
+        self.signing_key.to_bytes()
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    pub fn address(&self) -> Address {
+        address_from_public_key(&self.public_key())
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.signing_key.sign(message).to_bytes()
+    }
+
+    // what a payment to this wallet locks the coins with, so only a
+    // signature from its key can spend them
+    pub fn locking_script(&self) -> Vec<u8> {
+        Script::p2pkh(&pubkey_hash(&self.public_key()))
+            .to_bytes()
+            .expect("a p2pkh script always encodes")
+    }
+
+    // the unlocking script spending coins locked to this wallet, for a
+    // transaction whose signature hash is `message`
+    pub fn unlocking_script(&self, message: &[u8]) -> Vec<u8> {
+        Script::p2pkh_unlock(&self.sign(message), &self.public_key())
+            .to_bytes()
+            .expect("a p2pkh script always encodes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::miner;
+    use crate::blockchain::transaction::Transaction;
+    use crate::blockchain::BlockChain;
+
+    #[test]
+    fn coins_sent_to_a_wallet_address_are_spent_with_its_key() {
+        let wallet = Wallet::generate();
+        assert_eq!(Wallet::from_secret(wallet.secret()).address(), wallet.address());
+        assert_ne!(Wallet::generate().address(), wallet.address());
+
+        let miner = miner();
+        let mut chain = BlockChain::with_difficulty(miner.address().into(), 0);
+        for _ in 0..BlockChain::COINBASE_MATURITY {
+            chain.mining().unwrap();
+        }
+        let payment = Transaction::new(miner.address().into_bytes(), wallet.address().into_bytes(), 1).sign(&miner);
+        chain.add_transaction(&payment).unwrap();
+        chain.mining().unwrap();
+        assert_eq!(chain.calculate_total_amount(&wallet.address(), 1), 1);
+        // the wallet locks its own funds, a payment can't do it for it
+        let lock = Transaction::new(wallet.address().into_bytes(), wallet.address().into_bytes(), 0)
+            .with_locking_script(wallet.locking_script())
+            .sign(&wallet);
+        chain.add_transaction(&lock).unwrap();
+        chain.mining().unwrap();
+
+        // checking the signature costs gas
+        let unsigned = Transaction::new(wallet.address().into_bytes(), b"B".to_vec(), 1).with_gas(5_000, 0);
+        assert!(chain.verify_spending_conditions(&unsigned).is_err());
+        let unlocking = wallet.unlocking_script(&unsigned.signature_hash());
+        let signed = unsigned.with_unlocking_script(unlocking);
+        assert!(chain.verify_spending_conditions(&signed).is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::blockchain::address::Address;
+use crate::blockchain::clock::local_time;
+use crate::blockchain::index::TxLocation;
+use crate::blockchain::mempool::MempoolError;
+use crate::blockchain::transaction::Transaction;
+use crate::blockchain::wallet::Wallet;
+use crate::blockchain::BlockChain;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+pub enum WalletError {
+    // another wallet is loaded under the name
+    AlreadyLoaded(String),
+    UnknownWallet(String),
+    // an encrypted wallet that has to be unlocked to sign
+    Locked(String),
+    WrongPassphrase,
+    // asked to sign a transaction another address sends
+    NotSender(String),
+    // the payment the wallet signed didn't get into the pool
+    Rejected(MempoolError),
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::AlreadyLoaded(name) => write!(f, "a wallet named {} is already loaded", name),
+            WalletError::UnknownWallet(name) => write!(f, "no wallet named {} is loaded", name),
+            WalletError::Locked(name) => write!(f, "wallet {} is locked, unlock it with its passphrase first", name),
+            WalletError::WrongPassphrase => write!(f, "the passphrase is not the wallet's"),
+            WalletError::NotSender(name) => write!(f, "wallet {} is not the transaction's sender", name),
+            WalletError::Rejected(e) => write!(f, "payment rejected: {}", e),
+        }
+    }
+}
+
+impl From<MempoolError> for WalletError {
+    fn from(e: MempoolError) -> Self {
+        WalletError::Rejected(e)
+    }
+}
+
+// the wallets a node holds keys for, by a name its operator picks
+pub type Wallets = BTreeMap<String, Wallet>;
+
+// one request for one of the node's wallets. a transport (an http path like
+// /wallets/{name}/balance, an rpc call with a wallet parameter) parses into
+// this and hands it to `call_wallet` with the name. there is no call that
+// answers with a key, an encrypted wallet's is only ever used to sign
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalletCall {
+    Address,
+    Balance,
+    History,
+    Send { recipient: Vec<u8>, value: u64, fee: u64 },
+    // walletlock
+    Lock,
+    // walletunlock, for `timeout` seconds
+    Unlock { passphrase: String, timeout: u64 },
+    SignMessage { message: Vec<u8> },
+    // the transaction's bytes in hex
+    SignTransaction { transaction: Box<Transaction> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalletReply {
+    Address(Address),
+    Balance(i64),
+    // oldest first
+    History(Vec<TxLocation>),
+    Sent { txid: Vec<u8> },
+    Locked,
+    // local time in nanoseconds
+    Unlocked { until: u128 },
+    Signature(Vec<u8>),
+    Signed(Box<Transaction>),
+}
+
+impl WalletCall {
+    // a method name and its parameters in order, none if either is wrong
+    pub fn parse(method: &str, params: &[&str]) -> Option<WalletCall> {
+        match (method, params) {
+            ("address", []) => Some(WalletCall::Address),
+            ("balance", []) => Some(WalletCall::Balance),
+            ("history", []) => Some(WalletCall::History),
+            ("send", [recipient, value]) => Some(WalletCall::Send {
+                recipient: recipient.as_bytes().to_vec(),
+                value: value.parse().ok()?,
+                fee: 0,
+            }),
+            ("send", [recipient, value, fee]) => Some(WalletCall::Send {
+                recipient: recipient.as_bytes().to_vec(),
+                value: value.parse().ok()?,
+                fee: fee.parse().ok()?,
+            }),
+            ("walletlock", []) => Some(WalletCall::Lock),
+            ("walletunlock", [passphrase, timeout]) => Some(WalletCall::Unlock {
+                passphrase: passphrase.to_string(),
+                timeout: timeout.parse().ok()?,
+            }),
+            ("signmessage", [message]) => Some(WalletCall::SignMessage {
+                message: message.as_bytes().to_vec(),
+            }),
+            ("signtransaction", [transaction]) => Some(WalletCall::SignTransaction {
+                transaction: Box::new(Transaction::decode(&hex::decode(transaction).ok()?)?),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl BlockChain {
+    // blocks mined from now on index its address too, if the node only
+    // indexes watched addresses
+    pub fn load_wallet(&mut self, name: &str, wallet: Wallet) -> Result<(), WalletError> {
+        if self.wallets.contains_key(name) || self.keystore.contains(name) {
+            return Err(WalletError::AlreadyLoaded(name.to_string()));
+        }
+        if self.index.watch_list().is_some() {
+            self.watch_address(wallet.address().as_bytes());
+        }
+        self.wallets.insert(name.to_string(), wallet);
+        Ok(())
+    }
+
+    pub fn unload_wallet(&mut self, name: &str) -> Option<Wallet> {
+        self.wallets.remove(name)
+    }
+
+    // the key to sign with, an encrypted wallet's only while it is
+    // unlocked
+    pub fn wallet(&self, name: &str) -> Result<&Wallet, WalletError> {
+        match self.wallets.get(name) {
+            Some(wallet) => Ok(wallet),
+            None => self.keystore.wallet(name, local_time()),
+        }
+    }
+
+    // locked or not
+    pub fn wallet_address(&self, name: &str) -> Result<Address, WalletError> {
+        match (self.wallets.get(name), self.keystore.key(name)) {
+            (Some(wallet), _) => Ok(wallet.address()),
+            (None, Some(key)) => Ok(key.address()),
+            (None, None) => Err(WalletError::UnknownWallet(name.to_string())),
+        }
+    }
+
+    pub fn wallets(&self) -> &Wallets {
+        &self.wallets
+    }
+
+    // at the tip, the pool isn't included
+    pub fn wallet_balance(&self, name: &str) -> Result<i64, WalletError> {
+        let address: Address = self.wallet_address(name)?;
+        Ok(self.state.balance(address.as_bytes()))
+    }
+
+    pub fn wallet_history(&self, name: &str) -> Result<Vec<TxLocation>, WalletError> {
+        let address: Address = self.wallet_address(name)?;
+        Ok(self.index.address_history(address.as_bytes()).to_vec())
+    }
+
+    // signs a payment with the wallet's key and puts it in the pool. the
+    // nonce follows the wallet's transactions already in the pool, so
+    // several payments can wait for the same block
+    pub fn send_from(&mut self, name: &str, recipient: &[u8], value: u64, fee: u64) -> Result<Vec<u8>, WalletError> {
+        let wallet: Wallet = self.wallet(name)?.clone();
+        let sender: Vec<u8> = wallet.address().into_bytes();
+        let nonce: u64 = self.next_nonce(&sender);
+
+        let tx: Transaction = Transaction::new(sender, recipient.to_vec(), value)
+            .with_fee(fee)
+            .with_nonce(nonce)
+            .with_chain_id(self.chain_id)
+            .sign(&wallet);
+        let txid: Vec<u8> = tx.id();
+        self.add_transaction(&tx)?;
+        Ok(txid)
+    }
+
+    // every block mined with mining() from now on pays the wallet
+    pub fn set_reward_wallet(&mut self, name: &str) -> Result<(), WalletError> {
+        let address: Address = self.wallet_address(name)?;
+        self.set_reward_address(address.into());
+        Ok(())
+    }
+
+    // runs `call` against the wallet named `wallet`, for whatever routes
+    // requests to the node
+    pub fn call_wallet(&mut self, wallet: &str, call: WalletCall) -> Result<WalletReply, WalletError> {
+        // keys whose time is up don't wait in memory for the next unlock
+        self.keystore.expire(local_time());
+        match call {
+            WalletCall::Address => Ok(WalletReply::Address(self.wallet_address(wallet)?)),
+            WalletCall::Balance => Ok(WalletReply::Balance(self.wallet_balance(wallet)?)),
+            WalletCall::History => Ok(WalletReply::History(self.wallet_history(wallet)?)),
+            WalletCall::Send { recipient, value, fee } => {
+                let txid = self.send_from(wallet, &recipient, value, fee)?;
+                Ok(WalletReply::Sent { txid })
+            }
+            WalletCall::Lock => {
+                self.lock_wallet(wallet)?;
+                Ok(WalletReply::Locked)
+            }
+            WalletCall::Unlock { passphrase, timeout } => {
+                let until = self.unlock_wallet(wallet, &passphrase, Duration::from_secs(timeout))?;
+                Ok(WalletReply::Unlocked { until })
+            }
+            WalletCall::SignMessage { message } => Ok(WalletReply::Signature(self.sign_message(wallet, &message)?.to_vec())),
+            WalletCall::SignTransaction { transaction } => Ok(WalletReply::Signed(Box::new(self.sign_transaction(wallet, *transaction)?))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::test_utils::test_wallet;
+
+    #[test]
+    fn each_wallet_has_its_own_balance_history_and_payments() {
+        let mut chain = BlockChain::with_difficulty(test_wallet("miner").address().into(), 0);
+        chain.load_wallet("savings", test_wallet("savings")).unwrap();
+        chain.load_wallet("spending", test_wallet("spending")).unwrap();
+        assert_eq!(
+            chain.load_wallet("savings", test_wallet("other")),
+            Err(WalletError::AlreadyLoaded("savings".into()))
+        );
+
+        chain.set_reward_wallet("savings").unwrap();
+        for _ in 0..BlockChain::COINBASE_MATURITY + 2 {
+            chain.mining().unwrap();
+        }
+        let spending = chain.wallet("spending").unwrap().address().into_bytes();
+        let call = WalletCall::parse("send", &[&String::from_utf8(spending).unwrap(), "1"]).unwrap();
+        let first = chain.call_wallet("savings", call.clone()).unwrap();
+        // the second one waits in the pool behind the first
+        chain.call_wallet("savings", call).unwrap();
+        chain.mining().unwrap();
+
+        let WalletReply::Sent { txid } = first else {
+            panic!("a send replies with the txid");
+        };
+        assert!(chain.find_transaction(&txid).is_some());
+        assert_eq!(chain.wallet_balance("spending"), Ok(2));
+        assert_eq!(chain.call_wallet("spending", WalletCall::History).unwrap(), WalletReply::History(chain.wallet_history("spending").unwrap()));
+        assert_eq!(chain.wallet_history("spending").unwrap().len(), 2);
+        assert_eq!(chain.wallet_balance("checking"), Err(WalletError::UnknownWallet("checking".into())));
+    }
+}
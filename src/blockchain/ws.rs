@@ -0,0 +1,151 @@
+use crate::blockchain::events::{ChainEvent, EventKind};
+use crate::blockchain::node::Node;
+use crate::blockchain::BlockChain;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::{Message, WebSocket};
+
+// the chain's events pushed to websocket clients as they happen, one json
+// text message per event (see ChainEvent::to_json). a client picks the
+// events it wants in the query string, every kind without one:
+//
+//     ws://127.0.0.1:8546/?events=NewBlock,ChainReorg
+//     ws://127.0.0.1:8546/devnet?events=NewTransaction
+//
+// on a node the path picks the chain, as json-rpc's prefixes do. the
+// stream only goes one way, what a client sends is never read, and a
+// client that went away is noticed on the next message that can't be
+// written
+
+// a client has this long to finish its handshake, the accepting thread
+// waits for it
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// a ping after this long without an event, so a dead client is noticed
+// even on a quiet chain
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+// serves the chain's events until the process is stopped, a thread per
+// client. the chain is only locked while a client subscribes
+pub fn serve(chain: &Mutex<BlockChain>, address: &str) -> std::io::Result<()> {
+    accept(TcpListener::bind(address)?, |_| Some(chain))
+}
+
+// every chain the node hosts, under its json-rpc prefix
+pub fn serve_node(node: &Node, address: &str) -> std::io::Result<()> {
+    accept(TcpListener::bind(address)?, |path| node.by_prefix(path).map(|hosted| &*hosted.chain))
+}
+
+fn accept<'a>(listener: TcpListener, route: impl Fn(&str) -> Option<&'a Mutex<BlockChain>>) -> std::io::Result<()> {
+    info!(address = %listener.local_addr()?, "websocket events listening");
+    for stream in listener.incoming() {
+        let stream: TcpStream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!(error = %e, "websocket connection not accepted");
+                continue;
+            }
+        };
+        match subscribe(stream, &route) {
+            Ok((socket, events)) => {
+                std::thread::spawn(move || push(socket, events));
+            }
+            Err(e) => debug!(error = %e, "websocket handshake failed"),
+        }
+    }
+    Ok(())
+}
+
+// the events a query string asks for, e.g. "events=NewBlock,ChainReorg"
+fn requested_kinds(query: Option<&str>) -> Result<Vec<EventKind>, String> {
+    let names: Option<&str> = query
+        .unwrap_or("")
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("events="));
+    match names {
+        None => Ok(EventKind::ALL.to_vec()),
+        Some(names) => names
+            .split(',')
+            .map(|name| EventKind::parse(name).ok_or_else(|| format!("no event called {}", name)))
+            .collect(),
+    }
+}
+
+fn refuse(status: u16, reason: String) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason));
+    *response.status_mut() = tungstenite::http::StatusCode::from_u16(status).expect("a valid status");
+    response
+}
+
+// the handshake, subscribing the client to what it asked for on the chain
+// its path is for before it is answered, so it misses nothing that happens
+// after. a 404 for a path no chain claims, a 400 for an event that doesn't
+// exist
+// the error response's size is tungstenite's to choose
+#[allow(clippy::result_large_err)]
+fn subscribe<'a>(
+    stream: TcpStream,
+    route: &impl Fn(&str) -> Option<&'a Mutex<BlockChain>>,
+) -> Result<(WebSocket<TcpStream>, Receiver<ChainEvent>), String> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e| e.to_string())?;
+    let mut events: Option<Receiver<ChainEvent>> = None;
+    let socket = tungstenite::accept_hdr(stream, |request: &Request, response: Response| {
+        let chain = route(request.uri().path()).ok_or_else(|| refuse(404, "no chain at this path".to_string()))?;
+        let kinds: Vec<EventKind> = requested_kinds(request.uri().query()).map_err(|e| refuse(400, e))?;
+        events = Some(chain.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).subscribe(&kinds));
+        Ok(response)
+    })
+    .map_err(|e| e.to_string())?;
+    let events: Receiver<ChainEvent> = events.ok_or("the handshake was refused")?;
+    socket.get_ref().set_read_timeout(None).map_err(|e| e.to_string())?;
+    Ok((socket, events))
+}
+
+// writes events to the client until it goes away. dropping the receiver
+// then unsubscribes it
+fn push(mut socket: WebSocket<TcpStream>, events: Receiver<ChainEvent>) {
+    loop {
+        let message: Message = match events.recv_timeout(KEEPALIVE) {
+            Ok(event) => Message::text(event.to_json()),
+            Err(RecvTimeoutError::Timeout) => Message::Ping(Default::default()),
+            // the chain is gone
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = socket.close(None);
+                return;
+            }
+        };
+        if let Err(e) = socket.send(message) {
+            debug!(error = %e, "websocket client gone");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn a_client_gets_the_events_it_subscribed_to() {
+        let chain = Arc::new(Mutex::new(BlockChain::with_difficulty("miner".into(), 0)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let serving = Arc::clone(&chain);
+        std::thread::spawn(move || accept(listener, |_| Some(&*serving)));
+
+        let (mut client, _) = tungstenite::connect(format!("ws://{}/?events=NewBlock", address)).unwrap();
+        chain.lock().unwrap().mining().unwrap();
+        let tip = chain.lock().unwrap().last_block().unwrap().hash();
+        let message: String = client.read().unwrap().into_text().unwrap().to_string();
+        assert!(message.starts_with("{\"event\":\"NewBlock\""));
+        assert!(message.contains(&tip.to_string()));
+
+        let unknown = tungstenite::connect(format!("ws://{}/?events=NewBlock,Gossip", address));
+        assert!(matches!(unknown, Err(tungstenite::Error::Http(response)) if response.status() == 400));
+        assert_eq!(requested_kinds(Some("events=ChainReorg")), Ok(vec![EventKind::ChainReorg]));
+    }
+}
@@ -0,0 +1,2 @@
+// the node as a library, so fuzz targets and benchmarks can drive it
+pub mod blockchain;
@@ -1,83 +1,539 @@
-pub mod blockchain;
-use crate::blockchain::{transaction::Transaction, Serialization};
-use blockchain::{Block, BlockChain, BlockSearch, BlockSearchResult};
-// use transaction::*;
+use blockchain::blockchain::address::Address;
+use blockchain::blockchain::hash32::Hash32;
+use blockchain::blockchain::json::block_json;
+use blockchain::blockchain::node::{ChainSpec, HostError, Node};
+use blockchain::blockchain::verify::{verify_file, VerifyOptions, VerifyReport};
+use blockchain::blockchain::{wallet::Wallet, Block, BlockChain};
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "explorer", feature = "server"))]
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-fn main() {
-    let my_blockchain_address: &str = "my blockchain address";
-    let mut block_chain: BlockChain = BlockChain::new(my_blockchain_address.into());
-    // block_chain.print();
+#[cfg(feature = "tui")]
+mod tui;
 
-    // create transactions
-    // let trx_1 = Transaction::new("A".into(), "B".into(), 1);
-    let trx_1 = Transaction::new("A".to_string().into(), "B".to_string().into(), 1);
+// where the node keeps its blocks, relative to where it is started
+const CHAIN_DIR: &str = "chain-data";
 
-    // let trx_2 = Transaction::new("C".into(), "D".into(), 2);
-    // let trx_3 = Transaction::new("X".into(), "Y".into(), 3);
+#[derive(Debug, Parser)]
+#[command(name = "blockchain", about = "a proof of work blockchain node, built from scratch")]
+struct Cli {
+    /// the directory holding the block file, the pool and the keystore
+    #[arg(long, global = true, default_value = CHAIN_DIR)]
+    data_dir: PathBuf,
 
-    // add transactions to the pool and mint
-    block_chain.add_transaction(&trx_1);
-    // block_chain.mining();
+    /// mines a few blocks on a chain kept in memory and prints where the
+    /// time of each one went, stage by stage
+    ///
+    /// RUST_LOG=info also prints every stage and storage span as it closes
+    #[arg(long, value_name = "BLOCKS", num_args = 0..=1, default_missing_value = "5")]
+    profile: Option<usize>,
 
-    // add more transactions
-    // block_chain.add_transaction(trx_2);
-    // block_chain.add_transaction(trx_3);
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    block_chain.mining();
-    block_chain.print();
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// run a node on the saved chain
+    #[command(subcommand)]
+    Node(NodeCommand),
+    /// keys kept encrypted in the node's keystore
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// payments signed by one of the keystore's wallets
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// the saved chain as a whole
+    #[command(subcommand)]
+    Chain(ChainCommand),
+    /// one block of the saved chain
+    #[command(subcommand)]
+    Block(BlockCommand),
+    /// checks a chain file written by BlockChain::export and prints a one
+    /// line json report
+    ///
+    /// nothing is logged, so the output can be piped straight into a
+    /// script, and the exit code is 1 when the chain isn't valid
+    Verify {
+        file: PathBuf,
+        #[arg(long)]
+        difficulty: Option<usize>,
+        #[arg(long)]
+        chain_id: Option<u64>,
+        /// a chain made with a fixed difficulty, not the network's rules
+        #[arg(long)]
+        no_retarget: bool,
+    },
+    /// the terminal dashboard. it owns the terminal, so no log lines are
+    /// printed over it
+    #[cfg(feature = "tui")]
+    Tui,
+    /// serves the explorer on a node that keeps mining a block every few
+    /// seconds
+    #[cfg(feature = "explorer")]
+    Explorer {
+        #[arg(default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// the explorer on a regtest chain that only lives as long as the
+    /// process, a block every two seconds
+    #[cfg(feature = "explorer")]
+    Regtest {
+        #[arg(default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// runs a node behind the http api, blocks are mined when a client asks
+    /// for one
+    #[cfg(feature = "server")]
+    Server {
+        #[arg(default_value = "127.0.0.1:8000")]
+        address: String,
+    },
+}
 
-    println!(
-        "value for miner: {}",
-        block_chain.calculate_total_amount(my_blockchain_address.to_string())
-    );
-    println!(
-        "value for A: {}",
-        block_chain.calculate_total_amount("A".to_string())
-    );
-    println!(
-        "value for B: {}",
-        block_chain.calculate_total_amount("B".to_string())
-    );
-    // println!("value for D: {}", block_chain.calculate_total_amount("D".to_string()));
+#[derive(Debug, Subcommand)]
+enum NodeCommand {
+    /// mines a block every `interval` seconds until ctrl-c, which saves
+    /// the pool and closes the block file cleanly
+    Start {
+        /// where the rewards go, a new key's address if not given
+        #[arg(long)]
+        reward: Option<String>,
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+        /// also answer json-rpc on this address, e.g. 127.0.0.1:8332. needs
+        /// the rpc feature
+        ///
+        /// the main chain answers at /, a devnet at /devnet
+        #[arg(long)]
+        rpc: Option<String>,
+        /// push new blocks, transactions and reorgs to websocket clients on
+        /// this address, e.g. 127.0.0.1:8546. needs the ws feature
+        ///
+        /// the path picks the chain like json-rpc's, ?events=NewBlock,...
+        /// the events
+        #[arg(long)]
+        ws: Option<String>,
+        /// also host a devnet, with its own chain id and its files in
+        /// <data-dir>/devnet. it never shares a block or a transaction
+        /// with the main chain
+        #[arg(long)]
+        devnet: bool,
+        /// a json-rpc port of the devnet's own, besides /devnet
+        #[arg(long, requires = "devnet")]
+        devnet_rpc: Option<String>,
+    },
+}
 
+#[derive(Debug, Subcommand)]
+enum WalletCommand {
+    /// a new key, encrypted with the passphrase and saved in the keystore.
+    /// prints its address, never the key
+    New {
+        name: String,
+        #[arg(long)]
+        passphrase: String,
+    },
+    /// balance at the tip, and how much of it can be spent
+    Balance { address: String },
 }
 
-// // _create_hasher();
+#[derive(Debug, Subcommand)]
+enum TxCommand {
+    /// signs a payment with a keystore wallet and leaves it in the pool for
+    /// the node to mine. prints the txid
+    Send {
+        #[arg(long)]
+        from: String,
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long, default_value_t = 0)]
+        fee: u64,
+        /// the wallet's, it is unlocked only for this payment
+        #[arg(long)]
+        passphrase: String,
+    },
+}
 
-// let address: &str = "0xFake_address";
-// let mut block_chain: BlockChain = create_block_chain(address, false);
+#[derive(Debug, Subcommand)]
+enum ChainCommand {
+    /// every block as a line of json, oldest first
+    Print,
+    /// replays every block and checks it against its commitments. the exit
+    /// code is 1 when the chain isn't valid
+    Validate,
+}
 
-// // block 1
-// let previous_hash: Vec<u8> = get_previous_hash(&block_chain, false);
-// block_chain.create_block(1, &previous_hash);
+#[derive(Debug, Subcommand)]
+enum BlockCommand {
+    /// a block and its transactions as json, by height or by hash
+    Get { block: String },
+}
 
-// // block 2
-// let previous_hash: Vec<u8> = get_previous_hash(&block_chain, false);
-// block_chain.create_block(2, &previous_hash);
+fn main() {
+    let cli = Cli::parse();
 
-// // serializations/deserialization
-// let tx: Transaction = create_serialized_tx(false);
+    #[cfg(feature = "tui")]
+    if let Some(Command::Tui) = cli.command {
+        if let Err(e) = tui::dashboard(Wallet::generate()) {
+            eprintln!("dashboard failed: {}", e);
+        }
+        return;
+    }
+    if let Some(Command::Verify { file, difficulty, chain_id, no_retarget }) = &cli.command {
+        let mut options: VerifyOptions = VerifyOptions::default();
+        options.difficulty = difficulty.unwrap_or(options.difficulty);
+        options.chain_id = chain_id.unwrap_or(options.chain_id);
+        if *no_retarget {
+            options.retarget = None;
+        }
+        std::process::exit(verify(file, options));
+    }
 
-// // add transactions to the (last) block
-// block_chain.add_transaction(&tx);
+    // RUST_LOG picks the verbosity, e.g. RUST_LOG=debug. a node logs what
+    // it does, the other commands only what went wrong. logs go to stderr,
+    // what a command prints can be piped
+    let node: bool = match &cli.command {
+        Some(Command::Node(_)) => true,
+        #[cfg(feature = "explorer")]
+        Some(Command::Explorer { .. } | Command::Regtest { .. }) => true,
+        #[cfg(feature = "server")]
+        Some(Command::Server { .. }) => true,
+        _ => false,
+    };
+    let level: &str = if node { "info" } else { "warn" };
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level)))
+        .with_span_events(if cli.profile.is_some() { FmtSpan::CLOSE } else { FmtSpan::NONE })
+        .with_writer(std::io::stderr)
+        .init();
 
-// // so block 3 is going to grab all the current trxs available from the pool
-// block_chain.create_block(3, &block_chain.last_block().hash());
+    if let Some(blocks) = cli.profile {
+        profile(blocks);
+        return;
+    }
 
-// // show the entire blocks in the chain
-// // block_chain.print();
+    let dir: &Path = &cli.data_dir;
+    let code: i32 = match cli.command {
+        None => {
+            eprintln!("no command given, see `blockchain --help`");
+            2
+        }
+        Some(Command::Node(NodeCommand::Start { reward, interval, rpc, ws, devnet, devnet_rpc })) => {
+            let mut specs: Vec<ChainSpec> = vec![ChainSpec::mainnet("main").with_data_dir(dir).with_rpc_prefix("/")];
+            if devnet {
+                let mut spec: ChainSpec = ChainSpec::devnet("devnet").with_data_dir(dir.join("devnet"));
+                spec.rpc_address = devnet_rpc;
+                specs.push(spec);
+            }
+            start_node(specs, reward, Duration::from_secs(interval), rpc, ws)
+        }
+        Some(Command::Wallet(WalletCommand::New { name, passphrase })) => with_chain(dir, |chain| {
+            let wallet: Wallet = Wallet::generate();
+            let address: Address = wallet.address();
+            chain.load_wallet(&name, wallet).and_then(|()| chain.encrypt_wallet(&name, &passphrase)).map_err(|e| e.to_string())?;
+            println!("{}", address);
+            Ok(0)
+        }),
+        Some(Command::Wallet(WalletCommand::Balance { address })) => with_chain(dir, |chain| {
+            let height = chain.blocks().len() as u64;
+            println!(
+                "balance: {}, spendable: {}",
+                chain.state().balance(address.as_bytes()),
+                chain.state().spendable_balance(address.as_bytes(), height)
+            );
+            Ok(0)
+        }),
+        Some(Command::Tx(TxCommand::Send { from, to, amount, fee, passphrase })) => with_chain(dir, |chain| {
+            chain.unlock_wallet(&from, &passphrase, Duration::from_secs(60)).map_err(|e| e.to_string())?;
+            let sent = chain.send_from(&from, to.as_bytes(), amount, fee);
+            chain.lock_wallet(&from).map_err(|e| e.to_string())?;
+            println!("{}", hex::encode(sent.map_err(|e| e.to_string())?));
+            Ok(0)
+        }),
+        Some(Command::Chain(ChainCommand::Print)) => with_chain(dir, |chain| {
+            for (height, block) in chain.blocks().iter().enumerate() {
+                println!("{}", block_json(height, block));
+            }
+            Ok(0)
+        }),
+        Some(Command::Chain(ChainCommand::Validate)) => with_chain(dir, |chain| match chain.validate_chain() {
+            Ok(()) => {
+                println!("valid, {} blocks", chain.blocks().len());
+                Ok(0)
+            }
+            Err(e) => {
+                println!("invalid: {}", e);
+                Ok(1)
+            }
+        }),
+        Some(Command::Block(BlockCommand::Get { block })) => with_chain(dir, |chain| {
+            let height: Option<usize> = match block.parse::<usize>() {
+                Ok(height) => Some(height),
+                Err(_) => block
+                    .parse::<Hash32>()
+                    .ok()
+                    .and_then(|hash| chain.blocks().iter().position(|b| b.hash() == hash)),
+            };
+            let found: Option<&Block> = height.and_then(|height| chain.get_block(height));
+            match (height, found) {
+                (Some(height), Some(found)) => {
+                    println!("{}", block_json(height, found));
+                    Ok(0)
+                }
+                _ => {
+                    eprintln!("no block {}", block);
+                    Ok(1)
+                }
+            }
+        }),
+        Some(Command::Verify { .. }) => unreachable!("verify returned above"),
+        #[cfg(feature = "tui")]
+        Some(Command::Tui) => unreachable!("the dashboard returned above"),
+        #[cfg(feature = "explorer")]
+        Some(Command::Explorer { address }) => {
+            explorer_node(dir, &address);
+            0
+        }
+        #[cfg(feature = "explorer")]
+        Some(Command::Regtest { address }) => {
+            regtest_node(&address);
+            0
+        }
+        #[cfg(feature = "server")]
+        Some(Command::Server { address }) => {
+            api_node(dir, &address);
+            0
+        }
+    };
+    std::process::exit(code);
+}
 
-// // search block
-// search_blocks(&block_chain, &previous_hash, false);
+// opens the saved chain, runs `command` on it and closes it cleanly, so
+// the next command doesn't check every signature again. the exit code
+fn with_chain(
+    dir: &Path,
+    command: impl FnOnce(&mut BlockChain) -> Result<i32, String>,
+) -> i32 {
+    // nothing is mined, the reward address is never used
+    let mut chain = match BlockChain::open(dir, Wallet::generate().address().to_string()) {
+        Ok(chain) => chain,
+        Err(e) => {
+            eprintln!("chain not opened from {}: {}", dir.display(), e);
+            return 1;
+        }
+    };
+    let code: i32 = command(&mut chain).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        1
+    });
+    if let Err(e) = chain.shutdown() {
+        warn!(error = %e, "shutdown not saved");
+    }
+    code
+}
 
-// // block_chain.print();
+// the chain saved in `dir`, it keeps growing from one run to the next.
+// delete the directory to start over
+#[cfg(any(feature = "explorer", feature = "server"))]
+fn open_chain(dir: &Path, address: &str) -> BlockChain {
+    match BlockChain::open(dir, address.to_string()) {
+        Ok(chain) => chain,
+        Err(e) => {
+            warn!(error = %e, dir = %dir.display(), "chain not opened, this run is not saved");
+            BlockChain::new(address.to_string())
+        }
+    }
+}
 
-// // now we can make comparations between blocks
-// let block1: Block = Block::new(0, "previous hash".as_bytes().to_vec());
-// let block2: Block = Block::new(0, "previous hash".as_bytes().to_vec());
-// println!("block1 == block2: {:?}", block1 == block2);
+// ctrl-c waits for whatever holds the chain, a block being mined or
+// validated or a request being answered, then saves the pool and marks the
+// block file as cleanly closed before the process exits
+#[cfg(any(feature = "explorer", feature = "server"))]
+fn shutdown_on_ctrl_c(chain: Arc<Mutex<BlockChain>>) {
+    let handler = move || {
+        let mut chain = chain.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = chain.shutdown() {
+            warn!(error = %e, "shutdown not saved");
+        }
+        std::process::exit(0);
+    };
+    if let Err(e) = ctrlc::set_handler(handler) {
+        warn!(error = %e, "ctrl-c handler not installed, stopping won't be clean");
+    }
+}
 
-// // we can access to an specific block
-// let mut block_chain: BlockChain = create_block_chain(false);
-// let block: &Block = &block_chain[0];
-// println!("the first block is: {:?}", block);
+// the same for every chain of a node, each one is closed on its own
+fn shutdown_node_on_ctrl_c(node: Node) {
+    let handler = move || {
+        node.shutdown();
+        std::process::exit(0);
+    };
+    if let Err(e) = ctrlc::set_handler(handler) {
+        warn!(error = %e, "ctrl-c handler not installed, stopping won't be clean");
+    }
+}
+
+// hosts every chain in `specs` and mines a block on each of them every
+// interval. a chain whose files can't be opened runs in memory for this
+// run, like a single node's. the exit code when the chains can't be hosted
+// together
+fn start_node(specs: Vec<ChainSpec>, reward: Option<String>, interval: Duration, rpc: Option<String>, ws: Option<String>) -> i32 {
+    let reward: String = reward.unwrap_or_else(|| Wallet::generate().address().to_string());
+    let mut node: Node = Node::new();
+    for spec in specs {
+        let hosted = match node.add_chain(spec.clone(), reward.clone()) {
+            Err(HostError::Storage { name, error }) => {
+                warn!(chain = name.as_str(), %error, "chain not opened, this run is not saved");
+                node.add_chain(ChainSpec { data_dir: None, ..spec }, reward.clone())
+            }
+            hosted => hosted,
+        };
+        if let Err(e) = hosted {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    shutdown_node_on_ctrl_c(node.clone());
+    if rpc.is_some() || node.chains().iter().any(|hosted| hosted.spec.rpc_address.is_some()) {
+        #[cfg(feature = "rpc")]
+        {
+            let node = node.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = blockchain::blockchain::rpc::serve_node(&node, rpc.as_deref()) {
+                    warn!(error = %e, "json-rpc stopped");
+                }
+            });
+        }
+        #[cfg(not(feature = "rpc"))]
+        warn!("built without the rpc feature, json-rpc is off");
+    }
+    if let Some(ws) = ws {
+        #[cfg(feature = "ws")]
+        {
+            let node = node.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = blockchain::blockchain::ws::serve_node(&node, &ws) {
+                    warn!(error = %e, "websocket events stopped");
+                }
+            });
+        }
+        #[cfg(not(feature = "ws"))]
+        {
+            let _ = ws;
+            warn!("built without the ws feature, websocket events are off");
+        }
+    }
+    loop {
+        std::thread::sleep(interval);
+        for hosted in node.chains() {
+            // ctrl-c can stop the node while the block is mined
+            if let Err(e) = BlockChain::mine_async(&hosted.chain).wait() {
+                warn!(chain = hosted.spec.name.as_str(), error = %e, "block not mined");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "explorer")]
+fn explorer_node(dir: &Path, address: &str) {
+    let wallet: Wallet = Wallet::generate();
+    let mut node = open_chain(dir, &wallet.address().to_string());
+    if let Err(e) = node.load_wallet("node", wallet) {
+        warn!(error = %e, "wallet not loaded");
+    }
+    let chain = Arc::new(Mutex::new(node));
+    shutdown_on_ctrl_c(Arc::clone(&chain));
+    let miner = Arc::clone(&chain);
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_secs(5));
+            // something to look at besides the coinbase, once rewards mature
+            let _ = miner.lock().unwrap().send_from("node", b"B", 1, 0);
+            // the explorer keeps answering while the block is mined
+            if let Err(e) = BlockChain::mine_async(&miner).wait() {
+                warn!(error = %e, "block not mined");
+            }
+        }
+    });
+
+    if let Err(e) = blockchain::blockchain::explorer::serve(&chain, address) {
+        warn!(error = %e, "explorer stopped");
+    }
+}
+
+#[cfg(feature = "explorer")]
+fn regtest_node(address: &str) {
+    use blockchain::blockchain::genesis::GenesisConfig;
+    use blockchain::blockchain::regtest::BlockTimer;
+
+    // the node's wallet starts funded, its payments show up from the first
+    // blocks on
+    let wallet: Wallet = Wallet::generate();
+    let mut node = BlockChain::regtest(wallet.address().to_string(), &GenesisConfig::new().with_allocation(wallet.address(), 1_000));
+    if let Err(e) = node.load_wallet("node", wallet) {
+        warn!(error = %e, "wallet not loaded");
+    }
+    let chain = Arc::new(Mutex::new(node));
+    let _timer = BlockTimer::start(&chain, Duration::from_secs(2));
+    let sender = Arc::clone(&chain);
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let _ = sender.lock().unwrap().send_from("node", b"B", 1, 0);
+        }
+    });
+
+    if let Err(e) = blockchain::blockchain::explorer::serve(&chain, address) {
+        warn!(error = %e, "explorer stopped");
+    }
+}
+
+#[cfg(feature = "server")]
+fn api_node(dir: &Path, address: &str) {
+    let wallet: Wallet = Wallet::generate();
+    let mut node = open_chain(dir, &wallet.address().to_string());
+    if let Err(e) = node.load_wallet("node", wallet) {
+        warn!(error = %e, "wallet not loaded");
+    }
+    let chain = Arc::new(Mutex::new(node));
+    shutdown_on_ctrl_c(Arc::clone(&chain));
+    if let Err(e) = blockchain::blockchain::server::serve(&chain, address) {
+        warn!(error = %e, "api stopped");
+    }
+}
+
+fn profile(blocks: usize) {
+    let wallet: Wallet = Wallet::generate();
+    let mut chain: BlockChain = BlockChain::new(wallet.address().to_string());
+    if let Err(e) = chain.load_wallet("miner", wallet) {
+        warn!(error = %e, "wallet not loaded");
+    }
+    let recipient: Address = Wallet::generate().address();
+    for _ in 0..blocks {
+        // once the rewards mature every block carries a payment too
+        let _ = chain.send_from("miner", recipient.as_bytes(), 1, 0);
+        if let Err(e) = chain.mining() {
+            warn!(error = %e, "block not mined");
+            return;
+        }
+        print!("{}", chain.last_profile());
+    }
+
+    let started = Instant::now();
+    let valid = chain.validate_chain();
+    println!("chain validation: {:?} in {:.3} ms", valid, started.elapsed().as_secs_f64() * 1e3);
+}
+
+fn verify(file: &Path, options: VerifyOptions) -> i32 {
+    let report: VerifyReport = verify_file(file, options);
+    println!("{}", report.to_json());
+    if report.valid { 0 } else { 1 }
+}
@@ -1,4 +1,4 @@
-use crate::blockchain::{transaction::Transaction, Serialization};
+use crate::blockchain::{hash32::Hash32, transaction::Transaction, Serialization};
 use crate::blockchain::{Block, BlockChain, BlockSearch, BlockSearchResult};
 use sha2::{Digest, Sha256};
 
@@ -33,16 +33,16 @@ pub fn create_block_chain(address: String, print: bool) -> BlockChain {
     block_chain
 }
 
-pub fn get_previous_hash(block_chain: &BlockChain, print: bool) -> Vec<u8> {
+pub fn get_previous_hash(block_chain: &BlockChain, print: bool) -> Hash32 {
 
     // Display the hash of the block.
     // Right now there is only one block.
-    let previous_hash = block_chain.last_block().hash();
+    let previous_hash = block_chain.last_block().expect("a new chain has its genesis block").hash();
 
     // previous_hash is actually the message wrote in the block
     // in a Sha256 using its data for that.
     if print {
-        println!("previous_hash: {:?}\n", previous_hash);
+        println!("previous_hash: {}\n", previous_hash);
     }
 
     previous_hash
@@ -61,16 +61,16 @@ pub fn create_serialized_tx(print: bool) -> Transaction {
         let serialized_tx = tx.serialization();
 
         // decoded
-        let deserialized_tx = Transaction::deserialization(&serialized_tx);
+        let deserialized_tx = Transaction::deserialization(&serialized_tx).expect("a transaction we just serialized");
 
         println!("Transaction using modified display trait: {}", tx);
         println!("Transaction using non-modified debug trait: {:#?}", tx);
 
         println!("serialized transaction: {:?}\n", serialized_tx);
         println!("deserialized transaction: {:#?}\n", deserialized_tx);
+        assert_eq!(deserialized_tx, tx);
     }
 
-    // TODO: implement PartialEq and/or Eq to serialized_tx and deserialized_tx
     tx
 }
 
@@ -86,10 +86,10 @@ pub fn get_block_search_result(result: BlockSearchResult) {
             println!("the block chain is empty");
         }
         BlockSearchResult::FailOfPreviousHash(hash) => {
-            println!("not block has given previous hash as: {:?}", hash);
+            println!("not block has given previous hash as: {}", hash);
         }
         BlockSearchResult::FailOfBlockHash(hash) => {
-            println!("not block has given hash as: {:?}", hash);
+            println!("not block has given hash as: {}", hash);
         }
         BlockSearchResult::FailOfNonce(nonce) => {
             println!("no block has nonce as: {}", nonce);
@@ -103,12 +103,12 @@ pub fn get_block_search_result(result: BlockSearchResult) {
     }
 }
 
-pub fn search_blocks(block_chain: &BlockChain, previous_hash: &Vec<u8>, print: bool) {
+pub fn search_blocks(block_chain: &BlockChain, previous_hash: &Hash32, print: bool) {
     // search by index
     let block_search_result_enum = block_chain.search_block(BlockSearch::SearchByIndex(1));
 
     // search by hash
-    let hash_to_find = previous_hash.clone();
+    let hash_to_find = *previous_hash;
     let result = block_chain.search_block(BlockSearch::SearchByBlockHash(hash_to_find));
 
     if print {
@@ -0,0 +1,212 @@
+use blockchain::blockchain::audit::AuditEvent;
+use blockchain::blockchain::difficulty::HASHRATE_WINDOW;
+use blockchain::blockchain::transaction::Transaction;
+use blockchain::blockchain::wallet::Wallet;
+use blockchain::blockchain::BlockChain;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block as Panel, List, ListItem, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::{Duration, Instant};
+
+// how often the screen is redrawn and keys are read
+const FRAME: Duration = Duration::from_millis(250);
+// the demo node mines on its own this often
+const BLOCK_TIME: Duration = Duration::from_secs(3);
+const RECENT_BLOCKS: usize = 12;
+// lines of the event feed kept around, older ones scroll away
+const FEED_LENGTH: usize = 100;
+const HEX_SHOWN: usize = 16;
+
+// a node mining by itself with the dashboard on top. the audit log is
+// the node's event stream: every frame picks up the entries it hasn't
+// shown yet, so the screen follows blocks, pool changes and balances as
+// they happen.
+struct Dashboard {
+    chain: BlockChain,
+    // the node mines to it and signs its transfers with it
+    wallet: Wallet,
+    address: String,
+    // audit log entries already turned into feed lines
+    seen: usize,
+    feed: Vec<String>,
+    // the demo's own transfers, so they never conflict in the pool
+    next_nonce: u64,
+    last_block: Instant,
+}
+
+impl Dashboard {
+    fn new(wallet: Wallet) -> Self {
+        let address: String = wallet.address().into();
+        let mut dashboard = Dashboard {
+            chain: BlockChain::new(address.clone()),
+            wallet,
+            address,
+            seen: 0,
+            feed: Vec::<String>::new(),
+            next_nonce: 0,
+            last_block: Instant::now(),
+        };
+        dashboard.follow_events();
+        dashboard
+    }
+
+    // the miner pays someone out of its rewards, then mines
+    fn produce_block(&mut self) {
+        let recipients: [&str; 3] = ["alice", "bob", "carol"];
+        let recipient = recipients[(self.next_nonce % 3) as usize];
+        let mut tx = Transaction::new(self.address.clone().into(), recipient.into(), 1);
+        tx.nonce = self.next_nonce;
+        self.next_nonce += 1;
+        let _ = self.chain.add_transaction(&tx.sign(&self.wallet));
+
+        if let Err(e) = self.chain.mining() {
+            self.feed.push(format!("not mining: {}", e));
+        }
+        self.last_block = Instant::now();
+        self.follow_events();
+    }
+
+    fn follow_events(&mut self) {
+        let entries = self.chain.audit_log().entries();
+        for entry in &entries[self.seen..] {
+            let line = match &entry.event {
+                AuditEvent::BlockAppended { height, hash } => format!("block #{} {}", height, short(hash)),
+                AuditEvent::TransactionAdded { txid } => format!("pool + {}", short(txid)),
+                AuditEvent::TransactionReplaced { txid } => format!("pool ~ {}", short(txid)),
+                AuditEvent::TransactionRejected { txid } => format!("rejected {}: {}", short(txid), entry.cause),
+                AuditEvent::TransactionDropped { txid } => format!("dropped {}: {}", short(txid), entry.cause),
+                AuditEvent::TransactionEvicted { txid } => format!("pool - {}", short(txid)),
+                AuditEvent::BalanceChanged { address, delta, .. } => {
+                    format!("{} {:+}", String::from_utf8_lossy(address), delta)
+                }
+                AuditEvent::WatchedAddressActivity { address, height, txid } => {
+                    format!("watched {} in #{} {}", String::from_utf8_lossy(address), height, short(txid))
+                }
+                AuditEvent::Rollback { height, blocks } => format!("rolled back {} blocks to #{}", blocks, height),
+                AuditEvent::Reorg { fork_height, removed, added } => {
+                    format!("reorg at #{}: -{} +{}", fork_height, removed, added)
+                }
+            };
+            self.feed.push(line);
+        }
+        self.seen = entries.len();
+        if self.feed.len() > FEED_LENGTH {
+            self.feed.drain(..self.feed.len() - FEED_LENGTH);
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [status, body] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)]).areas(body);
+        let [blocks_area, pool_area] = Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(left);
+        let [peers_area, feed_area] = Layout::vertical([Constraint::Length(4), Constraint::Min(0)]).areas(right);
+
+        let metrics = self.chain.metrics();
+        let height = self.chain.blocks().len() - 1;
+        let next_block = BLOCK_TIME.saturating_sub(self.last_block.elapsed());
+        frame.render_widget(
+            Paragraph::new(format!(
+                "height {}   difficulty {}   hashrate ~{:.0} H/s   pool {}   next block in {}s   (q quits, m mines now)",
+                height,
+                self.chain.difficulty(),
+                self.chain.estimated_hashrate(HASHRATE_WINDOW),
+                metrics.mempool_size,
+                next_block.as_secs()
+            ))
+            .block(Panel::bordered().title(format!(" node {} ", self.address))),
+            status,
+        );
+
+        let blocks: Vec<ListItem> = self
+            .chain
+            .blocks()
+            .iter()
+            .enumerate()
+            .rev()
+            .take(RECENT_BLOCKS)
+            .map(|(height, block)| {
+                let miner = match block.miner() {
+                    Some(address) => String::from_utf8_lossy(&address).to_string(),
+                    None => "none".to_string(),
+                };
+                ListItem::new(format!(
+                    "#{:<4} {}  {} txs  miner {}",
+                    height,
+                    short(&block.hash()),
+                    block.transactions.len(),
+                    miner
+                ))
+            })
+            .collect();
+        frame.render_widget(List::new(blocks).block(Panel::bordered().title(" recent blocks ")), blocks_area);
+
+        let pool: Vec<ListItem> = self
+            .chain
+            .pending_transactions()
+            .iter()
+            .map(|tx| {
+                ListItem::new(format!(
+                    "{} -> {}  {} (fee {})",
+                    String::from_utf8_lossy(&tx.sender_address),
+                    String::from_utf8_lossy(&tx.recipient_address),
+                    tx.value,
+                    tx.fee
+                ))
+            })
+            .collect();
+        frame.render_widget(List::new(pool).block(Panel::bordered().title(" mempool ")), pool_area);
+
+        // the node doesn't talk to other nodes yet, the metrics say so too
+        let peers = if metrics.peer_count == 0 {
+            "no peers connected".to_string()
+        } else {
+            format!("{} peers connected", metrics.peer_count)
+        };
+        frame.render_widget(Paragraph::new(peers).block(Panel::bordered().title(" peers ")), peers_area);
+
+        let visible = feed_area.height.saturating_sub(2) as usize;
+        let feed: Vec<ListItem> = self
+            .feed
+            .iter()
+            .skip(self.feed.len().saturating_sub(visible))
+            .map(|line| ListItem::new(line.as_str()))
+            .collect();
+        frame.render_widget(List::new(feed).block(Panel::bordered().title(" events ")), feed_area);
+    }
+}
+
+fn short(bytes: &[u8]) -> String {
+    let mut text = hex::encode(bytes);
+    text.truncate(HEX_SHOWN);
+    text
+}
+
+fn run(terminal: &mut DefaultTerminal, dashboard: &mut Dashboard) -> std::io::Result<()> {
+    loop {
+        terminal.draw(|frame| dashboard.draw(frame))?;
+
+        if event::poll(FRAME)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('m') => dashboard.produce_block(),
+                _ => {}
+            }
+        }
+
+        if dashboard.last_block.elapsed() >= BLOCK_TIME {
+            dashboard.produce_block();
+        }
+    }
+}
+
+pub fn dashboard(wallet: Wallet) -> std::io::Result<()> {
+    let mut dashboard = Dashboard::new(wallet);
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut dashboard);
+    ratatui::restore();
+    result
+}